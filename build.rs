@@ -33,6 +33,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "proto/data-plane-api/envoy/type/metadata/v3/metadata.proto",
         "proto/data-plane-api/envoy/type/tracing/v3/custom_tag.proto",
         "proto/quilkin/relay/v1alpha1/relay.proto",
+        "proto/quilkin/plugin/v1alpha1/plugin.proto",
         "proto/quilkin/filters/capture/v1alpha1/capture.proto",
         "proto/quilkin/filters/compress/v1alpha1/compress.proto",
         "proto/quilkin/filters/concatenate_bytes/v1alpha1/concatenate_bytes.proto",
@@ -42,9 +43,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "proto/quilkin/filters/load_balancer/v1alpha1/load_balancer.proto",
         "proto/quilkin/filters/local_rate_limit/v1alpha1/local_rate_limit.proto",
         "proto/quilkin/filters/match/v1alpha1/match.proto",
+        "proto/quilkin/filters/mirror/v1alpha1/mirror.proto",
         "proto/quilkin/filters/pass/v1alpha1/pass.proto",
+        "proto/quilkin/filters/pcap/v1alpha1/pcap.proto",
         "proto/quilkin/filters/token_router/v1alpha1/token_router.proto",
         "proto/quilkin/filters/timestamp/v1alpha1/timestamp.proto",
+        "proto/quilkin/filters/wasm/v1alpha1/wasm.proto",
         "proto/udpa/xds/core/v3/resource_name.proto",
     ]
     .iter()
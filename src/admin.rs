@@ -23,10 +23,13 @@ use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server as HyperServer, StatusCode};
 
 use self::health::Health;
-use crate::config::Config;
+use crate::config::{history, validate::validate, Config};
 
 pub const PORT: u16 = 8000;
 
+/// The largest request body accepted by the admin endpoints.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
 /// Define which mode Quilkin is in.
 #[derive(Copy, Clone, Debug)]
 pub enum Mode {
@@ -52,7 +55,7 @@ pub fn server(
             Ok::<_, Infallible>(service_fn(move |req| {
                 let config = config.clone();
                 let health = health.clone();
-                async move { Ok::<_, Infallible>(handle_request(req, mode, config, health)) }
+                async move { Ok::<_, Infallible>(handle_request(req, mode, config, health).await) }
             }))
         }
     });
@@ -60,7 +63,7 @@ pub fn server(
     tokio::spawn(HyperServer::bind(&address).serve(make_svc))
 }
 
-fn handle_request(
+async fn handle_request(
     request: Request<Body>,
     mode: Mode,
     config: Arc<Config>,
@@ -87,6 +90,17 @@ fn handle_request(
                 .body(Body::from(format!("failed to create config dump: {err}")))
                 .unwrap(),
         },
+        (&Method::POST, "/config/validate") => validate_config(request.into_body()).await,
+        (&Method::GET, "/config/history") => json_response(&config.history.summary()),
+        (&Method::GET, "/config/history/diff") => diff_config(request.uri().query(), &config),
+        (&Method::POST, "/config/history/rollback") => {
+            rollback_config(request.uri().query(), &config)
+        }
+        (&Method::GET, "/xds/clients") => json_response(&config.xds_clients.summary()),
+        (&Method::GET, "/xds/agents") => json_response(&config.xds_clients.agents()),
+        (&Method::GET, "/pcap") => json_response(&crate::filters::pcap::status()),
+        (&Method::POST, "/pcap") => arm_packet_capture(request.uri().query()),
+        (&Method::DELETE, "/pcap") => json_response(&crate::filters::pcap::disarm()),
         (_, _) => {
             let mut response = Response::new(Body::empty());
             *response.status_mut() = StatusCode::NOT_FOUND;
@@ -95,6 +109,194 @@ fn handle_request(
     }
 }
 
+fn json_response<T: serde::Serialize>(value: &T) -> Response<Body> {
+    match serde_json::to_string(value) {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header(
+                "Content-Type",
+                hyper::header::HeaderValue::from_static("application/json"),
+            )
+            .body(Body::from(body))
+            .unwrap(),
+        Err(err) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(format!("failed to serialize response: {err}")))
+            .unwrap(),
+    }
+}
+
+/// Arms packet capture for any `Pcap` filters, limited by the optional
+/// `seconds` and `packets` query parameters.
+fn arm_packet_capture(query: Option<&str>) -> Response<Body> {
+    let mut duration = None;
+    let mut packets = None;
+
+    for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        let parsed = match &*key {
+            "seconds" => value
+                .parse()
+                .map(|seconds| duration = Some(std::time::Duration::from_secs(seconds))),
+            "packets" => value.parse().map(|value| packets = Some(value)),
+            _ => continue,
+        };
+
+        if let Err(err) = parsed {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("invalid `{key}` parameter: {err}")))
+                .unwrap();
+        }
+    }
+
+    match crate::filters::pcap::arm(duration, packets) {
+        Some(status) => json_response(&status),
+        None => bad_request("invalid `seconds` parameter: duration is too long".into()),
+    }
+}
+
+/// Validates the YAML configuration in `body`, returning the diagnostics,
+/// with an `422 Unprocessable Entity` status if it is invalid. Plugins listed
+/// in the configuration aren't loaded, only those already loaded can be used,
+/// and the hostnames of endpoints aren't resolved.
+async fn validate_config(body: Body) -> Response<Body> {
+    let body = match read_body(body).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let Ok(input) = std::str::from_utf8(&body) else {
+        return bad_request("configuration is not valid UTF-8".into());
+    };
+
+    let report = validate(input, <_>::default()).await;
+    let mut response = json_response(&report);
+    if !report.valid && response.status() == StatusCode::OK {
+        *response.status_mut() = StatusCode::UNPROCESSABLE_ENTITY;
+    }
+    response
+}
+
+/// Reads a request body of up to [`MAX_BODY_SIZE`] bytes.
+async fn read_body(mut body: Body) -> Result<Vec<u8>, Response<Body>> {
+    use hyper::body::HttpBody;
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk =
+            chunk.map_err(|error| bad_request(format!("failed to read request body: {error}")))?;
+        if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body(Body::from(format!(
+                    "request body is larger than {MAX_BODY_SIZE} bytes"
+                )))
+                .unwrap());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+/// The query parameters of the `/config/history` endpoints.
+#[derive(Default)]
+struct HistoryQuery {
+    resource: Option<history::Resource>,
+    from: Option<u64>,
+    to: Option<u64>,
+    version: Option<u64>,
+}
+
+impl HistoryQuery {
+    fn parse(query: Option<&str>) -> Result<Self, Response<Body>> {
+        let mut parsed = Self::default();
+
+        for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            let result = match &*key {
+                "resource" => value
+                    .parse()
+                    .map(|value| parsed.resource = Some(value))
+                    .map_err(|error: history::Error| error.to_string()),
+                "from" => parse_version(&value).map(|value| parsed.from = Some(value)),
+                "to" => parse_version(&value).map(|value| parsed.to = Some(value)),
+                "version" => parse_version(&value).map(|value| parsed.version = Some(value)),
+                _ => continue,
+            };
+
+            if let Err(error) = result {
+                return Err(bad_request(format!("invalid `{key}` parameter: {error}")));
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn required<T>(value: Option<T>, key: &str) -> Result<T, Response<Body>> {
+        value.ok_or_else(|| bad_request(format!("missing `{key}` parameter")))
+    }
+}
+
+fn parse_version(value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|error: std::num::ParseIntError| error.to_string())
+}
+
+fn bad_request(message: String) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(Body::from(message))
+        .unwrap()
+}
+
+fn history_error(error: history::Error) -> Response<Body> {
+    let status = match error {
+        history::Error::UnknownVersion(_) => StatusCode::NOT_FOUND,
+        history::Error::UnknownResource(_) => StatusCode::BAD_REQUEST,
+        history::Error::InvalidFilters(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    Response::builder()
+        .status(status)
+        .body(Body::from(error.to_string()))
+        .unwrap()
+}
+
+/// Returns the changes to the `resource` between the `from` and `to`
+/// versions, where `to` defaults to the latest version.
+fn diff_config(query: Option<&str>, config: &Config) -> Response<Body> {
+    let diff = HistoryQuery::parse(query).and_then(|query| {
+        let resource = HistoryQuery::required(query.resource, "resource")?;
+        let from = HistoryQuery::required(query.from, "from")?;
+        config
+            .history
+            .diff(resource, from, query.to)
+            .map_err(history_error)
+    });
+
+    match diff {
+        Ok(diff) => json_response(&diff),
+        Err(response) => response,
+    }
+}
+
+/// Rolls the `resource` back to `version`, returning the new version.
+fn rollback_config(query: Option<&str>, config: &Config) -> Response<Body> {
+    let version = HistoryQuery::parse(query).and_then(|query| {
+        let resource = HistoryQuery::required(query.resource, "resource")?;
+        let version = HistoryQuery::required(query.version, "version")?;
+        config
+            .history
+            .rollback(config, resource, version)
+            .map_err(history_error)
+    });
+
+    match version {
+        Ok(version) => json_response(&serde_json::json!({ "version": version })),
+        Err(response) => response,
+    }
+}
+
 fn check_proxy_readiness(config: &Config) -> Response<Body> {
     if config.clusters.read().endpoints().count() > 0 {
         return Response::new("ok".into());
@@ -141,6 +343,35 @@ mod tests {
         assert_eq!(response.status(), hyper::StatusCode::OK);
     }
 
+    #[test]
+    fn arm_packet_capture_invalid_query() {
+        let response = super::arm_packet_capture(Some("seconds=ten"));
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = super::arm_packet_capture(Some("seconds=18446744073709551615"));
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn config_history_invalid_query() {
+        let config = Config::default();
+        let response = super::diff_config(Some("resource=filters"), &config);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = super::diff_config(Some("resource=listeners&from=1"), &config);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = super::rollback_config(Some("resource=filters&version=1"), &config);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn validate_config() {
+        let response = super::validate_config(Body::from("version: v1alpha1\n")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = super::validate_config(Body::from("version: v1alpha1\nfoo: bar\n")).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = super::validate_config(Body::from(vec![b' '; MAX_BODY_SIZE + 1])).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
     #[test]
     fn check_proxy_readiness() {
         let config = Config::default();
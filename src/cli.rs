@@ -26,7 +26,7 @@ use crate::{admin::Mode, Config};
 
 pub use self::{
     agent::Agent, generate_config_schema::GenerateConfigSchema, manage::Manage, proxy::Proxy,
-    qcmp::Qcmp, relay::Relay,
+    qcmp::Qcmp, relay::Relay, validate::Validate,
 };
 
 macro_rules! define_port {
@@ -45,6 +45,7 @@ pub mod manage;
 pub mod proxy;
 pub mod qcmp;
 pub mod relay;
+pub mod validate;
 
 const ETC_CONFIG_PATH: &str = "/etc/quilkin/quilkin.yaml";
 const PORT_ENV_VAR: &str = "QUILKIN_PORT";
@@ -63,9 +64,21 @@ pub struct Cli {
     /// The port to bind for the admin server
     #[clap(long, env = "QUILKIN_ADMIN_ADDRESS")]
     pub admin_address: Option<std::net::SocketAddr>,
+    /// Native filter plugins to load before reading the configuration.
+    #[clap(long = "plugin", env = "QUILKIN_PLUGINS", value_delimiter = ',')]
+    pub plugins: Vec<PathBuf>,
     /// Whether Quilkin will report any results to stdout/stderr.
     #[clap(short, long, env)]
     pub quiet: bool,
+    /// Run each filter within a `filter` tracing span, recording its name,
+    /// label and direction in the filter's log events.
+    #[clap(long, env = "QUILKIN_FILTER_SPANS")]
+    pub filter_spans: bool,
+    /// Check that every metadata key read by a filter is written by an
+    /// earlier filter when creating a filter chain, either logging a warning
+    /// or rejecting the chain.
+    #[clap(long, env = "QUILKIN_METADATA_CHECK", value_enum, default_value_t)]
+    pub metadata_check: crate::filters::MetadataCheck,
     #[clap(subcommand)]
     pub command: Commands,
 }
@@ -80,6 +93,7 @@ pub enum Commands {
     Qcmp(Qcmp),
     Proxy(Proxy),
     Relay(Relay),
+    Validate(Validate),
 }
 
 impl Commands {
@@ -87,7 +101,7 @@ impl Commands {
         match self {
             Self::Proxy(_) | Self::Agent(_) => Some(Mode::Proxy),
             Self::Relay(_) | Self::Manage(_) => Some(Mode::Xds),
-            Self::GenerateConfigSchema(_) | Self::Qcmp(_) => None,
+            Self::GenerateConfigSchema(_) | Self::Qcmp(_) | Self::Validate(_) => None,
         }
     }
 }
@@ -119,8 +133,16 @@ impl Cli {
         }
 
         tracing::debug!(cli = ?self, "config parameters");
+        crate::filters::set_filter_spans(self.filter_spans);
+        crate::filters::set_metadata_check(self.metadata_check);
 
-        let config = Arc::new(Self::read_config(self.config)?);
+        if let Commands::Validate(validate) = &self.command {
+            // The configuration's own plugins are loaded while validating it.
+            load_plugins(&self.plugins)?;
+            return validate.validate().await;
+        }
+
+        let config = Arc::new(Self::read_config(self.config, &self.plugins)?);
         let _admin_task = self
             .command
             .admin_mode()
@@ -187,7 +209,7 @@ impl Cli {
                     let shutdown_rx = shutdown_rx.clone();
                     tokio::spawn(async move { relay.relay(config, shutdown_rx.clone()).await })
                 }
-                Commands::Qcmp(_) => unreachable!(),
+                Commands::Qcmp(_) | Commands::Validate(_) => unreachable!(),
             }
         })
         .retries(3)
@@ -202,22 +224,31 @@ impl Cli {
     }
 
     /// Searches for the configuration file, and panics if not found.
-    fn read_config<A: AsRef<Path>>(path: A) -> Result<Config, eyre::Error> {
+    /// Reads the configuration at `path`, loading `plugins` and the plugins
+    /// it lists before its filters are created.
+    fn read_config<A: AsRef<Path>>(path: A, plugins: &[PathBuf]) -> Result<Config, eyre::Error> {
         let path = path.as_ref();
-        let from_reader = |file| Config::from_reader(file).map_err(From::from);
+        let from_str = |input: String| {
+            load_plugins(plugins.iter().chain(&Config::plugins_from_str(&input)?))?;
+            Config::from_reader(input.as_bytes()).map_err(From::from)
+        };
+        let default = || {
+            load_plugins(plugins)?;
+            Ok(Config::default())
+        };
 
-        match std::fs::File::open(path) {
-            Ok(file) => (from_reader)(file),
+        match std::fs::read_to_string(path) {
+            Ok(input) => (from_str)(input),
             Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
                 tracing::debug!(path=%path.display(), "provided path not found");
-                match cfg!(unix).then(|| std::fs::File::open(ETC_CONFIG_PATH)) {
-                    Some(Ok(file)) => (from_reader)(file),
+                match cfg!(unix).then(|| std::fs::read_to_string(ETC_CONFIG_PATH)) {
+                    Some(Ok(input)) => (from_str)(input),
                     Some(Err(error)) if error.kind() == std::io::ErrorKind::NotFound => {
                         tracing::debug!(path=%path.display(), "/etc path not found");
-                        Ok(Config::default())
+                        (default)()
                     }
                     Some(Err(error)) => Err(error.into()),
-                    None => Ok(Config::default()),
+                    None => (default)(),
                 }
             }
             Err(error) => Err(error.into()),
@@ -225,6 +256,20 @@ impl Cli {
     }
 }
 
+/// Loads each plugin in `paths` in order, skipping paths that refer to a
+/// plugin that has already been loaded.
+fn load_plugins<'path>(paths: impl IntoIterator<Item = &'path PathBuf>) -> crate::Result<()> {
+    let mut loaded = std::collections::HashSet::new();
+    for path in paths {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if loaded.insert(canonical) {
+            crate::filters::FilterRegistry::load_plugin(path)?;
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -300,6 +345,9 @@ mod tests {
             admin_address: Some((Ipv4Addr::LOCALHOST, relay_admin_port).into()),
             config: <_>::default(),
             no_admin: false,
+            plugins: Vec::new(),
+            filter_spans: false,
+            metadata_check: <_>::default(),
             quiet: true,
             command: Commands::Relay(Relay {
                 providers: Some(Providers::File {
@@ -315,6 +363,9 @@ mod tests {
             quiet: true,
             admin_address: Some((Ipv4Addr::LOCALHOST, control_plane_admin_port).into()),
             config: <_>::default(),
+            plugins: Vec::new(),
+            filter_spans: false,
+            metadata_check: <_>::default(),
             command: Commands::Manage(Manage {
                 relay: vec!["http://localhost:7900".parse().unwrap()],
                 port: 7801,
@@ -324,6 +375,8 @@ mod tests {
                 provider: Providers::File {
                     path: endpoints_file.path().to_path_buf(),
                 },
+                auth: <_>::default(),
+                relay_auth: <_>::default(),
             }),
         };
 
@@ -333,6 +386,9 @@ mod tests {
             quiet: true,
             admin_address: Some((Ipv4Addr::LOCALHOST, proxy_admin_port).into()),
             config: <_>::default(),
+            plugins: Vec::new(),
+            filter_spans: false,
+            metadata_check: <_>::default(),
             command: Commands::Proxy(Proxy {
                 management_server: vec!["http://localhost:7800".parse().unwrap()],
                 ..<_>::default()
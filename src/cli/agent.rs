@@ -47,6 +47,8 @@ pub struct Agent {
     /// The configuration source for a management server.
     #[clap(subcommand)]
     pub provider: Option<crate::config::Providers>,
+    #[clap(flatten)]
+    pub relay_auth: crate::xds::ClientAuth,
 }
 
 impl Default for Agent {
@@ -58,6 +60,7 @@ impl Default for Agent {
             zone: <_>::default(),
             sub_zone: <_>::default(),
             provider: <_>::default(),
+            relay_auth: <_>::default(),
         }
     }
 }
@@ -84,6 +87,7 @@ impl Agent {
             let task = crate::xds::client::MdsClient::connect(
                 String::clone(&config.id.load()),
                 self.relay.clone(),
+                self.relay_auth.clone(),
             );
 
             tokio::select! {
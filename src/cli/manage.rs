@@ -43,6 +43,10 @@ pub struct Manage {
     /// The configuration source for a management server.
     #[clap(subcommand)]
     pub provider: crate::config::Providers,
+    #[clap(flatten)]
+    pub auth: crate::xds::ServerAuth,
+    #[clap(flatten)]
+    pub relay_auth: crate::xds::ClientAuth,
 }
 
 impl Manage {
@@ -71,6 +75,7 @@ impl Manage {
             let client = crate::xds::client::MdsClient::connect(
                 String::clone(&config.id.load()),
                 self.relay.clone(),
+                self.relay_auth.clone(),
             )
             .await?;
             Some(client.mds_client_stream(config.clone()))
@@ -78,9 +83,13 @@ impl Manage {
             None
         };
 
-        let server_task = tokio::spawn(crate::xds::server::spawn(self.port, config))
-            .map_err(From::from)
-            .and_then(std::future::ready);
+        let server_task = tokio::spawn(crate::xds::server::spawn(
+            self.port,
+            config,
+            self.auth.clone(),
+        ))
+        .map_err(From::from)
+        .and_then(std::future::ready);
 
         tokio::select! {
             result = server_task => result,
@@ -45,6 +45,28 @@ pub struct Proxy {
     /// One or more socket addresses to forward packets to.
     #[clap(short, long, env = "QUILKIN_DEST")]
     pub to: Vec<SocketAddr>,
+    /// The `region` of the proxy, sent to the management server so it can
+    /// select resources by locality.
+    #[clap(long, env = "QUILKIN_REGION")]
+    pub region: Option<String>,
+    /// The `zone` in the `region` of the proxy.
+    #[clap(long, env = "QUILKIN_ZONE")]
+    pub zone: Option<String>,
+    /// The `sub_zone` in the `zone` in the `region` of the proxy.
+    #[clap(long, env = "QUILKIN_SUB_ZONE")]
+    pub sub_zone: Option<String>,
+    /// A file the configuration received from `management_server` is saved
+    /// to, and loaded from on start, so the proxy can keep serving its last
+    /// known configuration while the management server is unreachable.
+    #[clap(long, env = "QUILKIN_XDS_CACHE")]
+    pub xds_cache: Option<std::path::PathBuf>,
+    /// Subscribes to every `management_server` at once, merging their
+    /// clusters, rather than connecting to one at a time. When more than one
+    /// sends a filter chain, the one given first is used.
+    #[clap(long, env = "QUILKIN_MERGE_MANAGEMENT_SERVERS")]
+    pub merge_management_servers: bool,
+    #[clap(flatten)]
+    pub auth: crate::xds::ClientAuth,
 }
 
 impl Default for Proxy {
@@ -55,6 +77,12 @@ impl Default for Proxy {
             port: PORT,
             qcmp_port: QCMP_PORT,
             to: <_>::default(),
+            region: <_>::default(),
+            zone: <_>::default(),
+            sub_zone: <_>::default(),
+            xds_cache: <_>::default(),
+            merge_management_servers: false,
+            auth: <_>::default(),
         }
     }
 }
@@ -101,27 +129,93 @@ impl Proxy {
         tracing::info!(port = self.port, proxy_id = &*id, "Starting");
 
         let sessions = SessionMap::new(SESSION_TIMEOUT_SECONDS, SESSION_EXPIRY_POLL_INTERVAL);
+        let mirror_sessions =
+            SessionMap::new(SESSION_TIMEOUT_SECONDS, SESSION_EXPIRY_POLL_INTERVAL);
 
+        let mut xds_tasks = Vec::new();
         let _xds_stream = if !self.management_server.is_empty() {
-            let client =
-                crate::xds::AdsClient::connect(String::clone(&id), self.management_server.clone())
-                    .await?;
-            let mut stream = client.xds_client_stream(config.clone());
-
-            tokio::time::sleep(std::time::Duration::from_nanos(1)).await;
-            stream
-                .discovery_request(ResourceType::Endpoint, &[])
-                .await?;
-            tokio::time::sleep(std::time::Duration::from_nanos(1)).await;
-            stream
-                .discovery_request(ResourceType::Listener, &[])
-                .await?;
-            Some((client, stream))
+            let locality = (self.region.is_some()
+                || self.zone.is_some()
+                || self.sub_zone.is_some())
+            .then(|| crate::endpoint::Locality {
+                region: self.region.clone().unwrap_or_default(),
+                zone: self.zone.clone().unwrap_or_default(),
+                sub_zone: self.sub_zone.clone().unwrap_or_default(),
+            });
+            let connect = |management_servers: Vec<Endpoint>, config: Arc<Config>| {
+                let id = String::clone(&id);
+                let auth = self.auth.clone();
+                let locality = locality.clone();
+                let remove_on_disconnect = self.merge_management_servers;
+                async move {
+                    let client = crate::xds::AdsClient::connect(id, management_servers, auth)
+                        .await?
+                        .with_locality(locality)
+                        .remove_on_disconnect(remove_on_disconnect);
+                    let mut stream = client.xds_client_stream(config);
+
+                    tokio::time::sleep(std::time::Duration::from_nanos(1)).await;
+                    stream
+                        .discovery_request(ResourceType::Endpoint, &[])
+                        .await?;
+                    tokio::time::sleep(std::time::Duration::from_nanos(1)).await;
+                    stream
+                        .discovery_request(ResourceType::Listener, &[])
+                        .await?;
+                    Ok::<_, eyre::Error>((client, stream))
+                }
+            };
+
+            let cached = match &self.xds_cache {
+                Some(path) => crate::config::snapshot::load(&config, path).await?,
+                None => false,
+            };
+
+            if self.merge_management_servers {
+                let sources: Vec<_> = self
+                    .management_server
+                    .iter()
+                    .map(|server| crate::xds::merge::Source::new(server.uri().to_string()))
+                    .collect();
+                for (server, source) in self.management_server.iter().zip(&sources) {
+                    let connect = connect(vec![server.clone()], source.config.clone());
+                    xds_tasks.push(tokio::spawn(async move {
+                        let _stream = connect.await?;
+                        std::future::pending::<Result<()>>().await
+                    }));
+                }
+                xds_tasks.push(crate::xds::merge::spawn(config.clone(), sources));
+                None
+            } else if cached {
+                // Serve the cached configuration while the management server
+                // is unreachable, rather than waiting for it to come back.
+                tracing::info!("using cached configuration until connected to a management server");
+                let connect = connect(self.management_server.clone(), config.clone());
+                xds_tasks.push(tokio::spawn(async move {
+                    let _stream = connect.await?;
+                    std::future::pending::<Result<()>>().await
+                }));
+                None
+            } else {
+                Some(connect(self.management_server.clone(), config.clone()).await?)
+            }
         } else {
             None
         };
 
-        self.run_recv_from(&config, sessions.clone())?;
+        let cache_task = self
+            .xds_cache
+            .clone()
+            .filter(|_| !self.management_server.is_empty())
+            .map(|path| {
+                tokio::spawn(crate::config::snapshot::persist(
+                    config.clone(),
+                    path,
+                    Duration::from_secs(crate::config::snapshot::DEFAULT_INTERVAL_SECONDS),
+                ))
+            });
+
+        self.run_recv_from(&config, sessions.clone(), mirror_sessions)?;
         crate::protocol::spawn(self.qcmp_port).await?;
         tracing::info!("Quilkin is ready");
 
@@ -137,6 +231,15 @@ impl Proxy {
         }
         tracing::info!("all sessions expired");
 
+        for task in xds_tasks {
+            task.abort();
+        }
+
+        if let Some((task, path)) = cache_task.zip(self.xds_cache.as_ref()) {
+            task.abort();
+            crate::config::snapshot::save(&config, path).await?;
+        }
+
         Ok(())
     }
 
@@ -145,7 +248,12 @@ impl Proxy {
     /// This function also spawns the set of worker tasks responsible for consuming packets
     /// off the aforementioned queue and processing them through the filter chain and session
     /// pipeline.
-    fn run_recv_from(&self, config: &Arc<Config>, sessions: SessionMap) -> Result<()> {
+    fn run_recv_from(
+        &self,
+        config: &Arc<Config>,
+        sessions: SessionMap,
+        mirror_sessions: SessionMap,
+    ) -> Result<()> {
         // The number of worker tasks to spawn. Each task gets a dedicated queue to
         // consume packets off.
         let num_workers = num_cpus::get();
@@ -159,6 +267,7 @@ impl Proxy {
                 socket: socket.clone(),
                 config: config.clone(),
                 sessions: sessions.clone(),
+                mirror_sessions: mirror_sessions.clone(),
             })
         }
 
@@ -229,6 +338,56 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn serves_cached_config_without_management_server() {
+        let mut t = TestHelper::default();
+
+        let endpoint = t.open_socket_and_recv_single_packet().await;
+
+        let cache = tempfile::tempdir().unwrap();
+        let cache_path = cache.path().join("xds.yaml");
+        let cached = Config::default();
+        cached.clusters.modify(|clusters| {
+            clusters.insert_default(vec![Endpoint::new(
+                endpoint.socket.local_addr().unwrap().into(),
+            )])
+        });
+        config::snapshot::save(&cached, &cache_path).await.unwrap();
+
+        // Nothing is listening on the management server's port.
+        let local_addr = available_addr().await;
+        let proxy = crate::cli::Proxy {
+            port: local_addr.port(),
+            management_server: vec![
+                format!("http://localhost:{}", available_addr().await.port())
+                    .parse()
+                    .unwrap(),
+            ],
+            xds_cache: Some(cache_path),
+            ..<_>::default()
+        };
+
+        let config = Arc::new(Config::default());
+        t.run_server(config.clone(), proxy, None);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        assert_eq!(1, config.clusters.read().endpoints().count());
+
+        let msg = "hello";
+        endpoint
+            .socket
+            .send_to(msg.as_bytes(), &local_addr)
+            .await
+            .unwrap();
+        assert_eq!(
+            msg,
+            timeout(Duration::from_secs(1), endpoint.packet_rx)
+                .await
+                .expect("should get a packet")
+                .unwrap()
+        );
+    }
+
     #[tokio::test]
     async fn run_client() {
         let mut t = TestHelper::default();
@@ -329,6 +488,7 @@ mod tests {
             socket: socket.clone(),
             config,
             sessions: <_>::default(),
+            mirror_sessions: <_>::default(),
         }
         .spawn();
 
@@ -361,7 +521,9 @@ mod tests {
             clusters.insert_default(vec![endpoint.socket.local_addr().unwrap()])
         });
 
-        proxy.run_recv_from(&config, <_>::default()).unwrap();
+        proxy
+            .run_recv_from(&config, <_>::default(), <_>::default())
+            .unwrap();
 
         let socket = create_socket().await;
         socket.send_to(msg.as_bytes(), &local_addr).await.unwrap();
@@ -34,8 +34,25 @@ pub struct Relay {
     /// Port for xDS management_server service
     #[clap(short, long, env = super::PORT_ENV_VAR, default_value_t = super::manage::PORT)]
     pub xds_port: u16,
+    /// The mDS endpoints of other relay replicas, which are pushed the
+    /// clusters this relay has been sent, so every replica serves the same
+    /// endpoints.
+    #[clap(long = "peer", env = "QUILKIN_RELAY_PEERS")]
+    pub peers: Vec<tonic::transport::Endpoint>,
+    /// A file the relay's clusters and filters are periodically saved to,
+    /// and loaded from on start.
+    #[clap(long, env = "QUILKIN_SNAPSHOT")]
+    pub snapshot: Option<std::path::PathBuf>,
+    /// How often, in seconds, `snapshot` is saved when the configuration has
+    /// changed.
+    #[clap(long, env = "QUILKIN_SNAPSHOT_INTERVAL", default_value_t = crate::config::snapshot::DEFAULT_INTERVAL_SECONDS)]
+    pub snapshot_interval: u64,
     #[clap(subcommand)]
     pub providers: Option<Providers>,
+    #[clap(flatten)]
+    pub auth: crate::xds::ServerAuth,
+    #[clap(flatten)]
+    pub peer_auth: crate::xds::ClientAuth,
 }
 
 impl Default for Relay {
@@ -43,7 +60,12 @@ impl Default for Relay {
         Self {
             mds_port: PORT,
             xds_port: super::manage::PORT,
+            peers: Vec::new(),
+            snapshot: None,
+            snapshot_interval: crate::config::snapshot::DEFAULT_INTERVAL_SECONDS,
             providers: None,
+            auth: <_>::default(),
+            peer_auth: <_>::default(),
         }
     }
 }
@@ -54,10 +76,46 @@ impl Relay {
         config: Arc<Config>,
         mut shutdown_rx: tokio::sync::watch::Receiver<()>,
     ) -> crate::Result<()> {
-        let xds_server = crate::xds::server::spawn(self.xds_port, config.clone());
+        // The snapshot is loaded before serving, so that proxies aren't sent
+        // empty clusters while agents reconnect.
+        let snapshot_task = if let Some(path) = &self.snapshot {
+            crate::config::snapshot::load(&config, path).await?;
+            Some(tokio::spawn(crate::config::snapshot::persist(
+                config.clone(),
+                path.clone(),
+                std::time::Duration::from_secs(self.snapshot_interval),
+            )))
+        } else {
+            None
+        };
+
+        let peer_tasks: Vec<_> = self
+            .peers
+            .iter()
+            .map(|peer| {
+                let config = config.clone();
+                let peer = peer.clone();
+                let auth = self.peer_auth.clone();
+                tokio::spawn(async move {
+                    tracing::info!(peer = %peer.uri(), "connecting to relay peer");
+                    let client = crate::xds::client::MdsClient::connect(
+                        String::clone(&config.id.load()),
+                        vec![peer],
+                        auth,
+                    )
+                    .await?;
+                    let _stream = client.mds_client_stream(config);
+                    std::future::pending::<crate::Result<()>>().await
+                })
+            })
+            .collect();
+
+        let xds_server =
+            crate::xds::server::spawn(self.xds_port, config.clone(), self.auth.clone());
         let mds_server = tokio::spawn(crate::xds::server::control_plane_discovery_server(
             self.mds_port,
             config.clone(),
+            self.auth.clone(),
         ));
 
         let _provider_task = if let Some(Providers::Agones {
@@ -109,7 +167,7 @@ impl Relay {
             None
         };
 
-        tokio::select! {
+        let result = tokio::select! {
             result = xds_server => {
                 result
             }
@@ -117,6 +175,69 @@ impl Relay {
                 result?
             }
             result = shutdown_rx.changed() => result.map_err(From::from),
+        };
+
+        for task in peer_tasks {
+            task.abort();
+        }
+
+        if let Some((task, path)) = snapshot_task.zip(self.snapshot.as_ref()) {
+            task.abort();
+            crate::config::snapshot::save(&config, path).await?;
         }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{cluster::Cluster, endpoint::Endpoint, test_utils::available_addr};
+
+    #[tokio::test]
+    async fn replicates_to_peers() {
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(());
+
+        let primary_config = Arc::new(Config::default());
+        let primary = Relay {
+            mds_port: available_addr().await.port(),
+            xds_port: available_addr().await.port(),
+            ..<_>::default()
+        };
+        let replica_config = Arc::new(Config::default());
+        replica_config.clusters.modify(|clusters| {
+            clusters.insert(Cluster::new_default(vec![Endpoint::new(
+                (std::net::Ipv4Addr::LOCALHOST, 4321).into(),
+            )
+            .into()]));
+        });
+        let replica = Relay {
+            mds_port: available_addr().await.port(),
+            xds_port: available_addr().await.port(),
+            peers: vec![format!("http://localhost:{}", primary.mds_port)
+                .parse()
+                .unwrap()],
+            ..<_>::default()
+        };
+
+        tokio::spawn({
+            let config = primary_config.clone();
+            let shutdown_rx = shutdown_rx.clone();
+            async move { primary.relay(config, shutdown_rx).await }
+        });
+        tokio::spawn(async move { replica.relay(replica_config, shutdown_rx).await });
+
+        for _ in 0..50 {
+            if primary_config.clusters.read().endpoints().count() == 1 {
+                // The replica is listed as an agent connected over mDS.
+                assert_eq!(1, primary_config.xds_clients.agents().len());
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        panic!("clusters weren't replicated to the peer relay");
     }
 }
@@ -0,0 +1,68 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::config::validate::{validate, Options, Report};
+
+/// Validates a configuration file, printing any problems found. Exits with an
+/// error if the configuration is invalid.
+#[derive(clap::Args, Clone, Debug)]
+pub struct Validate {
+    /// The path to the configuration file to validate.
+    pub path: std::path::PathBuf,
+    /// Print the diagnostics as JSON, rather than one per line.
+    #[clap(long)]
+    pub json: bool,
+}
+
+impl Validate {
+    pub async fn validate(&self) -> crate::Result<()> {
+        let input = tokio::fs::read_to_string(&self.path).await?;
+        let report = validate(
+            &input,
+            Options {
+                load_plugins: true,
+                resolve_endpoints: true,
+            },
+        )
+        .await;
+        self.print(&report)?;
+
+        if report.valid {
+            Ok(())
+        } else {
+            Err(eyre::eyre!(
+                "{} is not a valid configuration",
+                self.path.display()
+            ))
+        }
+    }
+
+    fn print(&self, report: &Report) -> crate::Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(report)?);
+            return Ok(());
+        }
+
+        for diagnostic in &report.diagnostics {
+            println!("{}: {diagnostic}", self.path.display());
+        }
+        for edge in &report.metadata {
+            println!("{}: {edge}", self.path.display());
+        }
+
+        Ok(())
+    }
+}
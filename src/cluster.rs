@@ -21,7 +21,11 @@ use once_cell::sync::Lazy;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::endpoint::{Endpoint, EndpointAddress, Locality, LocalityEndpoints, LocalitySet};
+use crate::{
+    endpoint::{Endpoint, EndpointAddress, Locality, LocalityEndpoints, LocalitySet},
+    filters::load_balancer::Policy,
+    xds::config::cluster::v3::cluster::LbPolicy,
+};
 
 const DEFAULT_CLUSTER_NAME: &str = "default";
 const SUBSYSTEM: &str = "cluster";
@@ -61,6 +65,16 @@ pub struct Cluster {
     #[serde(skip, default = "default_cluster_name")]
     pub name: String,
     pub localities: LocalitySet,
+    /// How packets are distributed across the cluster's endpoints, `None` if
+    /// left to the filter chain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lb_policy: Option<Policy>,
+    /// How the health of the cluster's endpoints is checked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub health_checks: Vec<HealthCheck>,
+    /// The limits on connections to the cluster's endpoints.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection_limits: Option<ConnectionLimits>,
 }
 
 impl Cluster {
@@ -69,6 +83,7 @@ impl Cluster {
         Self {
             name: name.into(),
             localities: localities.into(),
+            ..Self::default()
         }
     }
 
@@ -96,8 +111,13 @@ impl Cluster {
             .flat_map(|locality| locality.endpoints.iter())
     }
 
+    /// Merges the endpoints of `cluster` into this cluster, replacing its
+    /// load balancing, health check and connection limit settings.
     pub fn merge(&mut self, cluster: &Self) {
         self.localities.merge(&cluster.localities);
+        self.lb_policy = cluster.lb_policy;
+        self.health_checks = cluster.health_checks.clone();
+        self.connection_limits = cluster.connection_limits.clone();
     }
 }
 
@@ -105,6 +125,39 @@ fn default_cluster_name() -> String {
     DEFAULT_CLUSTER_NAME.into()
 }
 
+/// Active health checking of a cluster's endpoints.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheck {
+    /// How long to wait for a response to a check, in milliseconds.
+    pub timeout_ms: u64,
+    /// How long to wait between checks, in milliseconds.
+    pub interval_ms: u64,
+    /// The number of failed checks before an endpoint is unhealthy.
+    #[serde(default = "default_threshold")]
+    pub unhealthy_threshold: u32,
+    /// The number of successful checks before an endpoint is healthy again.
+    #[serde(default = "default_threshold")]
+    pub healthy_threshold: u32,
+}
+
+fn default_threshold() -> u32 {
+    1
+}
+
+/// The limits on connections to a cluster's endpoints.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectionLimits {
+    /// The maximum number of sessions to the cluster's endpoints.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    /// The maximum number of packets waiting to be sent to the cluster's
+    /// endpoints.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pending_requests: Option<u32>,
+}
+
 /// Represents a full snapshot of all clusters.
 #[derive(Clone, Default, Debug, Serialize)]
 pub struct ClusterMap(DashMap<String, Cluster>);
@@ -122,6 +175,10 @@ impl ClusterMap {
         self.0.insert(cluster.name.clone(), cluster)
     }
 
+    pub fn remove(&self, key: &str) -> Option<Cluster> {
+        self.0.remove(key).map(|(_, cluster)| cluster)
+    }
+
     pub fn get(&self, key: &str) -> Option<DashMapRef> {
         self.0.get(key)
     }
@@ -210,6 +267,17 @@ impl ClusterMap {
         self.localities().flat_map(|locality| locality.endpoints)
     }
 
+    /// Replaces the clusters in the map with the clusters in `map`, without
+    /// the map ever being empty in between.
+    pub fn replace(&self, map: Self) {
+        let names: std::collections::HashSet<_> =
+            map.0.iter().map(|entry| entry.key().clone()).collect();
+        for (name, cluster) in map.0 {
+            self.0.insert(name, cluster);
+        }
+        self.0.retain(|name, _| names.contains(name));
+    }
+
     pub fn merge(&self, map: Self) {
         for cluster in map.iter() {
             let span = tracing::info_span!("applied_cluster", cluster = cluster.name,);
@@ -230,6 +298,10 @@ impl ClusterMap {
 
 impl PartialEq for ClusterMap {
     fn eq(&self, rhs: &Self) -> bool {
+        if self.0.len() != rhs.0.len() {
+            return false;
+        }
+
         for a in self.iter() {
             match rhs.get(a.key()).filter(|b| *a.value() == **b) {
                 Some(_) => {}
@@ -327,14 +399,124 @@ impl From<Cluster> for crate::xds::config::endpoint::v3::ClusterLoadAssignment {
 
 impl From<&'_ Cluster> for crate::xds::config::cluster::v3::Cluster {
     fn from(cluster: &Cluster) -> Self {
-        Self {
+        use crate::xds::config::cluster::v3::{circuit_breakers::Thresholds, CircuitBreakers};
+
+        let mut xds = Self {
             name: cluster.name.clone(),
             load_assignment: Some(cluster.into()),
+            health_checks: cluster.health_checks.iter().map(From::from).collect(),
+            circuit_breakers: cluster
+                .connection_limits
+                .as_ref()
+                .map(|limits| CircuitBreakers {
+                    thresholds: vec![Thresholds {
+                        max_connections: limits.max_connections,
+                        max_pending_requests: limits.max_pending_requests,
+                        ..<_>::default()
+                    }],
+                    ..<_>::default()
+                }),
             ..Self::default()
+        };
+        xds.set_lb_policy(match cluster.lb_policy {
+            Some(Policy::RoundRobin) => LbPolicy::RoundRobin,
+            Some(Policy::Random) => LbPolicy::Random,
+            Some(Policy::Hash) => LbPolicy::RingHash,
+            // Endpoints are chosen by the filter chain.
+            None => LbPolicy::ClusterProvided,
+        });
+        xds
+    }
+}
+
+impl TryFrom<crate::xds::config::cluster::v3::Cluster> for Cluster {
+    type Error = eyre::Error;
+
+    fn try_from(cluster: crate::xds::config::cluster::v3::Cluster) -> Result<Self, Self::Error> {
+        use crate::xds::config::core::v3::RoutingPriority;
+
+        let lb_policy = match cluster.lb_policy() {
+            LbPolicy::RoundRobin => Some(Policy::RoundRobin),
+            LbPolicy::Random => Some(Policy::Random),
+            LbPolicy::RingHash | LbPolicy::Maglev => Some(Policy::Hash),
+            LbPolicy::ClusterProvided => None,
+            policy => {
+                return Err(eyre::eyre!(
+                    "unsupported lb_policy {}",
+                    policy.as_str_name()
+                ))
+            }
+        };
+
+        let connection_limits = cluster
+            .circuit_breakers
+            .and_then(|breakers| {
+                breakers
+                    .thresholds
+                    .into_iter()
+                    .find(|thresholds| thresholds.priority() == RoutingPriority::Default)
+            })
+            .map(|thresholds| ConnectionLimits {
+                max_connections: thresholds.max_connections,
+                max_pending_requests: thresholds.max_pending_requests,
+            });
+
+        let mut this = match cluster.load_assignment {
+            Some(assignment) => Self::try_from(assignment)?,
+            None => Self::default(),
+        };
+        this.name = cluster.name;
+        this.lb_policy = lb_policy;
+        this.health_checks = cluster
+            .health_checks
+            .into_iter()
+            .map(HealthCheck::try_from)
+            .collect::<Result<_, _>>()?;
+        this.connection_limits = connection_limits;
+        Ok(this)
+    }
+}
+
+impl From<&'_ HealthCheck> for crate::xds::config::core::v3::HealthCheck {
+    fn from(check: &HealthCheck) -> Self {
+        let duration = |millis: u64| prost_types::Duration {
+            seconds: (millis / 1000) as i64,
+            nanos: (millis % 1000) as i32 * 1_000_000,
+        };
+
+        Self {
+            timeout: Some(duration(check.timeout_ms)),
+            interval: Some(duration(check.interval_ms)),
+            unhealthy_threshold: Some(check.unhealthy_threshold),
+            healthy_threshold: Some(check.healthy_threshold),
+            ..<_>::default()
         }
     }
 }
 
+impl TryFrom<crate::xds::config::core::v3::HealthCheck> for HealthCheck {
+    type Error = eyre::Error;
+
+    fn try_from(check: crate::xds::config::core::v3::HealthCheck) -> Result<Self, Self::Error> {
+        let millis = |field: &str, duration: Option<prost_types::Duration>| {
+            let duration =
+                duration.ok_or_else(|| eyre::eyre!("health check {field} is required"))?;
+            u64::try_from(duration.seconds)
+                .ok()
+                .zip(u64::try_from(duration.nanos).ok())
+                .map(|(seconds, nanos)| seconds * 1000 + nanos / 1_000_000)
+                .ok_or_else(|| eyre::eyre!("health check {field} is negative"))
+        };
+
+        Ok(Self {
+            timeout_ms: millis("timeout", check.timeout)?,
+            interval_ms: millis("interval", check.interval)?,
+            unhealthy_threshold: check.unhealthy_threshold.unwrap_or_else(default_threshold),
+            healthy_threshold: check.healthy_threshold.unwrap_or_else(default_threshold),
+        })
+    }
+}
+
 impl From<&'_ Cluster> for crate::xds::config::endpoint::v3::ClusterLoadAssignment {
     fn from(cluster: &Cluster) -> Self {
         Self {
@@ -353,7 +535,7 @@ impl TryFrom<crate::xds::config::endpoint::v3::ClusterLoadAssignment> for Cluste
     ) -> Result<Self, Self::Error> {
         use crate::xds::config::endpoint::v3::lb_endpoint;
 
-        let localities = cla
+        let localities: LocalitySet = cla
             .endpoints
             .into_iter()
             .map(|locality| {
@@ -400,10 +582,7 @@ impl TryFrom<crate::xds::config::endpoint::v3::ClusterLoadAssignment> for Cluste
             })
             .collect::<Result<_, eyre::Error>>()?;
 
-        Ok(Cluster {
-            name: cla.cluster_name,
-            localities,
-        })
+        Ok(Cluster::new(cla.cluster_name, localities))
     }
 }
 
@@ -465,4 +644,38 @@ mod tests {
         assert_eq!(cluster1.localities[&Some(nl1)].endpoints.len(), 1);
         assert!(cluster1.localities[&Some(de1)].endpoints.is_empty());
     }
+
+    #[test]
+    fn xds_cluster_round_trip() {
+        let mut cluster = Cluster::new(
+            "game-servers",
+            vec![LocalityEndpoints::from((
+                Endpoint::new((Ipv4Addr::LOCALHOST, 7777).into()),
+                Locality::region("nl-1"),
+            ))],
+        );
+        for lb_policy in [None, Some(Policy::RoundRobin), Some(Policy::Hash)] {
+            cluster.lb_policy = lb_policy;
+            cluster.health_checks = vec![HealthCheck {
+                timeout_ms: 1500,
+                interval_ms: 10_000,
+                unhealthy_threshold: 3,
+                healthy_threshold: 1,
+            }];
+            cluster.connection_limits = Some(ConnectionLimits {
+                max_connections: Some(100),
+                max_pending_requests: None,
+            });
+
+            let xds = crate::xds::config::cluster::v3::Cluster::from(&cluster);
+            assert_eq!(cluster, Cluster::try_from(xds).unwrap());
+        }
+
+        let xds = crate::xds::config::cluster::v3::Cluster {
+            name: "game-servers".into(),
+            lb_policy: LbPolicy::LeastRequest.into(),
+            ..<_>::default()
+        };
+        assert!(Cluster::try_from(xds).is_err());
+    }
 }
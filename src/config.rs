@@ -25,22 +25,31 @@ use uuid::Uuid;
 
 mod config_type;
 mod error;
+pub mod history;
+mod nodes;
 pub mod providers;
 mod slot;
+pub mod snapshot;
+pub mod validate;
 pub mod watch;
 
 use crate::{
     cluster::{Cluster, ClusterMap},
     filters::prelude::*,
     xds::{
-        config::{endpoint::v3::ClusterLoadAssignment, listener::v3::Listener},
+        config::{core::v3::Node, endpoint::v3::ClusterLoadAssignment, listener::v3::Listener},
         service::discovery::v3::DiscoveryResponse,
         Resource, ResourceType,
     },
 };
 
 pub use self::{
-    config_type::ConfigType, error::ValidationError, providers::Providers, slot::Slot, watch::Watch,
+    config_type::ConfigType,
+    error::ValidationError,
+    nodes::{EndpointScope, NodeRule, NodeSelector},
+    providers::Providers,
+    slot::Slot,
+    watch::Watch,
 };
 
 base64_serde_type!(pub Base64Standard, base64::engine::general_purpose::STANDARD);
@@ -59,18 +68,68 @@ pub struct Config {
     pub clusters: Watch<ClusterMap>,
     #[serde(default)]
     pub filters: Slot<crate::filters::FilterChain>,
+    /// A candidate filter chain that is run on a copy of a sample of packets
+    /// alongside `filters`, only reporting how its decisions differ.
+    #[serde(
+        default = "Slot::<crate::filters::Canary>::empty",
+        skip_serializing_if = "Slot::is_none"
+    )]
+    pub canary: Slot<crate::filters::Canary>,
     #[serde(default = "default_proxy_id")]
     pub id: Slot<String>,
     #[serde(default)]
     pub version: Slot<Version>,
+    /// Rules selecting the resources served over xDS to specific proxies,
+    /// see [`NodeRule`].
+    #[serde(
+        default = "Slot::<Vec<NodeRule>>::empty",
+        skip_serializing_if = "Slot::is_none"
+    )]
+    pub nodes: Slot<Vec<NodeRule>>,
+    /// Native filter plugins to load, see [`crate::filters::plugin`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub plugins: Vec<std::path::PathBuf>,
+    /// The previously applied versions of the filter chain and clusters.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub history: Arc<history::History>,
+    /// The clients connected to this configuration's management server.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub xds_clients: Arc<crate::xds::Clients>,
 }
 
 impl Config {
     /// Attempts to deserialize `input` as a YAML object representing `Self`.
+    ///
+    /// Plugins listed in the configuration aren't loaded, so they must have
+    /// been loaded before, see [`Config::plugins_from_str`].
     pub fn from_reader<R: std::io::Read>(input: R) -> Result<Self, serde_yaml::Error> {
         serde_yaml::from_reader(input)
     }
 
+    /// Returns the plugins listed in the YAML configuration in `input`,
+    /// without deserializing the rest of it, so that they can be loaded
+    /// before its filters are created.
+    pub fn plugins_from_str(input: &str) -> Result<Vec<std::path::PathBuf>, serde_yaml::Error> {
+        #[derive(Deserialize)]
+        struct Plugins {
+            #[serde(default)]
+            plugins: Vec<std::path::PathBuf>,
+        }
+
+        serde_yaml::from_str::<Plugins>(input).map(|config| config.plugins)
+    }
+
+    /// Replaces the filter chain with `chain`, carrying over the state of any
+    /// filters in the current chain that support it, such as rate limits.
+    pub fn replace_filters(&self, chain: crate::filters::FilterChain) {
+        if let Some(previous) = self.filters.try_load() {
+            chain.migrate_state(&previous);
+        }
+        self.filters.store(Arc::new(chain));
+    }
+
     fn update_from_json(
         &self,
         map: serde_json::Map<String, serde_json::Value>,
@@ -87,7 +146,33 @@ impl Config {
             }
         }
 
-        replace_if_present!(filters, id);
+        replace_if_present!(id);
+
+        if let Some(value) = map.get("filters") {
+            tracing::debug!(%value, "replacing filters");
+            let chain: Option<crate::filters::FilterChain> = serde_json::from_value(value.clone())?;
+            if let Some(chain) = chain.filter(|chain| *self.filters.load() != *chain) {
+                self.replace_filters(chain);
+            }
+        }
+
+        if let Some(value) = map.get("canary") {
+            tracing::debug!(%value, "replacing canary");
+            match serde_json::from_value(value.clone())? {
+                Some(canary) => self.canary.store(Arc::new(canary)),
+                None => self.canary.remove(),
+            }
+        }
+
+        if let Some(value) = map.get("nodes") {
+            tracing::debug!(%value, "replacing node rules");
+            let rules: Option<Vec<NodeRule>> = serde_json::from_value(value.clone())?;
+            match rules {
+                Some(rules) if *self.nodes.load() != rules => self.nodes.store(Arc::new(rules)),
+                Some(_) => {}
+                None => self.nodes.remove(),
+            }
+        }
 
         if let Some(new_clusters) = map
             .get("clusters")
@@ -110,99 +195,182 @@ impl Config {
 
     pub fn discovery_request(
         &self,
-        _node_id: &str,
+        node: &Node,
         resource_type: ResourceType,
         names: &[String],
     ) -> Result<DiscoveryResponse, eyre::Error> {
+        let resources = self
+            .discovery_resources(resource_type, Some(node), names)?
+            .into_iter()
+            .map(|(_, resource)| resource)
+            .collect();
+
+        Ok(DiscoveryResponse {
+            resources,
+            type_url: resource_type.type_url().into(),
+            ..<_>::default()
+        })
+    }
+
+    /// Encodes the current resources of `resource_type`, paired with their
+    /// resource names. Only the resources in `names` are returned, unless
+    /// it's empty. When `node` is given, the resources are the ones selected
+    /// for it by the first matching rule in `nodes`, if any.
+    pub(crate) fn discovery_resources(
+        &self,
+        resource_type: ResourceType,
+        node: Option<&Node>,
+        names: &[String],
+    ) -> Result<Vec<(String, prost_types::Any)>, eyre::Error> {
+        let rules = self.nodes.load();
+        let rule = node.and_then(|node| NodeRule::find(&rules, node).map(|rule| (node, rule)));
+        let clusters = || -> Vec<Cluster> {
+            let clusters = self.clusters.read();
+            let mut clusters: Vec<Cluster> = if names.is_empty() {
+                clusters.iter().map(|entry| entry.value().clone()).collect()
+            } else {
+                names
+                    .iter()
+                    .filter_map(|name| clusters.get(name).map(|entry| entry.value().clone()))
+                    .collect()
+            };
+
+            if let Some((node, rule)) = rule {
+                for cluster in &mut clusters {
+                    rule.select_endpoints(node, cluster);
+                }
+            }
+
+            clusters
+        };
+
         let mut resources = Vec::new();
         match resource_type {
             ResourceType::Endpoint => {
-                for entry in self.clusters.read().iter() {
-                    resources.push(
-                        resource_type
-                            .encode_to_any(&ClusterLoadAssignment::try_from(entry.value())?)?,
-                    );
+                for cluster in clusters() {
+                    resources.push((
+                        cluster.name.clone(),
+                        resource_type.encode_to_any(&ClusterLoadAssignment::try_from(&cluster)?)?,
+                    ));
                 }
             }
             ResourceType::Listener => {
-                resources.push(resource_type.encode_to_any(&Listener {
-                    filter_chains: vec![(&*self.filters.load()).try_into()?],
-                    ..<_>::default()
-                })?);
+                let name = String::new();
+                if names.is_empty() || names.contains(&name) {
+                    let filters = self.filters.load();
+                    let filters = rule
+                        .and_then(|(_, rule)| rule.filters.as_ref())
+                        .unwrap_or(&filters);
+                    resources.push((
+                        name,
+                        resource_type.encode_to_any(&Listener {
+                            filter_chains: vec![filters.try_into()?],
+                            ..<_>::default()
+                        })?,
+                    ));
+                }
             }
             ResourceType::Cluster => {
-                let clusters: Vec<_> = if names.is_empty() {
-                    self.clusters
-                        .read()
-                        .iter()
-                        .map(|entry| entry.value().clone())
-                        .collect()
-                } else {
-                    names
-                        .iter()
-                        .filter_map(|name| {
-                            self.clusters
-                                .read()
-                                .get(name)
-                                .map(|entry| entry.value().clone())
-                        })
-                        .collect()
-                };
-
-                for cluster in clusters {
-                    resources.push(resource_type.encode_to_any(
-                        &crate::xds::config::cluster::v3::Cluster::try_from(&cluster)?,
-                    )?);
+                for cluster in clusters() {
+                    resources.push((
+                        cluster.name.clone(),
+                        resource_type.encode_to_any(
+                            &crate::xds::config::cluster::v3::Cluster::try_from(&cluster)?,
+                        )?,
+                    ));
                 }
             }
             resource => return Err(eyre::eyre!("Unsupported resource {}", resource.type_url())),
         };
 
-        Ok(DiscoveryResponse {
-            resources,
-            type_url: resource_type.type_url().into(),
-            ..<_>::default()
-        })
+        Ok(resources)
     }
 
     #[tracing::instrument(skip_all, fields(response = response.type_url()))]
     pub fn apply(&self, response: &Resource) -> crate::Result<()> {
-        tracing::trace!(resource=?response, "applying resource");
+        self.apply_all(std::slice::from_ref(response))
+    }
 
-        let apply_cluster = |cluster: Cluster| {
-            self.clusters
-                .write()
-                .default_entry(cluster.name.clone())
-                .merge(&cluster);
-        };
+    /// Applies every resource in `resources`, or none of them if any are
+    /// invalid, leaving the current configuration in place.
+    #[tracing::instrument(skip_all, fields(resources = resources.len()))]
+    pub fn apply_all(&self, resources: &[Resource]) -> crate::Result<()> {
+        enum Change {
+            Cluster(Cluster),
+            /// A cluster's endpoints, leaving its other settings as they are.
+            Endpoints(Cluster),
+            Filters(crate::filters::FilterChain),
+        }
 
-        match response {
-            Resource::Endpoint(cla) => {
-                let cluster = Cluster::try_from(*cla.clone()).unwrap();
-                (apply_cluster)(cluster)
+        let mut changes = Vec::with_capacity(resources.len());
+        for resource in resources {
+            tracing::trace!(?resource, "validating resource");
+            match resource {
+                Resource::Endpoint(cla) => {
+                    changes.push(Change::Endpoints(Cluster::try_from(*cla.clone())?));
+                }
+                Resource::Listener(listener) => {
+                    let chain = listener
+                        .filter_chains
+                        .get(0)
+                        .map(|chain| chain.filters.clone())
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(Filter::try_from)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    changes.push(Change::Filters(crate::filters::FilterChain::try_create(
+                        &chain,
+                    )?));
+                }
+                Resource::Cluster(cluster) => {
+                    changes.push(Change::Cluster(Cluster::try_from(*cluster.clone())?));
+                }
             }
-            Resource::Listener(listener) => {
-                let chain = listener
-                    .filter_chains
-                    .get(0)
-                    .map(|chain| chain.filters.clone())
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(Filter::try_from)
-                    .collect::<Result<Vec<_>, _>>()?;
-                self.filters.store(Arc::new(chain.try_into()?));
+        }
+
+        for change in changes {
+            match change {
+                Change::Cluster(cluster) => {
+                    self.clusters
+                        .write()
+                        .default_entry(cluster.name.clone())
+                        .merge(&cluster);
+                }
+                Change::Endpoints(cluster) => {
+                    self.clusters
+                        .write()
+                        .default_entry(cluster.name.clone())
+                        .localities
+                        .merge(&cluster.localities);
+                }
+                Change::Filters(chain) => self.replace_filters(chain),
             }
-            Resource::Cluster(cluster) => {
-                cluster
-                    .load_assignment
-                    .clone()
-                    .map(Cluster::try_from)
-                    .transpose()?
-                    .map(apply_cluster);
+        }
+
+        self.apply_metrics();
+        self.history.record(history::Source::Xds, self);
+        snapshot::stale().set(0);
+
+        Ok(())
+    }
+
+    /// Removes the resource called `name`, used when a delta xDS server
+    /// reports a resource as removed.
+    #[tracing::instrument(skip(self))]
+    pub fn remove(&self, resource_type: ResourceType, name: &str) -> crate::Result<()> {
+        match resource_type {
+            ResourceType::Cluster | ResourceType::Endpoint => {
+                self.clusters.write().remove(name);
             }
+            ResourceType::Listener => {
+                self.replace_filters(crate::filters::FilterChain::default());
+            }
+            resource => return Err(eyre::eyre!("Unsupported resource {}", resource.type_url())),
         }
 
         self.apply_metrics();
+        self.history.record(history::Source::Xds, self);
+        snapshot::stale().set(0);
 
         Ok(())
     }
@@ -219,8 +387,13 @@ impl Default for Config {
         Self {
             clusters: <_>::default(),
             filters: <_>::default(),
+            canary: Slot::empty(),
             id: default_proxy_id(),
             version: Slot::with_default(),
+            nodes: Slot::empty(),
+            plugins: Vec::new(),
+            history: <_>::default(),
+            xds_clients: <_>::default(),
         }
     }
 }
@@ -459,6 +632,53 @@ id: server-proxy
         );
     }
 
+    #[test]
+    fn parse_plugins() {
+        let yaml = "
+version: v1alpha1
+plugins:
+  - /nonexistent/plugin.so
+";
+        // Listed plugins are only loaded explicitly.
+        let config = Config::from_reader(yaml.as_bytes()).unwrap();
+        assert_eq!(
+            vec![std::path::PathBuf::from("/nonexistent/plugin.so")],
+            config.plugins
+        );
+        assert_eq!(config.plugins, Config::plugins_from_str(yaml).unwrap());
+        assert!(Config::plugins_from_str("version: v1alpha1")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn parse_canary() {
+        let config = Config::from_reader(
+            "
+version: v1alpha1
+canary:
+  fraction: 0.25
+  filters:
+    - name: quilkin.filters.drop.v1alpha1.Drop
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        let canary = config.canary.try_load().unwrap();
+        assert_eq!(1, canary.filters.len());
+        assert_eq!(0.25, canary.fraction);
+        assert!(Config::default().canary.try_load().is_none());
+        assert!(serde_json::to_value(Config::default())
+            .unwrap()
+            .get("canary")
+            .is_none());
+
+        let mut map = serde_json::Map::new();
+        map.insert("canary".into(), serde_json::Value::Null);
+        config.update_from_json(map, None).unwrap();
+        assert!(config.canary.is_none());
+    }
+
     #[test]
     fn deny_unused_fields() {
         let configs = vec![
@@ -0,0 +1,529 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A bounded history of the filter chains and clusters applied to a
+//! [`Config`], so that a bad update can be inspected and rolled back.
+//!
+//! Filter chains and clusters are recorded separately, as they usually come
+//! from different sources (e.g. a ConfigMap and the game servers in a
+//! cluster), and rolling back a filter chain shouldn't also roll back which
+//! endpoints are available.
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::ClusterMap,
+    config::{Config, Filter},
+    endpoint::Endpoint,
+    filters::FilterChain,
+};
+
+/// The number of versions of each resource that are kept by default.
+pub const DEFAULT_CAPACITY: usize = 16;
+
+/// Where a version of the configuration came from.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// A configuration file being watched for changes.
+    File,
+    /// An xDS management server.
+    Xds,
+    /// A Kubernetes ConfigMap or game server.
+    K8s,
+    /// A rollback to a previous version through the admin API.
+    Rollback,
+    /// A snapshot saved by a previous run, see [`crate::config::snapshot`].
+    Snapshot,
+}
+
+/// The parts of the configuration that are versioned.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resource {
+    Filters,
+    Clusters,
+}
+
+impl std::str::FromStr for Resource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "filters" => Ok(Self::Filters),
+            "clusters" => Ok(Self::Clusters),
+            _ => Err(Error::UnknownResource(s.into())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown resource `{0}`, expected `filters` or `clusters`")]
+    UnknownResource(String),
+    #[error("version {0} is not in the history")]
+    UnknownVersion(u64),
+    #[error("failed to recreate filter chain: {0}")]
+    InvalidFilters(#[from] crate::filters::CreationError),
+}
+
+/// A single version of a resource.
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry<T> {
+    pub version: u64,
+    pub timestamp: DateTime<Utc>,
+    pub source: Source,
+    #[serde(skip)]
+    pub value: T,
+}
+
+/// The versions of each resource in the history, oldest first.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub filters: Vec<Entry<()>>,
+    pub clusters: Vec<Entry<()>>,
+}
+
+/// A change to a single filter in a chain, by its position in the chain.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FilterChange {
+    pub index: usize,
+    pub from: Option<Filter>,
+    pub to: Option<Filter>,
+}
+
+/// The endpoints that were added to or removed from a cluster.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ClusterChange {
+    pub name: String,
+    pub added: Vec<Endpoint>,
+    pub removed: Vec<Endpoint>,
+}
+
+/// The changes between two versions of a resource.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Diff {
+    Filters(Vec<FilterChange>),
+    Clusters(Vec<ClusterChange>),
+}
+
+/// The recorded versions of a [`Config`]'s filter chain and clusters.
+#[derive(Debug)]
+pub struct History {
+    capacity: usize,
+    next_version: AtomicU64,
+    filters: Mutex<VecDeque<Entry<Vec<Filter>>>>,
+    clusters: Mutex<VecDeque<Entry<ClusterMap>>>,
+}
+
+impl History {
+    /// Creates a history keeping up to `capacity` versions of each resource.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            next_version: AtomicU64::new(1),
+            filters: <_>::default(),
+            clusters: <_>::default(),
+        }
+    }
+
+    /// Records the current filter chain and clusters of `config`, if they
+    /// have changed since they were last recorded.
+    pub fn record(&self, source: Source, config: &Config) {
+        self.record_filters(source, &config.filters.load());
+        self.record_clusters(source, &config.clusters.read());
+    }
+
+    /// Records `chain`, if it has changed since it was last recorded,
+    /// returning its version.
+    pub fn record_filters(&self, source: Source, chain: &FilterChain) -> u64 {
+        self.push(&self.filters, source, &chain.iter().collect())
+    }
+
+    /// Records `clusters`, if they have changed since they were last
+    /// recorded, returning their version.
+    pub fn record_clusters(&self, source: Source, clusters: &ClusterMap) -> u64 {
+        self.push(&self.clusters, source, clusters)
+    }
+
+    /// Appends `value`, unless it's the same as the latest entry. `value` is
+    /// only cloned once it's known to have changed, as cluster maps can be
+    /// large.
+    fn push<T: Clone + PartialEq>(
+        &self,
+        entries: &Mutex<VecDeque<Entry<T>>>,
+        source: Source,
+        value: &T,
+    ) -> u64 {
+        let mut entries = entries.lock();
+        if let Some(entry) = entries.back().filter(|entry| entry.value == *value) {
+            return entry.version;
+        }
+
+        let version = self.next_version.fetch_add(1, Ordering::Relaxed);
+        tracing::debug!(version, ?source, "recording configuration version");
+        while entries.len() >= self.capacity.max(1) {
+            entries.pop_front();
+        }
+
+        entries.push_back(Entry {
+            version,
+            timestamp: Utc::now(),
+            source,
+            value: value.clone(),
+        });
+        version
+    }
+
+    /// Lists the recorded versions of each resource.
+    pub fn summary(&self) -> Summary {
+        fn summarise<T>(entries: &Mutex<VecDeque<Entry<T>>>) -> Vec<Entry<()>> {
+            entries
+                .lock()
+                .iter()
+                .map(|entry| Entry {
+                    version: entry.version,
+                    timestamp: entry.timestamp,
+                    source: entry.source,
+                    value: (),
+                })
+                .collect()
+        }
+
+        Summary {
+            filters: summarise(&self.filters),
+            clusters: summarise(&self.clusters),
+        }
+    }
+
+    /// Returns the changes to `resource` between versions `from` and `to`,
+    /// defaulting to the latest version.
+    pub fn diff(&self, resource: Resource, from: u64, to: Option<u64>) -> Result<Diff, Error> {
+        match resource {
+            Resource::Filters => {
+                let (from, to) = Self::pair(&self.filters, from, to)?;
+                Ok(Diff::Filters(diff_filters(&from, &to)))
+            }
+            Resource::Clusters => {
+                let (from, to) = Self::pair(&self.clusters, from, to)?;
+                Ok(Diff::Clusters(diff_clusters(&from, &to)))
+            }
+        }
+    }
+
+    fn pair<T: Clone>(
+        entries: &Mutex<VecDeque<Entry<T>>>,
+        from: u64,
+        to: Option<u64>,
+    ) -> Result<(T, T), Error> {
+        let entries = entries.lock();
+        let find = |version| {
+            entries
+                .iter()
+                .find(|entry| entry.version == version)
+                .map(|entry| entry.value.clone())
+                .ok_or(Error::UnknownVersion(version))
+        };
+
+        let to = match to {
+            Some(to) => find(to)?,
+            None => entries
+                .back()
+                .map(|entry| entry.value.clone())
+                .ok_or(Error::UnknownVersion(from))?,
+        };
+
+        Ok((find(from)?, to))
+    }
+
+    /// Restores `resource` in `config` to `version`, recording it as a new
+    /// version, and returning that version.
+    pub fn rollback(
+        &self,
+        config: &Config,
+        resource: Resource,
+        version: u64,
+    ) -> Result<u64, Error> {
+        let new_version = match resource {
+            Resource::Filters => {
+                let filters = Self::find(&self.filters, version)?;
+                let chain = FilterChain::try_from(filters)?;
+                let new_version = self.record_filters(Source::Rollback, &chain);
+                config.replace_filters(chain);
+                new_version
+            }
+            Resource::Clusters => {
+                let clusters = Self::find(&self.clusters, version)?;
+                let new_version = self.record_clusters(Source::Rollback, &clusters);
+                config.clusters.modify(|map| map.replace(clusters.clone()));
+                config.apply_metrics();
+                new_version
+            }
+        };
+
+        tracing::info!(?resource, version, new_version, "rolled back configuration");
+        Ok(new_version)
+    }
+
+    fn find<T: Clone>(entries: &Mutex<VecDeque<Entry<T>>>, version: u64) -> Result<T, Error> {
+        entries
+            .lock()
+            .iter()
+            .find(|entry| entry.version == version)
+            .map(|entry| entry.value.clone())
+            .ok_or(Error::UnknownVersion(version))
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// The history isn't part of the configuration itself, so is ignored when
+/// comparing configurations.
+impl PartialEq for History {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+fn diff_filters(from: &[Filter], to: &[Filter]) -> Vec<FilterChange> {
+    (0..from.len().max(to.len()))
+        .filter_map(|index| {
+            let (from, to) = (from.get(index), to.get(index));
+            (from != to).then(|| FilterChange {
+                index,
+                from: from.cloned(),
+                to: to.cloned(),
+            })
+        })
+        .collect()
+}
+
+fn diff_clusters(from: &ClusterMap, to: &ClusterMap) -> Vec<ClusterChange> {
+    let endpoints = |map: &ClusterMap, name: &str| -> BTreeSet<Endpoint> {
+        map.get(name)
+            .map(|cluster| cluster.endpoints().cloned().collect())
+            .unwrap_or_default()
+    };
+
+    let mut names: Vec<_> = from
+        .iter()
+        .chain(to.iter())
+        .map(|entry| entry.key().clone())
+        .collect();
+    names.sort();
+    names.dedup();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let (from, to) = (endpoints(from, &name), endpoints(to, &name));
+            let added: Vec<_> = to.difference(&from).cloned().collect();
+            let removed: Vec<_> = from.difference(&to).cloned().collect();
+            (!added.is_empty() || !removed.is_empty()).then_some(ClusterChange {
+                name,
+                added,
+                removed,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::filters::{Drop, Pass, StaticFilter};
+
+    fn filter(name: &str) -> Filter {
+        Filter {
+            name: name.into(),
+            label: None,
+            config: None,
+        }
+    }
+
+    fn clusters(endpoints: &[&str]) -> ClusterMap {
+        let map = ClusterMap::default();
+        map.insert_default(
+            endpoints
+                .iter()
+                .map(|address| Endpoint::new(address.parse().unwrap()))
+                .collect::<std::collections::BTreeSet<_>>(),
+        );
+        map
+    }
+
+    #[test]
+    fn records_changes() {
+        let history = History::new(2);
+        let pass = FilterChain::try_from(vec![filter(Pass::NAME)]).unwrap();
+        let drop = FilterChain::try_from(vec![filter(Drop::NAME)]).unwrap();
+
+        assert_eq!(1, history.record_filters(Source::File, &pass));
+        assert_eq!(1, history.record_filters(Source::File, &pass));
+        assert_eq!(
+            2,
+            history.record_clusters(Source::K8s, &clusters(&["127.0.0.1:80"]))
+        );
+        assert_eq!(3, history.record_filters(Source::Xds, &drop));
+        assert_eq!(4, history.record_filters(Source::Xds, &pass));
+
+        let summary = history.summary();
+        assert_eq!(
+            vec![(3, Source::Xds), (4, Source::Xds)],
+            summary
+                .filters
+                .iter()
+                .map(|entry| (entry.version, entry.source))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, summary.clusters.len());
+        assert!(matches!(
+            history.diff(Resource::Filters, 1, None),
+            Err(Error::UnknownVersion(1))
+        ));
+    }
+
+    #[test]
+    fn zero_capacity_keeps_latest() {
+        let history = History::new(0);
+        history.record_clusters(Source::K8s, &clusters(&["127.0.0.1:80"]));
+        let version =
+            history.record_clusters(Source::K8s, &clusters(&["127.0.0.1:80", "127.0.0.1:81"]));
+
+        let summary = history.summary();
+        assert_eq!(
+            vec![version],
+            summary
+                .clusters
+                .iter()
+                .map(|entry| entry.version)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn records_added_clusters() {
+        let history = History::default();
+        let map = clusters(&["127.0.0.1:80"]);
+        let v1 = history.record_clusters(Source::K8s, &map);
+        assert_eq!(v1, history.record_clusters(Source::K8s, &map));
+
+        map.insert(crate::cluster::Cluster::new(
+            "other",
+            [crate::endpoint::LocalityEndpoints::from(Endpoint::new(
+                "127.0.0.1:82".parse().unwrap(),
+            ))],
+        ));
+        assert_ne!(v1, history.record_clusters(Source::K8s, &map));
+    }
+
+    #[test]
+    fn diff() {
+        let history = History::default();
+        let v1 = history.record_filters(
+            Source::File,
+            &FilterChain::try_from(vec![filter(Pass::NAME)]).unwrap(),
+        );
+        history.record_filters(
+            Source::File,
+            &FilterChain::try_from(vec![filter(Pass::NAME), filter(Drop::NAME)]).unwrap(),
+        );
+
+        assert_eq!(
+            Diff::Filters(vec![FilterChange {
+                index: 1,
+                from: None,
+                to: Some(filter(Drop::NAME)),
+            }]),
+            history.diff(Resource::Filters, v1, None).unwrap()
+        );
+
+        let v1 = history.record_clusters(Source::K8s, &clusters(&["127.0.0.1:80"]));
+        let v2 = history.record_clusters(Source::K8s, &clusters(&["127.0.0.1:81"]));
+        assert_eq!(
+            Diff::Clusters(vec![ClusterChange {
+                name: "default".into(),
+                added: vec![Endpoint::new("127.0.0.1:81".parse().unwrap())],
+                removed: vec![Endpoint::new("127.0.0.1:80".parse().unwrap())],
+            }]),
+            history.diff(Resource::Clusters, v1, Some(v2)).unwrap()
+        );
+        assert_eq!(
+            Diff::Clusters(Vec::new()),
+            history.diff(Resource::Clusters, v2, Some(v2)).unwrap()
+        );
+    }
+
+    #[test]
+    fn rollback() {
+        let config = Config::default();
+        config.filters.store(Arc::new(
+            FilterChain::try_from(vec![filter(Pass::NAME)]).unwrap(),
+        ));
+        config
+            .clusters
+            .modify(|map| map.replace(clusters(&["127.0.0.1:80"])));
+        config.history.record(Source::File, &config);
+
+        config.filters.store(Arc::new(
+            FilterChain::try_from(vec![filter(Drop::NAME)]).unwrap(),
+        ));
+        config
+            .clusters
+            .modify(|map| map.replace(clusters(&["127.0.0.1:81"])));
+        config.history.record(Source::File, &config);
+
+        let version = config.history.summary().filters[0].version;
+        let new_version = config
+            .history
+            .rollback(&config, Resource::Filters, version)
+            .unwrap();
+        assert_eq!(Pass::NAME, config.filters.load()[0].0);
+        // Rolling back the filters leaves the clusters as they are.
+        assert_eq!(*config.clusters.read(), clusters(&["127.0.0.1:81"]));
+
+        let summary = config.history.summary();
+        assert_eq!(3, summary.filters.len());
+        assert_eq!(new_version, summary.filters[2].version);
+        assert_eq!(Source::Rollback, summary.filters[2].source);
+
+        let version = summary.clusters[0].version;
+        config
+            .history
+            .rollback(&config, Resource::Clusters, version)
+            .unwrap();
+        assert_eq!(*config.clusters.read(), clusters(&["127.0.0.1:80"]));
+
+        assert!(matches!(
+            config.history.rollback(&config, Resource::Filters, 100),
+            Err(Error::UnknownVersion(100))
+        ));
+    }
+}
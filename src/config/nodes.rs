@@ -0,0 +1,237 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cluster::Cluster,
+    endpoint::{Locality, LocalityEndpoints},
+    filters::FilterChain,
+    xds::config::core::v3::Node,
+};
+
+/// Selects the resources served over xDS to the proxies whose node matches
+/// `node`. The first rule that matches a proxy is used.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NodeRule {
+    /// The proxies the rule applies to, every proxy if empty.
+    #[serde(default)]
+    pub node: NodeSelector,
+    /// The filter chain served to matching proxies instead of `filters`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filters: Option<FilterChain>,
+    /// Which endpoints are served to matching proxies, relative to the
+    /// proxy's locality.
+    #[serde(default)]
+    pub endpoints: EndpointScope,
+}
+
+impl NodeRule {
+    /// Returns the first rule in `rules` that matches `node`.
+    pub fn find<'rules>(rules: &'rules [Self], node: &Node) -> Option<&'rules Self> {
+        rules.iter().find(|rule| rule.node.matches(node))
+    }
+
+    /// Removes the endpoints in `cluster` that aren't served to `node`.
+    pub fn select_endpoints(&self, node: &Node, cluster: &mut Cluster) {
+        let locality = node
+            .locality
+            .clone()
+            .map(Locality::from)
+            .unwrap_or_default();
+        cluster
+            .localities
+            .retain(|endpoints| self.endpoints.contains(&locality, endpoints));
+    }
+}
+
+/// Matches proxies by the `Node` they send in their discovery requests.
+/// Every given field has to match.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NodeSelector {
+    /// Matches proxies with any of these IDs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ids: Vec<String>,
+    /// Matches proxies in this region.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Matches proxies in this zone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    /// Matches proxies in this sub-zone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub_zone: Option<String>,
+    /// Matches proxies whose node metadata has these string values.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl NodeSelector {
+    pub fn matches(&self, node: &Node) -> bool {
+        let locality = node.locality.clone().unwrap_or_default();
+        let matches = |expected: &Option<String>, actual: &str| {
+            expected
+                .as_deref()
+                .map_or(true, |expected| expected == actual)
+        };
+
+        (self.ids.is_empty() || self.ids.contains(&node.id))
+            && matches(&self.region, &locality.region)
+            && matches(&self.zone, &locality.zone)
+            && matches(&self.sub_zone, &locality.sub_zone)
+            && self.metadata.iter().all(|(key, expected)| {
+                node.metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.fields.get(key))
+                    .and_then(|value| match &value.kind {
+                        Some(prost_types::value::Kind::StringValue(value)) => Some(value),
+                        _ => None,
+                    })
+                    .map_or(false, |value| value == expected)
+            })
+    }
+}
+
+/// The endpoints served to a proxy, relative to its locality. Endpoints
+/// without a locality are always served.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum EndpointScope {
+    /// Every endpoint.
+    #[default]
+    All,
+    /// Only endpoints in the proxy's region.
+    Region,
+    /// Only endpoints in the proxy's region and zone.
+    Zone,
+    /// Only endpoints in the proxy's region, zone, and sub-zone.
+    SubZone,
+}
+
+impl EndpointScope {
+    fn contains(self, node: &Locality, endpoints: &LocalityEndpoints) -> bool {
+        let Some(locality) = &endpoints.locality else {
+            return true;
+        };
+
+        let region = locality.region == node.region;
+        let zone = region && locality.zone == node.zone;
+        match self {
+            Self::All => true,
+            Self::Region => region,
+            Self::Zone => zone,
+            Self::SubZone => zone && locality.sub_zone == node.sub_zone,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, region: &str, zone: &str) -> Node {
+        Node {
+            id: id.into(),
+            locality: Some(crate::xds::config::core::v3::Locality {
+                region: region.into(),
+                zone: zone.into(),
+                ..<_>::default()
+            }),
+            metadata: Some(prost_types::Struct {
+                fields: [(
+                    "tier".to_owned(),
+                    prost_types::Value {
+                        kind: Some(prost_types::value::Kind::StringValue("gold".into())),
+                    },
+                )]
+                .into(),
+            }),
+            ..<_>::default()
+        }
+    }
+
+    #[test]
+    fn first_matching_rule() {
+        let rules: Vec<NodeRule> = serde_yaml::from_str(
+            "
+- node:
+    ids: [proxy-a]
+  endpoints: ZONE
+- node:
+    region: eu
+    metadata:
+      tier: gold
+  endpoints: REGION
+- endpoints: ALL
+",
+        )
+        .unwrap();
+
+        let endpoints = |rule: Option<&NodeRule>| rule.map(|rule| rule.endpoints);
+        assert_eq!(
+            Some(EndpointScope::Zone),
+            endpoints(NodeRule::find(&rules, &node("proxy-a", "eu", "1")))
+        );
+        assert_eq!(
+            Some(EndpointScope::Region),
+            endpoints(NodeRule::find(&rules, &node("proxy-b", "eu", "1")))
+        );
+        assert_eq!(
+            Some(EndpointScope::All),
+            endpoints(NodeRule::find(&rules, &node("proxy-b", "us", "1")))
+        );
+        assert!(NodeRule::find(&rules[..1], &node("proxy-b", "us", "1")).is_none());
+    }
+
+    #[test]
+    fn select_endpoints() {
+        let endpoints = |locality: Option<Locality>, port| LocalityEndpoints {
+            locality,
+            endpoints: [crate::endpoint::Endpoint::new(
+                (std::net::Ipv4Addr::LOCALHOST, port).into(),
+            )]
+            .into(),
+        };
+        let mut cluster = Cluster::new(
+            "default",
+            [
+                endpoints(None, 1),
+                endpoints(Some(Locality::region("eu").zone("1")), 2),
+                endpoints(Some(Locality::region("eu").zone("2")), 3),
+                endpoints(Some(Locality::region("us").zone("1")), 4),
+            ],
+        );
+
+        let rule = NodeRule {
+            node: <_>::default(),
+            filters: None,
+            endpoints: EndpointScope::Region,
+        };
+        rule.select_endpoints(&node("proxy", "eu", "1"), &mut cluster);
+
+        let mut ports: Vec<_> = cluster
+            .endpoints()
+            .map(|endpoint| endpoint.address.port())
+            .collect();
+        ports.sort();
+        assert_eq!(vec![1, 2, 3], ports);
+    }
+}
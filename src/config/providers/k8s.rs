@@ -53,7 +53,7 @@ pub fn update_filters_from_configmap(
                     .map(serde_json::from_value)
                     .transpose()?
             {
-                config.filters.store(Arc::new(filters));
+                config.replace_filters(filters);
             }
 
             yield Ok(());
@@ -28,6 +28,7 @@ use serde::{Deserialize, Serialize};
 use crate::endpoint::Endpoint;
 
 const QUILKIN_TOKEN_LABEL: &str = "quilkin.dev/tokens";
+const QUILKIN_METADATA_LABEL: &str = "quilkin.dev/metadata";
 
 /// Auto-generated derived type for GameServerSpec via `CustomResource`
 #[derive(Clone, Debug, schemars::JsonSchema)]
@@ -50,7 +51,7 @@ impl GameServer {
 
             let tokens = self.tokens();
             let extra_metadata = {
-                let mut map = serde_json::Map::default();
+                let mut map = self.extra_metadata();
                 map.insert(
                     "name".into(),
                     self.metadata.name.clone().unwrap_or_default().into(),
@@ -68,6 +69,23 @@ impl GameServer {
         })
     }
 
+    /// Returns the user metadata from the `quilkin.dev/metadata` annotation,
+    /// which is a JSON object whose structure is kept as is.
+    pub fn extra_metadata(&self) -> serde_json::Map<String, serde_json::Value> {
+        self.metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(QUILKIN_METADATA_LABEL))
+            .and_then(|value| {
+                serde_json::from_str(value)
+                    .map_err(|error| {
+                        tracing::warn!(%error, gameserver = ?self.metadata.name, "invalid `{QUILKIN_METADATA_LABEL}` annotation");
+                    })
+                    .ok()
+            })
+            .unwrap_or_default()
+    }
+
     pub fn tokens(&self) -> std::collections::BTreeSet<Vec<u8>> {
         match self.metadata.annotations.as_ref() {
             Some(annotations) => annotations
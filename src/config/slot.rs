@@ -54,6 +54,16 @@ impl<T> Slot<T> {
     pub fn is_some(&self) -> bool {
         self.inner.load().is_some()
     }
+
+    /// Returns whether the slot is empty.
+    pub fn is_none(&self) -> bool {
+        !self.is_some()
+    }
+
+    /// Provides a reference to the underlying data, if present.
+    pub fn try_load(&self) -> Option<Arc<T>> {
+        self.inner.load_full()
+    }
 }
 
 impl<T: Default> Slot<T> {
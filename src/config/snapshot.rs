@@ -0,0 +1,154 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Snapshots of the clusters and filters in a [`Config`] saved to a local
+//! file, so that a restarted service starts from its last known state
+//! rather than an empty one.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+
+use crate::{config::history::Source, Config};
+
+/// How often, in seconds, a snapshot is saved by default.
+pub const DEFAULT_INTERVAL_SECONDS: u64 = 10;
+
+/// The fields of the configuration that are saved in a snapshot.
+const FIELDS: &[&str] = &["version", "clusters", "filters"];
+
+const SUBSYSTEM: &str = "config";
+
+/// Whether the configuration was loaded from a snapshot and hasn't been
+/// updated from a management server since.
+pub(crate) fn stale() -> &'static prometheus::IntGauge {
+    static STALE: Lazy<prometheus::IntGauge> = Lazy::new(|| {
+        crate::metrics::register(
+            prometheus::IntGauge::with_opts(crate::metrics::opts(
+                "stale",
+                SUBSYSTEM,
+                "Whether the configuration was loaded from a snapshot and not yet updated by a management server.",
+            ))
+            .unwrap(),
+        )
+    });
+
+    &STALE
+}
+
+/// Applies the snapshot saved at `path` to `config`, returning whether there
+/// was one.
+pub async fn load(config: &Config, path: &Path) -> crate::Result<bool> {
+    let buf = match tokio::fs::read(path).await {
+        Ok(buf) => buf,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(error) => return Err(error.into()),
+    };
+
+    tracing::info!(path = %path.display(), "loading configuration snapshot");
+    config.update_from_json(serde_yaml::from_slice(&buf)?, None)?;
+    config.history.record(Source::Snapshot, config);
+    stale().set(1);
+    Ok(true)
+}
+
+/// Saves the clusters and filters in `config` to `path`.
+pub async fn save(config: &Config, path: &Path) -> crate::Result<()> {
+    write(path, &encode(config)?).await
+}
+
+/// Saves `config` to `path` every `interval`, whenever it has changed.
+pub async fn persist(config: Arc<Config>, path: PathBuf, interval: Duration) -> crate::Result<()> {
+    let mut interval = tokio::time::interval(interval);
+    let mut saved = None;
+    loop {
+        interval.tick().await;
+        let contents = encode(&config)?;
+        if saved.as_ref() == Some(&contents) {
+            continue;
+        }
+
+        match write(&path, &contents).await {
+            Ok(()) => saved = Some(contents),
+            Err(error) => tracing::warn!(%error, path = %path.display(), "saving snapshot failed"),
+        }
+    }
+}
+
+fn encode(config: &Config) -> crate::Result<String> {
+    let serde_json::Value::Object(mut map) = serde_json::to_value(config)? else {
+        return Err(eyre::eyre!("configuration isn't an object"));
+    };
+
+    map.retain(|key, _| FIELDS.contains(&&**key));
+    Ok(serde_yaml::to_string(&map)?)
+}
+
+/// Writes `contents` to a temporary file that then replaces `path`, so a
+/// partially written snapshot is never loaded.
+async fn write(path: &Path, contents: &str) -> crate::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    tokio::fs::write(&temporary, contents).await?;
+    tokio::fs::rename(&temporary, path).await?;
+    tracing::debug!(path = %path.display(), "saved configuration snapshot");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        cluster::Cluster,
+        endpoint::Endpoint,
+        filters::{FilterChain, StaticFilter},
+    };
+
+    #[tokio::test]
+    async fn save_and_load() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("snapshot.yaml");
+
+        let config = Config::default();
+        config.clusters.modify(|clusters| {
+            clusters.insert(Cluster::new_default(vec![Endpoint::new(
+                (std::net::Ipv4Addr::LOCALHOST, 4321).into(),
+            )
+            .into()]));
+        });
+        config.replace_filters(
+            FilterChain::try_from(vec![crate::config::Filter {
+                name: crate::filters::Debug::factory().name().into(),
+                label: None,
+                config: None,
+            }])
+            .unwrap(),
+        );
+
+        let restored = Config::default();
+        assert!(!load(&restored, &path).await.unwrap());
+
+        save(&config, &path).await.unwrap();
+        assert!(load(&restored, &path).await.unwrap());
+        assert_eq!(config.clusters, restored.clusters);
+        assert_eq!(config.filters, restored.filters);
+    }
+}
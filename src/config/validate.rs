@@ -0,0 +1,945 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Semantic validation of a configuration file, reporting every problem found
+//! along with where it is in the file, rather than stopping at the first.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+use serde_yaml::Value;
+use yaml_rust::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+};
+
+use crate::{
+    config::{Config, Filter},
+    endpoint::{address::AddressKind, EndpointAddress},
+    filters::{metadata::MetadataKeys, r#match, FilterChain, FilterRegistry, Match, StaticFilter},
+};
+
+/// How long to wait for every endpoint's hostname to resolve.
+const RESOLVE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// The paths of the fields containing filter chains.
+const FILTER_CHAINS: [&[&str]; 2] = [&["filters"], &["canary", "filters"]];
+
+/// The path of the canary's filter chain.
+const CANARY_FILTERS: &str = "canary.filters";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The configuration can't be used.
+    Error,
+    /// The configuration can be used, but probably doesn't do what was
+    /// intended.
+    Warning,
+}
+
+/// A single problem found in a configuration.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The path to the value in the document, e.g. `filters[1].config`, or
+    /// empty for the document itself.
+    pub path: String,
+    /// The line of the value in the document, starting from 1.
+    pub line: Option<usize>,
+    /// The column of the value in the document, starting from 1.
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        write!(f, "{severity}")?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " at {line}:{column}")?;
+        }
+        if !self.path.is_empty() {
+            write!(f, " in `{}`", self.path)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// The result of validating a configuration.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// Whether the configuration has no errors, it may still have warnings.
+    pub valid: bool,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Every metadata key read by a filter in each filter chain.
+    pub metadata: Vec<MetadataEdge>,
+}
+
+/// A metadata key read by a filter, along with the filter that wrote it.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct MetadataEdge {
+    pub key: String,
+    /// The path of the filter that wrote the key, if it's always written.
+    pub writer: Option<String>,
+    /// The path of the filter, or `Match` condition, that reads the key.
+    pub reader: String,
+}
+
+impl std::fmt::Display for MetadataEdge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.writer {
+            Some(writer) => write!(f, "`{writer}` -> `{}`", self.reader)?,
+            None => write!(f, "(not written) -> `{}`", self.reader)?,
+        }
+        write!(f, ": metadata key `{}`", self.key)
+    }
+}
+
+/// Options controlling what [`validate`] checks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    /// Whether to load the native plugins listed in the configuration, so
+    /// that their filters can be validated. Loading a plugin runs its code.
+    pub load_plugins: bool,
+    /// Whether to check that the hostname of each endpoint resolves.
+    pub resolve_endpoints: bool,
+}
+
+/// Validates the YAML configuration in `input`.
+///
+/// Besides the structure of the configuration, this creates every filter in
+/// each filter chain, checks that any metadata keys read by a filter are
+/// written by an earlier filter, and optionally resolves the hostnames of
+/// endpoints.
+pub async fn validate(input: &str, options: Options) -> Report {
+    let mut validator = Validator {
+        positions: Positions::parse(input),
+        diagnostics: Vec::new(),
+        metadata: Vec::new(),
+    };
+    validator.run(input, options).await;
+
+    Report {
+        valid: validator
+            .diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.severity != Severity::Error),
+        diagnostics: validator.diagnostics,
+        metadata: validator.metadata,
+    }
+}
+
+struct Validator {
+    positions: Positions,
+    diagnostics: Vec<Diagnostic>,
+    metadata: Vec<MetadataEdge>,
+}
+
+impl Validator {
+    async fn run(&mut self, input: &str, options: Options) {
+        let document: Value = match serde_yaml::from_str(input) {
+            Ok(document) => document,
+            Err(error) => {
+                let location = error.location();
+                self.diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    path: String::new(),
+                    line: location.as_ref().map(|location| location.line()),
+                    column: location.as_ref().map(|location| location.column()),
+                    message: strip_location(&error.to_string()).into(),
+                });
+                return;
+            }
+        };
+
+        if options.load_plugins {
+            self.load_plugins(&document);
+        }
+
+        for path in FILTER_CHAINS {
+            let filters = path
+                .iter()
+                .try_fold(&document, |value, field| value.get(field))
+                .filter(|filters| !filters.is_null());
+            if let Some(filters) = filters {
+                self.check_filter_chain(&path.join("."), filters);
+            }
+        }
+
+        self.check_structure(document.clone());
+        if options.resolve_endpoints {
+            self.check_endpoints(&document).await;
+        }
+    }
+
+    fn push(&mut self, severity: Severity, path: String, message: impl Into<String>) {
+        let marker = self.positions.find(&path);
+        self.diagnostics.push(Diagnostic {
+            severity,
+            line: marker.map(|marker| marker.line()),
+            column: marker.map(|marker| marker.col() + 1),
+            path,
+            message: message.into(),
+        });
+    }
+
+    fn load_plugins(&mut self, document: &Value) {
+        let Some(Value::Sequence(plugins)) = document.get("plugins") else {
+            return;
+        };
+
+        for (index, plugin) in plugins.iter().enumerate() {
+            if let Some(path) = plugin.as_str() {
+                if let Err(error) = FilterRegistry::load_plugin(path) {
+                    self.push(
+                        Severity::Error,
+                        format!("plugins[{index}]"),
+                        error.to_string(),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Checks the fields of the configuration other than the filter chains,
+    /// which are checked filter by filter instead.
+    fn check_structure(&mut self, mut document: Value) {
+        for path in FILTER_CHAINS {
+            let (field, parents) = path.split_last().unwrap();
+            let parent = parents
+                .iter()
+                .try_fold(&mut document, |value, field| value.get_mut(field));
+            if let Some(Value::Mapping(mapping)) = parent {
+                mapping.remove(field);
+            }
+        }
+
+        // Only documents read from a string report the path of an error.
+        let result = serde_yaml::to_string(&document)
+            .and_then(|document| serde_yaml::from_str::<Config>(&document));
+
+        if let Err(error) = result {
+            let message = strip_location(&error.to_string()).to_owned();
+            let (path, message) = match message.split_once(": ") {
+                Some((path, message)) if !path.contains(' ') => (path.into(), message.into()),
+                _ => (String::new(), message),
+            };
+            self.push(Severity::Error, path, message);
+        }
+    }
+
+    fn check_filter_chain(&mut self, field: &str, filters: &Value) {
+        let filters: Vec<Filter> = match serde_yaml::from_value(filters.clone()) {
+            Ok(filters) => filters,
+            Err(error) => {
+                self.push(Severity::Error, field.into(), error.to_string());
+                return;
+            }
+        };
+
+        for (index, filter) in filters.iter().enumerate() {
+            let path = format!("{field}[{index}]");
+            if FilterRegistry::get_factory(&filter.name).is_none() {
+                self.push(
+                    Severity::Error,
+                    format!("{path}.name"),
+                    format!("unknown filter `{}`", filter.name),
+                );
+            } else if let Err(error) = FilterChain::try_create(std::slice::from_ref(filter)) {
+                let path = match filter.config {
+                    Some(_) => format!("{path}.config"),
+                    None => path,
+                };
+                // Any location is relative to the filter's configuration.
+                self.push(Severity::Error, path, strip_location(&error.to_string()));
+            } else if field == CANARY_FILTERS {
+                if let Some(name) =
+                    crate::filters::canary::side_effecting_filter(std::slice::from_ref(filter))
+                {
+                    self.push(
+                        Severity::Error,
+                        path,
+                        format!("`{name}` can't be used in a canary filter chain"),
+                    );
+                }
+            }
+        }
+
+        let analysis = MetadataAnalysis::new(field, &filters);
+        for (path, message) in analysis.warnings {
+            self.push(Severity::Warning, path, message);
+        }
+        self.metadata.extend(analysis.edges);
+    }
+
+    /// Checks that each endpoint's hostname resolves.
+    async fn check_endpoints(&mut self, document: &Value) {
+        let Some(Value::Mapping(clusters)) = document.get("clusters") else {
+            return;
+        };
+
+        let mut addresses = Vec::new();
+        for (name, cluster) in clusters {
+            let Some(Value::Sequence(localities)) = cluster.get("localities") else {
+                continue;
+            };
+
+            for (i, locality) in localities.iter().enumerate() {
+                let Some(Value::Sequence(endpoints)) = locality.get("endpoints") else {
+                    continue;
+                };
+
+                for (j, endpoint) in endpoints.iter().enumerate() {
+                    let Some(address) = endpoint.get("address").and_then(Value::as_str) else {
+                        continue;
+                    };
+
+                    let path = format!(
+                        "clusters.{}.localities[{i}].endpoints[{j}].address",
+                        name.as_str().unwrap_or_default()
+                    );
+                    addresses.push((path, address.to_owned()));
+                }
+            }
+        }
+
+        // Every hostname is resolved at once, sharing a single deadline.
+        let deadline = tokio::time::Instant::now() + RESOLVE_TIMEOUT;
+        let results = futures::future::join_all(
+            addresses
+                .into_iter()
+                .filter_map(|(path, address)| {
+                    // Invalid addresses were already reported when checking
+                    // the structure.
+                    let address = address.parse::<EndpointAddress>().ok()?;
+                    matches!(address.host, AddressKind::Name(_)).then_some((path, address))
+                })
+                .map(|(path, address)| async move {
+                    let result = tokio::time::timeout_at(deadline, address.to_socket_addr()).await;
+                    (path, address, result)
+                }),
+        )
+        .await;
+
+        for (path, address, result) in results {
+            match result {
+                Ok(Ok(_)) => {}
+                Ok(Err(error)) => self.push(
+                    Severity::Error,
+                    path,
+                    format!("failed to resolve `{address}`: {error}"),
+                ),
+                Err(_) => self.push(
+                    Severity::Error,
+                    path,
+                    format!("timed out resolving `{address}`"),
+                ),
+            }
+        }
+    }
+}
+
+/// The metadata keys read by the filters of a chain, along with the filters
+/// that wrote them, based on the [`MetadataKeys`] each filter declares.
+#[derive(Default)]
+pub(crate) struct MetadataAnalysis {
+    pub edges: Vec<MetadataEdge>,
+    /// The path of each read of a key that may not have been written, along
+    /// with why.
+    pub warnings: Vec<(String, String)>,
+}
+
+impl MetadataAnalysis {
+    /// Analyses the read path of the chain of `filters` at `path`.
+    pub fn new(path: &str, filters: &[Filter]) -> Self {
+        let mut analysis = Self::default();
+        analysis.check_chain(path, filters, &mut Flow::default());
+        analysis
+    }
+
+    /// Checks that every metadata key read by a filter in the chain at
+    /// `path` is written by an earlier filter. Filters that can write
+    /// arbitrary keys, such as `Wasm` or plugin filters, stop any further
+    /// checks.
+    fn check_chain(&mut self, path: &str, filters: &[Filter], flow: &mut Flow) {
+        for (index, filter) in filters.iter().enumerate() {
+            self.check_filter(&format!("{path}[{index}]"), filter, flow);
+        }
+    }
+
+    fn check_filter(&mut self, path: &str, filter: &Filter, flow: &mut Flow) {
+        if flow.unknown || flow.dropped {
+            return;
+        }
+
+        // The filters run by `Match` are checked as part of the chain, as
+        // they can depend on keys written before it.
+        if filter.name == Match::NAME {
+            let config = filter
+                .config
+                .clone()
+                .unwrap_or_else(|| serde_json::Value::Object(<_>::default()));
+            // Invalid configurations are reported when creating the filter.
+            if let Ok(config) = serde_json::from_value::<r#match::Config>(config) {
+                self.check_match(path, config, flow);
+            }
+            return;
+        }
+
+        let keys = match FilterRegistry::get_factory(&filter.name) {
+            Some(factory) => factory.metadata_keys(filter.config.as_ref()),
+            None => MetadataKeys::unknown(),
+        };
+
+        for key in &keys.reads {
+            self.check_key(path, key, flow);
+        }
+        for key in keys.writes {
+            flow.conditional.remove(&key);
+            flow.written.insert(key, path.into());
+        }
+        flow.unknown |= keys.writes_any;
+        flow.dropped |= keys.drops;
+    }
+
+    /// Checks the keys read by the branch conditions of the `Match` filter at
+    /// `path`, and the filters run by each branch. A key written by only some
+    /// branches may be missing for later filters.
+    fn check_match(&mut self, path: &str, config: r#match::Config, flow: &mut Flow) {
+        // The metadata of packets being written is separate, and no built in
+        // filter writes to it.
+        let Some(on_read) = config.on_read else {
+            return;
+        };
+        let path = format!("{path}.config.on_read");
+
+        if on_read.branches.iter().any(|branch| branch.value.is_some()) {
+            if let Some(key) = on_read.metadata_key {
+                self.check_key(&format!("{path}.metadataKey"), key, flow);
+            }
+        }
+
+        let mut branches = Vec::new();
+        for (index, branch) in on_read.branches.iter().enumerate() {
+            let path = format!("{path}.branches[{index}]");
+            for key in branch
+                .condition
+                .iter()
+                .flat_map(|condition| condition.metadata_keys(on_read.metadata_key))
+            {
+                self.check_key(&format!("{path}.condition"), key, flow);
+            }
+
+            let mut branch_flow = flow.clone();
+            self.check_branch(&path, &branch.filter, &mut branch_flow);
+            branches.push(branch_flow);
+        }
+
+        let mut fallthrough = flow.clone();
+        self.check_branch(
+            &format!("{path}.fallthrough"),
+            &on_read.fallthrough.0,
+            &mut fallthrough,
+        );
+        branches.push(fallthrough);
+
+        flow.join(&path, branches);
+    }
+
+    fn check_branch(&mut self, path: &str, filters: &r#match::Filters, flow: &mut Flow) {
+        match filters {
+            r#match::Filters::Single(filter) => self.check_filter(path, filter, flow),
+            r#match::Filters::Chain { filters } => {
+                self.check_chain(&format!("{path}.filters"), filters, flow)
+            }
+        }
+    }
+
+    /// Records that `key` is read by the value at `path`, warning if it may
+    /// not have been written.
+    fn check_key(&mut self, path: &str, key: impl std::fmt::Display, flow: &Flow) {
+        let key = key.to_string();
+        let writer = flow.written.get(&key).cloned();
+        if writer.is_none() {
+            let message = match flow.conditional.get(&key) {
+                Some(writer) => {
+                    format!("metadata key `{key}` is only written by some branches of `{writer}`")
+                }
+                None => format!("metadata key `{key}` is read before any filter writes it"),
+            };
+            self.warnings.push((path.into(), message));
+        }
+
+        self.edges.push(MetadataEdge {
+            key,
+            writer,
+            reader: path.into(),
+        });
+    }
+}
+
+/// What is known about the metadata of a packet at a point in a filter chain.
+#[derive(Clone, Default)]
+struct Flow {
+    /// The keys written by an earlier filter, along with the path of the
+    /// filter that last wrote them.
+    written: BTreeMap<String, String>,
+    /// The keys only written by some branches of a `Match` filter, along with
+    /// the path of the filter.
+    conditional: BTreeMap<String, String>,
+    /// Whether the packet has been dropped.
+    dropped: bool,
+    /// Whether a filter that can write any key has run.
+    unknown: bool,
+}
+
+impl Flow {
+    /// Joins the flows of every branch of the `Match` filter at `path`, where
+    /// only keys written by every branch that doesn't drop the packet are
+    /// always written.
+    fn join(&mut self, path: &str, branches: Vec<Flow>) {
+        if branches.iter().any(|branch| branch.unknown) {
+            self.unknown = true;
+            return;
+        }
+
+        let branches: Vec<_> = branches.into_iter().filter(|flow| !flow.dropped).collect();
+        if branches.is_empty() {
+            self.dropped = true;
+            return;
+        }
+
+        for branch in &branches {
+            for key in branch.written.keys() {
+                if branches
+                    .iter()
+                    .all(|branch| branch.written.contains_key(key))
+                {
+                    if self.written.get(key) != branch.written.get(key) {
+                        self.written.insert(key.clone(), path.into());
+                    }
+                } else if !self.written.contains_key(key) {
+                    self.conditional
+                        .entry(key.clone())
+                        .or_insert_with(|| path.into());
+                }
+            }
+
+            for (key, writer) in &branch.conditional {
+                self.conditional
+                    .entry(key.clone())
+                    .or_insert_with(|| writer.clone());
+            }
+        }
+
+        let written = &self.written;
+        self.conditional.retain(|key, _| !written.contains_key(key));
+    }
+}
+
+/// Removes the ` at line X column Y` suffix that `serde_yaml` adds to errors.
+fn strip_location(message: &str) -> &str {
+    match message.rfind(" at line ") {
+        Some(index) => &message[..index],
+        None => message,
+    }
+}
+
+/// The position of every value in a YAML document, by its path.
+#[derive(Default)]
+struct Positions {
+    stack: Vec<Frame>,
+    markers: HashMap<String, Marker>,
+}
+
+enum Frame {
+    Mapping { key: Option<String> },
+    Sequence { index: usize },
+}
+
+impl Positions {
+    fn parse(input: &str) -> Self {
+        let mut positions = Self::default();
+        // Syntax errors are reported by `serde_yaml`.
+        let _ = Parser::new(input.chars()).load(&mut positions, false);
+        positions
+    }
+
+    /// Returns the position of `path`, or of its closest parent.
+    fn find(&self, mut path: &str) -> Option<Marker> {
+        loop {
+            if let Some(marker) = self.markers.get(path) {
+                return Some(*marker);
+            }
+
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+
+    fn path(&self) -> String {
+        let mut path = String::new();
+        for frame in &self.stack {
+            match frame {
+                Frame::Mapping { key: Some(key) } if path.is_empty() => path.push_str(key),
+                Frame::Mapping { key: Some(key) } => {
+                    path.push('.');
+                    path.push_str(key);
+                }
+                Frame::Mapping { key: None } => {}
+                Frame::Sequence { index } => path.push_str(&format!("[{index}]")),
+            }
+        }
+        path
+    }
+
+    /// Records the start of a value, unless it's the key of a mapping.
+    fn start_value(&mut self, marker: Marker) {
+        if matches!(self.stack.last(), Some(Frame::Mapping { key: None })) {
+            return;
+        }
+
+        self.markers.entry(self.path()).or_insert(marker);
+    }
+
+    fn end_value(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Mapping { key }) => *key = None,
+            Some(Frame::Sequence { index }) => *index += 1,
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for Positions {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        match event {
+            Event::Scalar(value, ..) => match self.stack.last_mut() {
+                Some(Frame::Mapping { key: None }) => {
+                    // Block mappings are only reported as starting after their
+                    // first key, so a mapping starts at its first key instead.
+                    self.markers.entry(self.path()).or_insert(marker);
+                    if let Some(Frame::Mapping { key }) = self.stack.last_mut() {
+                        *key = Some(value);
+                    }
+                    // Point at the key rather than its value, which may start
+                    // on the following line.
+                    self.markers.entry(self.path()).or_insert(marker);
+                }
+                _ => {
+                    self.start_value(marker);
+                    self.end_value();
+                }
+            },
+            Event::Alias(_) => {
+                self.start_value(marker);
+                self.end_value();
+            }
+            Event::MappingStart(_) => {
+                self.stack.push(Frame::Mapping { key: None });
+            }
+            Event::SequenceStart(_) => {
+                self.start_value(marker);
+                self.stack.push(Frame::Sequence { index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.end_value();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn diagnostics(input: &str) -> Vec<(Severity, String, Option<usize>)> {
+        validate(input, Options::default())
+            .await
+            .diagnostics
+            .into_iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.path, diagnostic.line))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn valid() {
+        let report = validate(
+            "
+version: v1alpha1
+filters:
+  - name: quilkin.filters.capture.v1alpha1.Capture
+    config:
+      suffix:
+        size: 3
+  - name: quilkin.filters.token_router.v1alpha1.TokenRouter
+clusters:
+  default:
+    localities:
+      - endpoints:
+        - address: 127.0.0.1:7001
+",
+            Options::default(),
+        )
+        .await;
+
+        assert!(report.valid);
+        assert!(report.diagnostics.is_empty(), "{:?}", report.diagnostics);
+    }
+
+    #[tokio::test]
+    async fn reports_every_filter() {
+        assert_eq!(
+            vec![
+                (Severity::Error, "filters[0].name".into(), Some(4)),
+                (Severity::Error, "filters[2].config".into(), Some(7)),
+                (Severity::Error, "canary.filters[0].config".into(), Some(14)),
+                (Severity::Error, "canary.filters[1]".into(), Some(18)),
+            ],
+            diagnostics(
+                "
+version: v1alpha1
+filters:
+  - name: quilkin.filters.nope.v1alpha1.Nope
+  - name: quilkin.filters.pass.v1alpha1.Pass
+  - name: quilkin.filters.firewall.v1alpha1.Firewall
+    config:
+      on_read:
+        - action: MAYBE
+      on_write: []
+canary:
+  filters:
+    - name: quilkin.filters.firewall.v1alpha1.Firewall
+      config:
+        on_read:
+          - action: MAYBE
+        on_write: []
+    - name: quilkin.filters.mirror.v1alpha1.Mirror
+      config:
+        cluster: mirror
+"
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_structure() {
+        assert_eq!(
+            vec![(
+                Severity::Error,
+                "clusters.default.localities[0].endpoints[0]".into(),
+                Some(7)
+            )],
+            diagnostics(
+                "
+version: v1alpha1
+clusters:
+  default:
+    localities:
+      - endpoints:
+        - address: 127.0.0.1:7001
+          foo: bar
+"
+            )
+            .await
+        );
+
+        assert_eq!(
+            vec![(Severity::Error, "".into(), Some(2))],
+            diagnostics("\nversion: v1alpha1\nfoo: bar\n").await
+        );
+        assert_eq!(
+            vec![(Severity::Error, "".into(), Some(4))],
+            diagnostics("\nversion: v1alpha1\nfilters: [\n").await
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_metadata_keys() {
+        assert_eq!(
+            vec![(Severity::Warning, "filters[1]".into(), Some(9))],
+            diagnostics(
+                "
+version: v1alpha1
+filters:
+  - name: quilkin.filters.capture.v1alpha1.Capture
+    config:
+      metadataKey: quilkin.dev/tokne
+      suffix:
+        size: 3
+  - name: quilkin.filters.token_router.v1alpha1.TokenRouter
+    config:
+      metadataKey: quilkin.dev/token
+"
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_metadata_graph() {
+        let report = validate(
+            "
+version: v1alpha1
+filters:
+  - name: quilkin.filters.capture.v1alpha1.Capture
+    config:
+      metadataKey: quilkin.dev/token
+      suffix:
+        size: 3
+  - name: quilkin.filters.timestamp.v1alpha1.Timestamp
+    config:
+      metadataKey: quilkin.dev/sent
+  - name: quilkin.filters.token_router.v1alpha1.TokenRouter
+    config:
+      metadataKey: quilkin.dev/token
+",
+            Options::default(),
+        )
+        .await;
+
+        let edge = |key: &str, writer: Option<&str>, reader: &str| MetadataEdge {
+            key: key.into(),
+            writer: writer.map(String::from),
+            reader: reader.into(),
+        };
+        assert_eq!(
+            vec![
+                edge("quilkin.dev/sent", None, "filters[1]"),
+                edge("quilkin.dev/token", Some("filters[0]"), "filters[2]"),
+            ],
+            report.metadata
+        );
+        assert_eq!(
+            "`filters[0]` -> `filters[2]`: metadata key `quilkin.dev/token`",
+            report.metadata[1].to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_condition_keys() {
+        assert_eq!(
+            vec![(
+                Severity::Warning,
+                "filters[1].config.on_read.branches[1].condition".into(),
+                Some(15)
+            )],
+            diagnostics(
+                "
+version: v1alpha1
+filters:
+  - name: quilkin.filters.capture.v1alpha1.Capture
+    config:
+      metadataKey: quilkin.dev/version
+      prefix:
+        size: 1
+  - name: quilkin.filters.match.v1alpha1.Match
+    config:
+      on_read:
+        branches:
+          - condition: { exists: { metadataKey: quilkin.dev/version } }
+            name: quilkin.filters.pass.v1alpha1.Pass
+          - condition:
+              not: { exists: { metadataKey: quilkin.dev/verison } }
+            name: quilkin.filters.pass.v1alpha1.Pass
+"
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_conditional_keys() {
+        let filters = |fallthrough| {
+            format!(
+                "
+version: v1alpha1
+filters:
+  - name: quilkin.filters.match.v1alpha1.Match
+    config:
+      on_read:
+        metadataKey: quilkin.dev/kind
+        branches:
+          - value: 1
+            filters:
+              - name: quilkin.filters.capture.v1alpha1.Capture
+                config:
+                  suffix:
+                    size: 3
+              - name: quilkin.filters.token_router.v1alpha1.TokenRouter
+        fallthrough:
+          name: {fallthrough}
+  - name: quilkin.filters.token_router.v1alpha1.TokenRouter
+"
+            )
+        };
+
+        // The key is written by every branch that doesn't drop the packet.
+        assert_eq!(
+            vec![(
+                Severity::Warning,
+                "filters[0].config.on_read.metadataKey".into(),
+                Some(7)
+            )],
+            diagnostics(&filters(crate::filters::Drop::NAME)).await
+        );
+
+        let report = validate(&filters(crate::filters::Pass::NAME), Options::default()).await;
+        let diagnostic = report.diagnostics.last().unwrap();
+        assert_eq!(2, report.diagnostics.len(), "{:?}", report.diagnostics);
+        assert_eq!(2, report.diagnostics.len());
+        assert_eq!("filters[1]", diagnostic.path);
+        assert_eq!(
+            "metadata key `quilkin.dev/capture` is only written by some branches of `filters[0].config.on_read`",
+            diagnostic.message
+        );
+    }
+
+    #[tokio::test]
+    async fn resolves_endpoints() {
+        let input = "
+version: v1alpha1
+clusters:
+  default:
+    localities:
+      - endpoints:
+        - address: quilkin.invalid:7001
+";
+
+        assert!(validate(input, Options::default()).await.valid);
+
+        let report = validate(
+            input,
+            Options {
+                resolve_endpoints: true,
+                ..<_>::default()
+            },
+        )
+        .await;
+        assert!(!report.valid);
+        assert_eq!(
+            "clusters.default.localities[0].endpoints[0].address",
+            report.diagnostics[0].path
+        );
+    }
+}
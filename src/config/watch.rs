@@ -35,6 +35,12 @@ impl<T: Clone> Watch<T> {
         }
     }
 
+    /// Returns a handle to the current value, which shares any changes made
+    /// to it through interior mutability.
+    pub fn load(&self) -> std::sync::Arc<T> {
+        self.value.clone()
+    }
+
     pub fn watch(&self) -> watch::Receiver<T> {
         self.watchers.subscribe()
     }
@@ -17,7 +17,12 @@
 use futures::TryStreamExt;
 use std::sync::Arc;
 
-use crate::{endpoint::Locality, Config};
+use crate::{config::history::Source, endpoint::Locality, Config};
+
+/// How long to wait for game server changes to settle before recording the
+/// clusters in the configuration's history, as a fleet scaling up or down
+/// produces an event for every game server.
+const RECORD_CLUSTERS_AFTER: std::time::Duration = std::time::Duration::from_secs(1);
 
 pub async fn watch(
     gameservers_namespace: impl AsRef<str>,
@@ -44,12 +49,31 @@ pub async fn watch(
     tokio::pin!(configmap_reflector);
     tokio::pin!(gameserver_reflector);
 
+    let mut record_clusters_at: Option<tokio::time::Instant> = None;
     loop {
-        let Some(_) = tokio::select! {
-            result = configmap_reflector.try_next() => result?,
-            result = gameserver_reflector.try_next() => result?,
-        } else {
-            break Ok(());
-        };
+        tokio::select! {
+            result = configmap_reflector.try_next() => {
+                if result?.is_none() {
+                    break Ok(());
+                }
+
+                config.history.record_filters(Source::K8s, &config.filters.load());
+            }
+            result = gameserver_reflector.try_next() => {
+                if result?.is_none() {
+                    break Ok(());
+                }
+
+                record_clusters_at.get_or_insert_with(|| {
+                    tokio::time::Instant::now() + RECORD_CLUSTERS_AFTER
+                });
+            }
+            _ = tokio::time::sleep_until(record_clusters_at.unwrap_or_else(tokio::time::Instant::now)),
+                if record_clusters_at.is_some() =>
+            {
+                record_clusters_at = None;
+                config.history.record_clusters(Source::K8s, &config.clusters.read());
+            }
+        }
     }
 }
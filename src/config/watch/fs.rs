@@ -19,7 +19,7 @@ use std::sync::Arc;
 use notify::Watcher;
 use tracing::Instrument;
 
-use crate::Config;
+use crate::{config::history::Source, Config};
 
 pub async fn watch(
     config: Arc<Config>,
@@ -42,6 +42,7 @@ pub async fn watch(
     let buf = tokio::fs::read(&path).await?;
     tracing::info!("applying initial configuration");
     config.update_from_json(serde_yaml::from_slice(&buf)?, locality.clone())?;
+    config.history.record(Source::File, &config);
     watcher.watch(&path, notify::RecursiveMode::Recursive)?;
     tracing::info!("watching file");
 
@@ -63,6 +64,7 @@ pub async fn watch(
             tracing::info!(path = %path.display(), "file changed, updating config");
             let buf = tokio::fs::read(path).await?;
             config.update_from_json(serde_yaml::from_slice(&buf)?, locality.clone())?;
+            config.history.record(Source::File, &config);
         }
     }
 
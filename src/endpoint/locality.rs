@@ -276,6 +276,11 @@ impl LocalitySet {
         self.0.values_mut()
     }
 
+    /// Keeps only the localities for which `keep` returns `true`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&LocalityEndpoints) -> bool) {
+        self.0.retain(|_, endpoints| keep(endpoints));
+    }
+
     pub fn merge(&mut self, cluster: &Self) {
         for (key, value) in &cluster.0 {
             if tracing::enabled!(tracing::Level::INFO) {
@@ -19,12 +19,13 @@
 mod chain;
 mod error;
 mod factory;
-mod metadata;
 mod read;
 mod registry;
 mod set;
 mod write;
 
+pub(crate) mod canary;
+
 pub mod capture;
 pub mod compress;
 pub mod concatenate_bytes;
@@ -34,17 +35,22 @@ pub mod firewall;
 pub mod load_balancer;
 pub mod local_rate_limit;
 pub mod r#match;
+pub mod metadata;
 pub mod metrics;
+pub mod mirror;
 pub mod pass;
+pub mod pcap;
+pub mod plugin;
 pub mod timestamp;
 pub mod token_router;
+pub mod wasm;
 
 /// Prelude containing all types and traits required to implement [`Filter`] and
 /// [`FilterFactory`].
 pub mod prelude {
     pub use super::{
-        ConvertProtoConfigError, CreateFilterArgs, CreationError, Filter, FilterError,
-        FilterInstance, ReadContext, StaticFilter, WriteContext,
+        metadata::MetadataKeys, ConvertProtoConfigError, CreateFilterArgs, CreationError,
+        DropReason, Filter, FilterError, FilterInstance, ReadContext, StaticFilter, WriteContext,
     };
 }
 
@@ -56,22 +62,26 @@ pub use self::{
     concatenate_bytes::ConcatenateBytes,
     debug::Debug,
     drop::Drop,
-    error::{ConvertProtoConfigError, CreationError, FilterError},
+    error::{ConvertProtoConfigError, CreationError, DropReason, FilterError},
     factory::{CreateFilterArgs, DynFilterFactory, FilterFactory, FilterInstance},
     firewall::Firewall,
     load_balancer::LoadBalancer,
     local_rate_limit::LocalRateLimit,
+    mirror::Mirror,
     pass::Pass,
+    pcap::Pcap,
     r#match::Match,
     read::ReadContext,
     registry::FilterRegistry,
     set::{FilterMap, FilterSet},
     timestamp::Timestamp,
     token_router::TokenRouter,
+    wasm::Wasm,
     write::WriteContext,
 };
 
-pub use self::chain::FilterChain;
+pub use self::canary::Canary;
+pub use self::chain::{set_filter_spans, set_metadata_check, FilterChain, MetadataCheck};
 
 /// Statically safe version of [`Filter`], if you're writing a Rust filter, you
 /// should implement [`StaticFilter`] in addition to [`Filter`], as
@@ -142,6 +152,20 @@ where
         Self::try_from_config(config).unwrap()
     }
 
+    /// Returns the dynamic metadata keys read and written by the filter
+    /// created from `config`. By default a filter neither reads nor writes
+    /// any keys, filters that do should override this so that
+    /// [`FilterChain::try_create`] can check them.
+    fn metadata_keys(_config: Option<&Self::Configuration>) -> metadata::MetadataKeys {
+        <_>::default()
+    }
+
+    /// Carries over any state worth keeping, such as rate limiting buckets,
+    /// from `previous`, the instance of this filter in a chain that is being
+    /// replaced. Implementations should check that `previous` was configured
+    /// compatibly before taking its state. By default no state is kept.
+    fn migrate_state(&self, _previous: &Self) {}
+
     /// Creates a new dynamic [`FilterFactory`] virtual table.
     fn factory() -> DynFilterFactory
     where
@@ -198,9 +222,10 @@ pub trait Filter: Send + Sync {
     /// [`Filter::read`] is invoked when the proxy receives data from a
     /// downstream connection on the listening port.
     ///
-    /// This function should return an `Some` if the packet processing should
-    /// proceed. If the packet should be rejected, it will return [`None`]
-    /// instead. By default, the context passes through unchanged.
+    /// This function should return `Ok` if the packet processing should
+    /// proceed. If the packet should be rejected, it should return
+    /// [`FilterError::drop`] with the reason, or a [`FilterError`] if an error
+    /// occurred. By default, the context passes through unchanged.
     async fn read(&self, _: &mut ReadContext) -> Result<(), FilterError> {
         Ok(())
     }
@@ -209,8 +234,10 @@ pub trait Filter: Send + Sync {
     /// downstream connection via the listening port after receiving it via one
     /// of the upstream Endpoints.
     ///
-    /// This function should return an `Some` if the packet processing should
-    /// proceed. If the packet should be rejected, it will return [`None`]
+    /// This function should return `Ok` if the packet processing should
+    /// proceed. If the packet should be rejected, it should return
+    /// [`FilterError::drop`] with the reason, or a [`FilterError`] if an error
+    /// occurred.
     async fn write(&self, _: &mut WriteContext) -> Result<(), FilterError> {
         Ok(())
     }
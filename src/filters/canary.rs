@@ -0,0 +1,502 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Shadow evaluation of a candidate "canary" [`FilterChain`].
+//!
+//! The canary chain is run on a copy of a sample of packets after the active
+//! chain, and the decisions of both chains are compared. Any differences are
+//! only reported through metrics and sampled logs, the canary never affects
+//! the packets actually sent.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::{
+    endpoint::EndpointAddress,
+    filters::{metrics, prelude::*, FilterChain, LocalRateLimit, Mirror, Pcap},
+    metrics::Direction,
+};
+
+/// Only one in every `LOG_SAMPLE_RATE` differences is logged, to avoid
+/// flooding the logs when a canary chain disagrees with every packet.
+const LOG_SAMPLE_RATE: u64 = 100;
+
+/// The number of packets that can be waiting to be evaluated by a canary
+/// chain, further packets are skipped until it catches up.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Filters that can't be used in a canary chain, as running them on copies
+/// of packets would have effects beyond the canary's own decisions, or
+/// interfere with the active chain.
+const SIDE_EFFECTING_FILTERS: [&str; 3] = [Mirror::NAME, Pcap::NAME, LocalRateLimit::NAME];
+
+/// A candidate filter chain, evaluated on a sample of packets alongside the
+/// active filter chain.
+#[derive(Clone, Debug, Deserialize, Serialize, schemars::JsonSchema)]
+#[serde(try_from = "Config")]
+pub struct Canary {
+    /// The fraction of packets evaluated by the canary chain, between `0.0`
+    /// and `1.0`.
+    pub fraction: f64,
+    pub filters: FilterChain,
+    /// The queue of packets evaluated by a background task, started with
+    /// the first packet.
+    #[serde(skip)]
+    #[schemars(skip)]
+    queue: OnceCell<mpsc::Sender<Job>>,
+}
+
+/// The configuration of a [`Canary`].
+#[derive(Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default = "default_fraction")]
+    fraction: f64,
+    #[serde(default)]
+    filters: Vec<crate::config::Filter>,
+}
+
+/// Default value for [`Canary::fraction`]
+fn default_fraction() -> f64 {
+    1.0
+}
+
+impl Default for Canary {
+    fn default() -> Self {
+        Self {
+            fraction: default_fraction(),
+            filters: FilterChain::default(),
+            queue: OnceCell::new(),
+        }
+    }
+}
+
+impl PartialEq for Canary {
+    fn eq(&self, other: &Self) -> bool {
+        self.fraction == other.fraction && self.filters == other.filters
+    }
+}
+
+impl TryFrom<Config> for Canary {
+    type Error = CreationError;
+
+    fn try_from(config: Config) -> Result<Self, Self::Error> {
+        if !(0.0..=1.0).contains(&config.fraction) {
+            return Err(CreationError::FieldInvalid {
+                field: "fraction".into(),
+                reason: "fraction must be between 0.0 and 1.0".into(),
+            });
+        }
+
+        if let Some(name) = side_effecting_filter(&config.filters) {
+            return Err(CreationError::FieldInvalid {
+                field: "filters".into(),
+                reason: format!("`{name}` can't be used in a canary filter chain"),
+            });
+        }
+
+        Ok(Self {
+            fraction: config.fraction,
+            filters: metrics::with_chain(metrics::CANARY_CHAIN, || {
+                FilterChain::try_create(&config.filters)
+            })?,
+            queue: OnceCell::new(),
+        })
+    }
+}
+
+impl Canary {
+    /// Returns whether the next packet should be evaluated.
+    pub(crate) fn sample(&self) -> bool {
+        self.fraction >= 1.0 || rand::random::<f64>() < self.fraction
+    }
+
+    /// Evaluates the canary chain's `read` on `ctx`, a copy of the packet
+    /// given to the active chain, in the background.
+    pub(crate) fn read(&self, ctx: ReadContext, active: Decision) {
+        self.submit(Direction::Read, Job::Read(ctx, active));
+    }
+
+    /// Evaluates the canary chain's `write` on `ctx`, a copy of the packet
+    /// given to the active chain, in the background.
+    pub(crate) fn write(&self, ctx: WriteContext, active: Decision) {
+        self.submit(Direction::Write, Job::Write(ctx, active));
+    }
+
+    fn submit(&self, direction: Direction, job: Job) {
+        let queue = self.queue.get_or_init(|| {
+            let (sender, mut receiver) = mpsc::channel(QUEUE_CAPACITY);
+            let chain = self.filters.clone();
+            // Stops once the canary is replaced, and its sender is dropped.
+            tokio::spawn(async move {
+                while let Some(job) = receiver.recv().await {
+                    match job {
+                        Job::Read(ctx, active) => read(&chain, ctx, active).await,
+                        Job::Write(ctx, active) => write(&chain, ctx, active).await,
+                    }
+                }
+            });
+            sender
+        });
+
+        if queue.try_send(job).is_err() {
+            metrics::canary_packets_skipped_total(direction).inc();
+        }
+    }
+}
+
+/// A packet waiting to be evaluated by a canary chain.
+enum Job {
+    Read(ReadContext, Decision),
+    Write(WriteContext, Decision),
+}
+
+/// Returns the name of the first filter in `filters`, including filters
+/// nested within their configuration, that can't be used in a canary chain.
+pub(crate) fn side_effecting_filter(filters: &[crate::config::Filter]) -> Option<&'static str> {
+    fn find_name(name: &str) -> Option<&'static str> {
+        SIDE_EFFECTING_FILTERS
+            .into_iter()
+            .find(|filter| *filter == name)
+    }
+
+    fn find(value: &serde_json::Value) -> Option<&'static str> {
+        match value {
+            serde_json::Value::Object(map) => map
+                .get("name")
+                .and_then(serde_json::Value::as_str)
+                .and_then(find_name)
+                .or_else(|| map.values().find_map(find)),
+            serde_json::Value::Array(values) => values.iter().find_map(find),
+            _ => None,
+        }
+    }
+
+    filters.iter().find_map(|filter| {
+        find_name(&filter.name).or_else(|| filter.config.as_ref().and_then(find))
+    })
+}
+
+/// What a filter chain decided to do with a packet.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Decision {
+    /// The packet is sent to `endpoints` with `contents`.
+    Forward {
+        endpoints: Vec<EndpointAddress>,
+        contents: Vec<u8>,
+    },
+    /// The packet was dropped, with no reason if a filter returned an error.
+    Drop(Option<DropReason>),
+}
+
+impl Decision {
+    /// The decision of a chain's `read` on `ctx`.
+    pub(crate) fn read(result: &Result<(), FilterError>, ctx: &ReadContext) -> Self {
+        Self::new(result, || {
+            let mut endpoints: Vec<_> = ctx
+                .endpoints
+                .iter()
+                .map(|endpoint| endpoint.address.clone())
+                .collect();
+            // Filters such as `LoadBalancer` may reorder endpoints without
+            // changing where the packet is sent.
+            endpoints.sort_by_key(|address| address.to_string());
+            (endpoints, &ctx.contents)
+        })
+    }
+
+    /// The decision of a chain's `write` on `ctx`.
+    pub(crate) fn write(result: &Result<(), FilterError>, ctx: &WriteContext) -> Self {
+        Self::new(result, || (vec![ctx.dest.clone()], &ctx.contents))
+    }
+
+    fn new<'ctx>(
+        result: &Result<(), FilterError>,
+        forward: impl FnOnce() -> (Vec<EndpointAddress>, &'ctx Vec<u8>),
+    ) -> Self {
+        match result {
+            Ok(()) => {
+                let (endpoints, contents) = forward();
+                Self::Forward {
+                    endpoints,
+                    contents: contents.clone(),
+                }
+            }
+            Err(error) => Self::Drop(error.drop_reason()),
+        }
+    }
+}
+
+/// A way in which the canary chain's decision differed from the active chain.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Difference {
+    /// One chain forwarded the packet while the other dropped it.
+    Decision,
+    /// Both chains dropped the packet for different reasons.
+    DropReason,
+    /// Both chains forwarded the packet to different endpoints.
+    Endpoints,
+    /// Both chains forwarded the packet with different contents.
+    Contents,
+}
+
+impl Difference {
+    /// Returns the metric label value for the difference.
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Self::Decision => "decision",
+            Self::DropReason => "drop_reason",
+            Self::Endpoints => "endpoints",
+            Self::Contents => "contents",
+        }
+    }
+
+    /// Returns every way in which `canary` differs from `active`.
+    pub(crate) fn between(active: &Decision, canary: &Decision) -> Vec<Self> {
+        match (active, canary) {
+            (
+                Decision::Forward {
+                    endpoints,
+                    contents,
+                },
+                Decision::Forward {
+                    endpoints: canary_endpoints,
+                    contents: canary_contents,
+                },
+            ) => [
+                (endpoints != canary_endpoints).then_some(Self::Endpoints),
+                (contents != canary_contents).then_some(Self::Contents),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            (Decision::Drop(reason), Decision::Drop(canary_reason)) => (reason != canary_reason)
+                .then_some(Self::DropReason)
+                .into_iter()
+                .collect(),
+            _ => vec![Self::Decision],
+        }
+    }
+}
+
+/// Runs `chain`'s `read` on `ctx`, a copy of the packet given to the active
+/// chain, and records how it differs from the `active` decision.
+pub(crate) async fn read(chain: &FilterChain, mut ctx: ReadContext, active: Decision) {
+    let result = chain.read(&mut ctx).await;
+    record(Direction::Read, &active, &Decision::read(&result, &ctx));
+}
+
+/// Runs `chain`'s `write` on `ctx`, a copy of the packet given to the active
+/// chain, and records how it differs from the `active` decision.
+pub(crate) async fn write(chain: &FilterChain, mut ctx: WriteContext, active: Decision) {
+    let result = chain.write(&mut ctx).await;
+    record(Direction::Write, &active, &Decision::write(&result, &ctx));
+}
+
+fn record(direction: Direction, active: &Decision, canary: &Decision) {
+    static DIFFERENCES: AtomicU64 = AtomicU64::new(0);
+
+    metrics::canary_packets_total(direction).inc();
+    let differences = Difference::between(active, canary);
+    if differences.is_empty() {
+        return;
+    }
+
+    for difference in &differences {
+        metrics::canary_differences_total(direction, difference.label()).inc();
+    }
+
+    if DIFFERENCES.fetch_add(1, Ordering::Relaxed) % LOG_SAMPLE_RATE == 0 {
+        tracing::info!(
+            direction = direction.label(),
+            ?differences,
+            ?active,
+            ?canary,
+            "canary filter chain disagreed with the active filter chain"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forward(endpoints: &[&str], contents: &[u8]) -> Decision {
+        Decision::Forward {
+            endpoints: endpoints.iter().map(|ep| ep.parse().unwrap()).collect(),
+            contents: contents.to_vec(),
+        }
+    }
+
+    #[test]
+    fn differences() {
+        let active = forward(&["127.0.0.1:80"], b"hello");
+
+        assert!(Difference::between(&active, &active).is_empty());
+        assert_eq!(
+            vec![Difference::Endpoints],
+            Difference::between(&active, &forward(&["127.0.0.1:81"], b"hello"))
+        );
+        assert_eq!(
+            vec![Difference::Endpoints, Difference::Contents],
+            Difference::between(&active, &forward(&[], b"abc"))
+        );
+        assert_eq!(
+            vec![Difference::Decision],
+            Difference::between(&active, &Decision::Drop(None))
+        );
+        assert_eq!(
+            vec![Difference::DropReason],
+            Difference::between(
+                &Decision::Drop(Some(DropReason::Denied)),
+                &Decision::Drop(Some(DropReason::Intentional))
+            )
+        );
+        assert!(Difference::between(
+            &Decision::Drop(Some(DropReason::Denied)),
+            &Decision::Drop(Some(DropReason::Denied))
+        )
+        .is_empty());
+    }
+
+    fn canary(yaml: &str) -> Result<Canary, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    #[test]
+    fn config() {
+        let canary = canary(
+            "
+fraction: 0.5
+filters:
+  - name: quilkin.filters.pass.v1alpha1.Pass
+",
+        )
+        .unwrap();
+        assert_eq!(0.5, canary.fraction);
+        assert_eq!(1, canary.filters.len());
+
+        assert_eq!(1.0, self::canary("filters: []").unwrap().fraction);
+        assert!(self::canary("fraction: 2.0").is_err());
+        assert!(!self::canary("fraction: 0.0").unwrap().sample());
+        assert!(self::canary("fraction: 1.0").unwrap().sample());
+    }
+
+    #[test]
+    fn rejects_side_effecting_filters() {
+        let error = canary(
+            "
+filters:
+  - name: quilkin.filters.mirror.v1alpha1.Mirror
+    config:
+      cluster: mirror
+",
+        )
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("Mirror"), "{error}");
+
+        // Including filters nested within other filters.
+        assert!(canary(
+            "
+filters:
+  - name: quilkin.filters.match.v1alpha1.Match
+    config:
+      on_read:
+        metadataKey: myapp.com/token
+        branches:
+          - value: abc
+            name: quilkin.filters.local_rate_limit.v1alpha1.LocalRateLimit
+            config:
+              max_packets: 10
+"
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn metrics_are_labelled() {
+        let canary = canary(
+            "
+filters:
+  - name: quilkin.filters.pass.v1alpha1.Pass
+    label: canary-metrics
+",
+        )
+        .unwrap();
+        let invocations = |chain| {
+            metrics::invocations_total(
+                Direction::Read,
+                crate::filters::Pass::NAME,
+                "canary-metrics",
+                chain,
+            )
+            .get()
+        };
+
+        let ctx = ReadContext::new(
+            vec![crate::endpoint::Endpoint::new(
+                "127.0.0.1:80".parse().unwrap(),
+            )],
+            "127.0.0.1:70".parse().unwrap(),
+            b"a".to_vec(),
+        );
+        let active = Decision::read(&Ok(()), &ctx);
+        canary.read(ctx, active);
+
+        // Evaluated by the background task.
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+        while invocations(metrics::CANARY_CHAIN) == 0 && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(1, invocations(metrics::CANARY_CHAIN));
+        assert_eq!(0, invocations(metrics::ACTIVE_CHAIN));
+    }
+
+    #[tokio::test]
+    async fn read_records_differences() {
+        let endpoints = vec![
+            crate::endpoint::Endpoint::new("127.0.0.1:81".parse().unwrap()),
+            crate::endpoint::Endpoint::new("127.0.0.1:80".parse().unwrap()),
+        ];
+        let ctx = ReadContext::new(endpoints, "127.0.0.1:70".parse().unwrap(), b"a".to_vec());
+        let active = Decision::read(&Ok(()), &ctx);
+        assert_eq!(forward(&["127.0.0.1:80", "127.0.0.1:81"], b"a"), active);
+
+        let chain = FilterChain::try_from(vec![crate::config::Filter {
+            name: crate::filters::Drop::NAME.into(),
+            label: None,
+            config: None,
+        }])
+        .unwrap();
+
+        let packets = metrics::canary_packets_total(Direction::Read).get();
+        let differences =
+            metrics::canary_differences_total(Direction::Read, Difference::Decision.label()).get();
+        read(&chain, ctx, active).await;
+        assert_eq!(
+            packets + 1,
+            metrics::canary_packets_total(Direction::Read).get()
+        );
+        assert_eq!(
+            differences + 1,
+            metrics::canary_differences_total(Direction::Read, Difference::Decision.label()).get()
+        );
+    }
+}
@@ -16,6 +16,7 @@
 
 mod affix;
 mod config;
+mod offset;
 mod regex;
 
 crate::include_proto!("quilkin.filters.capture.v1alpha1");
@@ -27,6 +28,7 @@ use self::quilkin::filters::capture::v1alpha1 as proto;
 pub use self::{
     affix::{Prefix, Suffix},
     config::{Config, Strategy},
+    offset::{Decode, Field, LengthPrefix, Offset, Size, Struct},
     regex::Regex,
 };
 
@@ -35,6 +37,26 @@ pub trait CaptureStrategy {
     /// Capture packet data from the contents, and optionally returns a value if
     /// anything was captured.
     fn capture(&self, contents: &mut Vec<u8>) -> Option<metadata::Value>;
+
+    /// Capture packet data from the contents into `metadata`, returning
+    /// whether anything was captured. By default the captured value is stored
+    /// under `key`, strategies which capture several values can instead store
+    /// each value under its own key.
+    fn capture_into(
+        &self,
+        contents: &mut Vec<u8>,
+        key: metadata::Key,
+        metadata: &mut metadata::DynamicMetadata,
+    ) -> bool {
+        match self.capture(contents) {
+            Some(value) => {
+                tracing::trace!(%key, %value, "captured value");
+                metadata.insert(key, value);
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 pub struct Capture {
@@ -44,12 +66,20 @@ pub struct Capture {
 }
 
 impl Capture {
-    fn new(config: Config) -> Self {
-        Self {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        config
+            .strategy
+            .validate()
+            .map_err(|reason| CreationError::FieldInvalid {
+                field: "strategy".into(),
+                reason,
+            })?;
+
+        Ok(Self {
             capture: config.strategy.into_capture(),
             is_present_key: (config.metadata_key.to_string() + "/is_present").into(),
             metadata_key: config.metadata_key,
-        }
+        })
     }
 }
 
@@ -57,15 +87,13 @@ impl Capture {
 impl Filter for Capture {
     #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
     async fn read(&self, ctx: &mut ReadContext) -> Result<(), FilterError> {
-        let capture = self.capture.capture(&mut ctx.contents);
-        ctx.metadata.insert(
-            self.is_present_key,
-            metadata::Value::Bool(capture.is_some()),
-        );
+        let captured =
+            self.capture
+                .capture_into(&mut ctx.contents, self.metadata_key, &mut ctx.metadata);
+        ctx.metadata
+            .insert(self.is_present_key, metadata::Value::Bool(captured));
 
-        if let Some(value) = capture {
-            tracing::trace!(key=%self.metadata_key, %value, "captured value");
-            ctx.metadata.insert(self.metadata_key, value);
+        if captured {
             Ok(())
         } else {
             tracing::trace!(key = %self.metadata_key, "No value captured");
@@ -80,7 +108,29 @@ impl StaticFilter for Capture {
     type BinaryConfiguration = proto::Capture;
 
     fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
-        Ok(Capture::new(Self::ensure_config_exists(config)?))
+        Capture::new(Self::ensure_config_exists(config)?)
+    }
+
+    fn metadata_keys(config: Option<&Self::Configuration>) -> MetadataKeys {
+        let Some(config) = config else {
+            return <_>::default();
+        };
+
+        let mut writes = match &config.strategy {
+            Strategy::Struct(strategy) => strategy
+                .fields
+                .iter()
+                .filter_map(|field| field.metadata_key)
+                .map(|key| key.to_string())
+                .collect(),
+            _ => vec![config.metadata_key.to_string()],
+        };
+        writes.push(format!("{}/is_present", config.metadata_key));
+
+        MetadataKeys {
+            writes,
+            ..<_>::default()
+        }
     }
 }
 
@@ -124,6 +174,54 @@ mod tests {
         assert_end_strategy(&filter, CAPTURED_BYTES.into(), false).await;
     }
 
+    #[tokio::test]
+    async fn struct_strategy() {
+        let config = serde_json::json!({
+            "struct": {
+                "remove": true,
+                "fields": [
+                    { "metadataKey": "version", "size": 1, "decode": "BIG_ENDIAN" },
+                    { "metadataKey": TOKEN_KEY, "size": { "prefix": "U8" } },
+                ]
+            }
+        });
+        let filter = Capture::from_config(Some(serde_json::from_value(config).unwrap()));
+        let mut context = ReadContext::new(
+            vec![Endpoint::new("127.0.0.1:81".parse().unwrap())],
+            "127.0.0.1:80".parse().unwrap(),
+            b"\x02\x03abchello".to_vec(),
+        );
+
+        filter.read(&mut context).await.unwrap();
+        assert_eq!(b"hello", &*context.contents);
+        assert_eq!(
+            Some(&Value::Number(2)),
+            context.metadata.get(&"version".into())
+        );
+        assert_eq!(
+            Some(&Value::Bytes(b"abc".to_vec().into())),
+            context.metadata.get(&TOKEN_KEY.into())
+        );
+        assert_eq!(
+            Some(&Value::Bool(true)),
+            context
+                .metadata
+                .get(&format!("{CAPTURED_BYTES}/is_present").into())
+        );
+    }
+
+    #[test]
+    fn invalid_offset_config() {
+        let config = serde_json::json!({
+            "offset": {
+                "offset": 2,
+                "size": 16,
+                "decode": "LITTLE_ENDIAN",
+            }
+        });
+        assert!(Capture::try_from_config(Some(serde_json::from_value(config).unwrap())).is_err());
+    }
+
     #[test]
     fn invalid_config() {
         let config = serde_json::json!({
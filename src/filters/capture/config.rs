@@ -18,7 +18,7 @@ use std::convert::TryFrom;
 
 use serde::{Deserialize, Serialize};
 
-use super::{proto, Prefix, Regex, Suffix};
+use super::{proto, Decode, Field, LengthPrefix, Offset, Prefix, Regex, Size, Struct, Suffix};
 use crate::filters::{metadata::CAPTURED_BYTES, ConvertProtoConfigError};
 
 /// Strategy to apply for acquiring a set of bytes in the UDP packet
@@ -34,6 +34,13 @@ pub enum Strategy {
     /// Look for the set of bytes at the end of the packet
     #[serde(rename = "REGEX")]
     Regex(Regex),
+    /// Look for the set of bytes at an offset from the start of the packet
+    #[serde(rename = "OFFSET")]
+    Offset(Offset),
+    /// Look for several fields from the start of the packet, each captured
+    /// into its own metadata key
+    #[serde(rename = "STRUCT")]
+    Struct(Struct),
 }
 
 impl Strategy {
@@ -42,6 +49,17 @@ impl Strategy {
             Self::Prefix(value) => Box::from(value),
             Self::Suffix(value) => Box::from(value),
             Self::Regex(value) => Box::from(value),
+            Self::Offset(value) => Box::from(value),
+            Self::Struct(value) => Box::from(value),
+        }
+    }
+
+    /// Returns an error message if the strategy's configuration is invalid.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Prefix(_) | Self::Suffix(_) | Self::Regex(_) => Ok(()),
+            Self::Offset(value) => value.validate(),
+            Self::Struct(value) => value.validate(),
         }
     }
 }
@@ -64,11 +82,24 @@ impl From<Regex> for Strategy {
     }
 }
 
+impl From<Offset> for Strategy {
+    fn from(offset: Offset) -> Self {
+        Self::Offset(offset)
+    }
+}
+
+impl From<Struct> for Strategy {
+    fn from(value: Struct) -> Self {
+        Self::Struct(value)
+    }
+}
+
 #[derive(Debug, PartialEq, schemars::JsonSchema)]
 pub struct Config {
     /// The key to use when storing the captured value in the filter context.
     /// If a match was found it is available
-    /// under `{{metadata_key}}/is_present`.
+    /// under `{{metadata_key}}/is_present`. The `struct` strategy stores each
+    /// field under the field's own key instead.
     pub metadata_key: crate::metadata::Key,
     /// The capture strategy.
     pub strategy: Strategy,
@@ -87,6 +118,8 @@ impl Serialize for Config {
             Strategy::Prefix(value) => s.serialize_field("prefix", value)?,
             Strategy::Suffix(value) => s.serialize_field("suffix", value)?,
             Strategy::Regex(value) => s.serialize_field("regex", value)?,
+            Strategy::Offset(value) => s.serialize_field("offset", value)?,
+            Strategy::Struct(value) => s.serialize_field("struct", value)?,
         }
 
         s.end()
@@ -106,6 +139,8 @@ impl<'de> serde::Deserialize<'de> for Config {
             Prefix,
             Suffix,
             Regex,
+            Offset,
+            Struct,
         }
 
         struct ConfigVisitor;
@@ -162,6 +197,22 @@ impl<'de> serde::Deserialize<'de> for Config {
 
                             strategy = Some(Strategy::Regex(map.next_value()?));
                         }
+
+                        Field::Offset => {
+                            if strategy.is_some() {
+                                return (strategy_exists_err)();
+                            }
+
+                            strategy = Some(Strategy::Offset(map.next_value()?));
+                        }
+
+                        Field::Struct => {
+                            if strategy.is_some() {
+                                return (strategy_exists_err)();
+                            }
+
+                            strategy = Some(Strategy::Struct(map.next_value()?));
+                        }
                     }
                 }
 
@@ -169,7 +220,7 @@ impl<'de> serde::Deserialize<'de> for Config {
                     .unwrap_or_else(|| crate::metadata::Key::from_static(CAPTURED_BYTES));
                 let strategy = strategy.ok_or_else(|| {
                     serde::de::Error::custom(
-                        "Capture strategy of `regex`, `suffix`, or `prefix` is required",
+                        "Capture strategy of `regex`, `suffix`, `prefix`, `offset`, or `struct` is required",
                     )
                 })?;
 
@@ -227,6 +278,25 @@ impl From<Strategy> for proto::capture::Strategy {
             Strategy::Regex(regex) => Self::Regex(proto::capture::Regex {
                 regex: Some(regex.pattern.as_str().into()),
             }),
+            Strategy::Offset(offset) => Self::Offset(proto::capture::Offset {
+                offset: offset.offset,
+                size: Some(offset.size.into()),
+                decode: proto::capture::Decode::from(offset.decode).into(),
+                remove: Some(offset.remove),
+            }),
+            Strategy::Struct(value) => Self::Struct(proto::capture::Struct {
+                fields: value
+                    .fields
+                    .into_iter()
+                    .map(|field| proto::capture::r#struct::Field {
+                        metadata_key: field.metadata_key.map(|key| key.to_string()),
+                        offset: field.offset,
+                        size: Some(field.size.into()),
+                        decode: proto::capture::Decode::from(field.decode).into(),
+                    })
+                    .collect(),
+                remove: Some(value.remove),
+            }),
         }
     }
 }
@@ -256,10 +326,130 @@ impl TryFrom<proto::capture::Strategy> for Strategy {
                     })?,
                 })
             }
+            capture::Strategy::Offset(offset) => Self::Offset(Offset {
+                offset: offset.offset,
+                decode: offset.decode().into(),
+                size: offset
+                    .size
+                    .ok_or_else(|| {
+                        ConvertProtoConfigError::new("Missing", Some("Offset.size".into()))
+                    })?
+                    .try_into()?,
+                remove: offset.remove.unwrap_or_default(),
+            }),
+            capture::Strategy::Struct(value) => Self::Struct(Struct {
+                fields: value
+                    .fields
+                    .into_iter()
+                    .map(|field| {
+                        Ok(Field {
+                            metadata_key: field.metadata_key.as_deref().map(From::from),
+                            offset: field.offset,
+                            decode: field.decode().into(),
+                            size: field
+                                .size
+                                .ok_or_else(|| {
+                                    ConvertProtoConfigError::new(
+                                        "Missing",
+                                        Some("Struct.fields.size".into()),
+                                    )
+                                })?
+                                .try_into()?,
+                        })
+                    })
+                    .collect::<Result<_, ConvertProtoConfigError>>()?,
+                remove: value.remove.unwrap_or_default(),
+            }),
         })
     }
 }
 
+impl From<Size> for proto::capture::Size {
+    fn from(size: Size) -> Self {
+        use proto::capture::size;
+
+        Self {
+            size: Some(match size {
+                Size::Fixed(size) => size::Size::Fixed(size),
+                Size::LengthPrefixed { prefix } => {
+                    size::Size::LengthPrefix(size::LengthPrefix::from(prefix).into())
+                }
+            }),
+        }
+    }
+}
+
+impl TryFrom<proto::capture::Size> for Size {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::capture::Size) -> Result<Self, Self::Error> {
+        use proto::capture::size;
+
+        match p.size {
+            Some(size::Size::Fixed(size)) => Ok(Self::Fixed(size)),
+            Some(size::Size::LengthPrefix(prefix)) => size::LengthPrefix::try_from(prefix)
+                .map(|prefix| Self::LengthPrefixed {
+                    prefix: prefix.into(),
+                })
+                .map_err(|_| {
+                    ConvertProtoConfigError::new(
+                        format!("invalid length prefix: {prefix}"),
+                        Some("size.length_prefix".into()),
+                    )
+                }),
+            None => Err(ConvertProtoConfigError::new("Missing", Some("size".into()))),
+        }
+    }
+}
+
+impl From<LengthPrefix> for proto::capture::size::LengthPrefix {
+    fn from(prefix: LengthPrefix) -> Self {
+        match prefix {
+            LengthPrefix::U8 => Self::U8,
+            LengthPrefix::U16Be => Self::U16Be,
+            LengthPrefix::U16Le => Self::U16Le,
+            LengthPrefix::U32Be => Self::U32Be,
+            LengthPrefix::U32Le => Self::U32Le,
+            LengthPrefix::Varint => Self::Varint,
+        }
+    }
+}
+
+impl From<proto::capture::size::LengthPrefix> for LengthPrefix {
+    fn from(prefix: proto::capture::size::LengthPrefix) -> Self {
+        use proto::capture::size::LengthPrefix as Proto;
+
+        match prefix {
+            Proto::U8 => Self::U8,
+            Proto::U16Be => Self::U16Be,
+            Proto::U16Le => Self::U16Le,
+            Proto::U32Be => Self::U32Be,
+            Proto::U32Le => Self::U32Le,
+            Proto::Varint => Self::Varint,
+        }
+    }
+}
+
+impl From<Option<Decode>> for proto::capture::Decode {
+    fn from(decode: Option<Decode>) -> Self {
+        match decode {
+            None => Self::Bytes,
+            Some(Decode::BigEndian) => Self::BigEndian,
+            Some(Decode::LittleEndian) => Self::LittleEndian,
+        }
+    }
+}
+
+impl From<proto::capture::Decode> for Option<Decode> {
+    fn from(decode: proto::capture::Decode) -> Self {
+        match decode {
+            proto::capture::Decode::Bytes => None,
+            proto::capture::Decode::BigEndian => Some(Decode::BigEndian),
+            proto::capture::Decode::LittleEndian => Some(Decode::LittleEndian),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -267,23 +457,69 @@ mod tests {
 
     #[test]
     fn convert_proto_config() {
-        let test_cases = vec![(
-            "should succeed when all valid values are provided",
-            proto::Capture {
-                strategy: Some(proto::capture::Strategy::Suffix(proto::capture::Suffix {
-                    size: 42,
-                    remove: Some(true),
-                })),
-                metadata_key: Some("foobar".into()),
-            },
-            Some(Config {
-                metadata_key: "foobar".into(),
-                strategy: Strategy::Suffix(Suffix {
-                    size: 42,
-                    remove: true,
+        let test_cases = vec![
+            (
+                "should succeed when all valid values are provided",
+                proto::Capture {
+                    strategy: Some(proto::capture::Strategy::Suffix(proto::capture::Suffix {
+                        size: 42,
+                        remove: Some(true),
+                    })),
+                    metadata_key: Some("foobar".into()),
+                },
+                Some(Config {
+                    metadata_key: "foobar".into(),
+                    strategy: Strategy::Suffix(Suffix {
+                        size: 42,
+                        remove: true,
+                    }),
                 }),
-            }),
-        )];
+            ),
+            (
+                "should succeed with a struct strategy",
+                proto::Capture {
+                    strategy: Some(proto::capture::Strategy::Struct(proto::capture::Struct {
+                        fields: vec![proto::capture::r#struct::Field {
+                            metadata_key: Some("token".into()),
+                            offset: 2,
+                            size: Some(proto::capture::Size {
+                                size: Some(proto::capture::size::Size::LengthPrefix(
+                                    proto::capture::size::LengthPrefix::Varint.into(),
+                                )),
+                            }),
+                            decode: proto::capture::Decode::Bytes.into(),
+                        }],
+                        remove: None,
+                    })),
+                    metadata_key: Some("foobar".into()),
+                },
+                Some(Config {
+                    metadata_key: "foobar".into(),
+                    strategy: Strategy::Struct(Struct {
+                        fields: vec![Field {
+                            metadata_key: Some("token".into()),
+                            offset: 2,
+                            size: LengthPrefix::Varint.into(),
+                            decode: None,
+                        }],
+                        remove: false,
+                    }),
+                }),
+            ),
+            (
+                "should fail when an offset is missing its size",
+                proto::Capture {
+                    strategy: Some(proto::capture::Strategy::Offset(proto::capture::Offset {
+                        offset: 4,
+                        size: None,
+                        decode: proto::capture::Decode::BigEndian.into(),
+                        remove: None,
+                    })),
+                    metadata_key: Some("foobar".into()),
+                },
+                None,
+            ),
+        ];
 
         for (name, proto_config, expected) in test_cases {
             let result = Config::try_from(proto_config);
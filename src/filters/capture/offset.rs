@@ -0,0 +1,432 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::ops::Range;
+
+use crate::metadata::{DynamicMetadata, Key, Value};
+
+/// The size of a captured value.
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, schemars::JsonSchema, serde::Serialize,
+)]
+#[serde(untagged)]
+pub enum Size {
+    /// A fixed number of bytes.
+    Fixed(u32),
+    /// A variable number of bytes, given by an integer immediately preceding
+    /// the value.
+    LengthPrefixed {
+        /// The encoding of the length prefix.
+        prefix: LengthPrefix,
+    },
+}
+
+impl From<u32> for Size {
+    fn from(size: u32) -> Self {
+        Self::Fixed(size)
+    }
+}
+
+impl From<LengthPrefix> for Size {
+    fn from(prefix: LengthPrefix) -> Self {
+        Self::LengthPrefixed { prefix }
+    }
+}
+
+/// The encoding of the length of a length-prefixed value.
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, schemars::JsonSchema, serde::Serialize,
+)]
+pub enum LengthPrefix {
+    /// A single byte.
+    #[serde(rename = "U8")]
+    U8,
+    /// A big-endian 16-bit integer.
+    #[serde(rename = "U16_BE")]
+    U16Be,
+    /// A little-endian 16-bit integer.
+    #[serde(rename = "U16_LE")]
+    U16Le,
+    /// A big-endian 32-bit integer.
+    #[serde(rename = "U32_BE")]
+    U32Be,
+    /// A little-endian 32-bit integer.
+    #[serde(rename = "U32_LE")]
+    U32Le,
+    /// An unsigned LEB128 variable length integer, as used by protobuf.
+    #[serde(rename = "VARINT")]
+    Varint,
+}
+
+impl LengthPrefix {
+    /// Reads the length from the start of `bytes`, returning the length and
+    /// the number of bytes the prefix occupies.
+    fn read(&self, bytes: &[u8]) -> Option<(usize, usize)> {
+        let fixed = |size: usize, decode: Decode| {
+            let length = decode.decode(bytes.get(..size)?)?;
+            Some((usize::try_from(length).ok()?, size))
+        };
+
+        match self {
+            Self::U8 => fixed(1, Decode::BigEndian),
+            Self::U16Be => fixed(2, Decode::BigEndian),
+            Self::U16Le => fixed(2, Decode::LittleEndian),
+            Self::U32Be => fixed(4, Decode::BigEndian),
+            Self::U32Le => fixed(4, Decode::LittleEndian),
+            Self::Varint => {
+                let mut length = 0u64;
+                for (index, byte) in bytes.iter().enumerate().take(10) {
+                    length |= u64::from(byte & 0x7F) << (7 * index);
+                    if byte & 0x80 == 0 {
+                        return Some((usize::try_from(length).ok()?, index + 1));
+                    }
+                }
+
+                None
+            }
+        }
+    }
+}
+
+/// Decodes captured bytes as an unsigned integer, instead of storing them as
+/// bytes.
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, schemars::JsonSchema, serde::Serialize,
+)]
+pub enum Decode {
+    #[serde(rename = "BIG_ENDIAN")]
+    BigEndian,
+    #[serde(rename = "LITTLE_ENDIAN")]
+    LittleEndian,
+}
+
+impl Decode {
+    /// Decodes up to eight bytes as an integer.
+    fn decode(&self, bytes: &[u8]) -> Option<u64> {
+        if bytes.len() > std::mem::size_of::<u64>() {
+            return None;
+        }
+
+        let fold = |value: u64, byte: &u8| (value << 8) | u64::from(*byte);
+        Some(match self {
+            Self::BigEndian => bytes.iter().fold(0, fold),
+            Self::LittleEndian => bytes.iter().rev().fold(0, fold),
+        })
+    }
+}
+
+/// Locates a value of `size` starting at `start`, returning the range of the
+/// whole field including any length prefix, and the range of the value.
+fn locate(contents: &[u8], start: usize, size: Size) -> Option<(Range<usize>, Range<usize>)> {
+    let (value_start, length) = match size {
+        Size::Fixed(size) => (start, size as usize),
+        Size::LengthPrefixed { prefix } => {
+            let (length, prefix_size) = prefix.read(contents.get(start..)?)?;
+            (start + prefix_size, length)
+        }
+    };
+
+    let end = value_start.checked_add(length)?;
+    (end <= contents.len()).then_some((start..end, value_start..end))
+}
+
+fn to_value(bytes: &[u8], decode: Option<Decode>) -> Option<Value> {
+    match decode {
+        Some(decode) => decode.decode(bytes).map(Value::Number),
+        None => Some(Value::Bytes(bytes::Bytes::copy_from_slice(bytes))),
+    }
+}
+
+/// Returns an error message if a value of `size` can never be decoded.
+fn validate(size: Size, decode: Option<Decode>) -> Result<(), String> {
+    match (size, decode) {
+        (Size::Fixed(size), Some(_)) if size as usize > std::mem::size_of::<u64>() => Err(format!(
+            "a size of {size} bytes is too large to decode as an integer"
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Capture a value at a fixed offset from the start of the packet.
+#[derive(Debug, Eq, PartialEq, serde::Deserialize, schemars::JsonSchema, serde::Serialize)]
+pub struct Offset {
+    /// The number of bytes from the start of the packet to the value.
+    #[serde(default)]
+    pub offset: u32,
+    /// The size of the value, either a fixed number of bytes, or a length
+    /// prefix which is also captured.
+    pub size: Size,
+    /// Decode the value as an unsigned integer of up to eight bytes, rather
+    /// than capturing the bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decode: Option<Decode>,
+    /// Whether captured bytes are removed from the original packet.
+    #[serde(default)]
+    pub remove: bool,
+}
+
+impl Offset {
+    pub(super) fn validate(&self) -> Result<(), String> {
+        validate(self.size, self.decode)
+    }
+}
+
+impl super::CaptureStrategy for Offset {
+    fn capture(&self, contents: &mut Vec<u8>) -> Option<Value> {
+        let (field, value) = locate(contents, self.offset as usize, self.size)?;
+        let value = to_value(&contents[value], self.decode)?;
+
+        if self.remove {
+            contents.drain(field);
+        }
+
+        Some(value)
+    }
+}
+
+/// A single field of a [`Struct`].
+#[derive(Debug, Eq, PartialEq, serde::Deserialize, schemars::JsonSchema, serde::Serialize)]
+pub struct Field {
+    /// The key to store the captured value under. If not set, the field is
+    /// skipped over without being captured.
+    #[serde(
+        rename = "metadataKey",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub metadata_key: Option<Key>,
+    /// The number of bytes between the end of the previous field, or the
+    /// start of the packet for the first field, and this field.
+    #[serde(default)]
+    pub offset: u32,
+    /// The size of the field, either a fixed number of bytes, or a length
+    /// prefix which is also captured.
+    pub size: Size,
+    /// Decode the field as an unsigned integer of up to eight bytes, rather
+    /// than capturing the bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decode: Option<Decode>,
+}
+
+/// Capture several consecutive fields from the start of the packet, each into
+/// its own metadata key. Either every field is captured, or none are.
+#[derive(Debug, Eq, PartialEq, serde::Deserialize, schemars::JsonSchema, serde::Serialize)]
+pub struct Struct {
+    /// The fields in the order that they appear in the packet.
+    pub fields: Vec<Field>,
+    /// Whether captured fields are removed from the original packet, any
+    /// bytes between fields are left in place.
+    #[serde(default)]
+    pub remove: bool,
+}
+
+impl Struct {
+    pub(super) fn validate(&self) -> Result<(), String> {
+        if self.fields.is_empty() {
+            return Err("at least one field is required".into());
+        }
+
+        self.fields
+            .iter()
+            .try_for_each(|field| validate(field.size, field.decode))
+    }
+
+    /// Captures every field, returning each field's value alongside its
+    /// key, or `None` if any field could not be captured.
+    fn capture_fields(&self, contents: &mut Vec<u8>) -> Option<Vec<(Key, Value)>> {
+        let mut position = 0usize;
+        let mut ranges = Vec::with_capacity(self.fields.len());
+        let mut values = Vec::with_capacity(self.fields.len());
+
+        for field in &self.fields {
+            let start = position.checked_add(field.offset as usize)?;
+            let (range, value) = locate(contents, start, field.size)?;
+            if let Some(key) = field.metadata_key {
+                values.push((key, to_value(&contents[value], field.decode)?));
+            }
+            position = range.end;
+            ranges.push(range);
+        }
+
+        if self.remove {
+            for range in ranges.into_iter().rev() {
+                contents.drain(range);
+            }
+        }
+
+        Some(values)
+    }
+}
+
+impl super::CaptureStrategy for Struct {
+    fn capture(&self, contents: &mut Vec<u8>) -> Option<Value> {
+        self.capture_fields(contents)
+            .map(|values| Value::List(values.into_iter().map(|(_, value)| value).collect()))
+    }
+
+    fn capture_into(&self, contents: &mut Vec<u8>, _: Key, metadata: &mut DynamicMetadata) -> bool {
+        let Some(values) = self.capture_fields(contents) else {
+            return false;
+        };
+
+        for (key, value) in values {
+            tracing::trace!(%key, %value, "captured value");
+            metadata.insert(key, value);
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::capture::CaptureStrategy;
+
+    #[test]
+    fn offset_fixed() {
+        let mut offset = Offset {
+            offset: 2,
+            size: Size::Fixed(3),
+            decode: None,
+            remove: false,
+        };
+        let mut contents = b"heabcllo".to_vec();
+
+        assert_eq!(
+            Some(Value::Bytes(b"abc".to_vec().into())),
+            offset.capture(&mut contents)
+        );
+        assert_eq!(b"heabcllo", &*contents);
+
+        offset.remove = true;
+        assert_eq!(
+            Some(Value::Bytes(b"abc".to_vec().into())),
+            offset.capture(&mut contents)
+        );
+        assert_eq!(b"hello", &*contents);
+
+        offset.offset = 4;
+        assert_eq!(None, offset.capture(&mut contents));
+        assert_eq!(b"hello", &*contents);
+    }
+
+    #[test]
+    fn offset_decode() {
+        let mut offset = Offset {
+            offset: 1,
+            size: Size::Fixed(2),
+            decode: Some(Decode::BigEndian),
+            remove: false,
+        };
+        let mut contents = vec![0xFF, 0x01, 0x02, 0xFF];
+
+        assert_eq!(Some(Value::Number(0x0102)), offset.capture(&mut contents));
+        offset.decode = Some(Decode::LittleEndian);
+        assert_eq!(Some(Value::Number(0x0201)), offset.capture(&mut contents));
+    }
+
+    #[test]
+    fn offset_length_prefixed() {
+        let offset = Offset {
+            offset: 1,
+            size: LengthPrefix::U16Le.into(),
+            decode: None,
+            remove: true,
+        };
+        let mut contents = vec![0xFF, 3, 0, b'a', b'b', b'c', 0xFF];
+
+        assert_eq!(
+            Some(Value::Bytes(b"abc".to_vec().into())),
+            offset.capture(&mut contents)
+        );
+        assert_eq!(vec![0xFF, 0xFF], contents);
+
+        // The prefix claims more bytes than are in the packet.
+        let mut contents = vec![0xFF, 9, 0, b'a'];
+        assert_eq!(None, offset.capture(&mut contents));
+    }
+
+    #[test]
+    fn varint_prefix() {
+        assert_eq!(Some((1, 1)), LengthPrefix::Varint.read(&[0x01, 0xFF]));
+        assert_eq!(Some((300, 2)), LengthPrefix::Varint.read(&[0xAC, 0x02]));
+        assert_eq!(None, LengthPrefix::Varint.read(&[0xAC]));
+    }
+
+    #[test]
+    fn struct_fields() {
+        let strategy = Struct {
+            fields: vec![
+                Field {
+                    metadata_key: Some("id".into()),
+                    offset: 0,
+                    size: Size::Fixed(2),
+                    decode: Some(Decode::BigEndian),
+                },
+                Field {
+                    metadata_key: None,
+                    offset: 0,
+                    size: LengthPrefix::Varint.into(),
+                    decode: None,
+                },
+                Field {
+                    metadata_key: Some("token".into()),
+                    offset: 1,
+                    size: LengthPrefix::U8.into(),
+                    decode: None,
+                },
+            ],
+            remove: true,
+        };
+
+        let mut contents = vec![0, 7, 2, b'x', b'y', 0xFF, 3, b'a', b'b', b'c', b'!'];
+        let mut metadata = DynamicMetadata::new();
+        assert!(strategy.capture_into(&mut contents, "unused".into(), &mut metadata));
+        assert_eq!(Some(&Value::Number(7)), metadata.get(&"id".into()));
+        assert_eq!(
+            Some(&Value::Bytes(b"abc".to_vec().into())),
+            metadata.get(&"token".into())
+        );
+        assert_eq!(None, metadata.get(&"unused".into()));
+        assert_eq!(vec![0xFF, b'!'], contents);
+
+        // Nothing is captured or removed if any field is missing.
+        let mut contents = vec![0, 7, 2, b'x', b'y', 0xFF, 9, b'a'];
+        let mut metadata = DynamicMetadata::new();
+        assert!(!strategy.capture_into(&mut contents, "unused".into(), &mut metadata));
+        assert!(metadata.is_empty());
+        assert_eq!(8, contents.len());
+    }
+
+    #[test]
+    fn validation() {
+        assert!(Offset {
+            offset: 0,
+            size: Size::Fixed(9),
+            decode: Some(Decode::BigEndian),
+            remove: false,
+        }
+        .validate()
+        .is_err());
+        assert!(Struct {
+            fields: vec![],
+            remove: false
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -14,87 +14,96 @@
  * limitations under the License.
  */
 
-use prometheus::{exponential_buckets, Histogram};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use enum_map::EnumMap;
+use prometheus::{Histogram, IntCounter};
+use tracing::Instrument;
 
 use crate::{
     config::Filter as FilterConfig,
-    filters::{prelude::*, FilterRegistry},
-    metrics::{histogram_opts, CollectorExt},
+    filters::{metrics, prelude::*, FilterRegistry},
+    metrics::Direction,
 };
 
-const FILTER_LABEL: &str = "filter";
-
-/// Start the histogram bucket at an eighth of a millisecond, as we bucketed the full filter
-/// chain processing starting at a quarter of a millisecond, so we we will want finer granularity
-/// here.
-const BUCKET_START: f64 = 0.000125;
-
-const BUCKET_FACTOR: f64 = 2.5;
-
-/// At an exponential factor of 2.5 (BUCKET_FACTOR), 11 iterations gets us to just over half a
-/// second. Any processing that occurs over half a second is far too long, so we end
-/// the bucketing there as we don't care about granularity past this value.
-const BUCKET_COUNT: usize = 11;
-
 /// A chain of [`Filter`]s to be executed in order.
 ///
 /// Executes each filter, passing the [`ReadContext`] and [`WriteContext`]
 /// between each filter's execution, returning the result of data that has gone
 /// through all of the filters in the chain. If any of the filters in the chain
 /// return `None`, then the chain is broken, and `None` is returned.
+///
+/// Each filter in the chain reports its own metrics, identified by the
+/// filter's name and label, and can be run within a `filter` [`tracing`]
+/// span, see [`set_filter_spans`].
 #[derive(Clone, Default)]
 pub struct FilterChain {
     filters: Vec<(String, FilterInstance)>,
-    filter_read_duration_seconds: Vec<Histogram>,
-    filter_write_duration_seconds: Vec<Histogram>,
+    read_metrics: Vec<FilterMetrics>,
+    write_metrics: Vec<FilterMetrics>,
+}
+
+/// The metrics of a single filter in a chain, in one direction.
+#[derive(Clone)]
+struct FilterMetrics {
+    duration_seconds: Histogram,
+    invocations_total: IntCounter,
+    errors_total: IntCounter,
+    packets_dropped_total: EnumMap<DropReason, IntCounter>,
+}
+
+impl FilterMetrics {
+    fn new(direction: Direction, name: &str, instance: &FilterInstance) -> Self {
+        let label = instance.label().unwrap_or_default();
+        let chain = metrics::current_chain();
+
+        Self {
+            duration_seconds: metrics::duration_seconds(direction, name, label, chain),
+            invocations_total: metrics::invocations_total(direction, name, label, chain),
+            errors_total: metrics::errors_total(direction, name, label, chain),
+            packets_dropped_total: EnumMap::from_fn(|reason: DropReason| {
+                metrics::packets_dropped_total(direction, name, label, chain, reason.label())
+            }),
+        }
+    }
+
+    /// Records the outcome of running the filter.
+    fn record(&self, result: &Result<(), FilterError>) {
+        self.invocations_total.inc();
+        match result.as_ref().map_err(FilterError::drop_reason) {
+            Ok(()) => {}
+            Err(Some(reason)) => self.packets_dropped_total[reason].inc(),
+            Err(None) => self.errors_total.inc(),
+        }
+    }
 }
 
 impl FilterChain {
     pub fn new(filters: Vec<(String, FilterInstance)>) -> Result<Self, CreationError> {
-        let subsystem = "filter";
+        let metrics = |direction| {
+            filters
+                .iter()
+                .map(|(name, instance)| FilterMetrics::new(direction, name, instance))
+                .collect()
+        };
 
         Ok(Self {
-            filter_read_duration_seconds: filters
-                .iter()
-                .map(|(name, _)| {
-                    Histogram::with_opts(
-                        histogram_opts(
-                            "read_duration_seconds",
-                            subsystem,
-                            "Seconds taken to execute a given filter's `read`.",
-                            Some(
-                                exponential_buckets(BUCKET_START, BUCKET_FACTOR, BUCKET_COUNT)
-                                    .unwrap(),
-                            ),
-                        )
-                        .const_label(FILTER_LABEL, name),
-                    )
-                    .and_then(|histogram| histogram.register_if_not_exists())
-                })
-                .collect::<Result<_, prometheus::Error>>()?,
-            filter_write_duration_seconds: filters
-                .iter()
-                .map(|(name, _)| {
-                    Histogram::with_opts(
-                        histogram_opts(
-                            "write_duration_seconds",
-                            subsystem,
-                            "Seconds taken to execute a given filter's `write`.",
-                            Some(exponential_buckets(0.000125, 2.5, 11).unwrap()),
-                        )
-                        .const_label(FILTER_LABEL, name),
-                    )
-                    .and_then(|histogram| histogram.register_if_not_exists())
-                })
-                .collect::<Result<_, prometheus::Error>>()?,
+            read_metrics: metrics(Direction::Read),
+            write_metrics: metrics(Direction::Write),
             filters,
         })
     }
 
     /// Validates the filter configurations in the provided config and constructs
     /// a FilterChain if all configurations are valid.
+    ///
+    /// Unlike the `TryFrom` implementations, which are also used for the
+    /// chains run by other filters, this also checks the metadata keys read by
+    /// each filter, see [`set_metadata_check`].
     pub fn try_create(filter_configs: &[FilterConfig]) -> Result<Self, CreationError> {
-        Self::try_from(filter_configs)
+        let chain = Self::try_from(filter_configs)?;
+        check_metadata_keys(filter_configs, metadata_check())?;
+        Ok(chain)
     }
 
     pub fn len(&self) -> usize {
@@ -117,6 +126,30 @@ impl FilterChain {
                 },
             })
     }
+
+    /// Carries over the state of the filters in `previous`, the chain this
+    /// chain is replacing, to the filters in this chain with the same name
+    /// and label, in order, for filters that support it. See
+    /// [`StaticFilter::migrate_state`].
+    pub fn migrate_state(&self, previous: &Self) {
+        let mut migrated = vec![false; previous.filters.len()];
+        for (name, instance) in &self.filters {
+            let Some(index) = previous.filters.iter().enumerate().position(
+                |(index, (previous_name, previous_instance))| {
+                    !migrated[index]
+                        && previous_name == name
+                        && previous_instance.label() == instance.label()
+                },
+            ) else {
+                continue;
+            };
+            migrated[index] = true;
+
+            if let Some(factory) = FilterRegistry::get_factory(name) {
+                factory.migrate_state(&previous.filters[index].1, instance);
+            }
+        }
+    }
 }
 
 impl std::fmt::Debug for FilterChain {
@@ -181,7 +214,8 @@ impl TryFrom<&[FilterConfig]> for FilterChain {
             let filter = FilterRegistry::get(
                 &filter_config.name,
                 CreateFilterArgs::fixed(filter_config.config.clone()),
-            )?;
+            )?
+            .with_label(filter_config.label.clone());
 
             filters.push((filter_config.name.clone(), filter));
         }
@@ -224,7 +258,7 @@ impl<'de> serde::Deserialize<'de> for FilterChain {
     fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
         let filters = <Vec<FilterConfig>>::deserialize(de)?;
 
-        Self::try_from(filters).map_err(serde::de::Error::custom)
+        Self::try_create(&filters).map_err(serde::de::Error::custom)
     }
 }
 
@@ -260,20 +294,21 @@ impl schemars::JsonSchema for FilterChain {
 #[async_trait::async_trait]
 impl Filter for FilterChain {
     async fn read(&self, ctx: &mut ReadContext) -> Result<(), FilterError> {
-        for ((id, instance), histogram) in self
-            .filters
-            .iter()
-            .zip(self.filter_read_duration_seconds.iter())
-        {
+        for ((id, instance), metrics) in self.filters.iter().zip(&self.read_metrics) {
             tracing::trace!(%id, "read filtering packet");
-            let timer = histogram.start_timer();
-            let result = instance.filter().read(ctx).await;
+            let timer = metrics.duration_seconds.start_timer();
+            let result = instance
+                .filter()
+                .read(ctx)
+                .instrument(span(id, instance, Direction::Read))
+                .await;
             timer.stop_and_record();
+            metrics.record(&result);
             match result {
                 Ok(()) => tracing::trace!(%id, "read passing packet"),
                 Err(error) => {
                     tracing::trace!(%id, "read dropping packet");
-                    return Err(error);
+                    return Err(error.with_filter(id, instance.label()));
                 }
             }
         }
@@ -282,21 +317,21 @@ impl Filter for FilterChain {
     }
 
     async fn write(&self, ctx: &mut WriteContext) -> Result<(), FilterError> {
-        for ((id, instance), histogram) in self
-            .filters
-            .iter()
-            .rev()
-            .zip(self.filter_write_duration_seconds.iter().rev())
-        {
+        for ((id, instance), metrics) in self.filters.iter().zip(&self.write_metrics).rev() {
             tracing::trace!(%id, "write filtering packet");
-            let timer = histogram.start_timer();
-            let result = instance.filter().write(ctx).await;
+            let timer = metrics.duration_seconds.start_timer();
+            let result = instance
+                .filter()
+                .write(ctx)
+                .instrument(span(id, instance, Direction::Write))
+                .await;
             timer.stop_and_record();
+            metrics.record(&result);
             match result {
                 Ok(()) => tracing::trace!(%id, "write passing packet"),
                 Err(error) => {
                     tracing::trace!(%id, "write dropping packet");
-                    return Err(error);
+                    return Err(error.with_filter(id, instance.label()));
                 }
             }
         }
@@ -305,6 +340,83 @@ impl Filter for FilterChain {
     }
 }
 
+/// Whether filters are run within their own tracing span.
+static FILTER_SPANS: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether each filter in a chain is run within a `filter` tracing span,
+/// recording the filter's name, label and direction. Spans are created for
+/// every packet, so they're disabled by default.
+pub fn set_filter_spans(enabled: bool) {
+    FILTER_SPANS.store(enabled, Ordering::Relaxed);
+}
+
+/// How [`FilterChain::try_create`] checks that every metadata key read by a
+/// filter is written by an earlier filter in the chain.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum MetadataCheck {
+    /// Keys aren't checked.
+    #[default]
+    Off,
+    /// A warning is logged for each key that may be read before it's written.
+    Warn,
+    /// Creating the chain fails if any key may be read before it's written.
+    Deny,
+}
+
+/// The [`MetadataCheck`] used when creating filter chains.
+static METADATA_CHECK: AtomicU8 = AtomicU8::new(MetadataCheck::Off as u8);
+
+/// Sets how [`FilterChain::try_create`] checks the metadata keys read by the
+/// filters in a chain. Keys aren't checked by default.
+pub fn set_metadata_check(check: MetadataCheck) {
+    METADATA_CHECK.store(check as u8, Ordering::Relaxed);
+}
+
+fn metadata_check() -> MetadataCheck {
+    match METADATA_CHECK.load(Ordering::Relaxed) {
+        1 => MetadataCheck::Warn,
+        2 => MetadataCheck::Deny,
+        _ => MetadataCheck::Off,
+    }
+}
+
+fn check_metadata_keys(
+    filter_configs: &[FilterConfig],
+    check: MetadataCheck,
+) -> Result<(), CreationError> {
+    if check == MetadataCheck::Off {
+        return Ok(());
+    }
+
+    let analysis = crate::config::validate::MetadataAnalysis::new("filters", filter_configs);
+    for (path, message) in &analysis.warnings {
+        tracing::warn!(%path, "{message}");
+    }
+
+    match analysis.warnings.into_iter().next() {
+        Some((path, message)) if check == MetadataCheck::Deny => Err(CreationError::FieldInvalid {
+            field: path,
+            reason: message,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The span a filter is run in, which is only created if enabled with
+/// [`set_filter_spans`].
+fn span(id: &str, instance: &FilterInstance, direction: Direction) -> tracing::Span {
+    if !FILTER_SPANS.load(Ordering::Relaxed) {
+        return tracing::Span::none();
+    }
+
+    tracing::info_span!(
+        "filter",
+        name = id,
+        label = instance.label(),
+        direction = direction.label()
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
@@ -316,6 +428,64 @@ mod tests {
 
     use super::*;
 
+    #[tokio::test]
+    async fn migrate_state() {
+        let rate_limit = |label: &str| config::Filter {
+            name: crate::filters::LocalRateLimit::NAME.into(),
+            label: Some(label.into()),
+            config: Some(serde_json::json!({ "max_packets": 1, "period": 60 })),
+        };
+        let read = |chain: &FilterChain| {
+            let mut context = ReadContext::new(
+                vec![Endpoint::new("127.0.0.1:80".parse().unwrap())],
+                "127.0.0.1:8080".parse().unwrap(),
+                vec![],
+            );
+            let chain = chain.clone();
+            async move { chain.read(&mut context).await.is_ok() }
+        };
+
+        let previous = FilterChain::try_create(&[rate_limit("a")]).unwrap();
+        assert!(read(&previous).await);
+
+        let next =
+            FilterChain::try_create(&[Debug::as_filter_config(None).unwrap(), rate_limit("a")])
+                .unwrap();
+        next.migrate_state(&previous);
+        assert!(!read(&next).await);
+
+        // Filters are matched by their label.
+        let next = FilterChain::try_create(&[rate_limit("b")]).unwrap();
+        next.migrate_state(&previous);
+        assert!(read(&next).await);
+    }
+
+    #[test]
+    fn check_metadata_keys() {
+        let filters = [
+            config::Filter {
+                name: crate::filters::Capture::NAME.into(),
+                label: None,
+                config: Some(serde_json::json!({
+                    "metadataKey": "quilkin.dev/tokne",
+                    "suffix": { "size": 3 },
+                })),
+            },
+            config::Filter {
+                name: crate::filters::TokenRouter::NAME.into(),
+                label: None,
+                config: None,
+            },
+        ];
+
+        assert!(super::check_metadata_keys(&filters, MetadataCheck::Warn).is_ok());
+        assert!(matches!(
+            super::check_metadata_keys(&filters, MetadataCheck::Deny),
+            Err(CreationError::FieldInvalid { field, .. }) if field == "filters[1]"
+        ));
+        assert!(super::check_metadata_keys(&filters[..1], MetadataCheck::Deny).is_ok());
+    }
+
     #[test]
     fn from_config() {
         let provider = Debug::factory();
@@ -347,6 +517,34 @@ mod tests {
         ]
     }
 
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn filter_spans() {
+        let chain = FilterChain::try_create(&[Debug::as_filter_config(None).unwrap()]).unwrap();
+        let read = || async {
+            chain
+                .read(&mut ReadContext::new(
+                    endpoints(),
+                    "127.0.0.1:70".parse().unwrap(),
+                    b"hello".to_vec(),
+                ))
+                .await
+                .unwrap();
+        };
+
+        read().await;
+        assert!(logs_contain("Read filter event"));
+        assert!(!logs_contain("filter{name="));
+
+        set_filter_spans(true);
+        read().await;
+        set_filter_spans(false);
+        assert!(logs_contain(&format!(
+            "filter{{name=\"{}\" direction=\"read\"}}",
+            Debug::NAME
+        )));
+    }
+
     #[tokio::test]
     async fn chain_single_test_filter() {
         crate::test_utils::load_test_filters();
@@ -434,6 +632,146 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn chain_drop_records_filter() {
+        let chain = FilterChain::new(vec![
+            (
+                TestFilter::NAME.into(),
+                FilterInstance::new(serde_json::json!(null), Box::new(TestFilter)),
+            ),
+            (
+                crate::filters::Drop::NAME.into(),
+                FilterInstance::new(
+                    serde_json::json!(null),
+                    Box::new(crate::filters::Drop::from_config(None)),
+                ),
+            ),
+        ])
+        .unwrap();
+
+        let mut context = ReadContext::new(
+            endpoints(),
+            "127.0.0.1:70".parse().unwrap(),
+            b"hello".to_vec(),
+        );
+
+        let error = chain.read(&mut context).await.unwrap_err();
+        assert_eq!(Some(DropReason::Intentional), error.drop_reason());
+        assert_eq!(Some(crate::filters::Drop::NAME), error.filter_name());
+    }
+
+    #[tokio::test]
+    async fn per_filter_metrics() {
+        struct ErrorFilter;
+
+        #[async_trait::async_trait]
+        impl Filter for ErrorFilter {
+            async fn read(&self, _: &mut ReadContext) -> Result<(), FilterError> {
+                Err(FilterError::new("test error"))
+            }
+        }
+
+        let pass = crate::filters::Pass::NAME;
+        let drop = crate::filters::Drop::NAME;
+        let chain = FilterChain::try_from(vec![
+            config::Filter {
+                name: pass.into(),
+                label: Some("metrics-pass".into()),
+                config: None,
+            },
+            config::Filter {
+                name: drop.into(),
+                label: Some("metrics-drop".into()),
+                config: None,
+            },
+        ])
+        .unwrap();
+        assert_eq!(Some("metrics-drop"), chain[1].1.label());
+
+        for _ in 0..2 {
+            let mut context = ReadContext::new(
+                endpoints(),
+                "127.0.0.1:70".parse().unwrap(),
+                b"hello".to_vec(),
+            );
+            assert!(chain.read(&mut context).await.is_err());
+        }
+
+        let mut context = WriteContext::new(
+            endpoints()[0].clone(),
+            "127.0.0.1:70".parse().unwrap(),
+            "127.0.0.1:80".parse().unwrap(),
+            b"hello".to_vec(),
+        );
+        assert!(chain.write(&mut context).await.is_err());
+
+        let read = Direction::Read;
+        let write = Direction::Write;
+        assert_eq!(
+            2,
+            metrics::invocations_total(read, pass, "metrics-pass", metrics::ACTIVE_CHAIN).get()
+        );
+        assert_eq!(
+            2,
+            metrics::invocations_total(read, drop, "metrics-drop", metrics::ACTIVE_CHAIN).get()
+        );
+        assert_eq!(
+            2,
+            metrics::packets_dropped_total(
+                read,
+                drop,
+                "metrics-drop",
+                metrics::ACTIVE_CHAIN,
+                "Intentional"
+            )
+            .get()
+        );
+        assert_eq!(
+            0,
+            metrics::errors_total(read, drop, "metrics-drop", metrics::ACTIVE_CHAIN).get()
+        );
+        assert_eq!(
+            2,
+            metrics::duration_seconds(read, drop, "metrics-drop", metrics::ACTIVE_CHAIN)
+                .get_sample_count()
+        );
+        // Filters are run in reverse when writing, so the packet is dropped
+        // before reaching the `Pass` filter.
+        assert_eq!(
+            1,
+            metrics::packets_dropped_total(
+                write,
+                drop,
+                "metrics-drop",
+                metrics::ACTIVE_CHAIN,
+                "Intentional"
+            )
+            .get()
+        );
+        assert_eq!(
+            0,
+            metrics::invocations_total(write, pass, "metrics-pass", metrics::ACTIVE_CHAIN).get()
+        );
+
+        let chain = FilterChain::new(vec![(
+            "ErrorFilter".into(),
+            FilterInstance::new(serde_json::Value::Null, Box::new(ErrorFilter))
+                .with_label(Some("metrics-error".into())),
+        )])
+        .unwrap();
+        let mut context = ReadContext::new(
+            endpoints(),
+            "127.0.0.1:70".parse().unwrap(),
+            b"hello".to_vec(),
+        );
+        assert!(chain.read(&mut context).await.is_err());
+        assert_eq!(
+            1,
+            metrics::errors_total(read, "ErrorFilter", "metrics-error", metrics::ACTIVE_CHAIN)
+                .get()
+        );
+    }
+
     #[test]
     fn get_configs() {
         struct TestFilter2;
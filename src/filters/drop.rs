@@ -37,12 +37,12 @@ impl Drop {
 impl Filter for Drop {
     #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
     async fn read(&self, _: &mut ReadContext) -> Result<(), FilterError> {
-        Err(FilterError::new("intentionally dropped"))
+        Err(FilterError::drop(DropReason::Intentional))
     }
 
     #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
     async fn write(&self, _: &mut WriteContext) -> Result<(), FilterError> {
-        Err(FilterError::new("intentionally dropped"))
+        Err(FilterError::drop(DropReason::Intentional))
     }
 }
 
@@ -54,6 +54,13 @@ impl StaticFilter for Drop {
     fn try_from_config(_: Option<Self::Configuration>) -> Result<Self, CreationError> {
         Ok(Drop::new())
     }
+
+    fn metadata_keys(_: Option<&Self::Configuration>) -> MetadataKeys {
+        MetadataKeys {
+            drops: true,
+            ..<_>::default()
+        }
+    }
 }
 
 /// `pass` filter's configuration.
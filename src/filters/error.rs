@@ -19,12 +19,65 @@ use prometheus::Error as MetricsError;
 #[cfg(doc)]
 use crate::filters::{Filter, FilterFactory};
 
+/// The reason a [`Filter`] intentionally dropped a packet.
+///
+/// Unlike other [`FilterError`]s, drops are an expected part of normal
+/// operation (e.g. a firewall denying a source), and are only reported through
+/// the `packets_dropped_total` metric, with the reason as a bounded label.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, enum_map::Enum)]
+pub enum DropReason {
+    /// The filter always drops packets, e.g. [`crate::filters::Drop`], or a
+    /// custom filter chose to drop the packet.
+    Intentional,
+    /// The packet was denied by an access control rule.
+    Denied,
+    /// The packet exceeded the configured rate limit.
+    RateLimited,
+    /// The packet could not be routed to any endpoint.
+    NoRoute,
+}
+
+impl DropReason {
+    /// Returns the metric label value for the reason.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Intentional => "Intentional",
+            Self::Denied => "Denied",
+            Self::RateLimited => "RateLimited",
+            Self::NoRoute => "NoRoute",
+        }
+    }
+}
+
+impl std::fmt::Display for DropReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+enum ErrorKind {
+    Drop(DropReason),
+    Error(Box<dyn std::error::Error + Send + Sync + 'static>),
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Drop(reason) => write!(f, "dropped packet: {reason}"),
+            Self::Error(error) => write!(f, "error: {error}"),
+        }
+    }
+}
+
+/// The outcome of a [`Filter`] that has stopped processing a packet, either
+/// because it was intentionally dropped (see [`FilterError::drop`]), or
+/// because an error occurred.
 #[derive(thiserror::Error)]
-#[error("{}{} error: {source}", .label.as_deref().map(|label| format!("{}:", label)).unwrap_or_default(), .name.as_deref().unwrap_or_default())]
+#[error("{}{} {kind}", .label.as_deref().map(|label| format!("{}:", label)).unwrap_or_default(), .name.as_deref().unwrap_or_default())]
 pub struct FilterError {
     name: Option<String>,
     label: Option<String>,
-    source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    kind: ErrorKind,
 }
 
 impl FilterError {
@@ -32,8 +85,53 @@ impl FilterError {
         Self {
             name: None,
             label: None,
-            source: Box::from(error.to_string()),
+            kind: ErrorKind::Error(Box::from(error.to_string())),
+        }
+    }
+
+    /// Creates a new [`FilterError`] representing an intentional drop of the
+    /// packet for the given `reason`.
+    pub fn drop(reason: DropReason) -> Self {
+        Self {
+            name: None,
+            label: None,
+            kind: ErrorKind::Drop(reason),
+        }
+    }
+
+    /// Returns the reason the packet was dropped, if this represents an
+    /// intentional drop rather than an error.
+    pub fn drop_reason(&self) -> Option<DropReason> {
+        match self.kind {
+            ErrorKind::Drop(reason) => Some(reason),
+            ErrorKind::Error(_) => None,
+        }
+    }
+
+    /// Returns whether this represents an intentional drop.
+    pub fn is_drop(&self) -> bool {
+        self.drop_reason().is_some()
+    }
+
+    /// The name of the filter that stopped processing the packet, if known.
+    pub fn filter_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The label of the filter that stopped processing the packet, if any.
+    pub fn filter_label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Records the filter responsible for the error, if one hasn't already
+    /// been recorded by a nested filter.
+    pub(crate) fn with_filter(mut self, name: &str, label: Option<&str>) -> Self {
+        if self.name.is_none() {
+            self.name = Some(name.into());
+            self.label = label.map(String::from);
         }
+
+        self
     }
 }
 
@@ -48,7 +146,7 @@ impl std::fmt::Debug for FilterError {
         f.debug_struct("FilterError")
             .field("name", &self.name)
             .field("label", &self.label)
-            .field("source", &self.source.to_string())
+            .field("kind", &self.kind.to_string())
             .finish()
     }
 }
@@ -71,6 +169,21 @@ pub enum CreationError {
     InitializeMetricsFailed(String),
     #[error("Protobuf error: {}", .0)]
     ConvertProtoConfig(ConvertProtoConfigError),
+    #[error("failed to load plugin `{}`: {}", path, reason)]
+    PluginLoadFailed { path: String, reason: String },
+    #[error(
+        "plugin `{}` was built for plugin ABI version {}, expected version {}",
+        path,
+        actual,
+        expected
+    )]
+    PluginAbiMismatch {
+        path: String,
+        expected: u32,
+        actual: u32,
+    },
+    #[error("plugin filter `{}` failed: {}", name, reason)]
+    PluginFailed { name: String, reason: String },
     #[error("Infallible! This should never occur")]
     Infallible,
 }
@@ -14,11 +14,11 @@
  * limitations under the License.
  */
 
-use std::sync::Arc;
+use std::{any::Any, sync::Arc};
 
 use crate::{
     config::ConfigType,
-    filters::{CreationError, Filter, StaticFilter},
+    filters::{metadata::MetadataKeys, CreationError, Filter, StaticFilter},
 };
 
 /// An owned pointer to a dynamic [`FilterFactory`] instance.
@@ -27,37 +27,72 @@ pub type DynFilterFactory = Box<dyn FilterFactory>;
 /// The value returned by [`FilterFactory::create_filter`].
 #[derive(Clone)]
 #[non_exhaustive]
-pub struct FilterInstance(Arc<FilterInstanceData>);
+pub struct FilterInstance {
+    data: Arc<FilterInstanceData>,
+    /// The label given to the filter in its configuration.
+    label: Option<Arc<str>>,
+}
 
 struct FilterInstanceData {
     /// The configuration used to create the filter.
     pub config: serde_json::Value,
-    /// The configuration used to create the filter.
-    pub label: Option<String>,
     /// The created filter.
-    pub filter: Box<dyn Filter>,
+    pub filter: Arc<dyn Filter>,
+    /// The created filter, if its type is known, so that it can be
+    /// downcast.
+    pub any: Option<Arc<dyn Any + Send + Sync>>,
 }
 
 impl FilterInstance {
     /// Constructs a [`FilterInstance`].
     pub fn new(config: serde_json::Value, filter: Box<dyn Filter>) -> Self {
-        Self(Arc::new(FilterInstanceData {
-            config,
+        Self {
+            data: Arc::new(FilterInstanceData {
+                config,
+                filter: Arc::from(filter),
+                any: None,
+            }),
+            label: None,
+        }
+    }
+
+    /// Constructs a [`FilterInstance`] that can be downcast to `F` with
+    /// [`FilterInstance::downcast`].
+    pub fn from_static<F: Filter + 'static>(config: serde_json::Value, filter: F) -> Self {
+        let filter = Arc::new(filter);
+        Self {
+            data: Arc::new(FilterInstanceData {
+                config,
+                filter: filter.clone(),
+                any: Some(filter),
+            }),
             label: None,
-            filter,
-        }))
+        }
+    }
+
+    /// Sets the label identifying this instance of the filter, from
+    /// [`crate::config::Filter::label`].
+    pub fn with_label(mut self, label: Option<String>) -> Self {
+        self.label = label.map(Arc::from);
+        self
     }
 
     pub fn config(&self) -> &serde_json::Value {
-        &self.0.config
+        &self.data.config
     }
 
     pub fn label(&self) -> Option<&str> {
-        self.0.label.as_deref()
+        self.label.as_deref()
     }
 
     pub fn filter(&self) -> &dyn Filter {
-        &*self.0.filter
+        &*self.data.filter
+    }
+
+    /// Returns the filter as an `F`, if it was created as one with
+    /// [`FilterInstance::from_static`].
+    pub fn downcast<F: Filter + 'static>(&self) -> Option<&F> {
+        self.data.any.as_deref()?.downcast_ref()
     }
 }
 
@@ -82,6 +117,17 @@ pub trait FilterFactory: Sync + Send {
     /// Returns a filter based on the provided arguments.
     fn create_filter(&self, args: CreateFilterArgs) -> Result<FilterInstance, CreationError>;
 
+    /// Returns the dynamic metadata keys read and written by the filter
+    /// created from `config`, which by default may be any key.
+    fn metadata_keys(&self, _config: Option<&serde_json::Value>) -> MetadataKeys {
+        MetadataKeys::unknown()
+    }
+
+    /// Carries over any state, such as rate limiting buckets, from `previous`
+    /// to `next`, both created by this factory, when `next` replaces
+    /// `previous` in an updated filter chain. By default no state is kept.
+    fn migrate_state(&self, _previous: &FilterInstance, _next: &FilterInstance) {}
+
     /// Converts YAML configuration into its Protobuf equivalvent.
     fn encode_config_to_protobuf(
         &self,
@@ -126,12 +172,26 @@ where
             (serde_json::Value::Null, None)
         };
 
-        Ok(FilterInstance::new(
+        Ok(FilterInstance::from_static(
             config_json,
-            Box::from(F::try_from_config(config)?),
+            F::try_from_config(config)?,
         ))
     }
 
+    fn migrate_state(&self, previous: &FilterInstance, next: &FilterInstance) {
+        if let (Some(previous), Some(next)) = (previous.downcast::<F>(), next.downcast::<F>()) {
+            next.migrate_state(previous);
+        }
+    }
+
+    fn metadata_keys(&self, config: Option<&serde_json::Value>) -> MetadataKeys {
+        // Invalid configurations are reported when creating the filter.
+        match config.cloned().map(serde_json::from_value).transpose() {
+            Ok(config) => F::metadata_keys(config.as_ref()),
+            Err(_) => <_>::default(),
+        }
+    }
+
     fn encode_config_to_protobuf(
         &self,
         config: serde_json::Value,
@@ -67,7 +67,7 @@ impl Filter for Firewall {
                     }
                     Action::Deny => {
                         debug!(action = "Deny", event = "read", source = ?ctx.source);
-                        Err(FilterError::new(PacketDenied))
+                        Err(FilterError::drop(DropReason::Denied))
                     }
                 };
             }
@@ -77,7 +77,7 @@ impl Filter for Firewall {
             event = "read",
             source = ?ctx.source.to_string()
         );
-        Err(FilterError::new(PacketDenied))
+        Err(FilterError::drop(DropReason::Denied))
     }
 
     #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
@@ -95,7 +95,7 @@ impl Filter for Firewall {
                     }
                     Action::Deny => {
                         debug!(action = "Deny", event = "write", source = ?ctx.source);
-                        Err(FilterError::new(PacketDenied))
+                        Err(FilterError::drop(DropReason::Denied))
                     }
                 };
             }
@@ -106,14 +106,26 @@ impl Filter for Firewall {
             event = "write",
             source = ?ctx.source.to_string()
         );
-        Err(FilterError::new(PacketDenied))
+        Err(FilterError::drop(DropReason::Denied))
     }
 }
 
-#[derive(thiserror::Error, Debug)]
-#[error("packet denied")]
+/// The error previously returned when a packet was denied, which is now
+/// reported as a drop with [`DropReason::Denied`] instead.
+#[deprecated(note = "denied packets are reported as `DropReason::Denied` drops")]
+#[derive(Debug)]
 pub struct PacketDenied;
 
+#[allow(deprecated)]
+impl std::fmt::Display for PacketDenied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("packet denied")
+    }
+}
+
+#[allow(deprecated)]
+impl std::error::Error for PacketDenied {}
+
 #[cfg(test)]
 mod tests {
     use std::net::Ipv4Addr;
@@ -152,7 +164,10 @@ mod tests {
         assert!(logs_contain("quilkin::filters::firewall")); // the given name to the the logger by tracing
         assert!(logs_contain("Allow"));
 
-        assert!(firewall.read(&mut ctx).await.is_err());
+        assert_eq!(
+            Some(DropReason::Denied),
+            firewall.read(&mut ctx).await.unwrap_err().drop_reason()
+        );
     }
 
     #[tokio::test]
@@ -183,6 +198,9 @@ mod tests {
             local_addr,
             vec![],
         );
-        assert!(firewall.write(&mut ctx).await.is_err());
+        assert_eq!(
+            Some(DropReason::Denied),
+            firewall.write(&mut ctx).await.unwrap_err().drop_reason()
+        );
     }
 }
@@ -142,6 +142,16 @@ impl PortRange {
         }))
     }
 
+    /// Returns the inclusive lower bound of the range.
+    pub fn min(&self) -> u16 {
+        self.0.start
+    }
+
+    /// Returns the exclusive upper bound of the range.
+    pub fn max(&self) -> u16 {
+        self.0.end
+    }
+
     /// Returns true if the range contain the given `port`.
     pub fn contains(&self, port: &u16) -> bool {
         self.0.contains(port)
@@ -27,6 +27,7 @@ pub use config::{Config, Policy};
 
 /// Balances packets over the upstream endpoints.
 pub struct LoadBalancer {
+    policy: Policy,
     endpoint_chooser: Box<dyn EndpointChooser>,
 }
 
@@ -34,6 +35,7 @@ impl LoadBalancer {
     fn new(config: Config) -> Self {
         Self {
             endpoint_chooser: config.policy.as_endpoint_chooser(),
+            policy: config.policy,
         }
     }
 }
@@ -54,6 +56,16 @@ impl StaticFilter for LoadBalancer {
     fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
         Ok(LoadBalancer::new(Self::ensure_config_exists(config)?))
     }
+
+    /// Continues from the previous filter's position when both use the same
+    /// policy, so that updating the configuration doesn't send the next
+    /// packets to the first endpoint again.
+    fn migrate_state(&self, previous: &Self) {
+        if self.policy == previous.policy {
+            self.endpoint_chooser
+                .migrate_state(&*previous.endpoint_chooser);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -83,6 +95,33 @@ mod tests {
             .collect::<Vec<_>>()
     }
 
+    #[tokio::test]
+    async fn migrate_state() {
+        let addresses: Vec<EndpointAddress> =
+            vec![([127, 0, 0, 1], 8080).into(), ([127, 0, 0, 2], 8080).into()];
+        let source: EndpointAddress = ([127, 0, 0, 1], 9000).into();
+        let round_robin =
+            || LoadBalancer::from_config(serde_yaml::from_str("policy: ROUND_ROBIN").unwrap());
+
+        let previous = round_robin();
+        get_response_addresses(&previous, &addresses, source.clone()).await;
+
+        let next = round_robin();
+        next.migrate_state(&previous);
+        assert_eq!(
+            vec![addresses[1].clone()],
+            get_response_addresses(&next, &addresses, source.clone()).await
+        );
+
+        let random = LoadBalancer::from_config(serde_yaml::from_str("policy: RANDOM").unwrap());
+        let next = round_robin();
+        next.migrate_state(&random);
+        assert_eq!(
+            vec![addresses[0].clone()],
+            get_response_addresses(&next, &addresses, source).await
+        );
+    }
+
     #[tokio::test]
     async fn round_robin_load_balancer_policy() {
         let addresses: Vec<EndpointAddress> = vec![
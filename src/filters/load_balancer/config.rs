@@ -52,7 +52,7 @@ impl From<proto::LoadBalancer> for Config {
 
 /// Policy represents how a [`load_balancer`][super] distributes
 /// packets across endpoints.
-#[derive(Debug, Default, Deserialize, Serialize, Eq, PartialEq, JsonSchema)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq, JsonSchema)]
 pub enum Policy {
     /// Send packets to endpoints in turns.
     #[serde(rename = "ROUND_ROBIN")]
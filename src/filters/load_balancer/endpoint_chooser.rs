@@ -29,6 +29,15 @@ use crate::filters::ReadContext;
 pub trait EndpointChooser: Send + Sync {
     /// choose_endpoints asks for the next endpoint(s) to use.
     fn choose_endpoints(&self, endpoints: &mut ReadContext);
+
+    /// The position of the next endpoint, for choosers that keep one.
+    fn position(&self) -> Option<usize> {
+        None
+    }
+
+    /// Continues from the position of `previous`, a chooser of the same
+    /// kind, for choosers that keep one.
+    fn migrate_state(&self, _previous: &dyn EndpointChooser) {}
 }
 
 /// RoundRobinEndpointChooser chooses endpoints in round-robin order.
@@ -50,6 +59,16 @@ impl EndpointChooser for RoundRobinEndpointChooser {
         // Note: The index is guaranteed to be in range.
         ctx.endpoints = vec![ctx.endpoints[count % ctx.endpoints.len()].clone()];
     }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.next_endpoint.load(Ordering::Relaxed))
+    }
+
+    fn migrate_state(&self, previous: &dyn EndpointChooser) {
+        if let Some(position) = previous.position() {
+            self.next_endpoint.store(position, Ordering::Relaxed);
+        }
+    }
 }
 
 /// RandomEndpointChooser chooses endpoints in random order.
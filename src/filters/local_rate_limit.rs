@@ -150,7 +150,7 @@ impl LocalRateLimit {
 impl Filter for LocalRateLimit {
     async fn read(&self, ctx: &mut ReadContext) -> Result<(), FilterError> {
         self.acquire_token(&ctx.source)
-            .ok_or_else(|| FilterError::new("rate limit exceeded"))
+            .ok_or_else(|| FilterError::drop(DropReason::RateLimited))
     }
 }
 
@@ -162,6 +162,35 @@ impl StaticFilter for LocalRateLimit {
     fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
         Self::new(Self::ensure_config_exists(config)?)
     }
+
+    /// Keeps the bucket of each source, so that updating the configuration
+    /// doesn't reset every rate limit. Buckets are only kept if they count
+    /// packets over the same period, and share their counter with the
+    /// previous filter, which may still be processing packets.
+    fn migrate_state(&self, previous: &Self) {
+        if self.config.period != previous.config.period {
+            return;
+        }
+
+        let now_secs = self.state.now_relative_secs();
+        let previous_now_secs = previous.state.now_relative_secs();
+        for entry in previous.state.iter() {
+            let bucket = &entry.value().value;
+            // Each map's clock may have a different base, so the window is
+            // moved by how long ago it started.
+            let elapsed_secs = previous_now_secs
+                .saturating_sub(bucket.window_start_time_secs.load(Ordering::Relaxed));
+            self.state.insert(
+                entry.key().clone(),
+                Bucket {
+                    counter: bucket.counter.clone(),
+                    window_start_time_secs: Arc::new(AtomicU64::new(
+                        now_secs.saturating_sub(elapsed_secs),
+                    )),
+                },
+            );
+        }
+    }
 }
 
 /// Config represents a [self]'s configuration.
@@ -233,10 +262,39 @@ mod tests {
             result.unwrap();
             assert_eq!(context.contents, vec![9]);
         } else {
-            assert!(result.is_err());
+            assert_eq!(
+                Some(DropReason::RateLimited),
+                result.unwrap_err().drop_reason()
+            );
         }
     }
 
+    #[tokio::test]
+    async fn migrate_state() {
+        let config = || Config {
+            max_packets: 2,
+            period: 60,
+        };
+        let (address, other) = address_pair();
+
+        let previous = rate_limiter(config());
+        read(&previous, &address, true).await;
+        read(&previous, &address, true).await;
+
+        let next = rate_limiter(config());
+        next.migrate_state(&previous);
+        read(&next, &address, false).await;
+        read(&next, &other, true).await;
+
+        // Buckets over a different period aren't kept.
+        let next = rate_limiter(Config {
+            max_packets: 2,
+            period: 1,
+        });
+        next.migrate_state(&previous);
+        read(&next, &address, true).await;
+    }
+
     #[tokio::test]
     async fn config_minimum_period() {
         let factory = LocalRateLimit::factory();
@@ -14,49 +14,75 @@
  * limitations under the License.
  */
 
+mod condition;
 mod config;
 mod metrics;
 
-use crate::{filters::prelude::*, metadata, xds as envoy};
+use crate::{
+    filters::{prelude::*, FilterChain},
+    metadata, xds as envoy,
+};
 
 use self::{metrics::Metrics, quilkin::filters::matches::v1alpha1 as proto};
 
-pub use self::config::{Branch, Config, DirectionalConfig, Fallthrough};
+pub use self::{
+    condition::{Condition, Pattern},
+    config::{Branch, Config, DirectionalConfig, Fallthrough, Filters},
+};
 
 crate::include_proto!("quilkin.filters.matches.v1alpha1");
 
 struct ConfigInstance {
-    metadata_key: metadata::Key,
-    branches: Vec<(metadata::Value, (metadata::Key, FilterInstance))>,
-    fallthrough: (metadata::Key, FilterInstance),
+    metadata_key: Option<metadata::Key>,
+    branches: Vec<(Condition, FilterChain)>,
+    fallthrough: FilterChain,
 }
 
 impl ConfigInstance {
     fn new(config: config::DirectionalConfig) -> Result<Self, CreationError> {
-        let map_to_instance =
-            |filter: String, config_type: Option<serde_json::Value>| -> Result<_, CreationError> {
-                let instance = crate::filters::FilterRegistry::get(
-                    &filter,
-                    CreateFilterArgs::new(config_type.map(From::from)),
-                )?;
-                Ok((filter.into(), instance))
-            };
-
+        // The directional key is only required to be present when a branch
+        // compares against it with `value`, conditions handle missing keys
+        // themselves.
+        let metadata_key = config
+            .metadata_key
+            .filter(|_| config.branches.iter().any(|branch| branch.value.is_some()));
         let branches = config
             .branches
             .into_iter()
             .map(|branch| {
-                map_to_instance(branch.filter.name, branch.filter.config.clone())
-                    .map(|instance| (branch.value.clone(), instance))
+                let mut condition = match (branch.value, branch.condition) {
+                    (Some(value), None) => Condition::Equals {
+                        metadata_key: config.metadata_key,
+                        value,
+                    },
+                    (None, Some(condition)) => condition,
+                    _ => {
+                        return Err(CreationError::FieldInvalid {
+                            field: "branches".into(),
+                            reason: "each branch requires exactly one of `value` or `condition`"
+                                .into(),
+                        })
+                    }
+                };
+                condition.resolve(config.metadata_key)?;
+
+                FilterChain::try_from(branch.filter.as_slice()).map(|chain| (condition, chain))
             })
             .collect::<Result<_, _>>()?;
 
         Ok(Self {
-            metadata_key: config.metadata_key,
+            metadata_key,
             branches,
-            fallthrough: map_to_instance(config.fallthrough.0.name, config.fallthrough.0.config)?,
+            fallthrough: FilterChain::try_from(config.fallthrough.0.as_slice())?,
         })
     }
+
+    fn migrate_state(&self, previous: &Self) {
+        for ((_, chain), (_, previous)) in self.branches.iter().zip(&previous.branches) {
+            chain.migrate_state(previous);
+        }
+        self.fallthrough.migrate_state(&previous.fallthrough);
+    }
 }
 
 pub struct Match {
@@ -86,35 +112,40 @@ async fn match_filter<'config, 'ctx, Ctx, F>(
     config: &'config Option<ConfigInstance>,
     metrics: &'config Metrics,
     ctx: &'ctx mut Ctx,
-    get_metadata: impl for<'value> Fn(
-        &'value Ctx,
-        &'config metadata::Key,
-    ) -> Option<&'value metadata::Value>,
-    and_then: impl Fn(&'ctx mut Ctx, &'config FilterInstance) -> F,
+    get_metadata: impl for<'value> Fn(&'value Ctx) -> &'value metadata::DynamicMetadata,
+    and_then: impl Fn(&'ctx mut Ctx, &'config FilterChain) -> F,
 ) -> Result<(), FilterError>
 where
     F: std::future::Future<Output = Result<(), FilterError>>,
 {
     match config {
         Some(config) => {
-            let value = (get_metadata)(ctx, &config.metadata_key).ok_or_else(|| {
-                FilterError::new(format!("no metadata found for {}", config.metadata_key))
-            })?;
+            let metadata = (get_metadata)(ctx);
+            if let Some(key) = &config.metadata_key {
+                if !metadata.contains_key(key) {
+                    return Err(FilterError::new(format!("no metadata found for {key}")));
+                }
+            }
 
-            match config.branches.iter().find(|(key, _)| key == value) {
-                Some((value, instance)) => {
-                    tracing::trace!(key=%config.metadata_key, %value, filter=%instance.0, "Matched against branch");
+            match config
+                .branches
+                .iter()
+                .enumerate()
+                .find(|(_, (condition, _))| condition.matches(metadata))
+            {
+                Some((index, (_, chain))) => {
+                    tracing::trace!(branch = index, filters = ?chain, "Matched against branch");
                     metrics.packets_matched_total.inc();
-                    (and_then)(ctx, &instance.1).await
+                    (and_then)(ctx, chain).await
                 }
                 None => {
                     tracing::trace!(
-                        key = %config.metadata_key,
-                        fallthrough = %config.fallthrough.0,
+                        key = ?config.metadata_key,
+                        fallthrough = ?config.fallthrough,
                         "No match found, calling fallthrough"
                     );
                     metrics.packets_fallthrough_total.inc();
-                    (and_then)(ctx, &config.fallthrough.1).await
+                    (and_then)(ctx, &config.fallthrough).await
                 }
             }
         }
@@ -131,8 +162,8 @@ impl Filter for Match {
             &self.on_read_filters,
             &self.metrics,
             ctx,
-            |ctx, metadata_key| ctx.metadata.get(metadata_key),
-            |ctx, instance| instance.filter().read(ctx),
+            |ctx| &ctx.metadata,
+            |ctx, chain| chain.read(ctx),
         )
         .await
     }
@@ -143,8 +174,8 @@ impl Filter for Match {
             &self.on_write_filters,
             &self.metrics,
             ctx,
-            |ctx, metadata_key| ctx.metadata.get(metadata_key),
-            |ctx, instance| instance.filter().write(ctx),
+            |ctx| &ctx.metadata,
+            |ctx, chain| chain.write(ctx),
         )
         .await
     }
@@ -158,6 +189,19 @@ impl StaticFilter for Match {
     fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
         Self::new(Self::ensure_config_exists(config)?, Metrics::new())
     }
+
+    /// Migrates the state of the filters run by each branch, and the
+    /// fallthrough, to those at the same position in this filter.
+    fn migrate_state(&self, previous: &Self) {
+        for (config, previous) in [
+            (&self.on_read_filters, &previous.on_read_filters),
+            (&self.on_write_filters, &previous.on_write_filters),
+        ] {
+            if let (Some(config), Some(previous)) = (config, previous) {
+                config.migrate_state(previous);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -172,10 +216,11 @@ mod tests {
         let key = crate::metadata::Key::from_static("myapp.com/token");
         let config = Config {
             on_read: Some(DirectionalConfig {
-                metadata_key: key,
+                metadata_key: Some(key),
                 branches: vec![Branch {
-                    value: "abc".into(),
-                    filter: Pass::as_filter_config(None).unwrap(),
+                    value: Some("abc".into()),
+                    condition: None,
+                    filter: Pass::as_filter_config(None).unwrap().into(),
                 }],
                 fallthrough: <_>::default(),
             }),
@@ -223,4 +268,189 @@ mod tests {
         assert_eq!(1, filter.metrics.packets_matched_total.get());
         assert_eq!(1, filter.metrics.packets_fallthrough_total.get());
     }
+
+    #[tokio::test]
+    async fn conditions() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "on_read": {
+                "branches": [
+                    {
+                        "condition": { "all": [
+                            { "prefix": { "metadataKey": "myapp.com/token", "value": "abc" } },
+                            { "range": { "metadataKey": "myapp.com/version", "min": 2 } },
+                        ]},
+                        "name": Pass::NAME,
+                    },
+                    {
+                        "condition": { "exists": { "metadataKey": "myapp.com/token" } },
+                        "name": Drop::NAME,
+                    },
+                ],
+                "fallthrough": { "name": Pass::NAME },
+            }
+        }))
+        .unwrap();
+        // Unregistered counters, so as not to interfere with the `metrics` test.
+        let metrics = Metrics {
+            packets_matched_total: prometheus::IntCounter::new("matched", "matched").unwrap(),
+            packets_fallthrough_total: prometheus::IntCounter::new("fallthrough", "fallthrough")
+                .unwrap(),
+        };
+        let filter = Match::new(config, metrics).unwrap();
+
+        let ctx = |token: &str, version: u64| {
+            let mut ctx = ReadContext::new(
+                vec![Default::default()],
+                ([127, 0, 0, 1], 7000).into(),
+                b"hello".to_vec(),
+            );
+            ctx.metadata
+                .insert("myapp.com/token".into(), token.to_owned().into());
+            ctx.metadata
+                .insert("myapp.com/version".into(), version.into());
+            ctx
+        };
+
+        filter.read(&mut ctx("abcdef", 3)).await.unwrap();
+        assert!(filter
+            .read(&mut ctx("abcdef", 1))
+            .await
+            .unwrap_err()
+            .is_drop());
+        assert!(filter.read(&mut ctx("xyz", 3)).await.unwrap_err().is_drop());
+        filter
+            .read(&mut ReadContext::new(
+                vec![Default::default()],
+                ([127, 0, 0, 1], 7000).into(),
+                b"hello".to_vec(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(1, filter.metrics.packets_fallthrough_total.get());
+    }
+
+    #[tokio::test]
+    async fn conditions_without_directional_key() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "on_read": {
+                "metadataKey": "myapp.com/token",
+                "branches": [
+                    {
+                        "condition": { "exists": { "metadataKey": "myapp.com/other" } },
+                        "name": Drop::NAME,
+                    },
+                ],
+                "fallthrough": { "name": Pass::NAME },
+            }
+        }))
+        .unwrap();
+        let metrics = Metrics {
+            packets_matched_total: prometheus::IntCounter::new("matched", "matched").unwrap(),
+            packets_fallthrough_total: prometheus::IntCounter::new("fallthrough", "fallthrough")
+                .unwrap(),
+        };
+        let filter = Match::new(config, metrics).unwrap();
+
+        let mut ctx = ReadContext::new(
+            vec![Default::default()],
+            ([127, 0, 0, 1], 7000).into(),
+            b"hello".to_vec(),
+        );
+        filter.read(&mut ctx).await.unwrap();
+        assert_eq!(1, filter.metrics.packets_fallthrough_total.get());
+
+        ctx.metadata
+            .insert("myapp.com/other".into(), "abc".to_owned().into());
+        assert!(filter.read(&mut ctx).await.unwrap_err().is_drop());
+    }
+
+    #[test]
+    fn invalid_branches() {
+        let config = |branch| {
+            serde_json::from_value::<Config>(serde_json::json!({
+                "on_read": { "branches": [branch] }
+            }))
+            .unwrap()
+        };
+
+        // `value` without a `metadataKey` to compare against.
+        assert!(Match::try_from_config(Some(config(serde_json::json!({
+            "value": "abc",
+            "name": Pass::NAME,
+        }))))
+        .is_err());
+        // Both `value` and `condition`.
+        assert!(Match::try_from_config(Some(config(serde_json::json!({
+            "value": "abc",
+            "condition": { "exists": { "metadataKey": "myapp.com/token" } },
+            "name": Pass::NAME,
+        }))))
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn nested_chains() {
+        let config = serde_yaml::from_str::<Vec<crate::config::Filter>>(
+            "
+- name: quilkin.filters.capture.v1alpha1.Capture
+  config:
+    metadataKey: myapp.com/kind
+    prefix:
+      size: 1
+      remove: true
+- name: quilkin.filters.match.v1alpha1.Match
+  config:
+    on_read:
+      metadataKey: myapp.com/kind
+      branches:
+        - value: a
+          filters:
+            - name: quilkin.filters.concatenate_bytes.v1alpha1.ConcatenateBytes
+              config:
+                on_read: APPEND
+                on_write: DO_NOTHING
+                bytes: Yg== # b
+            - name: quilkin.filters.concatenate_bytes.v1alpha1.ConcatenateBytes
+              config:
+                on_read: APPEND
+                on_write: DO_NOTHING
+                bytes: Yw== # c
+      fallthrough:
+        filters:
+          - name: quilkin.filters.concatenate_bytes.v1alpha1.ConcatenateBytes
+            config:
+              on_read: APPEND
+              on_write: DO_NOTHING
+              bytes: eg== # z
+",
+        )
+        .unwrap();
+        let chain = FilterChain::try_from(config).unwrap();
+
+        let read = |contents: &[u8]| {
+            let mut ctx = ReadContext::new(
+                vec![Default::default()],
+                ([127, 0, 0, 1], 7000).into(),
+                contents.to_vec(),
+            );
+            let chain = &chain;
+            async move {
+                chain.read(&mut ctx).await.unwrap();
+                ctx.contents
+            }
+        };
+
+        assert_eq!(b"hellobc", &*read(b"ahello").await);
+        assert_eq!(b"helloz", &*read(b"xhello").await);
+
+        // The nested chains survive the conversion to and from xDS.
+        let listener = envoy::config::listener::v3::FilterChain::try_from(&chain).unwrap();
+        let filters = listener
+            .filters
+            .into_iter()
+            .map(crate::config::Filter::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(chain, FilterChain::try_from(filters).unwrap());
+    }
 }
@@ -0,0 +1,546 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use super::proto;
+use crate::{
+    filters::{ConvertProtoConfigError, CreationError},
+    metadata::{DynamicMetadata, Key, Value},
+};
+
+use self::proto::r#match::condition as proto_condition;
+
+/// A condition on a packet's dynamic metadata that selects a
+/// [`Branch`][super::Branch].
+///
+/// Conditions that inspect a single value read it from their `metadataKey`,
+/// or from the `metadataKey` of the surrounding
+/// [`DirectionalConfig`][super::DirectionalConfig] when it is omitted. A
+/// condition on a key that isn't present in the metadata doesn't match.
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Condition {
+    /// Matches when every condition matches.
+    All(Vec<Condition>),
+    /// Matches when at least one condition matches.
+    Any(Vec<Condition>),
+    /// Matches when the condition doesn't match.
+    Not(Box<Condition>),
+    /// Matches when the key is present.
+    Exists {
+        #[serde(
+            rename = "metadataKey",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        metadata_key: Option<Key>,
+    },
+    /// Matches when the value is equal to `value`, as compared by
+    /// [`Value::loose_eq`].
+    Equals {
+        #[serde(
+            rename = "metadataKey",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        metadata_key: Option<Key>,
+        value: Value,
+    },
+    /// Matches numbers between `min` and `max` inclusive. Either bound can be
+    /// omitted.
+    Range {
+        #[serde(
+            rename = "metadataKey",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        metadata_key: Option<Key>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min: Option<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<Value>,
+    },
+    /// Matches strings or bytes that start with `value`.
+    Prefix {
+        #[serde(
+            rename = "metadataKey",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        metadata_key: Option<Key>,
+        value: Value,
+    },
+    /// Matches strings or bytes containing a match of the regular expression.
+    Regex {
+        #[serde(
+            rename = "metadataKey",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        metadata_key: Option<Key>,
+        pattern: Pattern,
+    },
+    /// Matches when the value is equal to any of `values`, as compared by
+    /// [`Value::loose_eq`].
+    In {
+        #[serde(
+            rename = "metadataKey",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        metadata_key: Option<Key>,
+        values: Vec<Value>,
+    },
+}
+
+impl Condition {
+    /// Sets the metadata key of every condition that omits it to
+    /// `default_key`, and checks that the condition is valid.
+    pub(crate) fn resolve(&mut self, default_key: Option<Key>) -> Result<(), CreationError> {
+        match self {
+            Self::All(conditions) | Self::Any(conditions) => conditions
+                .iter_mut()
+                .try_for_each(|condition| condition.resolve(default_key)),
+            Self::Not(condition) => condition.resolve(default_key),
+            Self::Exists { metadata_key }
+            | Self::Equals { metadata_key, .. }
+            | Self::Regex { metadata_key, .. }
+            | Self::In { metadata_key, .. } => resolve_key(metadata_key, default_key),
+            Self::Range {
+                metadata_key,
+                min,
+                max,
+            } => {
+                for (field, bound) in [("min", &*min), ("max", &*max)] {
+                    if bound
+                        .as_ref()
+                        .map_or(false, |bound| bound.as_f64().is_none())
+                    {
+                        return Err(CreationError::FieldInvalid {
+                            field: field.into(),
+                            reason: "range bounds must be numbers".into(),
+                        });
+                    }
+                }
+
+                resolve_key(metadata_key, default_key)
+            }
+            Self::Prefix {
+                metadata_key,
+                value,
+            } => {
+                if raw_bytes(value).is_none() {
+                    return Err(CreationError::FieldInvalid {
+                        field: "value".into(),
+                        reason: "prefixes must be a string or bytes".into(),
+                    });
+                }
+
+                resolve_key(metadata_key, default_key)
+            }
+        }
+    }
+
+    /// Returns every metadata key the condition reads, using `default_key`
+    /// for conditions that omit their key.
+    pub fn metadata_keys(&self, default_key: Option<Key>) -> Vec<Key> {
+        match self {
+            Self::All(conditions) | Self::Any(conditions) => conditions
+                .iter()
+                .flat_map(|condition| condition.metadata_keys(default_key))
+                .collect(),
+            Self::Not(condition) => condition.metadata_keys(default_key),
+            Self::Exists { metadata_key }
+            | Self::Equals { metadata_key, .. }
+            | Self::Range { metadata_key, .. }
+            | Self::Prefix { metadata_key, .. }
+            | Self::Regex { metadata_key, .. }
+            | Self::In { metadata_key, .. } => metadata_key.or(default_key).into_iter().collect(),
+        }
+    }
+
+    /// Returns whether the condition matches `metadata`.
+    pub fn matches(&self, metadata: &DynamicMetadata) -> bool {
+        let lookup = |key: &Option<Key>| key.as_ref().and_then(|key| metadata.get(key));
+
+        match self {
+            Self::All(conditions) => conditions.iter().all(|c| c.matches(metadata)),
+            Self::Any(conditions) => conditions.iter().any(|c| c.matches(metadata)),
+            Self::Not(condition) => !condition.matches(metadata),
+            Self::Exists { metadata_key } => lookup(metadata_key).is_some(),
+            Self::Equals {
+                metadata_key,
+                value,
+            } => lookup(metadata_key).map_or(false, |found| found.loose_eq(value)),
+            Self::Range {
+                metadata_key,
+                min,
+                max,
+            } => lookup(metadata_key).map_or(false, |value| {
+                let above_min = min.as_ref().map_or(true, |min| {
+                    matches!(
+                        numeric_cmp(value, min),
+                        Some(Ordering::Greater | Ordering::Equal)
+                    )
+                });
+                let below_max = max.as_ref().map_or(true, |max| {
+                    matches!(
+                        numeric_cmp(value, max),
+                        Some(Ordering::Less | Ordering::Equal)
+                    )
+                });
+
+                above_min && below_max
+            }),
+            Self::Prefix {
+                metadata_key,
+                value: prefix,
+            } => lookup(metadata_key)
+                .and_then(raw_bytes)
+                .zip(raw_bytes(prefix))
+                .map_or(false, |(value, prefix)| value.starts_with(prefix)),
+            Self::Regex {
+                metadata_key,
+                pattern,
+            } => lookup(metadata_key)
+                .and_then(raw_bytes)
+                .map_or(false, |value| pattern.0.is_match(value)),
+            Self::In {
+                metadata_key,
+                values,
+            } => {
+                lookup(metadata_key).map_or(false, |value| values.iter().any(|x| value.loose_eq(x)))
+            }
+        }
+    }
+}
+
+fn resolve_key(
+    metadata_key: &mut Option<Key>,
+    default_key: Option<Key>,
+) -> Result<(), CreationError> {
+    if metadata_key.is_none() {
+        *metadata_key = Some(default_key.ok_or_else(|| CreationError::FieldInvalid {
+            field: "metadataKey".into(),
+            reason: "a key is required when the directional config has no `metadataKey`".into(),
+        })?);
+    }
+
+    Ok(())
+}
+
+/// Returns the contents of string and byte values.
+fn raw_bytes(value: &Value) -> Option<&[u8]> {
+    match value {
+        Value::Bytes(value) => Some(value),
+        Value::String(value) => Some(value.as_bytes()),
+        _ => None,
+    }
+}
+
+/// Compares two numeric values, comparing integers exactly.
+fn numeric_cmp(a: &Value, b: &Value) -> Option<Ordering> {
+    let integer = |value: &Value| match value {
+        Value::Number(value) => Some(i128::from(*value)),
+        Value::Integer(value) => Some(i128::from(*value)),
+        _ => None,
+    };
+
+    match (integer(a), integer(b)) {
+        (Some(a), Some(b)) => Some(a.cmp(&b)),
+        _ => a.as_f64()?.partial_cmp(&b.as_f64()?),
+    }
+}
+
+/// A regular expression used by [`Condition::Regex`].
+#[derive(Debug, Deserialize, Serialize, schemars::JsonSchema)]
+#[serde(transparent)]
+pub struct Pattern(
+    #[serde(with = "serde_regex")]
+    #[schemars(with = "String")]
+    pub regex::bytes::Regex,
+);
+
+impl PartialEq for Pattern {
+    fn eq(&self, rhs: &Self) -> bool {
+        self.0.as_str() == rhs.0.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl From<Condition> for proto::r#match::Condition {
+    fn from(condition: Condition) -> Self {
+        use proto_condition::Kind;
+
+        let key = |key: Option<Key>| key.map(|key| key.to_string());
+        let conditions = |conditions: Vec<Condition>| proto_condition::Conditions {
+            conditions: conditions.into_iter().map(From::from).collect(),
+        };
+
+        let kind = match condition {
+            Condition::All(conditions_) => Kind::All(conditions(conditions_)),
+            Condition::Any(conditions_) => Kind::Any(conditions(conditions_)),
+            Condition::Not(condition) => Kind::Not(Box::new((*condition).into())),
+            Condition::Exists { metadata_key } => Kind::Exists(proto_condition::Exists {
+                metadata_key: key(metadata_key),
+            }),
+            Condition::Equals {
+                metadata_key,
+                value,
+            } => Kind::Equals(proto_condition::Equals {
+                metadata_key: key(metadata_key),
+                value: Some(value.into()),
+            }),
+            Condition::Range {
+                metadata_key,
+                min,
+                max,
+            } => Kind::Range(proto_condition::Range {
+                metadata_key: key(metadata_key),
+                min: min.map(From::from),
+                max: max.map(From::from),
+            }),
+            Condition::Prefix {
+                metadata_key,
+                value,
+            } => Kind::Prefix(proto_condition::Prefix {
+                metadata_key: key(metadata_key),
+                // Bytes are encoded separately, as `google.protobuf.Value`
+                // would turn them into a list of numbers.
+                value: Some(match value {
+                    Value::Bytes(bytes) => proto_condition::prefix::Value::Bytes(bytes.to_vec()),
+                    Value::String(string) => proto_condition::prefix::Value::String(string),
+                    value => proto_condition::prefix::Value::String(value.to_string()),
+                }),
+            }),
+            Condition::Regex {
+                metadata_key,
+                pattern,
+            } => Kind::Regex(proto_condition::Regex {
+                metadata_key: key(metadata_key),
+                pattern: pattern.0.as_str().into(),
+            }),
+            Condition::In {
+                metadata_key,
+                values,
+            } => Kind::In(proto_condition::In {
+                metadata_key: key(metadata_key),
+                values: values.into_iter().map(From::from).collect(),
+            }),
+        };
+
+        Self { kind: Some(kind) }
+    }
+}
+
+impl TryFrom<proto::r#match::Condition> for Condition {
+    type Error = eyre::Report;
+
+    fn try_from(condition: proto::r#match::Condition) -> Result<Self, Self::Error> {
+        use proto_condition::Kind;
+
+        let key = |key: Option<String>| key.map(Key::from);
+        let value = |value: Option<prost_types::Value>, field: &str| -> crate::Result<Value> {
+            value
+                .ok_or_else(|| ConvertProtoConfigError::new("Missing", Some(field.into())))?
+                .try_into()
+        };
+        let conditions = |conditions: proto_condition::Conditions| {
+            conditions
+                .conditions
+                .into_iter()
+                .map(Self::try_from)
+                .collect::<crate::Result<_>>()
+        };
+
+        Ok(
+            match condition
+                .kind
+                .ok_or_else(|| ConvertProtoConfigError::new("Missing", Some("kind".into())))?
+            {
+                Kind::All(all) => Self::All(conditions(all)?),
+                Kind::Any(any) => Self::Any(conditions(any)?),
+                Kind::Not(condition) => Self::Not(Box::new((*condition).try_into()?)),
+                Kind::Exists(exists) => Self::Exists {
+                    metadata_key: key(exists.metadata_key),
+                },
+                Kind::Equals(equals) => Self::Equals {
+                    metadata_key: key(equals.metadata_key),
+                    value: value(equals.value, "value")?,
+                },
+                Kind::Range(range) => Self::Range {
+                    metadata_key: key(range.metadata_key),
+                    min: range.min.map(TryFrom::try_from).transpose()?,
+                    max: range.max.map(TryFrom::try_from).transpose()?,
+                },
+                Kind::Prefix(prefix) => Self::Prefix {
+                    metadata_key: key(prefix.metadata_key),
+                    value: match prefix.value.ok_or_else(|| {
+                        ConvertProtoConfigError::new("Missing", Some("value".into()))
+                    })? {
+                        proto_condition::prefix::Value::String(string) => Value::String(string),
+                        proto_condition::prefix::Value::Bytes(bytes) => Value::Bytes(bytes.into()),
+                    },
+                },
+                Kind::Regex(regex) => Self::Regex {
+                    metadata_key: key(regex.metadata_key),
+                    pattern: Pattern(regex::bytes::Regex::new(&regex.pattern).map_err(
+                        |error| ConvertProtoConfigError::new(error, Some("pattern".into())),
+                    )?),
+                },
+                Kind::In(r#in) => Self::In {
+                    metadata_key: key(r#in.metadata_key),
+                    values: r#in
+                        .values
+                        .into_iter()
+                        .map(TryFrom::try_from)
+                        .collect::<crate::Result<_>>()?,
+                },
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> DynamicMetadata {
+        DynamicMetadata::from([
+            (Key::from_static("version"), Value::Number(3)),
+            (Key::from_static("kind"), Value::Bytes(vec![1].into())),
+            (Key::from_static("token"), Value::Bytes("abc123".into())),
+            (Key::from_static("region"), Value::String("us-east1".into())),
+        ])
+    }
+
+    fn from_yaml(yaml: &str) -> Result<Condition, serde_yaml::Error> {
+        serde_yaml::with::singleton_map_recursive::deserialize(serde_yaml::Deserializer::from_str(
+            yaml,
+        ))
+    }
+
+    fn condition(yaml: &str) -> Condition {
+        let mut condition = from_yaml(yaml).unwrap();
+        condition.resolve(Some(Key::from_static("token"))).unwrap();
+        condition
+    }
+
+    #[test]
+    fn predicates() {
+        let metadata = metadata();
+        let matches = |yaml| condition(yaml).matches(&metadata);
+
+        assert!(matches("exists: {}"));
+        assert!(!matches("exists: { metadataKey: missing }"));
+        assert!(matches("equals: { metadataKey: version, value: 3 }"));
+        assert!(!matches("equals: { metadataKey: kind, value: 1 }"));
+        assert!(matches("range: { metadataKey: version, min: 1, max: 3 }"));
+        assert!(matches("range: { metadataKey: version, min: 2.5 }"));
+        assert!(!matches("range: { metadataKey: version, max: 2 }"));
+        assert!(!matches("range: { metadataKey: region, min: 0 }"));
+        assert!(matches("prefix: { value: abc }"));
+        assert!(matches("prefix: { metadataKey: region, value: us- }"));
+        assert!(!matches("prefix: { value: xyz }"));
+        assert!(matches("regex: { pattern: '^[a-z]+[0-9]+$' }"));
+        assert!(!matches("regex: { metadataKey: region, pattern: '^eu-' }"));
+        assert!(matches("in: { metadataKey: version, values: [1, 2, 3] }"));
+        assert!(!matches("in: { metadataKey: version, values: [4, 5] }"));
+    }
+
+    #[test]
+    fn combinators() {
+        let metadata = metadata();
+        let matches = |yaml| condition(yaml).matches(&metadata);
+
+        assert!(matches(
+            "
+all:
+  - range: { metadataKey: version, min: 2 }
+  - any:
+    - prefix: { metadataKey: region, value: eu- }
+    - prefix: { metadataKey: region, value: us- }
+  - not:
+      exists: { metadataKey: missing }
+"
+        ));
+        assert!(!matches(
+            "
+any:
+  - equals: { metadataKey: version, value: 1 }
+  - not:
+      exists: {}
+"
+        ));
+    }
+
+    #[test]
+    fn invalid() {
+        let resolve = |yaml, key| from_yaml(yaml).unwrap().resolve(key);
+
+        assert!(resolve("exists: {}", None).is_err());
+        assert!(resolve("all: [ { exists: {} } ]", None).is_err());
+        assert!(resolve("range: { min: abc }", Some(Key::from_static("key"))).is_err());
+        assert!(resolve("prefix: { value: 1 }", Some(Key::from_static("key"))).is_err());
+        assert!(from_yaml("regex: { pattern: '(' }").is_err());
+    }
+
+    #[test]
+    fn convert_proto() {
+        let condition = from_yaml(
+            "
+all:
+  - range: { metadataKey: version, min: 2, max: 5 }
+  - not:
+      regex: { pattern: '^eu-' }
+  - in: { values: [abc, 1] }
+",
+        )
+        .unwrap();
+
+        let proto = proto::r#match::Condition::from(condition);
+        let expected = from_yaml(
+            "
+all:
+  - range: { metadataKey: version, min: 2, max: 5 }
+  - not:
+      regex: { pattern: '^eu-' }
+  - in: { values: [abc, 1] }
+",
+        )
+        .unwrap();
+        assert_eq!(expected, Condition::try_from(proto).unwrap());
+    }
+
+    #[test]
+    fn convert_proto_prefix() {
+        for value in [
+            Value::Bytes(bytes::Bytes::from_static(&[0xde, 0xad])),
+            Value::String("abc".into()),
+        ] {
+            let condition = || Condition::Prefix {
+                metadata_key: Some(Key::from_static("token")),
+                value: value.clone(),
+            };
+            let proto = proto::r#match::Condition::from(condition());
+            assert_eq!(condition(), Condition::try_from(proto).unwrap());
+        }
+    }
+}
@@ -16,10 +16,11 @@
 
 use serde::{Deserialize, Serialize};
 
-use super::proto;
+use super::{proto, Condition};
 use crate::{
     config::Filter,
-    filters::{ConvertProtoConfigError, StaticFilter},
+    filters::{ConvertProtoConfigError, CreationError, StaticFilter},
+    xds::config::listener::v3::Filter as ListenerFilter,
 };
 
 /// Configuration for [`Match`][super::Match].
@@ -69,9 +70,14 @@ impl TryFrom<proto::Match> for Config {
 /// Configuration for a specific direction.
 #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
 pub struct DirectionalConfig {
-    /// The key for the metadata to compare against.
-    #[serde(rename = "metadataKey")]
-    pub metadata_key: crate::metadata::Key,
+    /// The key for the metadata to compare against. Required when any branch
+    /// uses `value`, and the default key of branch conditions.
+    #[serde(
+        rename = "metadataKey",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub metadata_key: Option<crate::metadata::Key>,
     /// List of filters to compare and potentially run if any match.
     pub branches: Vec<Branch>,
     /// The behaviour for when none of the `branches` match.
@@ -83,14 +89,17 @@ impl TryFrom<DirectionalConfig> for proto::r#match::Config {
     type Error = crate::filters::CreationError;
 
     fn try_from(config: DirectionalConfig) -> Result<Self, Self::Error> {
+        let (fallthrough, fallthrough_filters) = config.fallthrough.0.into_proto()?;
+
         Ok(Self {
-            metadata_key: Some(config.metadata_key.to_string()),
+            metadata_key: config.metadata_key.map(|key| key.to_string()),
             branches: config
                 .branches
                 .into_iter()
                 .map(TryFrom::try_from)
                 .collect::<Result<_, _>>()?,
-            fallthrough: config.fallthrough.try_into().map(Some)?,
+            fallthrough,
+            fallthrough_filters,
         })
     }
 }
@@ -100,41 +109,50 @@ impl TryFrom<proto::r#match::Config> for DirectionalConfig {
 
     fn try_from(value: proto::r#match::Config) -> Result<Self, Self::Error> {
         Ok(Self {
-            metadata_key: value.metadata_key.map(From::from).ok_or_else(|| {
-                ConvertProtoConfigError::new("Missing", Some("metadata_key".into()))
-            })?,
+            metadata_key: value.metadata_key.map(From::from),
             branches: value
                 .branches
                 .into_iter()
                 .map(proto::r#match::Branch::try_into)
                 .collect::<Result<_, _>>()?,
-            fallthrough: value
-                .fallthrough
-                .ok_or_else(|| ConvertProtoConfigError::new("Missing", Some("fallthrough".into())))?
-                .try_into()
+            fallthrough: Filters::from_proto(value.fallthrough, value.fallthrough_filters)
                 .map(Fallthrough)?,
         })
     }
 }
 
 /// A specific match branch. The filter is run when `value` matches the value
-/// defined in `metadata_key`.
+/// defined in `metadata_key`, or when `condition` matches. Exactly one of
+/// `value` or `condition` must be set.
 #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
 pub struct Branch {
     /// The value to compare against the dynamic metadata.
-    pub value: crate::metadata::Value,
-    /// The filter to run on successful matches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<crate::metadata::Value>,
+    /// The condition to evaluate against the dynamic metadata.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_yaml::with::singleton_map_recursive"
+    )]
+    #[schemars(with = "Option<Condition>")]
+    pub condition: Option<Condition>,
+    /// The filters to run on successful matches.
     #[serde(flatten)]
-    pub filter: Filter,
+    pub filter: Filters,
 }
 
 impl TryFrom<Branch> for proto::r#match::Branch {
     type Error = crate::filters::CreationError;
 
     fn try_from(branch: Branch) -> Result<Self, Self::Error> {
+        let (filter, filters) = branch.filter.into_proto()?;
+
         Ok(Self {
-            value: Some(branch.value.into()),
-            filter: branch.filter.try_into().map(Some)?,
+            value: branch.value.map(From::from),
+            filter,
+            condition: branch.condition.map(From::from),
+            filters,
         })
     }
 }
@@ -144,34 +162,85 @@ impl TryFrom<proto::r#match::Branch> for Branch {
 
     fn try_from(branch: proto::r#match::Branch) -> Result<Self, Self::Error> {
         Ok(Self {
-            value: branch
-                .value
-                .ok_or_else(|| ConvertProtoConfigError::new("Missing", Some("value".into())))?
-                .try_into()?,
-            filter: branch
-                .filter
-                .map(|filter| filter.try_into())
-                .transpose()?
-                .ok_or_else(|| ConvertProtoConfigError::new("Missing", Some("filter".into())))?,
+            value: branch.value.map(TryFrom::try_from).transpose()?,
+            condition: branch.condition.map(TryFrom::try_from).transpose()?,
+            filter: Filters::from_proto(branch.filter, branch.filters)?,
+        })
+    }
+}
+
+/// The filters run by a branch, or by the fallthrough.
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, schemars::JsonSchema)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Filters {
+    /// A chain of filters, run in sequence.
+    Chain { filters: Vec<Filter> },
+    /// A single filter.
+    Single(Filter),
+}
+
+impl Filters {
+    /// Returns the filters in the order they are run.
+    pub fn as_slice(&self) -> &[Filter] {
+        match self {
+            Self::Chain { filters } => filters,
+            Self::Single(filter) => std::slice::from_ref(filter),
+        }
+    }
+
+    /// Converts into the single filter and chain fields of the protobuf
+    /// messages, only one of which is set.
+    fn into_proto(self) -> Result<(Option<ListenerFilter>, Vec<ListenerFilter>), CreationError> {
+        Ok(match self {
+            Self::Chain { filters } => (
+                None,
+                filters
+                    .into_iter()
+                    .map(TryFrom::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Self::Single(filter) => (Some(filter.try_into()?), Vec::new()),
+        })
+    }
+
+    /// Converts from the single filter and chain fields of the protobuf
+    /// messages, an empty chain is encoded with neither field set.
+    fn from_proto(
+        filter: Option<ListenerFilter>,
+        filters: Vec<ListenerFilter>,
+    ) -> Result<Self, eyre::Report> {
+        Ok(match filter {
+            Some(filter) if filters.is_empty() => Self::Single(filter.try_into()?),
+            _ => Self::Chain {
+                filters: filters
+                    .into_iter()
+                    .map(TryFrom::try_from)
+                    .collect::<Result<_, _>>()?,
+            },
         })
     }
 }
 
+impl From<Filter> for Filters {
+    fn from(filter: Filter) -> Self {
+        Self::Single(filter)
+    }
+}
+
+impl From<Vec<Filter>> for Filters {
+    fn from(filters: Vec<Filter>) -> Self {
+        Self::Chain { filters }
+    }
+}
+
 /// The behaviour when the none of branches match. Defaults to dropping packets.
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize, schemars::JsonSchema)]
 #[serde(transparent)]
-pub struct Fallthrough(pub Filter);
+pub struct Fallthrough(pub Filters);
 
 impl Default for Fallthrough {
     fn default() -> Self {
-        Self(crate::filters::Drop::as_filter_config(None).unwrap())
-    }
-}
-
-impl TryFrom<Fallthrough> for crate::xds::config::listener::v3::Filter {
-    type Error = crate::filters::CreationError;
-    fn try_from(fallthrough: Fallthrough) -> Result<Self, Self::Error> {
-        fallthrough.0.try_into()
+        Self(crate::filters::Drop::as_filter_config(None).unwrap().into())
     }
 }
 
@@ -195,10 +264,13 @@ on_read:
             config,
             Config {
                 on_read: Some(DirectionalConfig {
-                    metadata_key: "quilkin.dev/captured_bytes".into(),
+                    metadata_key: Some("quilkin.dev/captured_bytes".into()),
                     branches: vec![Branch {
-                        value: String::from("abc").into(),
-                        filter: crate::filters::Debug::as_filter_config(None).unwrap(),
+                        value: Some(String::from("abc").into()),
+                        condition: None,
+                        filter: crate::filters::Debug::as_filter_config(None)
+                            .unwrap()
+                            .into(),
                     }],
                     fallthrough: <_>::default(),
                 }),
@@ -206,4 +278,97 @@ on_read:
             }
         )
     }
+    #[test]
+    fn serde_condition() {
+        let matches_yaml = "
+on_read:
+    branches:
+        - condition:
+            all:
+                - prefix: { metadataKey: myapp.com/token, value: abc }
+                - range: { metadataKey: myapp.com/version, min: 2 }
+          name: quilkin.filters.debug.v1alpha1.Debug
+        ";
+
+        let config = serde_yaml::from_str::<Config>(matches_yaml).unwrap();
+        let on_read = config.on_read.as_ref().unwrap();
+        assert_eq!(None, on_read.metadata_key);
+        assert!(matches!(
+            on_read.branches[0].condition,
+            Some(Condition::All(ref conditions)) if conditions.len() == 2
+        ));
+
+        let proto = proto::Match::try_from(config).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<Config>(matches_yaml).unwrap(),
+            Config::try_from(proto).unwrap()
+        );
+    }
+
+    #[test]
+    fn serde_filters() {
+        let matches_yaml = "
+on_read:
+    metadataKey: quilkin.dev/captured_bytes
+    branches:
+        - value: abc
+          filters:
+            - name: quilkin.filters.debug.v1alpha1.Debug
+            - name: quilkin.filters.pass.v1alpha1.Pass
+    fallthrough:
+        filters:
+            - name: quilkin.filters.debug.v1alpha1.Debug
+            - name: quilkin.filters.drop.v1alpha1.Drop
+        ";
+
+        let config = serde_yaml::from_str::<Config>(matches_yaml).unwrap();
+        let on_read = config.on_read.as_ref().unwrap();
+        assert_eq!(
+            Filters::from(vec![
+                crate::filters::Debug::as_filter_config(None).unwrap(),
+                crate::filters::Pass::as_filter_config(None).unwrap(),
+            ]),
+            on_read.branches[0].filter
+        );
+        assert_eq!(2, on_read.fallthrough.0.as_slice().len());
+
+        let proto = proto::Match::try_from(config).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<Config>(matches_yaml).unwrap(),
+            Config::try_from(proto).unwrap()
+        );
+
+        // An empty chain has neither field set in protobuf.
+        let config = Config {
+            on_read: Some(DirectionalConfig {
+                metadata_key: None,
+                branches: vec![],
+                fallthrough: Fallthrough(Filters::Chain { filters: vec![] }),
+            }),
+            on_write: None,
+        };
+        let proto = proto::Match::try_from(config).unwrap();
+        assert_eq!(
+            Filters::Chain { filters: vec![] },
+            Config::try_from(proto)
+                .unwrap()
+                .on_read
+                .unwrap()
+                .fallthrough
+                .0
+        );
+
+        // A branch can't have both a filter and a chain of filters.
+        assert!(serde_yaml::from_str::<Config>(
+            "
+on_read:
+    branches:
+        - value: abc
+          name: quilkin.filters.debug.v1alpha1.Debug
+          filters:
+            - name: quilkin.filters.pass.v1alpha1.Pass
+        "
+        )
+        .is_err());
+    }
 }
@@ -20,3 +20,39 @@
 /// byte slices it extracts from each packet.
 /// - **Type** `Vec<u8>`
 pub const CAPTURED_BYTES: &str = "quilkin.dev/capture";
+
+/// The dynamic metadata keys a filter reads and writes while processing
+/// packets being read, used to check that every key a filter reads is written
+/// by an earlier filter in the chain.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MetadataKeys {
+    /// The keys the filter reads.
+    pub reads: Vec<String>,
+    /// The keys the filter writes.
+    pub writes: Vec<String>,
+    /// Whether the filter may write keys that can't be known from its
+    /// configuration, such as a `Wasm` module, in which case nothing is
+    /// checked after it.
+    pub writes_any: bool,
+    /// Whether the filter drops every packet, in which case nothing is
+    /// checked after it.
+    pub drops: bool,
+}
+
+impl MetadataKeys {
+    /// The keys of a filter that may read or write any key.
+    pub fn unknown() -> Self {
+        Self {
+            writes_any: true,
+            ..<_>::default()
+        }
+    }
+
+    /// The keys of a filter that only reads `key`.
+    pub fn reads(key: impl std::fmt::Display) -> Self {
+        Self {
+            reads: vec![key.to_string()],
+            ..<_>::default()
+        }
+    }
+}
@@ -14,10 +14,59 @@
  * limitations under the License.
  */
 
+use std::cell::Cell;
+
 use once_cell::sync::Lazy;
-use prometheus::{HistogramVec, IntCounterVec};
+use prometheus::{exponential_buckets, Histogram, HistogramVec, IntCounter, IntCounterVec};
+
+use crate::metrics::{histogram_opts, registry, Direction};
+
+/// The labels identifying a filter in a [`FilterChain`][crate::filters::FilterChain],
+/// by its name, the optional label from its configuration, and whether it's
+/// part of the active or canary chain.
+const FILTER_LABELS: [&str; 3] = ["filter", "filter_label", "chain"];
+
+/// The `chain` label of the active filter chain.
+pub(crate) const ACTIVE_CHAIN: &str = "active";
+/// The `chain` label of the canary filter chain.
+pub(crate) const CANARY_CHAIN: &str = "canary";
+
+thread_local! {
+    static CHAIN: Cell<&'static str> = Cell::new(ACTIVE_CHAIN);
+}
+
+/// Creates filter chains with `create`, which report their per-filter
+/// metrics with `chain` as their `chain` label, including any chains nested
+/// within their filters.
+pub(crate) fn with_chain<T>(chain: &'static str, create: impl FnOnce() -> T) -> T {
+    struct Reset(&'static str);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            CHAIN.with(|chain| chain.set(self.0));
+        }
+    }
+
+    let _reset = Reset(CHAIN.with(|current| current.replace(chain)));
+    create()
+}
+
+/// The `chain` label of filter chains currently being created.
+pub(crate) fn current_chain() -> &'static str {
+    CHAIN.with(Cell::get)
+}
+
+/// Start the histogram bucket at an eighth of a millisecond, as we bucketed the full filter
+/// chain processing starting at a quarter of a millisecond, so we we will want finer granularity
+/// here.
+const BUCKET_START: f64 = 0.000125;
+
+const BUCKET_FACTOR: f64 = 2.5;
 
-use crate::metrics::{registry, Direction};
+/// At an exponential factor of 2.5 (BUCKET_FACTOR), 11 iterations gets us to just over half a
+/// second. Any processing that occurs over half a second is far too long, so we end
+/// the bucketing there as we don't care about granularity past this value.
+const BUCKET_COUNT: usize = 11;
 
 pub(crate) fn counter(
     id: &str,
@@ -71,3 +120,170 @@ pub(crate) fn histogram(
         metadata.first().copied().unwrap_or_default(),
     ])
 }
+
+/// Seconds taken to execute a filter in a chain.
+pub(crate) fn duration_seconds(
+    direction: Direction,
+    filter: &str,
+    label: &str,
+    chain: &str,
+) -> Histogram {
+    fn histogram(name: &str, help: &str) -> HistogramVec {
+        prometheus::register_histogram_vec_with_registry! {
+            histogram_opts(
+                name,
+                "filter",
+                help,
+                exponential_buckets(BUCKET_START, BUCKET_FACTOR, BUCKET_COUNT).unwrap(),
+            ),
+            &FILTER_LABELS,
+            registry(),
+        }
+        .unwrap()
+    }
+
+    static READ_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+        histogram(
+            "read_duration_seconds",
+            "Seconds taken to execute a given filter's `read`.",
+        )
+    });
+    static WRITE_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+        histogram(
+            "write_duration_seconds",
+            "Seconds taken to execute a given filter's `write`.",
+        )
+    });
+
+    match direction {
+        Direction::Read => &READ_DURATION_SECONDS,
+        Direction::Write => &WRITE_DURATION_SECONDS,
+    }
+    .with_label_values(&[filter, label, chain])
+}
+
+/// Packets passed to a filter in a chain.
+pub(crate) fn invocations_total(
+    direction: Direction,
+    filter: &str,
+    label: &str,
+    chain: &str,
+) -> IntCounter {
+    static INVOCATIONS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "filter_invocations_total",
+                "Total number of packets passed to a given filter",
+            },
+            &[FILTER_LABELS[0], FILTER_LABELS[1], FILTER_LABELS[2], Direction::LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    INVOCATIONS_TOTAL.with_label_values(&[filter, label, chain, direction.label()])
+}
+
+/// Packets a filter in a chain failed to process.
+pub(crate) fn errors_total(
+    direction: Direction,
+    filter: &str,
+    label: &str,
+    chain: &str,
+) -> IntCounter {
+    static ERRORS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "filter_errors_total",
+                "Total number of errors returned by a given filter",
+            },
+            &[FILTER_LABELS[0], FILTER_LABELS[1], FILTER_LABELS[2], Direction::LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    ERRORS_TOTAL.with_label_values(&[filter, label, chain, direction.label()])
+}
+
+/// Packets intentionally dropped by a filter in a chain.
+pub(crate) fn packets_dropped_total(
+    direction: Direction,
+    filter: &str,
+    label: &str,
+    chain: &str,
+    reason: &str,
+) -> IntCounter {
+    static PACKETS_DROPPED_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "filter_packets_dropped_total",
+                "Total number of packets dropped by a given filter",
+            },
+            &[
+                FILTER_LABELS[0],
+                FILTER_LABELS[1],
+                FILTER_LABELS[2],
+                Direction::LABEL,
+                "reason"
+            ],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    PACKETS_DROPPED_TOTAL.with_label_values(&[filter, label, chain, direction.label(), reason])
+}
+
+/// Packets evaluated by a canary filter chain.
+pub(crate) fn canary_packets_total(direction: Direction) -> IntCounter {
+    static CANARY_PACKETS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "filter_canary_packets_total",
+                "Total number of packets evaluated by the canary filter chain",
+            },
+            &[Direction::LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    CANARY_PACKETS_TOTAL.with_label_values(&[direction.label()])
+}
+
+/// Packets where a canary filter chain's decision differed from the active
+/// filter chain.
+pub(crate) fn canary_differences_total(direction: Direction, difference: &str) -> IntCounter {
+    static CANARY_DIFFERENCES_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "filter_canary_differences_total",
+                "Total number of differences between the canary and active filter chains",
+            },
+            &[Direction::LABEL, "difference"],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    CANARY_DIFFERENCES_TOTAL.with_label_values(&[direction.label(), difference])
+}
+
+/// Packets skipped by a canary filter chain, as it was still evaluating
+/// earlier packets.
+pub(crate) fn canary_packets_skipped_total(direction: Direction) -> IntCounter {
+    static CANARY_PACKETS_SKIPPED_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+        prometheus::register_int_counter_vec_with_registry! {
+            prometheus::opts! {
+                "filter_canary_packets_skipped_total",
+                "Total number of sampled packets skipped by the canary filter chain",
+            },
+            &[Direction::LABEL],
+            registry(),
+        }
+        .unwrap()
+    });
+
+    CANARY_PACKETS_SKIPPED_TOTAL.with_label_values(&[direction.label()])
+}
@@ -0,0 +1,280 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{filters::prelude::*, metrics::Direction};
+
+crate::include_proto!("quilkin.filters.mirror.v1alpha1");
+use self::quilkin::filters::mirror::v1alpha1 as proto;
+
+/// Copies a fraction of packets received from downstream to the endpoints of
+/// a secondary cluster. The endpoints of the cluster are looked up for each
+/// packet, so they follow any changes to the cluster, and they never receive
+/// the original packet. Replies from the mirror endpoints are discarded, and
+/// mirror sessions are kept separate from the proxy's regular sessions, so
+/// mirroring never affects the routing of the original packet.
+pub struct Mirror {
+    cluster: String,
+    fraction: f64,
+    packets_mirrored_total: prometheus::IntCounter,
+}
+
+impl Mirror {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        if config.cluster.is_empty() {
+            return Err(CreationError::FieldInvalid {
+                field: "cluster".into(),
+                reason: "a cluster name is required".into(),
+            });
+        }
+
+        if !(0.0..=1.0).contains(&config.fraction) {
+            return Err(CreationError::FieldInvalid {
+                field: "fraction".into(),
+                reason: "value must be between 0.0 and 1.0".into(),
+            });
+        }
+
+        Ok(Self {
+            cluster: config.cluster,
+            fraction: config.fraction,
+            packets_mirrored_total: crate::filters::metrics::counter(
+                Self::NAME,
+                "packets_mirrored_total",
+                "Total number of packets copied to the mirror endpoints.",
+                Direction::Read,
+            ),
+        })
+    }
+
+    fn should_mirror(&self) -> bool {
+        self.fraction >= 1.0 || rand::random::<f64>() < self.fraction
+    }
+}
+
+#[async_trait::async_trait]
+impl Filter for Mirror {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    async fn read(&self, ctx: &mut ReadContext) -> Result<(), FilterError> {
+        let Some(clusters) = ctx.clusters.clone() else {
+            return Ok(());
+        };
+        let Some(cluster) = clusters.get(&self.cluster) else {
+            tracing::trace!(cluster = %self.cluster, "mirror cluster not found");
+            return Ok(());
+        };
+
+        // The mirror endpoints only ever receive copies of packets.
+        let addresses: HashSet<_> = cluster
+            .endpoints()
+            .map(|endpoint| &endpoint.address)
+            .collect();
+        ctx.endpoints
+            .retain(|endpoint| !addresses.contains(&endpoint.address));
+
+        if self.should_mirror() {
+            ctx.mirror_endpoints.extend(cluster.endpoints().cloned());
+            self.packets_mirrored_total.inc();
+        }
+
+        Ok(())
+    }
+}
+
+impl StaticFilter for Mirror {
+    const NAME: &'static str = "quilkin.filters.mirror.v1alpha1.Mirror";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::Mirror;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Self::new(Self::ensure_config_exists(config)?)
+    }
+}
+
+/// `mirror` filter's configuration.
+#[derive(Serialize, Deserialize, Debug, PartialEq, schemars::JsonSchema)]
+pub struct Config {
+    /// The name of the cluster whose endpoints mirrored packets are sent to.
+    pub cluster: String,
+    /// The fraction of packets to mirror, between `0.0` and `1.0`. Defaults to
+    /// mirroring every packet.
+    #[serde(default = "default_fraction")]
+    pub fraction: f64,
+}
+
+/// Default value for [`Config::fraction`]
+fn default_fraction() -> f64 {
+    1.0
+}
+
+impl From<Config> for proto::Mirror {
+    fn from(config: Config) -> Self {
+        Self {
+            cluster: config.cluster,
+            fraction: Some(config.fraction),
+        }
+    }
+}
+
+impl TryFrom<proto::Mirror> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::Mirror) -> Result<Self, Self::Error> {
+        Ok(Self {
+            cluster: p.cluster,
+            fraction: p.fraction.unwrap_or_else(default_fraction),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::{
+        cluster::{Cluster, ClusterMap},
+        endpoint::Endpoint,
+        test_utils::assert_write_no_change,
+    };
+
+    fn mirror(fraction: f64) -> Mirror {
+        Mirror::from_config(Some(Config {
+            cluster: "mirror".into(),
+            fraction,
+        }))
+    }
+
+    fn read_ctx() -> ReadContext {
+        let clusters = ClusterMap::new_with_default_cluster(vec![Endpoint::new(
+            (Ipv4Addr::LOCALHOST, 8080).into(),
+        )]);
+        clusters.insert(Cluster::new(
+            "mirror",
+            vec![vec![Endpoint::new((Ipv4Addr::LOCALHOST, 9000).into())].into()],
+        ));
+
+        ReadContext::new(
+            clusters.endpoints().collect(),
+            (Ipv4Addr::LOCALHOST, 7000).into(),
+            b"hello".to_vec(),
+        )
+        .clusters(std::sync::Arc::new(clusters))
+    }
+
+    #[tokio::test]
+    async fn mirrors_packets() {
+        let filter = mirror(1.0);
+        let mut ctx = read_ctx();
+        filter.read(&mut ctx).await.unwrap();
+
+        assert_eq!(
+            vec![Endpoint::new((Ipv4Addr::LOCALHOST, 9000).into())],
+            ctx.mirror_endpoints
+        );
+        assert_eq!(
+            vec![Endpoint::new((Ipv4Addr::LOCALHOST, 8080).into())],
+            ctx.endpoints
+        );
+        assert_eq!(b"hello", &*ctx.contents);
+    }
+
+    #[tokio::test]
+    async fn follows_cluster() {
+        let filter = mirror(1.0);
+        let mut ctx = read_ctx();
+        ctx.clusters.as_ref().unwrap().insert(Cluster::new(
+            "mirror",
+            vec![vec![Endpoint::new((Ipv4Addr::LOCALHOST, 9001).into())].into()],
+        ));
+        filter.read(&mut ctx).await.unwrap();
+
+        assert_eq!(
+            vec![Endpoint::new((Ipv4Addr::LOCALHOST, 9001).into())],
+            ctx.mirror_endpoints
+        );
+
+        // A missing cluster mirrors nothing.
+        let mut ctx = read_ctx();
+        ctx.clusters
+            .as_ref()
+            .unwrap()
+            .replace(ClusterMap::new_with_default_cluster(vec![Endpoint::new(
+                (Ipv4Addr::LOCALHOST, 8080).into(),
+            )]));
+        filter.read(&mut ctx).await.unwrap();
+        assert!(ctx.mirror_endpoints.is_empty());
+    }
+
+    #[tokio::test]
+    async fn zero_fraction() {
+        let filter = mirror(0.0);
+        let mut ctx = read_ctx();
+        filter.read(&mut ctx).await.unwrap();
+
+        assert!(ctx.mirror_endpoints.is_empty());
+        // The mirror cluster still doesn't receive the original packet.
+        assert_eq!(
+            vec![Endpoint::new((Ipv4Addr::LOCALHOST, 8080).into())],
+            ctx.endpoints
+        );
+    }
+
+    #[tokio::test]
+    async fn write() {
+        assert_write_no_change(&mirror(1.0)).await;
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(Mirror::try_from_config(Some(Config {
+            cluster: String::new(),
+            fraction: 1.0,
+        }))
+        .is_err());
+        assert!(Mirror::try_from_config(Some(Config {
+            cluster: "mirror".into(),
+            fraction: 1.5,
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn convert_proto_config() {
+        let config = Config {
+            cluster: "mirror".into(),
+            fraction: 0.25,
+        };
+
+        let proto = proto::Mirror::from(Config {
+            cluster: config.cluster.clone(),
+            fraction: config.fraction,
+        });
+        assert_eq!(config, Config::try_from(proto).unwrap());
+        assert_eq!(
+            default_fraction(),
+            Config::try_from(proto::Mirror {
+                cluster: "mirror".into(),
+                fraction: None,
+            })
+            .unwrap()
+            .fraction
+        );
+    }
+}
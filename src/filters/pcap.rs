@@ -0,0 +1,601 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod writer;
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use ipnetwork::IpNetwork;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use self::writer::{Record, RotatingWriter};
+use crate::{
+    endpoint::{address::AddressKind, EndpointAddress},
+    filters::{
+        firewall::{Action, PortRange, Rule},
+        prelude::*,
+    },
+    metrics::Direction,
+};
+
+crate::include_proto!("quilkin.filters.pcap.v1alpha1");
+use self::quilkin::filters::pcap::v1alpha1 as proto;
+
+/// The maximum number of captured packets waiting to be written to disk,
+/// packets captured while the queue is full are discarded.
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Process wide capture state, shared by every [`Pcap`] filter and controlled
+/// through the admin server.
+static CAPTURE: Lazy<CaptureState> = Lazy::new(<_>::default);
+
+#[derive(Default)]
+struct CaptureState {
+    armed: AtomicBool,
+    limits: parking_lot::Mutex<Limits>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Limits {
+    deadline: Option<Instant>,
+    remaining_packets: Option<u64>,
+}
+
+impl CaptureState {
+    /// Returns whether a packet should be captured, consuming one packet from
+    /// the remaining packet limit if it should.
+    fn take_packet(&self) -> bool {
+        if !self.armed.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        let mut limits = self.limits.lock();
+        let expired = limits
+            .deadline
+            .map_or(false, |deadline| Instant::now() >= deadline);
+        if expired || limits.remaining_packets == Some(0) {
+            self.armed.store(false, Ordering::Relaxed);
+            return false;
+        }
+
+        if let Some(remaining) = &mut limits.remaining_packets {
+            *remaining -= 1;
+        }
+
+        true
+    }
+}
+
+/// The current state of packet capture, as reported by the admin server.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct CaptureStatus {
+    pub armed: bool,
+    /// The number of seconds until capture is disarmed, if limited by time.
+    pub remaining_seconds: Option<u64>,
+    /// The number of packets that can still be captured, if limited by count.
+    pub remaining_packets: Option<u64>,
+}
+
+/// Arms packet capture for every [`Pcap`] filter. Capture is disarmed once
+/// `duration` has elapsed or `packets` packets have been captured, whichever
+/// happens first. If neither is provided, capture remains armed until
+/// [`disarm`] is called.
+///
+/// Returns `None` without arming capture if `duration` is too long to
+/// represent.
+pub fn arm(duration: Option<Duration>, packets: Option<u64>) -> Option<CaptureStatus> {
+    let deadline = match duration {
+        Some(duration) => Some(Instant::now().checked_add(duration)?),
+        None => None,
+    };
+
+    *CAPTURE.limits.lock() = Limits {
+        deadline,
+        remaining_packets: packets,
+    };
+    CAPTURE.armed.store(true, Ordering::Relaxed);
+    tracing::info!(?duration, ?packets, "packet capture armed");
+    Some(status())
+}
+
+/// Stops packet capture for every [`Pcap`] filter.
+pub fn disarm() -> CaptureStatus {
+    CAPTURE.armed.store(false, Ordering::Relaxed);
+    tracing::info!("packet capture disarmed");
+    status()
+}
+
+/// Returns the current state of packet capture.
+pub fn status() -> CaptureStatus {
+    let limits = *CAPTURE.limits.lock();
+    let now = Instant::now();
+    let armed = CAPTURE.armed.load(Ordering::Relaxed)
+        && limits.deadline.map_or(true, |deadline| now < deadline)
+        && limits.remaining_packets != Some(0);
+
+    if !armed {
+        return CaptureStatus::default();
+    }
+
+    CaptureStatus {
+        armed,
+        remaining_seconds: limits
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(now).as_secs()),
+        remaining_packets: limits.remaining_packets,
+    }
+}
+
+/// Writes packets travelling in either direction to rotating [pcapng] files,
+/// which can be opened directly in tools such as Wireshark. The filter only
+/// captures packets while capture has been armed through the admin server.
+///
+/// [pcapng]: https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-01.html
+pub struct Pcap {
+    rules: Vec<Rule>,
+    sender: mpsc::SyncSender<Record>,
+    writer: std::thread::JoinHandle<()>,
+    metrics: Metrics,
+}
+
+struct Metrics {
+    packets_captured_total_read: prometheus::IntCounter,
+    packets_captured_total_write: prometheus::IntCounter,
+    packets_discarded_total_read: prometheus::IntCounter,
+    packets_discarded_total_write: prometheus::IntCounter,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let captured = |direction| {
+            crate::filters::metrics::counter(
+                Pcap::NAME,
+                "packets_captured_total",
+                "Total number of packets written to capture files.",
+                direction,
+            )
+        };
+        let discarded = |direction| {
+            crate::filters::metrics::counter(
+                Pcap::NAME,
+                "packets_discarded_total",
+                "Total number of captured packets discarded because the capture queue was full.",
+                direction,
+            )
+        };
+
+        Self {
+            packets_captured_total_read: captured(Direction::Read),
+            packets_captured_total_write: captured(Direction::Write),
+            packets_discarded_total_read: discarded(Direction::Read),
+            packets_discarded_total_write: discarded(Direction::Write),
+        }
+    }
+}
+
+impl Pcap {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        if config.directory.as_os_str().is_empty() {
+            return Err(CreationError::FieldInvalid {
+                field: "directory".into(),
+                reason: "a directory is required".into(),
+            });
+        }
+
+        let (sender, receiver) = mpsc::sync_channel::<Record>(QUEUE_CAPACITY);
+        let mut writer = RotatingWriter::new(
+            config.directory,
+            config.max_file_size,
+            config.max_files as usize,
+        );
+
+        // File IO is blocking, so the files are written from a dedicated
+        // thread which exits once the filter has been dropped.
+        let writer = std::thread::Builder::new()
+            .name("pcap-writer".into())
+            .spawn(move || {
+                while let Ok(record) = receiver.recv() {
+                    let result = std::iter::once(record)
+                        .chain(receiver.try_iter())
+                        .try_for_each(|record| writer.write(&record))
+                        .and_then(|_| writer.flush());
+
+                    if let Err(error) = result {
+                        tracing::warn!(%error, "failed to write packet capture");
+                    }
+                }
+            })
+            .map_err(|error| CreationError::FieldInvalid {
+                field: "directory".into(),
+                reason: format!("failed to start capture writer: {error}"),
+            })?;
+
+        Ok(Self {
+            rules: config.rules,
+            sender,
+            writer,
+            metrics: Metrics::new(),
+        })
+    }
+
+    /// Drops the filter, waiting until every captured packet has been
+    /// written.
+    pub fn close(self) {
+        drop(self.sender);
+        if self.writer.join().is_err() {
+            tracing::warn!("packet capture writer panicked");
+        }
+    }
+
+    /// Returns whether a packet between `source` and `dest` matches the
+    /// filter's rules. The first rule which contains either address decides
+    /// whether the packet is captured, and every packet is captured if there
+    /// are no rules.
+    fn matches(&self, source: SocketAddr, dest: SocketAddr) -> bool {
+        if self.rules.is_empty() {
+            return true;
+        }
+
+        self.rules
+            .iter()
+            .find(|rule| rule.contains(source) || rule.contains(dest))
+            .map_or(false, |rule| rule.action == Action::Allow)
+    }
+
+    fn capture(
+        &self,
+        record: Record,
+        captured: &prometheus::IntCounter,
+        discarded: &prometheus::IntCounter,
+    ) {
+        match self.sender.try_send(record) {
+            Ok(()) => captured.inc(),
+            Err(_) => discarded.inc(),
+        }
+    }
+}
+
+/// Returns the socket address of `address` without resolving any domain
+/// names, which are recorded as the unspecified address instead.
+fn socket_addr(address: &EndpointAddress) -> SocketAddr {
+    match &address.host {
+        AddressKind::Ip(ip) => (*ip, address.port).into(),
+        AddressKind::Name(_) => (IpAddr::V4(Ipv4Addr::UNSPECIFIED), address.port).into(),
+    }
+}
+
+#[async_trait::async_trait]
+impl Filter for Pcap {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    async fn read(&self, ctx: &mut ReadContext) -> Result<(), FilterError> {
+        let source = socket_addr(&ctx.source);
+        let destinations: Vec<_> = ctx
+            .endpoints
+            .iter()
+            .map(|endpoint| socket_addr(&endpoint.address))
+            .collect();
+
+        // Each packet is captured once, the destination is only known if a
+        // single endpoint remains, such as after a `TokenRouter`.
+        let dest = match &*destinations {
+            [dest] => *dest,
+            _ => (IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0).into(),
+        };
+        let matches = match &*destinations {
+            [] => self.matches(source, dest),
+            destinations => destinations.iter().any(|dest| self.matches(source, *dest)),
+        };
+
+        if !matches || !CAPTURE.take_packet() {
+            return Ok(());
+        }
+
+        self.capture(
+            Record {
+                timestamp: SystemTime::now(),
+                direction: writer::Direction::Inbound,
+                source,
+                dest,
+                contents: ctx.contents.clone(),
+            },
+            &self.metrics.packets_captured_total_read,
+            &self.metrics.packets_discarded_total_read,
+        );
+
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    async fn write(&self, ctx: &mut WriteContext) -> Result<(), FilterError> {
+        let source = socket_addr(&ctx.source);
+        let dest = socket_addr(&ctx.dest);
+
+        if !self.matches(source, dest) || !CAPTURE.take_packet() {
+            return Ok(());
+        }
+
+        self.capture(
+            Record {
+                timestamp: SystemTime::now(),
+                direction: writer::Direction::Outbound,
+                source,
+                dest,
+                contents: ctx.contents.clone(),
+            },
+            &self.metrics.packets_captured_total_write,
+            &self.metrics.packets_discarded_total_write,
+        );
+
+        Ok(())
+    }
+}
+
+impl StaticFilter for Pcap {
+    const NAME: &'static str = "quilkin.filters.pcap.v1alpha1.Pcap";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::Pcap;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Self::new(Self::ensure_config_exists(config)?)
+    }
+}
+
+/// `pcap` filter's configuration.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, schemars::JsonSchema)]
+pub struct Config {
+    /// The directory that capture files are written to, it is created when
+    /// the first packet is captured if it doesn't already exist.
+    pub directory: PathBuf,
+    /// Rules deciding which packets are captured, using the same format as
+    /// the `Firewall` filter. A rule applies to a packet if either its source
+    /// or destination matches, with `ALLOW` capturing the packet and `DENY`
+    /// skipping it. If no rules are provided, every packet is captured.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// The size in bytes after which a new capture file is started.
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: u64,
+    /// The number of capture files to keep, the oldest file is removed once
+    /// this is exceeded.
+    #[serde(default = "default_max_files")]
+    pub max_files: u32,
+}
+
+/// Default value for [`Config::max_file_size`]
+fn default_max_file_size() -> u64 {
+    16 * 1024 * 1024
+}
+
+/// Default value for [`Config::max_files`]
+fn default_max_files() -> u32 {
+    8
+}
+
+impl From<Config> for proto::Pcap {
+    fn from(config: Config) -> Self {
+        Self {
+            directory: config.directory.to_string_lossy().into_owned(),
+            rules: config
+                .rules
+                .into_iter()
+                .map(|rule| proto::pcap::Rule {
+                    action: match rule.action {
+                        Action::Allow => proto::pcap::Action::Allow,
+                        Action::Deny => proto::pcap::Action::Deny,
+                    } as i32,
+                    source: rule.source.to_string(),
+                    ports: rule
+                        .ports
+                        .into_iter()
+                        .map(|range| proto::pcap::PortRange {
+                            min: range.min().into(),
+                            max: range.max().into(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            max_file_size: Some(config.max_file_size),
+            max_files: Some(config.max_files),
+        }
+    }
+}
+
+impl TryFrom<proto::Pcap> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::Pcap) -> Result<Self, Self::Error> {
+        fn convert_port(
+            range: &proto::pcap::PortRange,
+        ) -> Result<PortRange, ConvertProtoConfigError> {
+            let min = u16::try_from(range.min).map_err(|err| {
+                ConvertProtoConfigError::new(
+                    format!("min too large: {err}"),
+                    Some("port.min".into()),
+                )
+            })?;
+            let max = u16::try_from(range.max).map_err(|err| {
+                ConvertProtoConfigError::new(
+                    format!("max too large: {err}"),
+                    Some("port.max".into()),
+                )
+            })?;
+
+            PortRange::new(min, max)
+                .map_err(|err| ConvertProtoConfigError::new(format!("{err}"), Some("ports".into())))
+        }
+
+        fn convert_rule(rule: &proto::pcap::Rule) -> Result<Rule, ConvertProtoConfigError> {
+            let action = match rule.action() {
+                proto::pcap::Action::Allow => Action::Allow,
+                proto::pcap::Action::Deny => Action::Deny,
+            };
+            let source = IpNetwork::try_from(rule.source.as_str()).map_err(|err| {
+                ConvertProtoConfigError::new(
+                    format!("invalid source: {err:?}"),
+                    Some("source".into()),
+                )
+            })?;
+            let ports = rule
+                .ports
+                .iter()
+                .map(convert_port)
+                .collect::<Result<_, _>>()?;
+
+            Ok(Rule {
+                action,
+                source,
+                ports,
+            })
+        }
+
+        Ok(Self {
+            directory: p.directory.into(),
+            rules: p.rules.iter().map(convert_rule).collect::<Result<_, _>>()?,
+            max_file_size: p.max_file_size.unwrap_or_else(default_max_file_size),
+            max_files: p.max_files.unwrap_or_else(default_max_files),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{endpoint::Endpoint, test_utils::assert_write_no_change};
+
+    fn pcap(directory: &std::path::Path, rules: Vec<Rule>) -> Pcap {
+        Pcap::from_config(Some(Config {
+            directory: directory.into(),
+            rules,
+            max_file_size: default_max_file_size(),
+            max_files: default_max_files(),
+        }))
+    }
+
+    #[test]
+    fn matches_rules() {
+        let directory = tempfile::tempdir().unwrap();
+        let rule = |action, source: &str| Rule {
+            action,
+            source: source.parse().unwrap(),
+            ports: vec![PortRange::new(0, u16::MAX).unwrap()],
+        };
+        let filter = pcap(
+            directory.path(),
+            vec![
+                rule(Action::Deny, "192.168.0.10/32"),
+                rule(Action::Allow, "192.168.0.0/24"),
+            ],
+        );
+
+        let upstream = (Ipv4Addr::new(10, 0, 0, 1), 7000).into();
+        assert!(filter.matches((Ipv4Addr::new(192, 168, 0, 1), 5000).into(), upstream));
+        assert!(filter.matches(upstream, (Ipv4Addr::new(192, 168, 0, 1), 5000).into()));
+        assert!(!filter.matches((Ipv4Addr::new(192, 168, 0, 10), 5000).into(), upstream));
+        assert!(!filter.matches((Ipv4Addr::new(172, 16, 0, 1), 5000).into(), upstream));
+
+        let filter = pcap(directory.path(), vec![]);
+        assert!(filter.matches((Ipv4Addr::new(172, 16, 0, 1), 5000).into(), upstream));
+    }
+
+    // Capture state is process wide, so arming is only exercised in a single
+    // test to avoid interfering with other tests.
+    #[tokio::test]
+    async fn arm_and_capture() {
+        let directory = tempfile::tempdir().unwrap();
+        let filter = pcap(directory.path(), vec![]);
+        let read_ctx = || {
+            ReadContext::new(
+                vec![
+                    Endpoint::new((Ipv4Addr::LOCALHOST, 8080).into()),
+                    Endpoint::new((Ipv4Addr::LOCALHOST, 8081).into()),
+                ],
+                (Ipv4Addr::LOCALHOST, 7000).into(),
+                b"hello".to_vec(),
+            )
+        };
+
+        disarm();
+        filter.read(&mut read_ctx()).await.unwrap();
+        assert_eq!(0, filter.metrics.packets_captured_total_read.get());
+
+        assert_eq!(
+            CaptureStatus {
+                armed: true,
+                remaining_seconds: None,
+                remaining_packets: Some(2),
+            },
+            arm(None, Some(2)).unwrap()
+        );
+        for _ in 0..3 {
+            let mut ctx = read_ctx();
+            filter.read(&mut ctx).await.unwrap();
+            assert_eq!(b"hello", &*ctx.contents);
+        }
+        assert_eq!(2, filter.metrics.packets_captured_total_read.get());
+        assert!(!status().armed);
+
+        // A duration too long to represent doesn't arm capture.
+        assert!(arm(Some(Duration::from_secs(u64::MAX)), None).is_none());
+        assert!(!status().armed);
+
+        arm(Some(Duration::from_secs(60)), None).unwrap();
+        assert_write_no_change(&filter).await;
+        assert_eq!(1, filter.metrics.packets_captured_total_write.get());
+        assert!(status().remaining_seconds.is_some());
+        assert!(!disarm().armed);
+
+        filter.close();
+        let files = std::fs::read_dir(directory.path()).unwrap().count();
+        assert_eq!(1, files);
+    }
+
+    #[test]
+    fn convert_proto_config() {
+        let config = Config {
+            directory: "/tmp/captures".into(),
+            rules: vec![Rule {
+                action: Action::Deny,
+                source: "192.168.0.0/24".parse().unwrap(),
+                ports: vec![PortRange::new(10, 100).unwrap()],
+            }],
+            max_file_size: 1024,
+            max_files: 2,
+        };
+
+        let proto = proto::Pcap::from(config.clone());
+        assert_eq!(config, Config::try_from(proto).unwrap());
+    }
+
+    #[test]
+    fn missing_directory() {
+        assert!(Pcap::try_from_config(Some(Config {
+            directory: PathBuf::new(),
+            rules: vec![],
+            max_file_size: default_max_file_size(),
+            max_files: default_max_files(),
+        }))
+        .is_err());
+    }
+}
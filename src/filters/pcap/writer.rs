@@ -0,0 +1,391 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Encoding of captured packets into rotating [pcapng] files.
+//!
+//! [pcapng]: https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-01.html
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+/// Raw IP packets, the IP version is determined by the first nibble.
+const LINKTYPE_RAW: u16 = 101;
+const OPTION_EPB_FLAGS: u16 = 2;
+const UDP_PROTOCOL: u8 = 17;
+const DEFAULT_TTL: u8 = 64;
+
+/// Which way a captured packet was travelling through the proxy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Direction {
+    /// Received from downstream, travelling upstream.
+    Inbound,
+    /// Received from upstream, travelling downstream.
+    Outbound,
+}
+
+/// A single captured packet.
+#[derive(Clone, Debug)]
+pub(super) struct Record {
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    pub source: SocketAddr,
+    pub dest: SocketAddr,
+    pub contents: Vec<u8>,
+}
+
+/// Writes [`Record`]s to a set of pcapng files in a directory, starting a
+/// new file once the current one reaches `max_file_size` and removing the
+/// oldest file once there are more than `max_files`.
+pub(super) struct RotatingWriter {
+    directory: PathBuf,
+    max_file_size: u64,
+    max_files: usize,
+    files: VecDeque<PathBuf>,
+    current: Option<(BufWriter<File>, u64)>,
+}
+
+impl RotatingWriter {
+    pub fn new(directory: PathBuf, max_file_size: u64, max_files: usize) -> Self {
+        Self {
+            directory,
+            max_file_size,
+            max_files: max_files.max(1),
+            files: VecDeque::new(),
+            current: None,
+        }
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        let block = enhanced_packet_block(record);
+
+        let needs_rotation = match &self.current {
+            Some((_, size)) => {
+                *size > header_len() && size + block.len() as u64 > self.max_file_size
+            }
+            None => true,
+        };
+
+        if needs_rotation {
+            self.rotate()?;
+        }
+
+        let (file, size) = self.current.as_mut().unwrap();
+        file.write_all(&block)?;
+        *size += block.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((file, _)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        static FILE_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+        if let Some((mut file, _)) = self.current.take() {
+            file.flush()?;
+        }
+
+        std::fs::create_dir_all(&self.directory)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = self.directory.join(format!(
+            "quilkin-{timestamp}-{}.pcapng",
+            FILE_SEQUENCE.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let mut file = BufWriter::new(File::options().write(true).create_new(true).open(&path)?);
+        file.write_all(&section_header_block())?;
+        file.write_all(&interface_description_block())?;
+        self.current = Some((file, header_len()));
+        self.files.push_back(path);
+
+        while self.files.len() > self.max_files {
+            let path = self.files.pop_front().unwrap();
+            if let Err(error) = std::fs::remove_file(&path) {
+                tracing::warn!(%error, path = %path.display(), "failed to remove old capture file");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn header_len() -> u64 {
+    (section_header_block().len() + interface_description_block().len()) as u64
+}
+
+/// Wraps `body` in a pcapng block of type `kind`.
+fn block(kind: u32, body: &[u8]) -> Vec<u8> {
+    let padding = (4 - body.len() % 4) % 4;
+    let length = (12 + body.len() + padding) as u32;
+    let mut block = Vec::with_capacity(length as usize);
+    block.extend_from_slice(&kind.to_le_bytes());
+    block.extend_from_slice(&length.to_le_bytes());
+    block.extend_from_slice(body);
+    block.resize(block.len() + padding, 0);
+    block.extend_from_slice(&length.to_le_bytes());
+    block
+}
+
+fn section_header_block() -> Vec<u8> {
+    let mut body = Vec::with_capacity(16);
+    body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    // Major and minor version.
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // Section length, -1 means unspecified.
+    body.extend_from_slice(&(-1i64).to_le_bytes());
+    block(SECTION_HEADER_BLOCK, &body)
+}
+
+fn interface_description_block() -> Vec<u8> {
+    let mut body = Vec::with_capacity(8);
+    body.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    // Reserved.
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // Snapshot length, zero means no limit.
+    body.extend_from_slice(&0u32.to_le_bytes());
+    block(INTERFACE_DESCRIPTION_BLOCK, &body)
+}
+
+fn enhanced_packet_block(record: &Record) -> Vec<u8> {
+    let packet = encode_udp_packet(record.source, record.dest, &record.contents);
+    // The default timestamp resolution is microseconds.
+    let micros = record
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+    let padding = (4 - packet.len() % 4) % 4;
+
+    let mut body = Vec::with_capacity(20 + packet.len() + padding + 12);
+    // Interface ID
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(micros as u32).to_le_bytes());
+    // Captured and original packet length.
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(&packet);
+    body.resize(body.len() + padding, 0);
+
+    let flags: u32 = match record.direction {
+        Direction::Inbound => 0b01,
+        Direction::Outbound => 0b10,
+    };
+    body.extend_from_slice(&OPTION_EPB_FLAGS.to_le_bytes());
+    body.extend_from_slice(&4u16.to_le_bytes());
+    body.extend_from_slice(&flags.to_le_bytes());
+    // End of options.
+    body.extend_from_slice(&[0; 4]);
+
+    block(ENHANCED_PACKET_BLOCK, &body)
+}
+
+/// Encodes `contents` as a UDP datagram inside an IPv4 or IPv6 packet. If the
+/// addresses are of mixed families, the IPv4 address is mapped to IPv6.
+fn encode_udp_packet(source: SocketAddr, dest: SocketAddr, contents: &[u8]) -> Vec<u8> {
+    let udp_length = (8 + contents.len()) as u16;
+    let mut udp = Vec::with_capacity(udp_length as usize);
+    udp.extend_from_slice(&source.port().to_be_bytes());
+    udp.extend_from_slice(&dest.port().to_be_bytes());
+    udp.extend_from_slice(&udp_length.to_be_bytes());
+    udp.extend_from_slice(&[0; 2]);
+    udp.extend_from_slice(contents);
+
+    let (source_ip, dest_ip) = match (to_canonical(source.ip()), to_canonical(dest.ip())) {
+        (IpAddr::V4(source), IpAddr::V4(dest)) => {
+            let mut pseudo_header = Vec::with_capacity(12);
+            pseudo_header.extend_from_slice(&source.octets());
+            pseudo_header.extend_from_slice(&dest.octets());
+            pseudo_header.extend_from_slice(&[0, UDP_PROTOCOL]);
+            pseudo_header.extend_from_slice(&udp_length.to_be_bytes());
+            set_udp_checksum(&mut udp, &pseudo_header);
+
+            let total_length = 20 + udp_length;
+            let mut packet = Vec::with_capacity(total_length as usize);
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&total_length.to_be_bytes());
+            // Identification, and the "don't fragment" flag.
+            packet.extend_from_slice(&[0, 0, 0x40, 0]);
+            packet.extend_from_slice(&[DEFAULT_TTL, UDP_PROTOCOL, 0, 0]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&dest.octets());
+            let checksum = checksum(&[&packet]);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+            packet.extend_from_slice(&udp);
+            return packet;
+        }
+        (source, dest) => (to_ipv6(source), to_ipv6(dest)),
+    };
+
+    let mut pseudo_header = Vec::with_capacity(40);
+    pseudo_header.extend_from_slice(&source_ip.octets());
+    pseudo_header.extend_from_slice(&dest_ip.octets());
+    pseudo_header.extend_from_slice(&u32::from(udp_length).to_be_bytes());
+    pseudo_header.extend_from_slice(&[0, 0, 0, UDP_PROTOCOL]);
+    set_udp_checksum(&mut udp, &pseudo_header);
+
+    let mut packet = Vec::with_capacity(40 + udp.len());
+    packet.extend_from_slice(&[0x60, 0, 0, 0]);
+    packet.extend_from_slice(&udp_length.to_be_bytes());
+    packet.extend_from_slice(&[UDP_PROTOCOL, DEFAULT_TTL]);
+    packet.extend_from_slice(&source_ip.octets());
+    packet.extend_from_slice(&dest_ip.octets());
+    packet.extend_from_slice(&udp);
+    packet
+}
+
+fn to_canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn set_udp_checksum(udp: &mut [u8], pseudo_header: &[u8]) {
+    let checksum = match checksum(&[pseudo_header, udp]) {
+        // A computed checksum of zero is transmitted as all ones.
+        0 => 0xFFFF,
+        checksum => checksum,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// The internet checksum (RFC 1071) over the concatenation of `parts`, each
+/// part other than the last is expected to be of even length.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for part in parts {
+        for chunk in part.chunks(2) {
+            let word = match chunk {
+                [high, low] => u16::from_be_bytes([*high, *low]),
+                [high] => u16::from_be_bytes([*high, 0]),
+                _ => unreachable!(),
+            };
+            sum += u32::from(word);
+        }
+    }
+
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn ipv4_packet() {
+        let packet = encode_udp_packet(
+            (Ipv4Addr::new(192, 168, 0, 1), 7000).into(),
+            (Ipv4Addr::new(192, 168, 0, 2), 8000).into(),
+            b"hello",
+        );
+
+        assert_eq!(20 + 8 + 5, packet.len());
+        assert_eq!(0x45, packet[0]);
+        assert_eq!(UDP_PROTOCOL, packet[9]);
+        assert_eq!([192, 168, 0, 1], packet[12..16]);
+        assert_eq!([192, 168, 0, 2], packet[16..20]);
+        assert_eq!(7000u16.to_be_bytes(), packet[20..22]);
+        assert_eq!(8000u16.to_be_bytes(), packet[22..24]);
+        assert_eq!(b"hello", &packet[28..]);
+        // A valid header sums to zero.
+        assert_eq!(0, checksum(&[&packet[..20]]));
+    }
+
+    #[test]
+    fn mixed_family_packet() {
+        let packet = encode_udp_packet(
+            (Ipv4Addr::LOCALHOST, 7000).into(),
+            (Ipv6Addr::LOCALHOST, 8000).into(),
+            b"hello",
+        );
+
+        assert_eq!(40 + 8 + 5, packet.len());
+        assert_eq!(0x60, packet[0]);
+        assert_eq!(Ipv4Addr::LOCALHOST.to_ipv6_mapped().octets(), packet[8..24]);
+        assert_eq!(Ipv6Addr::LOCALHOST.octets(), packet[24..40]);
+    }
+
+    #[test]
+    fn rotates_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let record = Record {
+            timestamp: SystemTime::now(),
+            direction: Direction::Inbound,
+            source: (Ipv4Addr::LOCALHOST, 7000).into(),
+            dest: (Ipv4Addr::LOCALHOST, 8000).into(),
+            contents: vec![0; 100],
+        };
+        let block_len = enhanced_packet_block(&record).len() as u64;
+
+        // Fits exactly two packets per file, and keeps at most two files.
+        let mut writer =
+            RotatingWriter::new(directory.path().into(), header_len() + block_len * 2, 2);
+        for _ in 0..5 {
+            writer.write(&record).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let mut files = std::fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(2, files.len());
+
+        let sizes = files
+            .iter()
+            .map(|path| std::fs::metadata(path).unwrap().len())
+            .collect::<Vec<_>>();
+        assert!(sizes.contains(&(header_len() + block_len * 2)));
+        assert!(sizes.contains(&(header_len() + block_len)));
+
+        let contents = std::fs::read(&files[0]).unwrap();
+        assert_eq!(SECTION_HEADER_BLOCK.to_le_bytes(), contents[..4]);
+    }
+}
@@ -0,0 +1,673 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Loading filters from native plugins, shared libraries which provide
+//! [`StaticFilter`]s through a stable C ABI, allowing proprietary filters to
+//! be used without recompiling Quilkin.
+//!
+//! Plugins are built as a `cdylib` depending on `quilkin`, and export their
+//! filters with [`export_filter_plugin!`][crate::export_filter_plugin].
+//! As a plugin has its own copy of Quilkin's runtime state, its filters are
+//! run outside of any Tokio runtime, and any metrics they register aren't
+//! exported by the proxy.
+//!
+//! Plugin filters are called synchronously on the proxy's worker threads, so
+//! they must not block. Their futures are polled once, and a filter that
+//! doesn't complete immediately fails with an error instead of being waited
+//! on.
+
+#[doc(hidden)]
+pub mod ffi;
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::c_void,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::ConfigType,
+    endpoint::{Endpoint, EndpointAddress},
+    filters::{prelude::*, DynFilterFactory, FilterFactory},
+    metadata::{DynamicMetadata, Key, Value},
+};
+
+pub use self::ffi::ABI_VERSION;
+
+crate::include_proto!("quilkin.plugin.v1alpha1");
+use self::quilkin::plugin::v1alpha1 as proto;
+
+/// Exports [`StaticFilter`]s from a plugin library, so that they can be
+/// loaded with [`Plugin::load`].
+///
+/// ```rust,ignore
+/// quilkin::export_filter_plugin!(Greet, Farewell);
+/// ```
+#[macro_export]
+macro_rules! export_filter_plugin {
+    ($($filter:ty),+ $(,)?) => {
+        #[no_mangle]
+        pub extern "C" fn quilkin_filter_plugin(
+        ) -> *const $crate::filters::plugin::ffi::PluginDescriptor {
+            use $crate::filters::plugin::ffi::{FilterDescriptor, PluginDescriptor};
+
+            static FILTERS: &[FilterDescriptor] = &[$(FilterDescriptor::new::<$filter>()),+];
+            static DESCRIPTOR: PluginDescriptor = PluginDescriptor::new(FILTERS);
+            &DESCRIPTOR
+        }
+    };
+}
+
+/// A loaded plugin library.
+pub struct Plugin {
+    path: PathBuf,
+    descriptor: &'static ffi::PluginDescriptor,
+}
+
+impl Plugin {
+    /// Loads the plugin library at `path`, checking that it was built with a
+    /// compatible [`ABI_VERSION`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CreationError> {
+        let path = path.as_ref();
+        let load_failed = |reason: String| CreationError::PluginLoadFailed {
+            path: path.display().to_string(),
+            reason,
+        };
+
+        // SAFETY: Loading a library runs its initialisation code, plugins are
+        // trusted in the same way as the proxy's own binary.
+        let library = unsafe { libloading::Library::new(path) }
+            .map_err(|error| load_failed(error.to_string()))?;
+        // Plugins are never unloaded, as filters created from them may be
+        // running at any time.
+        let library: &'static libloading::Library = Box::leak(Box::new(library));
+
+        // SAFETY: The entry point's signature is part of the ABI.
+        let entry_point = unsafe { library.get::<ffi::EntryPoint>(ffi::ENTRY_POINT.as_bytes()) }
+            .map_err(|error| load_failed(error.to_string()))?;
+
+        // SAFETY: The library is never unloaded.
+        unsafe { Self::from_entry_point(path, *entry_point) }
+    }
+
+    /// Creates a plugin from its entry point.
+    ///
+    /// # Safety
+    /// `entry_point` must have been exported by
+    /// [`export_filter_plugin!`][crate::export_filter_plugin], from a library
+    /// that is never unloaded.
+    pub unsafe fn from_entry_point(
+        path: impl AsRef<Path>,
+        entry_point: ffi::EntryPoint,
+    ) -> Result<Self, CreationError> {
+        let path = path.as_ref();
+        let descriptor = entry_point();
+
+        if descriptor.is_null() {
+            return Err(CreationError::PluginLoadFailed {
+                path: path.display().to_string(),
+                reason: "plugin returned no descriptor".into(),
+            });
+        }
+
+        // Only the version is read until it's known to be compatible, as the
+        // rest of the descriptor's layout may differ between versions.
+        let abi_version = descriptor.cast::<u32>().read();
+        if abi_version != ABI_VERSION {
+            return Err(CreationError::PluginAbiMismatch {
+                path: path.display().to_string(),
+                expected: ABI_VERSION,
+                actual: abi_version,
+            });
+        }
+
+        Ok(Self {
+            path: path.into(),
+            descriptor: &*descriptor,
+        })
+    }
+
+    /// Returns the path the plugin was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns factories for each of the filters provided by the plugin.
+    pub fn factories(&self) -> Result<Vec<DynFilterFactory>, CreationError> {
+        let filters = match self.descriptor.filters_len {
+            0 => &[][..],
+            // SAFETY: Checked by `from_entry_point`.
+            len => unsafe { std::slice::from_raw_parts(self.descriptor.filters, len) },
+        };
+
+        filters
+            .iter()
+            .map(|filter| {
+                // SAFETY: Filter names are static strings in the plugin.
+                let name = unsafe { filter.name.as_bytes() }
+                    .and_then(|name| std::str::from_utf8(name).ok())
+                    .ok_or_else(|| CreationError::PluginLoadFailed {
+                        path: self.path.display().to_string(),
+                        reason: "filter name is not valid UTF-8".into(),
+                    })?;
+
+                Ok(Box::new(PluginFilterFactory {
+                    name,
+                    filter,
+                    free_buffer: self.descriptor.free_buffer,
+                }) as DynFilterFactory)
+            })
+            .collect()
+    }
+}
+
+/// A [`FilterFactory`] for a filter provided by a plugin.
+#[derive(Clone, Copy)]
+struct PluginFilterFactory {
+    name: &'static str,
+    filter: &'static ffi::FilterDescriptor,
+    free_buffer: unsafe extern "C" fn(ffi::Buffer),
+}
+
+impl PluginFilterFactory {
+    /// Makes a call into the plugin, returning its output, or its error
+    /// message.
+    fn call(&self, call: impl FnOnce(*mut ffi::Buffer) -> bool) -> Result<Vec<u8>, String> {
+        let mut buffer = ffi::Buffer::EMPTY;
+        let success = call(&mut buffer);
+        let output = buffer.to_vec();
+        // SAFETY: The buffer was allocated by the plugin.
+        unsafe { (self.free_buffer)(buffer) };
+
+        if success {
+            Ok(output)
+        } else {
+            Err(String::from_utf8_lossy(&output).into_owned())
+        }
+    }
+
+    fn error(&self, reason: String) -> CreationError {
+        CreationError::PluginFailed {
+            name: self.name.into(),
+            reason,
+        }
+    }
+}
+
+impl FilterFactory for PluginFilterFactory {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn config_schema(&self) -> schemars::schema::RootSchema {
+        self.call(|out| unsafe { (self.filter.config_schema)(ffi::Slice::NULL, out) })
+            .map_err(|error| self.error(error))
+            .and_then(|schema| serde_json::from_slice(&schema).map_err(From::from))
+            .unwrap_or_else(|error| {
+                tracing::warn!(%error, "failed to get plugin filter's config schema");
+                schemars::schema_for!(serde_json::Value)
+            })
+    }
+
+    fn create_filter(&self, args: CreateFilterArgs) -> Result<FilterInstance, CreationError> {
+        let config = match args.config {
+            None => None,
+            Some(ConfigType::Static(config)) => Some(config),
+            Some(ConfigType::Dynamic(config)) => Some(self.encode_config_to_json(config)?),
+        };
+        let json = config.as_ref().map(serde_json::to_vec).transpose()?;
+
+        let mut filter = std::ptr::null_mut();
+        self.call(|out| unsafe {
+            (self.filter.create)(
+                json.as_deref().map_or(ffi::Slice::NULL, ffi::Slice::new),
+                &mut filter,
+                out,
+            )
+        })
+        .map_err(|error| self.error(error))?;
+
+        Ok(FilterInstance::new(
+            config.unwrap_or_default(),
+            Box::new(PluginFilter {
+                factory: *self,
+                filter,
+            }),
+        ))
+    }
+
+    fn encode_config_to_protobuf(
+        &self,
+        config: serde_json::Value,
+    ) -> Result<prost_types::Any, CreationError> {
+        let config = serde_json::to_vec(&config)?;
+        let value = self
+            .call(|out| unsafe { (self.filter.encode_config)(ffi::Slice::new(&config), out) })
+            .map_err(|error| self.error(error))?;
+
+        Ok(prost_types::Any {
+            type_url: self.name.into(),
+            value,
+        })
+    }
+
+    fn encode_config_to_json(
+        &self,
+        config: prost_types::Any,
+    ) -> Result<serde_json::Value, CreationError> {
+        if self.name != config.type_url {
+            return Err(CreationError::MismatchedTypes {
+                expected: self.name.into(),
+                actual: config.type_url,
+            });
+        }
+
+        let json = self
+            .call(|out| unsafe { (self.filter.decode_config)(ffi::Slice::new(&config.value), out) })
+            .map_err(|error| self.error(error))?;
+
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+/// A filter created by a plugin.
+struct PluginFilter {
+    factory: PluginFilterFactory,
+    filter: *mut c_void,
+}
+
+// SAFETY: Plugin filters are created from `StaticFilter`s, which are `Send`
+// and `Sync`.
+unsafe impl Send for PluginFilter {}
+unsafe impl Sync for PluginFilter {}
+
+impl PluginFilter {
+    fn call(
+        &self,
+        call: ffi::FilterCall,
+        packet: proto::Packet,
+    ) -> Result<proto::Packet, FilterError> {
+        use prost::Message;
+        use proto::outcome::{DropReason as Reason, Outcome};
+
+        let packet = packet.encode_to_vec();
+        let outcome = self
+            .factory
+            .call(|out| unsafe { call(self.filter, ffi::Slice::new(&packet), out) })
+            .map_err(FilterError::new)?;
+
+        match proto::Outcome::decode(&*outcome)
+            .map_err(FilterError::new)?
+            .outcome
+        {
+            Some(Outcome::Packet(packet)) => Ok(packet),
+            Some(Outcome::Drop(reason)) => Err(FilterError::drop(
+                match Reason::try_from(reason).unwrap_or(Reason::Intentional) {
+                    Reason::Intentional => DropReason::Intentional,
+                    Reason::Denied => DropReason::Denied,
+                    Reason::RateLimited => DropReason::RateLimited,
+                    Reason::NoRoute => DropReason::NoRoute,
+                },
+            )),
+            Some(Outcome::Error(error)) => Err(FilterError::new(error)),
+            None => Err(FilterError::new("plugin returned no outcome")),
+        }
+    }
+}
+
+impl Drop for PluginFilter {
+    fn drop(&mut self) {
+        // SAFETY: The filter was created by the same plugin.
+        unsafe { (self.factory.filter.destroy)(self.filter) }
+    }
+}
+
+#[async_trait::async_trait]
+impl Filter for PluginFilter {
+    async fn read(&self, ctx: &mut ReadContext) -> Result<(), FilterError> {
+        let packet = self.call(
+            self.factory.filter.read,
+            proto::Packet {
+                contents: ctx.contents.clone(),
+                metadata: metadata_to_proto(ctx.metadata.clone()),
+                source: ctx.source.to_string(),
+                destination: None,
+                endpoints: ctx.endpoints.iter().map(endpoint_to_proto).collect(),
+            },
+        )?;
+
+        // Only the removal of endpoints is applied, any other changes the
+        // plugin made to them are ignored.
+        let retained = packet
+            .endpoints
+            .iter()
+            .map(|endpoint| endpoint.address.parse())
+            .collect::<Result<HashSet<EndpointAddress>, _>>()
+            .map_err(FilterError::new)?;
+
+        ctx.contents = packet.contents;
+        ctx.metadata = metadata_from_proto(packet.metadata).map_err(FilterError::new)?;
+        ctx.endpoints
+            .retain(|endpoint| retained.contains(&endpoint.address));
+        Ok(())
+    }
+
+    async fn write(&self, ctx: &mut WriteContext) -> Result<(), FilterError> {
+        let packet = self.call(
+            self.factory.filter.write,
+            proto::Packet {
+                contents: ctx.contents.clone(),
+                metadata: metadata_to_proto(ctx.metadata.clone()),
+                source: ctx.source.to_string(),
+                destination: Some(ctx.dest.to_string()),
+                endpoints: Vec::new(),
+            },
+        )?;
+
+        ctx.contents = packet.contents;
+        ctx.metadata = metadata_from_proto(packet.metadata).map_err(FilterError::new)?;
+        Ok(())
+    }
+}
+
+fn endpoint_to_proto(endpoint: &Endpoint) -> proto::Endpoint {
+    proto::Endpoint {
+        address: endpoint.address.to_string(),
+        metadata: serde_json::to_value(&endpoint.metadata)
+            .ok()
+            .and_then(|metadata| Value::try_from(metadata).ok())
+            .map(From::from),
+    }
+}
+
+fn endpoint_from_proto(endpoint: proto::Endpoint) -> crate::Result<Endpoint> {
+    let address = endpoint.address.parse()?;
+    Ok(match endpoint.metadata {
+        Some(metadata) => Endpoint::with_metadata(
+            address,
+            serde_json::from_value::<crate::endpoint::EndpointMetadata>(
+                Value::try_from(metadata)?.into(),
+            )?,
+        ),
+        None => Endpoint::new(address),
+    })
+}
+
+fn metadata_to_proto(metadata: DynamicMetadata) -> HashMap<String, proto::Value> {
+    metadata
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.into()))
+        .collect()
+}
+
+fn metadata_from_proto(metadata: HashMap<String, proto::Value>) -> crate::Result<DynamicMetadata> {
+    metadata
+        .into_iter()
+        .map(|(key, value)| Ok((Key::new(key), value.try_into()?)))
+        .collect()
+}
+
+impl From<Value> for proto::Value {
+    fn from(value: Value) -> Self {
+        use proto::value::{Kind, List, Map};
+
+        Self {
+            kind: Some(match value {
+                Value::Bool(value) => Kind::Bool(value),
+                Value::Number(value) => Kind::Number(value),
+                Value::Integer(value) => Kind::Integer(value),
+                Value::Float(value) => Kind::Float(value),
+                Value::String(value) => Kind::String(value),
+                Value::Bytes(value) => Kind::Bytes(value.into()),
+                Value::List(values) => Kind::List(List {
+                    values: values.into_iter().map(From::from).collect(),
+                }),
+                Value::Map(values) => Kind::Map(Map {
+                    values: values
+                        .into_iter()
+                        .map(|(key, value)| (key, value.into()))
+                        .collect(),
+                }),
+            }),
+        }
+    }
+}
+
+impl TryFrom<proto::Value> for Value {
+    type Error = eyre::Report;
+
+    fn try_from(value: proto::Value) -> Result<Self, Self::Error> {
+        use proto::value::Kind;
+
+        Ok(match value.kind {
+            None => return Err(eyre::eyre!("unexpected missing value")),
+            Some(Kind::Bool(value)) => Self::Bool(value),
+            Some(Kind::Number(value)) => Self::Number(value),
+            Some(Kind::Integer(value)) => Self::Integer(value),
+            Some(Kind::Float(value)) => Self::Float(value),
+            Some(Kind::String(value)) => Self::String(value),
+            Some(Kind::Bytes(value)) => Self::Bytes(value.into()),
+            Some(Kind::List(list)) => Self::List(
+                list.values
+                    .into_iter()
+                    .map(Self::try_from)
+                    .collect::<crate::Result<_>>()?,
+            ),
+            Some(Kind::Map(map)) => Self::Map(
+                map.values
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, value.try_into()?)))
+                    .collect::<crate::Result<_>>()?,
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::filters::{Capture, Drop, StaticFilter, TokenRouter};
+
+    crate::export_filter_plugin!(Capture, Drop, TokenRouter);
+
+    fn factories() -> Vec<DynFilterFactory> {
+        unsafe { Plugin::from_entry_point("test", quilkin_filter_plugin) }
+            .unwrap()
+            .factories()
+            .unwrap()
+    }
+
+    fn read_ctx(contents: &[u8]) -> ReadContext {
+        ReadContext::new(
+            vec![Endpoint::new((Ipv4Addr::LOCALHOST, 8080).into())],
+            (Ipv4Addr::LOCALHOST, 7001).into(),
+            contents.to_vec(),
+        )
+    }
+
+    #[tokio::test]
+    async fn filters() {
+        let factories = factories();
+        assert_eq!(
+            vec![Capture::NAME, Drop::NAME, TokenRouter::NAME],
+            factories
+                .iter()
+                .map(|factory| factory.name())
+                .collect::<Vec<_>>()
+        );
+
+        let config = serde_json::json!({ "suffix": { "size": 3, "remove": true } });
+        let capture = factories[0]
+            .create_filter(CreateFilterArgs::fixed(Some(config.clone())))
+            .unwrap();
+        assert_eq!(&config, capture.config());
+
+        let mut ctx = read_ctx(b"helloabc");
+        capture.filter().read(&mut ctx).await.unwrap();
+        assert_eq!(b"hello", &*ctx.contents);
+        assert_eq!(
+            Some(&Value::Bytes("abc".into())),
+            ctx.metadata
+                .get(&Key::from_static(crate::filters::metadata::CAPTURED_BYTES))
+        );
+
+        // Drop reasons are preserved across the plugin boundary.
+        let drop = factories[1]
+            .create_filter(CreateFilterArgs::fixed(None))
+            .unwrap();
+        assert_eq!(
+            Some(DropReason::Intentional),
+            drop.filter()
+                .read(&mut read_ctx(b"hello"))
+                .await
+                .unwrap_err()
+                .drop_reason()
+        );
+
+        // Endpoint metadata is passed to plugins, so they can route on it.
+        let router = factories[2]
+            .create_filter(CreateFilterArgs::fixed(None))
+            .unwrap();
+        let mut ctx = ReadContext::new(
+            vec![
+                Endpoint::with_metadata(
+                    (Ipv4Addr::LOCALHOST, 8080).into(),
+                    crate::endpoint::Metadata {
+                        tokens: vec!["abc"].into_iter().map(From::from).collect(),
+                    },
+                ),
+                Endpoint::new((Ipv4Addr::LOCALHOST, 8081).into()),
+            ],
+            (Ipv4Addr::LOCALHOST, 7001).into(),
+            b"hello".to_vec(),
+        );
+        ctx.metadata.insert(
+            Key::from_static(crate::filters::metadata::CAPTURED_BYTES),
+            Value::Bytes("abc".into()),
+        );
+        router.filter().read(&mut ctx).await.unwrap();
+        assert_eq!(
+            vec![EndpointAddress::from((Ipv4Addr::LOCALHOST, 8080))],
+            ctx.endpoints
+                .iter()
+                .map(|endpoint| endpoint.address.clone())
+                .collect::<Vec<_>>()
+        );
+
+        ctx.metadata.insert(
+            Key::from_static(crate::filters::metadata::CAPTURED_BYTES),
+            Value::Bytes("xyz".into()),
+        );
+        assert_eq!(
+            Some(DropReason::NoRoute),
+            router
+                .filter()
+                .read(&mut ctx)
+                .await
+                .unwrap_err()
+                .drop_reason()
+        );
+    }
+
+    #[tokio::test]
+    async fn dynamic_config() {
+        let factories = factories();
+        let config = serde_json::json!({ "suffix": { "size": 3, "remove": false } });
+        let any = factories[0]
+            .encode_config_to_protobuf(config.clone())
+            .unwrap();
+        assert_eq!(Capture::NAME, any.type_url);
+        assert_eq!(
+            Capture::factory()
+                .encode_config_to_json(any.clone())
+                .unwrap(),
+            factories[0].encode_config_to_json(any.clone()).unwrap()
+        );
+
+        let capture = factories[0]
+            .create_filter(CreateFilterArgs::dynamic(Some(any)))
+            .unwrap();
+        let mut ctx = read_ctx(b"helloabc");
+        capture.filter().read(&mut ctx).await.unwrap();
+        assert_eq!(b"helloabc", &*ctx.contents);
+
+        assert!(matches!(
+            factories[0].create_filter(CreateFilterArgs::fixed(None)),
+            Err(CreationError::PluginFailed { .. })
+        ));
+        assert!(matches!(
+            factories[0].encode_config_to_json(prost_types::Any {
+                type_url: Drop::NAME.into(),
+                value: Vec::new(),
+            }),
+            Err(CreationError::MismatchedTypes { .. })
+        ));
+    }
+
+    #[test]
+    fn abi_mismatch() {
+        extern "C" fn old_plugin() -> *const ffi::PluginDescriptor {
+            static DESCRIPTOR: ffi::PluginDescriptor = ffi::PluginDescriptor {
+                abi_version: 0,
+                ..ffi::PluginDescriptor::new(&[])
+            };
+            &DESCRIPTOR
+        }
+
+        assert_eq!(
+            CreationError::PluginAbiMismatch {
+                path: "old".into(),
+                expected: ABI_VERSION,
+                actual: 0,
+            },
+            unsafe { Plugin::from_entry_point("old", old_plugin) }
+                .err()
+                .unwrap()
+        );
+
+        assert!(matches!(
+            Plugin::load("/nonexistent/plugin.so").err().unwrap(),
+            CreationError::PluginLoadFailed { .. }
+        ));
+    }
+
+    #[test]
+    fn value_round_trip() {
+        let value = Value::Map(
+            [
+                ("bytes".into(), Value::Bytes("abc".into())),
+                ("string".into(), Value::String("abc".into())),
+                (
+                    "list".into(),
+                    Value::List(vec![
+                        Value::Number(1),
+                        Value::Integer(-1),
+                        Value::Float(1.5),
+                        Value::Bool(true),
+                    ]),
+                ),
+            ]
+            .into(),
+        );
+
+        assert_eq!(
+            value,
+            Value::try_from(proto::Value::from(value.clone())).unwrap()
+        );
+    }
+}
@@ -0,0 +1,367 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The C ABI between Quilkin and native filter plugins.
+//!
+//! A plugin exports a [`ENTRY_POINT`] function returning a
+//! [`PluginDescriptor`], which lists the filters it provides. Configuration
+//! is passed as JSON, or as the encoded protobuf value of the filter's
+//! [`prost_types::Any`] configuration, and packets are passed as encoded
+//! [`proto::Packet`]s, so that no Rust types cross the boundary. Plugins
+//! don't implement any of this directly, they use
+//! [`export_filter_plugin!`][crate::export_filter_plugin] which wraps their
+//! [`StaticFilter`]s.
+
+use std::{any::Any, ffi::c_void, future::Future, panic::AssertUnwindSafe};
+
+use futures::FutureExt;
+use prost::Message;
+
+use super::proto;
+use crate::{
+    endpoint::{Endpoint, EndpointAddress},
+    filters::prelude::*,
+};
+
+/// The version of the ABI described by this module, which must match the
+/// version a plugin was built with for it to be loaded.
+pub const ABI_VERSION: u32 = 1;
+
+/// The name of the function a plugin exports to describe itself.
+pub const ENTRY_POINT: &str = "quilkin_filter_plugin";
+
+/// The signature of a plugin's [`ENTRY_POINT`].
+pub type EntryPoint = unsafe extern "C" fn() -> *const PluginDescriptor;
+
+/// A borrowed slice of bytes, or no bytes at all if `ptr` is null.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Slice {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl Slice {
+    pub const NULL: Self = Self {
+        ptr: std::ptr::null(),
+        len: 0,
+    };
+
+    pub const fn new(bytes: &[u8]) -> Self {
+        Self {
+            ptr: bytes.as_ptr(),
+            len: bytes.len(),
+        }
+    }
+
+    /// # Safety
+    /// `ptr` must be null, or valid for reads of `len` bytes for `'a`.
+    pub unsafe fn as_bytes<'a>(self) -> Option<&'a [u8]> {
+        (!self.ptr.is_null()).then(|| std::slice::from_raw_parts(self.ptr, self.len))
+    }
+}
+
+/// A buffer allocated by a plugin, which must be released with the plugin's
+/// [`PluginDescriptor::free_buffer`].
+#[repr(C)]
+pub struct Buffer {
+    pub ptr: *mut u8,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl Buffer {
+    pub const EMPTY: Self = Self {
+        ptr: std::ptr::null_mut(),
+        len: 0,
+        capacity: 0,
+    };
+
+    fn from_vec(bytes: Vec<u8>) -> Self {
+        let mut bytes = std::mem::ManuallyDrop::new(bytes);
+        Self {
+            ptr: bytes.as_mut_ptr(),
+            len: bytes.len(),
+            capacity: bytes.capacity(),
+        }
+    }
+
+    /// Copies the contents of the buffer.
+    pub fn to_vec(&self) -> Vec<u8> {
+        if self.ptr.is_null() {
+            Vec::new()
+        } else {
+            // SAFETY: Buffers are only created from vectors.
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }.to_vec()
+        }
+    }
+}
+
+/// Describes a plugin and the filters it provides.
+#[repr(C)]
+pub struct PluginDescriptor {
+    /// The [`ABI_VERSION`] the plugin was built with. This is the first field
+    /// so that it can be checked before relying on the rest of the layout.
+    pub abi_version: u32,
+    pub filters: *const FilterDescriptor,
+    pub filters_len: usize,
+    pub free_buffer: unsafe extern "C" fn(Buffer),
+}
+
+// SAFETY: Descriptors are immutable statics.
+unsafe impl Sync for PluginDescriptor {}
+
+impl PluginDescriptor {
+    pub const fn new(filters: &'static [FilterDescriptor]) -> Self {
+        Self {
+            abi_version: ABI_VERSION,
+            filters: filters.as_ptr(),
+            filters_len: filters.len(),
+            free_buffer,
+        }
+    }
+}
+
+/// A fallible call, which returns `true` and writes its output to `out` on
+/// success, or returns `false` and writes a UTF-8 error message to `out`.
+pub type Call = unsafe extern "C" fn(input: Slice, out: *mut Buffer) -> bool;
+
+/// A fallible call on a filter created by [`FilterDescriptor::create`],
+/// taking an encoded [`proto::Packet`], and writing an encoded
+/// [`proto::Outcome`] to `out`.
+pub type FilterCall =
+    unsafe extern "C" fn(filter: *const c_void, packet: Slice, out: *mut Buffer) -> bool;
+
+/// Describes a single filter provided by a plugin.
+#[repr(C)]
+pub struct FilterDescriptor {
+    /// The filter's UTF-8 name, see [`FilterFactory::name`].
+    pub name: Slice,
+    /// Writes the JSON schema of the filter's configuration, the input is
+    /// ignored.
+    pub config_schema: Call,
+    /// Creates a filter from its JSON configuration, or from no configuration
+    /// if the input is null, writing the filter's pointer to `filter`.
+    pub create:
+        unsafe extern "C" fn(config: Slice, filter: *mut *mut c_void, out: *mut Buffer) -> bool,
+    /// Destroys a filter created by `create`.
+    pub destroy: unsafe extern "C" fn(filter: *mut c_void),
+    pub read: FilterCall,
+    pub write: FilterCall,
+    /// Converts JSON configuration into its encoded protobuf equivalent.
+    pub encode_config: Call,
+    /// Converts encoded protobuf configuration into its JSON equivalent.
+    pub decode_config: Call,
+}
+
+// SAFETY: Descriptors are immutable statics.
+unsafe impl Sync for FilterDescriptor {}
+
+impl FilterDescriptor {
+    pub const fn new<F>() -> Self
+    where
+        F: StaticFilter + 'static,
+        CreationError: From<<F::Configuration as TryFrom<F::BinaryConfiguration>>::Error>
+            + From<<F::BinaryConfiguration as TryFrom<F::Configuration>>::Error>,
+    {
+        Self {
+            name: Slice::new(F::NAME.as_bytes()),
+            config_schema: config_schema::<F>,
+            create: create::<F>,
+            destroy,
+            read,
+            write,
+            encode_config: encode_config::<F>,
+            decode_config: decode_config::<F>,
+        }
+    }
+}
+
+/// Runs `f`, writing its output or error to `out`, and catching any panics
+/// so that they don't unwind across the ABI boundary.
+unsafe fn guard(out: *mut Buffer, f: impl FnOnce() -> crate::Result<Vec<u8>>) -> bool {
+    let (success, output) = match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(output)) => (true, output),
+        Ok(Err(error)) => (false, error.to_string().into_bytes()),
+        Err(panic) => (false, panic_message(&*panic).into_bytes()),
+    };
+
+    *out = Buffer::from_vec(output);
+    success
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic");
+    format!("plugin panicked: {message}")
+}
+
+unsafe extern "C" fn free_buffer(buffer: Buffer) {
+    if !buffer.ptr.is_null() {
+        drop(Vec::from_raw_parts(buffer.ptr, buffer.len, buffer.capacity));
+    }
+}
+
+unsafe extern "C" fn config_schema<F>(_: Slice, out: *mut Buffer) -> bool
+where
+    F: StaticFilter + 'static,
+    CreationError: From<<F::Configuration as TryFrom<F::BinaryConfiguration>>::Error>
+        + From<<F::BinaryConfiguration as TryFrom<F::Configuration>>::Error>,
+{
+    guard(out, || {
+        Ok(serde_json::to_vec(&F::factory().config_schema())?)
+    })
+}
+
+unsafe extern "C" fn create<F>(config: Slice, filter: *mut *mut c_void, out: *mut Buffer) -> bool
+where
+    F: StaticFilter + 'static,
+    CreationError: From<<F::Configuration as TryFrom<F::BinaryConfiguration>>::Error>
+        + From<<F::BinaryConfiguration as TryFrom<F::Configuration>>::Error>,
+{
+    guard(out, || {
+        let config = config.as_bytes().map(serde_json::from_slice).transpose()?;
+        let instance = F::factory().create_filter(CreateFilterArgs::fixed(config))?;
+        *filter = Box::into_raw(Box::new(instance)).cast();
+        Ok(Vec::new())
+    })
+}
+
+unsafe extern "C" fn destroy(filter: *mut c_void) {
+    drop(Box::from_raw(filter.cast::<FilterInstance>()));
+}
+
+unsafe extern "C" fn read(filter: *const c_void, packet: Slice, out: *mut Buffer) -> bool {
+    guard(out, || {
+        let instance = &*filter.cast::<FilterInstance>();
+        let packet = proto::Packet::decode(packet.as_bytes().unwrap_or_default())?;
+        let mut ctx = ReadContext::new(
+            packet
+                .endpoints
+                .into_iter()
+                .map(super::endpoint_from_proto)
+                .collect::<crate::Result<_>>()?,
+            packet.source.parse()?,
+            packet.contents,
+        )
+        .metadata(super::metadata_from_proto(packet.metadata)?);
+
+        let result = poll_once(instance.filter().read(&mut ctx));
+
+        Ok(outcome(result, || proto::Packet {
+            contents: ctx.contents,
+            metadata: super::metadata_to_proto(ctx.metadata),
+            endpoints: ctx.endpoints.iter().map(super::endpoint_to_proto).collect(),
+            ..<_>::default()
+        })
+        .encode_to_vec())
+    })
+}
+
+unsafe extern "C" fn write(filter: *const c_void, packet: Slice, out: *mut Buffer) -> bool {
+    guard(out, || {
+        let instance = &*filter.cast::<FilterInstance>();
+        let packet = proto::Packet::decode(packet.as_bytes().unwrap_or_default())?;
+        let source: EndpointAddress = packet.source.parse()?;
+        let mut ctx = WriteContext::new(
+            Endpoint::new(source.clone()),
+            source,
+            packet
+                .destination
+                .ok_or_else(|| eyre::eyre!("missing destination"))?
+                .parse()?,
+            packet.contents,
+        );
+        ctx.metadata = super::metadata_from_proto(packet.metadata)?;
+
+        let result = poll_once(instance.filter().write(&mut ctx));
+
+        Ok(outcome(result, || proto::Packet {
+            contents: ctx.contents,
+            metadata: super::metadata_to_proto(ctx.metadata),
+            ..<_>::default()
+        })
+        .encode_to_vec())
+    })
+}
+
+/// Runs a filter's future to completion without blocking, as plugin filters
+/// are called synchronously from the proxy's worker threads. Filters that
+/// would need to wait fail instead.
+fn poll_once(future: impl Future<Output = Result<(), FilterError>>) -> Result<(), FilterError> {
+    future.now_or_never().unwrap_or_else(|| {
+        Err(FilterError::new(
+            "plugin filter didn't complete without waiting",
+        ))
+    })
+}
+
+unsafe extern "C" fn encode_config<F>(config: Slice, out: *mut Buffer) -> bool
+where
+    F: StaticFilter + 'static,
+    CreationError: From<<F::Configuration as TryFrom<F::BinaryConfiguration>>::Error>
+        + From<<F::BinaryConfiguration as TryFrom<F::Configuration>>::Error>,
+{
+    guard(out, || {
+        let config = serde_json::from_slice(config.as_bytes().unwrap_or_default())?;
+        Ok(F::factory().encode_config_to_protobuf(config)?.value)
+    })
+}
+
+unsafe extern "C" fn decode_config<F>(config: Slice, out: *mut Buffer) -> bool
+where
+    F: StaticFilter + 'static,
+    CreationError: From<<F::Configuration as TryFrom<F::BinaryConfiguration>>::Error>
+        + From<<F::BinaryConfiguration as TryFrom<F::Configuration>>::Error>,
+{
+    guard(out, || {
+        let config = F::factory().encode_config_to_json(prost_types::Any {
+            type_url: F::NAME.into(),
+            value: config.as_bytes().unwrap_or_default().to_vec(),
+        })?;
+        Ok(serde_json::to_vec(&config)?)
+    })
+}
+
+fn outcome(
+    result: Result<(), FilterError>,
+    packet: impl FnOnce() -> proto::Packet,
+) -> proto::Outcome {
+    use proto::outcome::{DropReason as Reason, Outcome};
+
+    let outcome = match result {
+        Ok(()) => Outcome::Packet(packet()),
+        Err(error) => match error.drop_reason() {
+            Some(reason) => Outcome::Drop(
+                match reason {
+                    DropReason::Intentional => Reason::Intentional,
+                    DropReason::Denied => Reason::Denied,
+                    DropReason::RateLimited => Reason::RateLimited,
+                    DropReason::NoRoute => Reason::NoRoute,
+                }
+                .into(),
+            ),
+            None => Outcome::Error(error.to_string()),
+        },
+    };
+
+    proto::Outcome {
+        outcome: Some(outcome),
+    }
+}
@@ -14,14 +14,18 @@
  * limitations under the License.
  */
 
+use std::sync::Arc;
+
 #[cfg(doc)]
-use crate::filters::Filter;
+use crate::filters::{Filter, Mirror};
 use crate::{
+    cluster::ClusterMap,
     endpoint::{Endpoint, EndpointAddress},
     metadata::DynamicMetadata,
 };
 
 /// The input arguments to [`Filter::read`].
+#[derive(Clone)]
 #[non_exhaustive]
 pub struct ReadContext {
     /// The upstream endpoints that the packet will be forwarded to.
@@ -32,6 +36,12 @@ pub struct ReadContext {
     pub contents: Vec<u8>,
     /// Arbitrary values that can be passed from one filter to another.
     pub metadata: DynamicMetadata,
+    /// Endpoints that a copy of the packet will also be sent to, any replies
+    /// from these endpoints are discarded.
+    pub mirror_endpoints: Vec<Endpoint>,
+    /// The clusters that `endpoints` were taken from, for filters such as
+    /// [`Mirror`] that look up a cluster by name.
+    pub clusters: Option<Arc<ClusterMap>>,
 }
 
 impl ReadContext {
@@ -42,9 +52,16 @@ impl ReadContext {
             source,
             contents,
             metadata: DynamicMetadata::new(),
+            mirror_endpoints: Vec::new(),
+            clusters: None,
         }
     }
 
+    pub fn clusters(mut self, clusters: Arc<ClusterMap>) -> Self {
+        self.clusters = Some(clusters);
+        self
+    }
+
     pub fn metadata(mut self, metadata: DynamicMetadata) -> Self {
         self.metadata = metadata;
         self
@@ -18,7 +18,7 @@ use arc_swap::ArcSwap;
 use once_cell::sync::Lazy;
 
 use crate::filters::{
-    CreateFilterArgs, CreationError, DynFilterFactory, FilterInstance, FilterSet,
+    plugin::Plugin, CreateFilterArgs, CreationError, DynFilterFactory, FilterInstance, FilterSet,
 };
 
 static REGISTRY: Lazy<ArcSwap<FilterSet>> =
@@ -43,6 +43,20 @@ impl FilterRegistry {
         REGISTRY.store(std::sync::Arc::from(registry));
     }
 
+    /// Loads the native filter plugin at `path`, and registers the filters it
+    /// provides, see [`Plugin`].
+    pub fn load_plugin(path: impl AsRef<std::path::Path>) -> Result<(), CreationError> {
+        let plugin = Plugin::load(path)?;
+        let factories = plugin.factories()?;
+        tracing::info!(
+            path = %plugin.path().display(),
+            filters = ?factories.iter().map(|factory| factory.name()).collect::<Vec<_>>(),
+            "loaded filter plugin"
+        );
+        Self::register(factories);
+        Ok(())
+    }
+
     /// Creates and returns a new dynamic instance of [`Filter`][crate::filters::Filter] for a given
     /// `key`. Errors if the filter cannot be found, or if there is a
     /// configuration issue.
@@ -34,6 +34,9 @@ pub type FilterMap = std::collections::HashMap<&'static str, Arc<DynFilterFactor
 /// - [`capture`][filters::capture]
 /// - [`token_router`][filters::token_router]
 /// - [`compress`][filters::compress]
+/// - [`mirror`][filters::mirror]
+/// - [`pcap`][filters::pcap]
+/// - [`wasm`][filters::wasm]
 #[derive(Clone)]
 pub struct FilterSet(FilterMap);
 
@@ -53,9 +56,12 @@ impl FilterSet {
                 filters::LoadBalancer::factory(),
                 filters::LocalRateLimit::factory(),
                 filters::Match::factory(),
+                filters::Mirror::factory(),
                 filters::Pass::factory(),
+                filters::Pcap::factory(),
                 filters::Timestamp::factory(),
                 filters::TokenRouter::factory(),
+                filters::Wasm::factory(),
             ]
             .into_iter()
             .chain(filters),
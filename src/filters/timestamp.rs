@@ -37,7 +37,7 @@ impl Timestamp {
         let value = metadata
             .get(&self.config.metadata_key)
             .and_then(|item| match item {
-                Value::Number(item) => Some(*item as i64),
+                Value::Number(_) | Value::Integer(_) | Value::Float(_) => item.as_i64(),
                 Value::Bytes(vec) => Some(i64::from_be_bytes((**vec).try_into().ok()?)),
                 _ => None,
             });
@@ -116,6 +116,12 @@ impl StaticFilter for Timestamp {
     fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
         Self::new(Self::ensure_config_exists(config)?)
     }
+
+    fn metadata_keys(config: Option<&Self::Configuration>) -> MetadataKeys {
+        config
+            .map(|config| MetadataKeys::reads(config.metadata_key))
+            .unwrap_or_default()
+    }
 }
 
 /// Config represents a [self]'s configuration.
@@ -169,10 +175,8 @@ mod tests {
             (std::net::Ipv4Addr::UNSPECIFIED, 0).into(),
             b"hello".to_vec(),
         );
-        ctx.metadata.insert(
-            TIMESTAMP_KEY.into(),
-            Value::Number(Utc::now().timestamp() as u64),
-        );
+        ctx.metadata
+            .insert(TIMESTAMP_KEY.into(), Value::Integer(Utc::now().timestamp()));
 
         filter.read(&mut ctx).await.unwrap();
 
@@ -47,38 +47,86 @@ impl StaticFilter for TokenRouter {
     fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
         Ok(TokenRouter::new(config.unwrap_or_default()))
     }
+
+    fn metadata_keys(config: Option<&Self::Configuration>) -> MetadataKeys {
+        MetadataKeys::reads(config.map_or_else(default_metadata_key, |config| config.metadata_key))
+    }
 }
 
 #[async_trait::async_trait]
 impl Filter for TokenRouter {
     async fn read(&self, ctx: &mut ReadContext) -> Result<(), FilterError> {
-        match ctx.metadata.get(&self.config.metadata_key) {
-            Some(metadata::Value::Bytes(token)) => {
-                ctx.endpoints.retain(|endpoint| {
-                    if endpoint.metadata.known.tokens.contains(&**token) {
-                        tracing::trace!(%endpoint.address, token = &*crate::utils::base64_encode(token), "Endpoint matched");
-                        true
-                    } else {
-                        false
-                    }
-                });
-
-                if ctx.endpoints.is_empty() {
-                    Err(FilterError::new(Error::NoEndpointMatch(
+        let token = match ctx.metadata.get(&self.config.metadata_key) {
+            Some(metadata::Value::Bytes(token)) => Token::Bytes(token),
+            Some(value) => match value.as_u64() {
+                Some(token) => Token::Integer(token),
+                None => {
+                    return Err(FilterError::new(Error::InvalidType(
                         self.config.metadata_key,
-                        crate::utils::base64_encode(token),
+                        value.clone(),
                     )))
-                } else {
-                    Ok(())
                 }
+            },
+            // A missing token is usually a misconfigured metadata key, rather
+            // than a packet that can't be routed, so it is reported as an
+            // error.
+            None => {
+                return Err(FilterError::new(Error::NoTokenFound(
+                    self.config.metadata_key,
+                )))
             }
-            Some(value) => Err(FilterError::new(Error::InvalidType(
-                self.config.metadata_key,
-                value.clone(),
-            ))),
-            None => Err(FilterError::new(Error::NoTokenFound(
+        };
+
+        ctx.endpoints.retain(|endpoint| {
+            if token.matches(&endpoint.metadata.known.tokens) {
+                tracing::trace!(%endpoint.address, %token, "Endpoint matched");
+                true
+            } else {
+                false
+            }
+        });
+
+        if ctx.endpoints.is_empty() {
+            tracing::debug!(error = %Error::NoEndpointMatch(
                 self.config.metadata_key,
-            ))),
+                token.to_string(),
+            ), "dropping packet");
+            Err(FilterError::drop(DropReason::NoRoute))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A routing token found in the dynamic metadata.
+enum Token<'value> {
+    /// Matches an endpoint token with the same bytes.
+    Bytes(&'value [u8]),
+    /// Matches an endpoint token of up to eight bytes which has the same
+    /// value when decoded as a big-endian integer.
+    Integer(u64),
+}
+
+impl Token<'_> {
+    fn matches(&self, tokens: &std::collections::BTreeSet<Vec<u8>>) -> bool {
+        match self {
+            Self::Bytes(token) => tokens.contains(*token),
+            Self::Integer(value) => tokens.iter().any(|token| {
+                token.len() <= std::mem::size_of::<u64>()
+                    && token
+                        .iter()
+                        .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte))
+                        == *value
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bytes(token) => crate::utils::base64_encode(token).fmt(f),
+            Self::Integer(token) => token.fmt(f),
         }
     }
 }
@@ -87,7 +135,7 @@ impl Filter for TokenRouter {
 pub enum Error {
     #[error("no routing token found for `{0}`")]
     NoTokenFound(crate::metadata::Key),
-    #[error("key `{0}` was found but wasn't bytes, found {1:?}")]
+    #[error("key `{0}` was found but wasn't bytes or an unsigned integer, found {1:?}")]
     InvalidType(crate::metadata::Key, crate::metadata::Value),
     #[error("no endpoint matched token `{1}` from `{0}`")]
     NoEndpointMatch(crate::metadata::Key, String),
@@ -222,17 +270,32 @@ mod tests {
         ctx.metadata
             .insert(CAPTURED_BYTES.into(), Value::Bytes(b"567".to_vec().into()));
 
-        assert!(filter.read(&mut ctx).await.is_err());
+        assert_eq!(
+            Some(DropReason::NoRoute),
+            filter.read(&mut ctx).await.unwrap_err().drop_reason()
+        );
 
         // no key
         let mut ctx = new_ctx();
-        assert!(filter.read(&mut ctx).await.is_err());
+        assert!(!filter.read(&mut ctx).await.unwrap_err().is_drop());
+
+        // integer key, matching the endpoint token decoded as big endian
+        let mut ctx = new_ctx();
+        ctx.metadata
+            .insert(CAPTURED_BYTES.into(), Value::Number(0x313233));
+        assert_read(&filter, ctx).await;
+
+        // negative integer key
+        let mut ctx = new_ctx();
+        ctx.metadata
+            .insert(CAPTURED_BYTES.into(), Value::Integer(-1));
+        assert!(!filter.read(&mut ctx).await.unwrap_err().is_drop());
 
         // wrong type key
         let mut ctx = new_ctx();
         ctx.metadata
             .insert(CAPTURED_BYTES.into(), Value::String(String::from("wrong")));
-        assert!(filter.read(&mut ctx).await.is_err());
+        assert!(!filter.read(&mut ctx).await.unwrap_err().is_drop());
     }
 
     #[tokio::test]
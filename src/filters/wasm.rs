@@ -0,0 +1,729 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+mod host;
+
+use std::{
+    path::PathBuf,
+    sync::{atomic::AtomicUsize, Arc},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Base64Standard, filters::prelude::*, metadata::DynamicMetadata};
+
+use self::host::{Changes, HostState};
+
+crate::include_proto!("quilkin.filters.wasm.v1alpha1");
+use self::quilkin::filters::wasm::v1alpha1 as proto;
+
+/// The guest export called with each packet received from downstream.
+const READ_EXPORT: &str = "read";
+/// The guest export called with each packet received from upstream.
+const WRITE_EXPORT: &str = "write";
+/// The guest export called to allocate a buffer for the packet contents.
+const ALLOC_EXPORT: &str = "alloc";
+/// The guest's exported linear memory.
+const MEMORY_EXPORT: &str = "memory";
+
+/// Runs custom packet processing logic from a WebAssembly module.
+///
+/// The module exports `read` and/or `write`, which are called with a pointer
+/// to and the length of the packet's contents, written to a buffer obtained
+/// from the module's `alloc` export. The module can inspect and modify the
+/// packet through the host functions in the `quilkin` import module, see
+/// [`host`] for details. Module instances are pooled and reused between
+/// packets, and each call is limited to the configured amount of fuel, and
+/// optionally to a wall clock timeout.
+pub struct Wasm {
+    module: wasmi::Module,
+    linker: wasmi::Linker<HostState>,
+    fuel: u64,
+    max_memory: Option<usize>,
+    timeout: Option<Duration>,
+    /// The number of metadata keys interned by the module's instances.
+    new_keys: Arc<AtomicUsize>,
+    has_read: bool,
+    has_write: bool,
+    instances: parking_lot::Mutex<Vec<Instance>>,
+}
+
+impl Wasm {
+    fn new(config: Config) -> Result<Self, CreationError> {
+        let bytes = match config.module {
+            Module::Path(path) => {
+                std::fs::read(&path).map_err(|error| CreationError::FieldInvalid {
+                    field: "path".into(),
+                    reason: format!("failed to read `{}`: {error}", path.display()),
+                })?
+            }
+            Module::Inline(bytes) => bytes,
+        };
+
+        let mut engine_config = wasmi::Config::default();
+        engine_config.consume_fuel(true);
+        let engine = wasmi::Engine::new(&engine_config);
+
+        let module = wasmi::Module::new(&engine, &bytes[..]).map_err(|error| {
+            CreationError::FieldInvalid {
+                field: "module".into(),
+                reason: error.to_string(),
+            }
+        })?;
+
+        let has_export = |name| module.exports().any(|export| export.name() == name);
+        for required in [MEMORY_EXPORT, ALLOC_EXPORT] {
+            if !has_export(required) {
+                return Err(CreationError::FieldInvalid {
+                    field: "module".into(),
+                    reason: format!("module must export `{required}`"),
+                });
+            }
+        }
+
+        let wasm = Self {
+            has_read: has_export(READ_EXPORT),
+            has_write: has_export(WRITE_EXPORT),
+            linker: host::linker(&engine).map_err(|error| CreationError::FieldInvalid {
+                field: "module".into(),
+                reason: error.to_string(),
+            })?,
+            module,
+            fuel: config.fuel,
+            max_memory: config
+                .max_memory
+                .map(|max| usize::try_from(max).unwrap_or(usize::MAX)),
+            timeout: config.timeout_ms.map(Duration::from_millis),
+            new_keys: <_>::default(),
+            instances: <_>::default(),
+        };
+
+        // Instantiate eagerly, so that missing imports and failing start
+        // functions are reported when the filter is created.
+        let instance = wasm
+            .instantiate()
+            .map_err(|error| CreationError::FieldInvalid {
+                field: "module".into(),
+                reason: error.to_string(),
+            })?;
+        wasm.instances.lock().push(instance);
+
+        Ok(wasm)
+    }
+
+    fn instantiate(&self) -> Result<Instance, wasmi::Error> {
+        let mut limits = wasmi::StoreLimitsBuilder::new();
+        if let Some(max_memory) = self.max_memory {
+            limits = limits.memory_size(max_memory);
+        }
+
+        let mut store = wasmi::Store::new(
+            self.module.engine(),
+            HostState {
+                limits: limits.build(),
+                new_keys: self.new_keys.clone(),
+                ..<_>::default()
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+
+        let instance = self
+            .linker
+            .instantiate(&mut store, &self.module)?
+            .start(&mut store)?;
+
+        Ok(Instance {
+            memory: instance
+                .get_memory(&store, MEMORY_EXPORT)
+                .ok_or_else(|| wasmi::Error::from(host::trap("missing `memory` export")))?,
+            alloc: instance.get_typed_func(&store, ALLOC_EXPORT)?,
+            read: self
+                .has_read
+                .then(|| instance.get_typed_func(&store, READ_EXPORT))
+                .transpose()?,
+            write: self
+                .has_write
+                .then(|| instance.get_typed_func(&store, WRITE_EXPORT))
+                .transpose()?,
+            store,
+            fuel_added: 0,
+        })
+    }
+
+    /// Calls the guest with the packet, taking an instance from the pool, and
+    /// returning it afterwards unless it trapped or timed out. The guest's
+    /// changes are only applied to the packet if the call succeeds.
+    async fn call(
+        &self,
+        export: Export,
+        contents: &mut Vec<u8>,
+        metadata: &mut DynamicMetadata,
+        source: String,
+        destination: Option<String>,
+    ) -> Result<(), FilterError> {
+        let pooled = self.instances.lock().pop();
+        let mut instance = match pooled {
+            Some(instance) => instance,
+            None => self.instantiate().map_err(FilterError::new)?,
+        };
+
+        let changes = match self.timeout {
+            None => {
+                let mut packet = Packet {
+                    contents: std::mem::take(contents),
+                    metadata: std::mem::take(metadata),
+                    source,
+                    destination,
+                };
+                let result = instance.call(export, &mut packet, self.fuel);
+                *contents = packet.contents;
+                *metadata = packet.metadata;
+                self.release(instance, result)?
+            }
+            // The guest can't be interrupted, so it's run on a blocking
+            // thread with a copy of the packet, which keeps running until it
+            // returns or runs out of fuel if the timeout elapses first.
+            Some(timeout) => {
+                let mut packet = Packet {
+                    contents: contents.clone(),
+                    metadata: metadata.clone(),
+                    source,
+                    destination,
+                };
+                let fuel = self.fuel;
+                let task = tokio::task::spawn_blocking(move || {
+                    let result = instance.call(export, &mut packet, fuel);
+                    (instance, result)
+                });
+
+                match tokio::time::timeout(timeout, task).await {
+                    Ok(Ok((instance, result))) => self.release(instance, result)?,
+                    Ok(Err(error)) => return Err(FilterError::new(error)),
+                    Err(_) => {
+                        return Err(FilterError::new(format!(
+                            "call exceeded timeout of {timeout:?}"
+                        )))
+                    }
+                }
+            }
+        };
+
+        if let Some(new_contents) = changes.contents {
+            *contents = new_contents;
+        }
+        metadata.extend(changes.metadata);
+
+        match changes.dropped {
+            true => Err(FilterError::drop(DropReason::Intentional)),
+            false => Ok(()),
+        }
+    }
+
+    /// Returns `instance` to the pool if the call succeeded.
+    fn release(
+        &self,
+        instance: Instance,
+        result: Result<Changes, wasmi::Error>,
+    ) -> Result<Changes, FilterError> {
+        if result.is_ok() {
+            self.instances.lock().push(instance);
+        }
+
+        result.map_err(FilterError::new)
+    }
+}
+
+#[async_trait::async_trait]
+impl Filter for Wasm {
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    async fn read(&self, ctx: &mut ReadContext) -> Result<(), FilterError> {
+        if !self.has_read {
+            return Ok(());
+        }
+
+        self.call(
+            Export::Read,
+            &mut ctx.contents,
+            &mut ctx.metadata,
+            ctx.source.to_string(),
+            None,
+        )
+        .await
+    }
+
+    #[cfg_attr(feature = "instrument", tracing::instrument(skip(self, ctx)))]
+    async fn write(&self, ctx: &mut WriteContext) -> Result<(), FilterError> {
+        if !self.has_write {
+            return Ok(());
+        }
+
+        self.call(
+            Export::Write,
+            &mut ctx.contents,
+            &mut ctx.metadata,
+            ctx.source.to_string(),
+            Some(ctx.dest.to_string()),
+        )
+        .await
+    }
+}
+
+impl StaticFilter for Wasm {
+    const NAME: &'static str = "quilkin.filters.wasm.v1alpha1.Wasm";
+    type Configuration = Config;
+    type BinaryConfiguration = proto::Wasm;
+
+    fn try_from_config(config: Option<Self::Configuration>) -> Result<Self, CreationError> {
+        Self::new(Self::ensure_config_exists(config)?)
+    }
+
+    fn metadata_keys(_: Option<&Self::Configuration>) -> MetadataKeys {
+        MetadataKeys::unknown()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Export {
+    Read,
+    Write,
+}
+
+/// The parts of a packet's context that are exposed to the guest.
+struct Packet {
+    contents: Vec<u8>,
+    metadata: DynamicMetadata,
+    source: String,
+    destination: Option<String>,
+}
+
+/// An instantiated module.
+struct Instance {
+    store: wasmi::Store<HostState>,
+    memory: wasmi::Memory,
+    alloc: wasmi::TypedFunc<i32, i32>,
+    read: Option<wasmi::TypedFunc<(i32, i32), ()>>,
+    write: Option<wasmi::TypedFunc<(i32, i32), ()>>,
+    /// The total fuel added to `store`, as `wasmi` only reports the fuel
+    /// consumed.
+    fuel_added: u64,
+}
+
+impl Instance {
+    /// Runs `export` with the packet, returning the changes the guest made.
+    fn call(
+        &mut self,
+        export: Export,
+        packet: &mut Packet,
+        fuel: u64,
+    ) -> Result<Changes, wasmi::Error> {
+        let func = match export {
+            Export::Read => self.read,
+            Export::Write => self.write,
+        };
+        let Some(func) = func else {
+            return Ok(Changes::default());
+        };
+
+        // Refill the fuel for this call.
+        let remaining = self.fuel_added - self.store.fuel_consumed().unwrap_or_default();
+        self.store.add_fuel(fuel.saturating_sub(remaining))?;
+        self.fuel_added += fuel.saturating_sub(remaining);
+
+        let len = i32::try_from(packet.contents.len())
+            .map_err(|_| host::trap("packet is too large for the guest"))?;
+        let state = self.store.data_mut();
+        state.metadata = std::mem::take(&mut packet.metadata);
+        state.source = std::mem::take(&mut packet.source);
+        state.destination = packet.destination.take();
+        state.changes = Changes::default();
+
+        let result = self
+            .alloc
+            .call(&mut self.store, len)
+            .and_then(|ptr| {
+                self.memory
+                    .write(&mut self.store, ptr as u32 as usize, &packet.contents)
+                    .map_err(|_| host::trap("out of bounds memory access"))?;
+                Ok(ptr)
+            })
+            .and_then(|ptr| func.call(&mut self.store, (ptr, len)));
+
+        let state = self.store.data_mut();
+        packet.metadata = std::mem::take(&mut state.metadata);
+        let changes = std::mem::take(&mut state.changes);
+
+        Ok(result.map(|()| changes)?)
+    }
+}
+
+/// `wasm` filter's configuration.
+#[derive(Serialize, Deserialize, Debug, PartialEq, schemars::JsonSchema)]
+pub struct Config {
+    /// The WebAssembly module to run.
+    #[serde(flatten)]
+    pub module: Module,
+    /// The maximum amount of fuel a single call into the module can consume,
+    /// roughly one unit per instruction executed. Calls that run out of fuel
+    /// fail, bounding the time spent processing a packet.
+    #[serde(default = "default_fuel")]
+    pub fuel: u64,
+    /// The maximum size of the module's linear memory, in bytes.
+    #[serde(rename = "maxMemory", default, skip_serializing_if = "Option::is_none")]
+    pub max_memory: Option<u64>,
+    /// The maximum wall clock time a single call into the module can take,
+    /// in milliseconds. When set, calls are run on a blocking thread, and
+    /// packets are dropped with an error if the call doesn't return in time.
+    #[serde(rename = "timeoutMs", default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Default value for [`Config::fuel`]
+fn default_fuel() -> u64 {
+    1_000_000
+}
+
+/// Where a WebAssembly module is loaded from.
+#[derive(Serialize, Deserialize, Debug, PartialEq, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Module {
+    /// A path to a binary module file.
+    Path(PathBuf),
+    /// A base64 encoded binary module.
+    Inline(
+        #[serde(
+            deserialize_with = "Base64Standard::deserialize",
+            serialize_with = "Base64Standard::serialize"
+        )]
+        #[schemars(with = "String")]
+        Vec<u8>,
+    ),
+}
+
+impl From<Config> for proto::Wasm {
+    fn from(config: Config) -> Self {
+        Self {
+            module: Some(match config.module {
+                Module::Path(path) => proto::wasm::Module::Path(path.display().to_string()),
+                Module::Inline(bytes) => proto::wasm::Module::Inline(bytes),
+            }),
+            fuel: Some(config.fuel),
+            max_memory: config.max_memory,
+            timeout_ms: config.timeout_ms,
+        }
+    }
+}
+
+impl TryFrom<proto::Wasm> for Config {
+    type Error = ConvertProtoConfigError;
+
+    fn try_from(p: proto::Wasm) -> Result<Self, Self::Error> {
+        Ok(Self {
+            module: match p
+                .module
+                .ok_or_else(|| ConvertProtoConfigError::missing_field("module"))?
+            {
+                proto::wasm::Module::Path(path) => Module::Path(path.into()),
+                proto::wasm::Module::Inline(bytes) => Module::Inline(bytes),
+            },
+            fuel: p.fuel.unwrap_or_else(default_fuel),
+            max_memory: p.max_memory,
+            timeout_ms: p.timeout_ms,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::{
+        endpoint::Endpoint,
+        metadata::{Key, Value},
+    };
+
+    /// A module that appends the last byte of the source address and the
+    /// `myapp.com/suffix` metadata to packets, drops packets starting with
+    /// `x`, loops forever on packets starting with `l`, traps after changing
+    /// packets starting with `t`, and records the packet's length in
+    /// `myapp.com/len`.
+    const MODULE: &str = r#"
+(module
+  (import "quilkin" "source" (func $source (param i32 i32) (result i32)))
+  (import "quilkin" "get_metadata" (func $get_metadata (param i32 i32 i32 i32) (result i32)))
+  (import "quilkin" "set_metadata_u64" (func $set_metadata_u64 (param i32 i32 i64)))
+  (import "quilkin" "set_contents" (func $set_contents (param i32 i32)))
+  (import "quilkin" "drop" (func $drop))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "myapp.com/suffix")
+  (data (i32.const 16) "myapp.com/len")
+  (func (export "alloc") (param $len i32) (result i32)
+    i32.const 1024)
+  (func (export "read") (param $ptr i32) (param $len i32)
+    (local $end i32)
+    (local $n i32)
+    (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 120))
+      (then (call $drop) (return)))
+    (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 108))
+      (then (loop $forever (br $forever))))
+    (call $set_metadata_u64 (i32.const 16) (i32.const 13) (i64.extend_i32_u (local.get $len)))
+    (local.set $end (i32.add (local.get $ptr) (local.get $len)))
+    ;; write the source address to scratch space, and append its last byte.
+    (local.set $n (call $source (i32.const 256) (i32.const 64)))
+    (i32.store8 (local.get $end)
+      (i32.load8_u (i32.add (i32.const 255) (local.get $n))))
+    (local.set $end (i32.add (local.get $end) (i32.const 1)))
+    (local.set $n
+      (call $get_metadata (i32.const 0) (i32.const 16) (local.get $end) (i32.const 64)))
+    (if (i32.gt_s (local.get $n) (i32.const 0))
+      (then (local.set $end (i32.add (local.get $end) (local.get $n)))))
+    (call $set_contents (local.get $ptr) (i32.sub (local.get $end) (local.get $ptr)))
+    (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 116))
+      (then unreachable))))
+"#;
+
+    fn wasm(fuel: u64) -> Wasm {
+        Wasm::try_from_config(Some(Config {
+            module: Module::Inline(wat::parse_str(MODULE).unwrap()),
+            fuel,
+            max_memory: None,
+            timeout_ms: None,
+        }))
+        .unwrap()
+    }
+
+    fn read_ctx(contents: &[u8]) -> ReadContext {
+        ReadContext::new(
+            vec![Endpoint::new((Ipv4Addr::LOCALHOST, 8080).into())],
+            (Ipv4Addr::LOCALHOST, 7001).into(),
+            contents.to_vec(),
+        )
+    }
+
+    #[tokio::test]
+    async fn read() {
+        let filter = wasm(default_fuel());
+
+        let mut ctx = read_ctx(b"hello");
+        ctx.metadata.insert(
+            Key::from_static("myapp.com/suffix"),
+            Value::Bytes("abc".into()),
+        );
+        filter.read(&mut ctx).await.unwrap();
+        assert_eq!(b"hello1abc", &*ctx.contents);
+        assert_eq!(
+            Some(&Value::Number(5)),
+            ctx.metadata.get(&Key::from_static("myapp.com/len"))
+        );
+
+        // Instances are reused between packets.
+        let mut ctx = read_ctx(b"hi");
+        filter.read(&mut ctx).await.unwrap();
+        assert_eq!(b"hi1", &*ctx.contents);
+        assert_eq!(1, filter.instances.lock().len());
+    }
+
+    #[tokio::test]
+    async fn drop() {
+        let filter = wasm(default_fuel());
+        let mut ctx = read_ctx(b"xyz");
+        assert_eq!(
+            Some(DropReason::Intentional),
+            filter.read(&mut ctx).await.unwrap_err().drop_reason()
+        );
+        assert_eq!(b"xyz", &*ctx.contents);
+    }
+
+    #[tokio::test]
+    async fn out_of_fuel() {
+        let filter = wasm(10_000);
+        let mut ctx = read_ctx(b"loop");
+        let error = filter.read(&mut ctx).await.unwrap_err();
+        assert!(!error.is_drop());
+        assert_eq!(b"loop", &*ctx.contents);
+
+        // The trapped instance is discarded, and the next packet still has
+        // its full amount of fuel.
+        let mut ctx = read_ctx(b"hello");
+        filter.read(&mut ctx).await.unwrap();
+        assert_eq!(b"hello1", &*ctx.contents);
+    }
+
+    #[tokio::test]
+    async fn trap_discards_changes() {
+        let filter = wasm(default_fuel());
+        let mut ctx = read_ctx(b"trap");
+        assert!(!filter.read(&mut ctx).await.unwrap_err().is_drop());
+        assert_eq!(b"trap", &*ctx.contents);
+        assert!(ctx.metadata.is_empty());
+        assert!(filter.instances.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        // Enough fuel to loop for far longer than the timeout, without
+        // keeping the blocking thread busy for long after the test.
+        let filter = Wasm::try_from_config(Some(Config {
+            module: Module::Inline(wat::parse_str(MODULE).unwrap()),
+            fuel: 50_000_000,
+            max_memory: None,
+            timeout_ms: Some(1),
+        }))
+        .unwrap();
+
+        let mut ctx = read_ctx(b"loop");
+        let error = filter.read(&mut ctx).await.unwrap_err();
+        assert!(!error.is_drop());
+        assert!(error.to_string().contains("timeout"), "{error}");
+        assert_eq!(b"loop", &*ctx.contents);
+
+        // Calls that finish in time are applied as usual.
+        let filter = Wasm::try_from_config(Some(Config {
+            module: Module::Inline(wat::parse_str(MODULE).unwrap()),
+            fuel: default_fuel(),
+            max_memory: None,
+            timeout_ms: Some(10_000),
+        }))
+        .unwrap();
+        let mut ctx = read_ctx(b"hello");
+        filter.read(&mut ctx).await.unwrap();
+        assert_eq!(b"hello1", &*ctx.contents);
+        assert_eq!(
+            Some(&Value::Number(5)),
+            ctx.metadata.get(&Key::from_static("myapp.com/len"))
+        );
+        assert_eq!(1, filter.instances.lock().len());
+    }
+
+    #[tokio::test]
+    async fn long_key() {
+        let filter = Wasm::try_from_config(Some(Config {
+            module: Module::Inline(
+                wat::parse_str(format!(
+                    r#"(module
+                        (import "quilkin" "set_metadata_u64" (func $set (param i32 i32 i64)))
+                        (memory (export "memory") 1)
+                        (func (export "alloc") (param i32) (result i32) i32.const 1024)
+                        (func (export "read") (param i32 i32)
+                          (call $set (i32.const 0) (i32.const {}) (i64.const 1))))"#,
+                    host::MAX_KEY_LEN + 1
+                ))
+                .unwrap(),
+            ),
+            fuel: default_fuel(),
+            max_memory: None,
+            timeout_ms: None,
+        }))
+        .unwrap();
+
+        let mut ctx = read_ctx(b"hello");
+        assert!(!filter.read(&mut ctx).await.unwrap_err().is_drop());
+        assert!(ctx.metadata.is_empty());
+    }
+
+    #[tokio::test]
+    async fn write_passthrough() {
+        crate::test_utils::assert_write_no_change(&wasm(default_fuel())).await;
+    }
+
+    #[test]
+    fn invalid_module() {
+        assert!(Wasm::try_from_config(Some(Config {
+            module: Module::Inline(b"not wasm".to_vec()),
+            fuel: default_fuel(),
+            max_memory: None,
+            timeout_ms: None,
+        }))
+        .is_err());
+
+        // Missing the `alloc` export.
+        assert!(Wasm::try_from_config(Some(Config {
+            module: Module::Inline(
+                wat::parse_str(r#"(module (memory (export "memory") 1))"#).unwrap()
+            ),
+            fuel: default_fuel(),
+            max_memory: None,
+            timeout_ms: None,
+        }))
+        .is_err());
+
+        // Imports an unknown host function.
+        assert!(Wasm::try_from_config(Some(Config {
+            module: Module::Inline(
+                wat::parse_str(
+                    r#"(module
+                        (import "quilkin" "unknown" (func))
+                        (memory (export "memory") 1)
+                        (func (export "alloc") (param i32) (result i32) i32.const 0))"#
+                )
+                .unwrap()
+            ),
+            fuel: default_fuel(),
+            max_memory: None,
+            timeout_ms: None,
+        }))
+        .is_err());
+
+        // Requires more memory than allowed.
+        assert!(Wasm::try_from_config(Some(Config {
+            module: Module::Inline(
+                wat::parse_str(
+                    r#"(module
+                        (memory (export "memory") 2)
+                        (func (export "alloc") (param i32) (result i32) i32.const 0))"#
+                )
+                .unwrap()
+            ),
+            fuel: default_fuel(),
+            max_memory: Some(65536),
+            timeout_ms: None,
+        }))
+        .is_err());
+
+        assert!(Wasm::try_from_config(Some(Config {
+            module: Module::Path("/nonexistent/filter.wasm".into()),
+            fuel: default_fuel(),
+            max_memory: None,
+            timeout_ms: None,
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn convert_proto_config() {
+        let config = Config {
+            module: Module::Inline(vec![0, 97, 115, 109]),
+            fuel: 500,
+            max_memory: Some(65536),
+            timeout_ms: Some(50),
+        };
+        let proto = proto::Wasm::from(Config {
+            module: Module::Inline(vec![0, 97, 115, 109]),
+            fuel: 500,
+            max_memory: Some(65536),
+            timeout_ms: Some(50),
+        });
+        assert_eq!(config, Config::try_from(proto).unwrap());
+
+        let config: Config = serde_yaml::from_str("path: /etc/quilkin/filter.wasm").unwrap();
+        assert_eq!(
+            Module::Path("/etc/quilkin/filter.wasm".into()),
+            config.module
+        );
+        assert_eq!(default_fuel(), config.fuel);
+    }
+}
@@ -0,0 +1,267 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Host functions available to guest modules, imported from the `quilkin`
+//! module. Pointers and lengths refer to the guest's exported memory.
+//!
+//! - `source(ptr, len) -> i32` writes the packet's source address as
+//!   `ip:port`, returning its length, or `-1` if it doesn't fit.
+//! - `destination(ptr, len) -> i32` writes the packet's destination address
+//!   like `source`, returning `-1` when reading as packets from downstream
+//!   have no destination yet.
+//! - `set_contents(ptr, len)` replaces the packet's contents.
+//! - `get_metadata(key_ptr, key_len, ptr, len) -> i32` writes the bytes or
+//!   string value of a metadata key, returning its length, `-1` if the key
+//!   is missing or the value doesn't fit, or `-2` if it has another type.
+//! - `set_metadata(key_ptr, key_len, ptr, len)` sets a metadata key to bytes.
+//! - `get_metadata_u64(key_ptr, key_len, out_ptr) -> i32` writes an unsigned
+//!   integer metadata value to `out_ptr` in little-endian order, returning `8`,
+//!   `-1` if the key is missing, or `-2` if it isn't an unsigned integer.
+//! - `set_metadata_u64(key_ptr, key_len, value: i64)` sets a metadata key to
+//!   an unsigned integer.
+//! - `drop()` drops the packet once the guest returns.
+//!
+//! Changes to the packet are buffered, and only applied once the guest
+//! returns without trapping. Keys are limited to [`MAX_KEY_LEN`] bytes, and a
+//! module can only introduce [`MAX_NEW_KEYS`] keys that weren't already in
+//! use, as keys are interned for the lifetime of the process.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use wasmi::{core::Trap, Caller, Extern, Linker};
+
+use crate::metadata::{DynamicMetadata, Key, Value};
+
+const MODULE: &str = "quilkin";
+const MISSING: i32 = -1;
+const WRONG_TYPE: i32 = -2;
+
+/// The longest metadata key a guest can read or write.
+pub(super) const MAX_KEY_LEN: usize = 256;
+/// The number of keys that weren't already interned that a module can set.
+pub(super) const MAX_NEW_KEYS: usize = 1024;
+
+/// The state of the packet being processed by an instance.
+#[derive(Default)]
+pub(super) struct HostState {
+    pub limits: wasmi::StoreLimits,
+    /// The packet's metadata before the call.
+    pub metadata: DynamicMetadata,
+    pub source: String,
+    pub destination: Option<String>,
+    pub changes: Changes,
+    /// The number of keys the module has interned, shared by all of its
+    /// instances.
+    pub new_keys: Arc<AtomicUsize>,
+}
+
+/// The changes a guest made to a packet during a call.
+#[derive(Default)]
+pub(super) struct Changes {
+    /// The contents set by the guest, if any.
+    pub contents: Option<Vec<u8>>,
+    pub metadata: DynamicMetadata,
+    pub dropped: bool,
+}
+
+impl HostState {
+    fn get_metadata(&self, key: Key) -> Option<&Value> {
+        self.changes
+            .metadata
+            .get(&key)
+            .or_else(|| self.metadata.get(&key))
+    }
+}
+
+pub(super) fn trap(message: &str) -> Trap {
+    Trap::new(message.to_owned())
+}
+
+/// Creates a linker defining all of the host functions.
+pub(super) fn linker(
+    engine: &wasmi::Engine,
+) -> Result<Linker<HostState>, wasmi::errors::LinkerError> {
+    let mut linker = Linker::new(engine);
+
+    linker
+        .func_wrap(
+            MODULE,
+            "source",
+            |mut caller: Caller<HostState>, ptr: i32, len: i32| {
+                let address = caller.data().source.clone();
+                write_bytes(&mut caller, address.as_bytes(), ptr, len)
+            },
+        )?
+        .func_wrap(
+            MODULE,
+            "destination",
+            |mut caller: Caller<HostState>, ptr: i32, len: i32| match caller
+                .data()
+                .destination
+                .clone()
+            {
+                Some(address) => write_bytes(&mut caller, address.as_bytes(), ptr, len),
+                None => Ok(MISSING),
+            },
+        )?
+        .func_wrap(
+            MODULE,
+            "set_contents",
+            |mut caller: Caller<HostState>, ptr: i32, len: i32| {
+                let contents = read_bytes(&mut caller, ptr, len)?;
+                caller.data_mut().changes.contents = Some(contents);
+                Ok(())
+            },
+        )?
+        .func_wrap(
+            MODULE,
+            "get_metadata",
+            |mut caller: Caller<HostState>, key_ptr: i32, key_len: i32, ptr: i32, len: i32| {
+                let Some(key) = lookup_key(&mut caller, key_ptr, key_len)? else {
+                    return Ok(MISSING);
+                };
+                let value = match caller.data().get_metadata(key) {
+                    None => return Ok(MISSING),
+                    Some(Value::Bytes(bytes)) => bytes.to_vec(),
+                    Some(Value::String(string)) => string.clone().into_bytes(),
+                    Some(_) => return Ok(WRONG_TYPE),
+                };
+                write_bytes(&mut caller, &value, ptr, len)
+            },
+        )?
+        .func_wrap(
+            MODULE,
+            "set_metadata",
+            |mut caller: Caller<HostState>, key_ptr: i32, key_len: i32, ptr: i32, len: i32| {
+                let key = intern_key(&mut caller, key_ptr, key_len)?;
+                let value = read_bytes(&mut caller, ptr, len)?;
+                caller
+                    .data_mut()
+                    .changes
+                    .metadata
+                    .insert(key, Value::Bytes(value.into()));
+                Ok(())
+            },
+        )?
+        .func_wrap(
+            MODULE,
+            "get_metadata_u64",
+            |mut caller: Caller<HostState>, key_ptr: i32, key_len: i32, out_ptr: i32| {
+                let Some(key) = lookup_key(&mut caller, key_ptr, key_len)? else {
+                    return Ok(MISSING);
+                };
+                let value = match caller.data().get_metadata(key) {
+                    None => return Ok(MISSING),
+                    Some(Value::Number(value)) => *value,
+                    Some(_) => return Ok(WRONG_TYPE),
+                };
+                write_bytes(&mut caller, &value.to_le_bytes(), out_ptr, 8)
+            },
+        )?
+        .func_wrap(
+            MODULE,
+            "set_metadata_u64",
+            |mut caller: Caller<HostState>, key_ptr: i32, key_len: i32, value: i64| {
+                let key = intern_key(&mut caller, key_ptr, key_len)?;
+                caller
+                    .data_mut()
+                    .changes
+                    .metadata
+                    .insert(key, Value::Number(value as u64));
+                Ok(())
+            },
+        )?
+        .func_wrap(MODULE, "drop", |mut caller: Caller<HostState>| {
+            caller.data_mut().changes.dropped = true;
+        })?;
+
+    Ok(linker)
+}
+
+fn memory(caller: &Caller<HostState>) -> Result<wasmi::Memory, Trap> {
+    caller
+        .get_export(super::MEMORY_EXPORT)
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| trap("missing `memory` export"))
+}
+
+/// Returns the range of guest memory at `ptr`, trapping if it's out of bounds.
+fn range(memory: &[u8], ptr: i32, len: i32) -> Result<std::ops::Range<usize>, Trap> {
+    let start = ptr as u32 as usize;
+    let end = start.saturating_add(len as u32 as usize);
+    (end <= memory.len())
+        .then_some(start..end)
+        .ok_or_else(|| trap("out of bounds memory access"))
+}
+
+fn read_bytes(caller: &mut Caller<HostState>, ptr: i32, len: i32) -> Result<Vec<u8>, Trap> {
+    let data = memory(caller)?.data(&*caller);
+    Ok(data[range(data, ptr, len)?].to_vec())
+}
+
+fn read_key(caller: &mut Caller<HostState>, ptr: i32, len: i32) -> Result<String, Trap> {
+    if len as u32 as usize > MAX_KEY_LEN {
+        return Err(trap("metadata key is too long"));
+    }
+
+    String::from_utf8(read_bytes(caller, ptr, len)?)
+        .map_err(|_| trap("metadata key is not valid UTF-8"))
+}
+
+/// Reads a key without interning it, returning `None` if it has never been
+/// interned, in which case it can't be present in the metadata.
+fn lookup_key(caller: &mut Caller<HostState>, ptr: i32, len: i32) -> Result<Option<Key>, Trap> {
+    read_key(caller, ptr, len).map(Key::get)
+}
+
+/// Reads a key, interning it if it's new and the module hasn't used up its
+/// allowance of new keys.
+fn intern_key(caller: &mut Caller<HostState>, ptr: i32, len: i32) -> Result<Key, Trap> {
+    let key = read_key(caller, ptr, len)?;
+    if let Some(key) = Key::get(&key) {
+        return Ok(key);
+    }
+
+    caller
+        .data()
+        .new_keys
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+            (count < MAX_NEW_KEYS).then_some(count + 1)
+        })
+        .map_err(|_| trap("too many new metadata keys"))?;
+    Ok(Key::new(key))
+}
+
+/// Writes `bytes` to guest memory if it fits within `len`, returning the
+/// number of bytes written, or [`MISSING`] if it doesn't fit.
+fn write_bytes(
+    caller: &mut Caller<HostState>,
+    bytes: &[u8],
+    ptr: i32,
+    len: i32,
+) -> Result<i32, Trap> {
+    if bytes.len() > len as u32 as usize {
+        return Ok(MISSING);
+    }
+
+    let data = memory(caller)?.data_mut(caller);
+    let range = range(data, ptr, bytes.len() as i32)?;
+    data[range].copy_from_slice(bytes);
+    Ok(bytes.len() as i32)
+}
@@ -25,6 +25,7 @@ use crate::{
 use crate::filters::Filter;
 
 /// The input arguments to [`Filter::write`].
+#[derive(Clone)]
 #[non_exhaustive]
 pub struct WriteContext {
     /// The upstream endpoint that we're expecting packets from.
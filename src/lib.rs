@@ -60,8 +60,11 @@ mod external_doc_tests {
     #![doc = include_str!("../docs/src/services/proxy/filters/load_balancer.md")]
     #![doc = include_str!("../docs/src/services/proxy/filters/local_rate_limit.md")]
     #![doc = include_str!("../docs/src/services/proxy/filters/match.md")]
+    #![doc = include_str!("../docs/src/services/proxy/filters/mirror.md")]
+    #![doc = include_str!("../docs/src/services/proxy/filters/pcap.md")]
     #![doc = include_str!("../docs/src/services/proxy/filters/timestamp.md")]
     #![doc = include_str!("../docs/src/services/proxy/filters/token_router.md")]
+    #![doc = include_str!("../docs/src/services/proxy/filters/wasm.md")]
     #![doc = include_str!("../docs/src/services/proxy/filters/writing_custom_filters.md")]
     #![doc = include_str!("../docs/src/services/xds/providers/filesystem.md")]
 }
@@ -21,7 +21,10 @@ pub mod build {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
 }
 
-use std::{collections::HashMap, convert::TryFrom};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+};
 
 use crate::xds::config::core::v3::Metadata as ProtoMetadata;
 
@@ -32,14 +35,24 @@ pub type DynamicMetadata = HashMap<Key, Value>;
 
 pub const KEY: &str = "quilkin.dev";
 
-#[derive(
-    Clone, Debug, PartialOrd, serde::Serialize, serde::Deserialize, Eq, Ord, schemars::JsonSchema,
-)]
+/// A dynamic metadata value.
+///
+/// Values are equal, and are ordered, by their variant and then by their inner
+/// value, with floats compared using [`f64::total_cmp`]. Use
+/// [`Value::loose_eq`] to compare values of different variants, such as the
+/// same number stored as a [`Value::Number`] and a [`Value::Float`].
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(untagged)]
 pub enum Value {
     Bool(bool),
+    /// An unsigned integer.
     Number(u64),
+    /// A signed integer.
+    Integer(i64),
+    /// A floating point number.
+    Float(f64),
     List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
     String(String),
     Bytes(bytes::Bytes),
 }
@@ -71,6 +84,110 @@ impl Value {
             _ => None,
         }
     }
+
+    /// Returns the value of `self` as an `i64` if it is an integer, or a
+    /// float without a fractional part, that fits within an `i64`.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Number(value) => i64::try_from(*value).ok(),
+            Self::Integer(value) => Some(*value),
+            Self::Float(value) => {
+                (value.fract() == 0.0 && *value >= i64::MIN as f64 && *value < i64::MAX as f64)
+                    .then_some(*value as i64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the value of `self` as a `u64` if it is a non-negative
+    /// integer, or a non-negative float without a fractional part, that fits
+    /// within a `u64`.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::Number(value) => Some(*value),
+            Self::Integer(value) => u64::try_from(*value).ok(),
+            Self::Float(value) => {
+                (value.fract() == 0.0 && *value >= 0.0 && *value < u64::MAX as f64)
+                    .then_some(*value as u64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the value of `self` as an `f64` if it is numeric, integers
+    /// outside of `f64`'s exact range lose precision.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(value) => Some(*value as f64),
+            Self::Integer(value) => Some(*value as f64),
+            Self::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns the inner list of `self` if it matches [`Value::List`].
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Self::List(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the inner map of `self` if it matches [`Value::Map`].
+    pub fn as_map(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Self::Map(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns whether `self` and `other` represent the same value, even if
+    /// they are different variants. Numbers are equal if they represent the
+    /// same number, strings are equal to bytes with the same contents, and
+    /// the elements of lists and maps are compared loosely. Unlike `==`,
+    /// this isn't transitive.
+    pub fn loose_eq(&self, other: &Self) -> bool {
+        if let Some(eq) = self.numeric_eq(other) {
+            return eq;
+        }
+
+        match (self, other) {
+            (Self::String(a), Self::Bytes(b)) | (Self::Bytes(b), Self::String(a)) => {
+                a.as_bytes() == &b[..]
+            }
+            (Self::List(a), Self::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.loose_eq(b))
+            }
+            (Self::Map(a), Self::Map(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .all(|((a_key, a), (b_key, b))| a_key == b_key && a.loose_eq(b))
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /// Returns whether `self` and `other` are numeric and represent the same
+    /// number, or `None` if either isn't numeric.
+    fn numeric_eq(&self, other: &Self) -> Option<bool> {
+        Some(match (self, other) {
+            (Self::Number(a), Self::Number(b)) => a == b,
+            (Self::Integer(a), Self::Integer(b)) => a == b,
+            (Self::Number(a), Self::Integer(b)) | (Self::Integer(b), Self::Number(a)) => {
+                i128::from(*a) == i128::from(*b)
+            }
+            (Self::Float(a), Self::Float(b)) => a == b || (a.is_nan() && b.is_nan()),
+            (number @ Self::Float(_), integer) | (integer, number @ Self::Float(_)) => {
+                match integer {
+                    Self::Number(value) => number.as_u64() == Some(*value),
+                    Self::Integer(value) => number.as_i64() == Some(*value),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        })
+    }
 }
 
 impl std::fmt::Display for Value {
@@ -78,6 +195,8 @@ impl std::fmt::Display for Value {
         match &self {
             Self::Bool(value) => value.fmt(f),
             Self::Number(value) => value.fmt(f),
+            Self::Integer(value) => value.fmt(f),
+            Self::Float(value) => value.fmt(f),
             Self::String(value) => value.fmt(f),
             Self::Bytes(value) => crate::utils::base64_encode(value).fmt(f),
             Self::List(values) => {
@@ -95,6 +214,21 @@ impl std::fmt::Display for Value {
 
                 write!(f, "]")
             }
+            Self::Map(values) => {
+                write!(f, "{{")?;
+                let mut first = true;
+                for (key, value) in values {
+                    if first {
+                        first = false;
+                    } else {
+                        write!(f, ",")?;
+                    }
+
+                    write!(f, "{key}:{value}")?;
+                }
+
+                write!(f, "}}")
+            }
         }
     }
 }
@@ -116,7 +250,10 @@ from_value! {
     (value) {
         bool => Self::Bool(value),
         u64 => Self::Number(value),
+        i64 => Self::Integer(value),
+        f64 => Self::Float(value),
         Vec<Self> => Self::List(value),
+        BTreeMap<String, Self> => Self::Map(value),
         String => Self::String(value),
         &str => Self::String(value.into()),
         bytes::Bytes => Self::Bytes(value),
@@ -137,21 +274,45 @@ impl<const N: usize> From<&[u8; N]> for Value {
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Bool(a), Self::Bool(b)) => a == b,
-            (Self::Bool(_), _) => false,
-            (Self::Number(a), Self::Number(b)) => a == b,
-            (Self::Number(num), Self::Bytes(bytes)) => {
-                bytes.len() == 1 && *num == u64::from(bytes[0])
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    /// Orders values by their variant, and then by their inner value, with
+    /// floats ordered by [`f64::total_cmp`].
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        fn rank(value: &Value) -> u8 {
+            match value {
+                Value::Bool(_) => 0,
+                Value::Number(_) => 1,
+                Value::Integer(_) => 2,
+                Value::Float(_) => 3,
+                Value::List(_) => 4,
+                Value::Map(_) => 5,
+                Value::String(_) => 6,
+                Value::Bytes(_) => 7,
             }
-            (Self::Number(_), _) => false,
-            (Self::List(a), Self::List(b)) => a == b,
-            (Self::List(_), _) => false,
-            (Self::String(a), Self::String(b)) => a == b,
-            (Self::Bytes(a), Self::Bytes(b)) => a == b,
-            (Self::String(a), Self::Bytes(b)) | (Self::Bytes(b), Self::String(a)) => a == b,
-            (Self::String(_), _) => false,
-            (Self::Bytes(_), _) => false,
+        }
+
+        match (self, other) {
+            (Self::Bool(a), Self::Bool(b)) => a.cmp(b),
+            (Self::Number(a), Self::Number(b)) => a.cmp(b),
+            (Self::Integer(a), Self::Integer(b)) => a.cmp(b),
+            (Self::Float(a), Self::Float(b)) => a.total_cmp(b),
+            (Self::List(a), Self::List(b)) => a.cmp(b),
+            (Self::Map(a), Self::Map(b)) => a.cmp(b),
+            (Self::String(a), Self::String(b)) => a.cmp(b),
+            (Self::Bytes(a), Self::Bytes(b)) => a.cmp(b),
+            (a, b) => rank(a).cmp(&rank(b)),
         }
     }
 }
@@ -163,6 +324,8 @@ impl From<Value> for prost_types::Value {
         Self {
             kind: Some(match value {
                 Value::Number(number) => Kind::NumberValue(number as f64),
+                Value::Integer(number) => Kind::NumberValue(number as f64),
+                Value::Float(number) => Kind::NumberValue(number),
                 Value::String(string) => Kind::StringValue(string),
                 Value::Bool(value) => Kind::BoolValue(value),
                 Value::Bytes(bytes) => Kind::ListValue(prost_types::ListValue {
@@ -176,6 +339,12 @@ impl From<Value> for prost_types::Value {
                 Value::List(list) => Kind::ListValue(prost_types::ListValue {
                     values: list.into_iter().map(From::from).collect(),
                 }),
+                Value::Map(map) => Kind::StructValue(prost_types::Struct {
+                    fields: map
+                        .into_iter()
+                        .map(|(key, value)| (key, value.into()))
+                        .collect(),
+                }),
             }),
         }
     }
@@ -194,7 +363,7 @@ impl TryFrom<prost_types::Value> for Value {
 
         match value {
             Kind::NullValue(_) => Err(eyre::eyre!("unexpected missing value")),
-            Kind::NumberValue(number) => Ok(Self::Number(number as u64)),
+            Kind::NumberValue(number) => Ok(Self::from_f64(number)),
             Kind::StringValue(string) => Ok(Self::String(string)),
             Kind::BoolValue(value) => Ok(Self::Bool(value)),
             Kind::ListValue(list) => Ok(Self::List(
@@ -203,11 +372,89 @@ impl TryFrom<prost_types::Value> for Value {
                     .map(prost_types::Value::try_into)
                     .collect::<crate::Result<_>>()?,
             )),
-            Kind::StructValue(_) => Err(eyre::eyre!("unexpected struct value")),
+            Kind::StructValue(value) => Ok(Self::Map(
+                value
+                    .fields
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, value.try_into()?)))
+                    .collect::<crate::Result<_>>()?,
+            )),
         }
     }
 }
 
+impl Value {
+    /// Converts a float into the narrowest numeric variant that represents
+    /// it exactly, as protobuf and JSON don't distinguish between integers
+    /// and floats.
+    fn from_f64(number: f64) -> Self {
+        let value = Self::Float(number);
+        if let Some(unsigned) = value.as_u64() {
+            Self::Number(unsigned)
+        } else if let Some(integer) = value.as_i64() {
+            Self::Integer(integer)
+        } else {
+            value
+        }
+    }
+}
+
+impl From<Value> for serde_json::Value {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Bool(value) => Self::Bool(value),
+            Value::Number(value) => Self::from(value),
+            Value::Integer(value) => Self::from(value),
+            Value::Float(value) => Self::from(value),
+            Value::String(value) => Self::String(value),
+            // Matches the conversion to protobuf, which has no bytes type.
+            Value::Bytes(value) => Self::Array(value.into_iter().map(Self::from).collect()),
+            Value::List(values) => Self::Array(values.into_iter().map(From::from).collect()),
+            Value::Map(values) => Self::Object(
+                values
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl TryFrom<serde_json::Value> for Value {
+    type Error = eyre::Report;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        use serde_json::Value as Json;
+
+        Ok(match value {
+            Json::Null => return Err(eyre::eyre!("unexpected missing value")),
+            Json::Bool(value) => Self::Bool(value),
+            Json::Number(number) => {
+                if let Some(value) = number.as_u64() {
+                    Self::Number(value)
+                } else if let Some(value) = number.as_i64() {
+                    Self::Integer(value)
+                } else {
+                    Self::Float(number.as_f64().unwrap_or(f64::NAN))
+                }
+            }
+            Json::String(value) => Self::String(value),
+            Json::Array(values) => Self::List(
+                values
+                    .into_iter()
+                    .map(Self::try_from)
+                    .collect::<crate::Result<_>>()?,
+            ),
+            Json::Object(values) => Self::Map(
+                values
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, value.try_into()?)))
+                    .collect::<crate::Result<_>>()?,
+            ),
+        })
+    }
+}
+
 /// Represents a view into the metadata object attached to another object. `T`
 /// represents metadata known to Quilkin under `quilkin.dev` (available under
 /// the [`KEY`] constant.)
@@ -232,6 +479,20 @@ impl<T: Default> MetadataView<T> {
         }
     }
 
+    /// Returns the user created metadata under `key` as a [`Value`], if
+    /// present and not null.
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.unknown
+            .get(key)
+            .cloned()
+            .and_then(|value| Value::try_from(value).ok())
+    }
+
+    /// Inserts `value` into the user created metadata under `key`.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<Value>) {
+        self.unknown.insert(key.into(), value.into().into());
+    }
+
     pub fn with_unknown(
         known: impl Into<T>,
         unknown: serde_json::Map<String, serde_json::Value>,
@@ -311,3 +572,94 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equality_is_consistent_with_ordering() {
+        assert_ne!(Value::Number(5), Value::Integer(5));
+        assert_ne!(Value::Integer(5), Value::Float(5.0));
+        assert_eq!(Value::Float(f64::NAN), Value::Float(f64::NAN));
+        assert_ne!(
+            Value::Number(5),
+            Value::Bytes(bytes::Bytes::from_static(&[5]))
+        );
+
+        let values = [
+            Value::Number(5),
+            Value::Integer(5),
+            Value::Float(5.0),
+            Value::Bytes(bytes::Bytes::from_static(&[5])),
+        ];
+        for a in &values {
+            for b in &values {
+                assert_eq!(a == b, a.cmp(b).is_eq(), "{a:?} {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn loose_equality() {
+        assert!(Value::Number(5).loose_eq(&Value::Integer(5)));
+        assert!(Value::Integer(5).loose_eq(&Value::Float(5.0)));
+        assert!(Value::Float(f64::NAN).loose_eq(&Value::Float(f64::NAN)));
+        assert!(!Value::Integer(-1).loose_eq(&Value::Number(u64::MAX)));
+        assert!(!Value::Number(5).loose_eq(&Value::Float(5.5)));
+        assert!(!Value::Number(5).loose_eq(&Value::Bytes(bytes::Bytes::from_static(&[5]))));
+        assert!(Value::String("abc".into()).loose_eq(&Value::Bytes("abc".into())));
+        assert!(Value::List(vec![Value::Number(1)]).loose_eq(&Value::List(vec![Value::Float(1.0)])));
+        assert!(!Value::List(vec![Value::Number(1)]).loose_eq(&Value::List(vec![])));
+    }
+
+    #[test]
+    fn prost_round_trip() {
+        let value = Value::Map(BTreeMap::from([
+            ("count".into(), Value::Number(3)),
+            ("offset".into(), Value::Integer(-7)),
+            ("ratio".into(), Value::Float(0.5)),
+            ("name".into(), Value::String("quilkin".into())),
+            (
+                "list".into(),
+                Value::List(vec![Value::Bool(true), Value::Number(1)]),
+            ),
+        ]));
+
+        let proto = prost_types::Value::from(value.clone());
+        assert_eq!(value, Value::try_from(proto).unwrap());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let value = Value::Map(BTreeMap::from([
+            ("count".into(), Value::Number(3)),
+            ("offset".into(), Value::Integer(-7)),
+            ("ratio".into(), Value::Float(0.5)),
+        ]));
+
+        let json = serde_json::Value::from(value.clone());
+        assert_eq!(
+            serde_json::json!({ "count": 3, "offset": -7, "ratio": 0.5 }),
+            json
+        );
+        assert_eq!(value, Value::try_from(json).unwrap());
+        assert!(Value::try_from(serde_json::Value::Null).is_err());
+    }
+
+    #[test]
+    fn metadata_view_round_trip() {
+        let mut metadata = MetadataView::<crate::endpoint::Metadata>::default();
+        let session = Value::Map(BTreeMap::from([
+            ("region".into(), Value::String("us-east".into())),
+            ("players".into(), Value::Number(16)),
+        ]));
+        metadata.insert("session", session.clone());
+
+        let metadata =
+            MetadataView::<crate::endpoint::Metadata>::try_from(ProtoMetadata::from(metadata))
+                .unwrap();
+        assert_eq!(Some(session), metadata.get("session"));
+        assert_eq!(None, metadata.get("missing"));
+    }
+}
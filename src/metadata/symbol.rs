@@ -29,6 +29,12 @@ impl Key {
         Self(INTERNER.get_or_intern(key.as_ref()))
     }
 
+    /// Returns the key if it has already been interned, without interning
+    /// it, as interned keys are never freed.
+    pub fn get<A: AsRef<str>>(key: A) -> Option<Self> {
+        INTERNER.get(key.as_ref()).map(Self)
+    }
+
     pub fn from_static(key: &'static str) -> Self {
         Self(INTERNER.get_or_intern_static(key))
     }
@@ -143,13 +149,15 @@ impl Symbol {
     ///
     /// - [`Value::Bytes`] The value is copied as-is.
     /// - [`Value::String`] The value is interpreted as a base64 string.
-    /// - [`Value::Number`] The value is an eight byte number encoded as big endian.
+    /// - [`Value::Number`] and [`Value::Integer`] The value is an eight
+    ///   byte number encoded as big endian.
     pub fn resolve_to_bytes<'literal: 'metadata, 'metadata>(
         &'literal self,
         metadata: &'metadata DynamicMetadata,
     ) -> Option<bytes::Bytes> {
         match self.resolve(metadata) {
             Some(Value::Number(value)) => Some(Vec::from(value.to_be_bytes()).into()),
+            Some(Value::Integer(value)) => Some(Vec::from(value.to_be_bytes()).into()),
             Some(Value::Bytes(bytes)) => Some(bytes.clone()),
             Some(Value::String(string)) => Some(crate::utils::base64_decode(string).ok()?.into()),
             _ => None,
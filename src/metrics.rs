@@ -178,9 +178,12 @@ pub(crate) fn packets_total(direction: Direction, asn: Option<&IpNetEntry>) -> I
     ])
 }
 
+/// Packets that were dropped, either intentionally by a filter, or due to an
+/// error. `reason` and `filter` **must** come from a bounded set of values.
 pub(crate) fn packets_dropped_total(
     direction: Direction,
-    source: &str,
+    reason: &str,
+    filter: &str,
     asn: Option<&IpNetEntry>,
 ) -> IntCounter {
     static PACKETS_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
@@ -189,7 +192,7 @@ pub(crate) fn packets_dropped_total(
                 "packets_dropped_total",
                 "Total number of dropped packets",
             },
-            &[Direction::LABEL, "source", ASN_LABEL, PREFIX_LABEL],
+            &[Direction::LABEL, "reason", "filter", ASN_LABEL, PREFIX_LABEL],
             registry(),
         }
         .unwrap()
@@ -197,7 +200,8 @@ pub(crate) fn packets_dropped_total(
 
     PACKETS_DROPPED.with_label_values(&[
         direction.label(),
-        source,
+        reason,
+        filter,
         &asn.map(|asn| asn.r#as.to_string()).unwrap_or_default(),
         asn.map(|asn| &*asn.prefix).unwrap_or_default(),
     ])
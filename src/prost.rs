@@ -37,7 +37,7 @@ pub fn value_from_kind(kind: Kind) -> Value {
     match kind {
         Kind::NullValue(_) => Value::Null,
         Kind::BoolValue(v) => Value::Bool(v),
-        Kind::NumberValue(v) => Value::Number(serde_json::Number::from(v as i64)), // TODO: Call out in documentation or find solution
+        Kind::NumberValue(v) => number_from_f64(v),
         Kind::StringValue(v) => Value::String(v),
         Kind::ListValue(v) => Value::Array(
             v.values
@@ -55,6 +55,19 @@ pub fn value_from_kind(kind: Kind) -> Value {
     }
 }
 
+/// Converts a protobuf number into the narrowest JSON number that represents
+/// it exactly, as protobuf doesn't distinguish between integers and floats.
+fn number_from_f64(number: f64) -> Value {
+    if number.fract() == 0.0 && number >= 0.0 && number < u64::MAX as f64 {
+        Value::from(number as u64)
+    } else if number.fract() == 0.0 && number >= i64::MIN as f64 && number < i64::MAX as f64 {
+        Value::from(number as i64)
+    } else {
+        // Non-finite numbers can't be represented in JSON, and become null.
+        Value::from(number)
+    }
+}
+
 pub fn struct_from_json(value: Value) -> Option<prost_types::Struct> {
     match from_json(value) {
         prost_types::Value {
@@ -22,7 +22,7 @@ use tokio::net::UdpSocket;
 
 use crate::{
     endpoint::{Endpoint, EndpointAddress},
-    filters::{Filter, ReadContext},
+    filters::{canary, Filter, ReadContext},
     Config,
 };
 
@@ -46,6 +46,9 @@ pub(crate) struct DownstreamReceiveWorkerConfig {
     pub socket: Arc<UdpSocket>,
     pub config: Arc<Config>,
     pub sessions: SessionMap,
+    /// Sessions used for mirroring packets, kept separate from `sessions`
+    /// so that they never affect the routing of regular traffic.
+    pub mirror_sessions: SessionMap,
 }
 
 impl DownstreamReceiveWorkerConfig {
@@ -55,6 +58,7 @@ impl DownstreamReceiveWorkerConfig {
             socket,
             config,
             sessions,
+            mirror_sessions,
         } = self;
 
         tokio::spawn(async move {
@@ -89,7 +93,7 @@ impl DownstreamReceiveWorkerConfig {
                                 }
                                 last_received_at = Some(packet.received_at);
 
-                                Self::spawn_process_task(packet, source, worker_id, &socket, &config, &sessions)
+                                Self::spawn_process_task(packet, source, worker_id, &socket, &config, &sessions, &mirror_sessions)
                             }
                             Err(error) => {
                                 tracing::error!(%error, "error receiving packet");
@@ -110,6 +114,7 @@ impl DownstreamReceiveWorkerConfig {
         socket: &Arc<UdpSocket>,
         config: &Arc<Config>,
         sessions: &SessionMap,
+        mirror_sessions: &SessionMap,
     ) {
         tracing::trace!(
             id = worker_id,
@@ -122,6 +127,7 @@ impl DownstreamReceiveWorkerConfig {
         tokio::spawn({
             let config = config.clone();
             let sessions = sessions.clone();
+            let mirror_sessions = mirror_sessions.clone();
             let socket = socket.clone();
 
             async move {
@@ -129,8 +135,14 @@ impl DownstreamReceiveWorkerConfig {
 
                 let asn_info = packet.asn_info.clone();
                 let asn_info = asn_info.as_ref();
-                match Self::process_downstream_received_packet(packet, config, socket, sessions)
-                    .await
+                match Self::process_downstream_received_packet(
+                    packet,
+                    config,
+                    socket,
+                    sessions,
+                    mirror_sessions,
+                )
+                .await
                 {
                     Ok(size) => {
                         crate::metrics::packets_total(crate::metrics::READ, asn_info).inc();
@@ -138,11 +150,15 @@ impl DownstreamReceiveWorkerConfig {
                             .inc_by(size as u64);
                     }
                     Err(error) => {
-                        let source = error.to_string();
-                        crate::metrics::errors_total(crate::metrics::READ, &source, asn_info).inc();
+                        if !error.is_drop() {
+                            let source = error.to_string();
+                            crate::metrics::errors_total(crate::metrics::READ, &source, asn_info)
+                                .inc();
+                        }
                         crate::metrics::packets_dropped_total(
                             crate::metrics::READ,
-                            &source,
+                            error.drop_reason(),
+                            error.filter_name(),
                             asn_info,
                         )
                         .inc();
@@ -160,15 +176,55 @@ impl DownstreamReceiveWorkerConfig {
         config: Arc<Config>,
         downstream_socket: Arc<UdpSocket>,
         sessions: SessionMap,
+        mirror_sessions: SessionMap,
     ) -> Result<usize, PipelineError> {
-        let endpoints: Vec<_> = config.clusters.read().endpoints().collect();
+        let clusters = config.clusters.load();
+        let endpoints: Vec<_> = clusters.endpoints().collect();
         if endpoints.is_empty() {
             return Err(PipelineError::NoUpstreamEndpoints);
         }
 
         let filters = config.filters.load();
-        let mut context = ReadContext::new(endpoints, packet.source.into(), packet.contents);
-        filters.read(&mut context).await?;
+        let mut context =
+            ReadContext::new(endpoints, packet.source.into(), packet.contents).clusters(clusters);
+        let canary = config
+            .canary
+            .try_load()
+            .filter(|canary| canary.sample())
+            .map(|canary| (canary, context.clone()));
+        let result = filters.read(&mut context).await;
+        if let Some((canary, copy)) = canary {
+            canary.read(copy, canary::Decision::read(&result, &context));
+        }
+        result?;
+
+        if !context.mirror_endpoints.is_empty() {
+            // Mirrored packets are sent separately, so they never delay the
+            // original packet.
+            let mirror_endpoints = std::mem::take(&mut context.mirror_endpoints);
+            let contents = context.contents.clone();
+            let source = context.source.clone();
+            let config = config.clone();
+            let asn_info = packet.asn_info.clone();
+            tokio::spawn(async move {
+                for endpoint in mirror_endpoints {
+                    if let Err(error) = Self::session_send_packet(
+                        &contents,
+                        &source,
+                        &endpoint,
+                        None,
+                        &config,
+                        &mirror_sessions,
+                        asn_info.clone(),
+                    )
+                    .await
+                    {
+                        tracing::debug!(%error, dest = %endpoint.address, "failed to mirror packet");
+                    }
+                }
+            });
+        }
+
         let mut bytes_written = 0;
 
         for endpoint in context.endpoints.iter() {
@@ -176,7 +232,7 @@ impl DownstreamReceiveWorkerConfig {
                 &context.contents,
                 &context.source,
                 endpoint,
-                &downstream_socket,
+                Some(&downstream_socket),
                 &config,
                 &sessions,
                 packet.asn_info.clone(),
@@ -187,13 +243,15 @@ impl DownstreamReceiveWorkerConfig {
         Ok(bytes_written)
     }
 
-    /// Send a packet received from `recv_addr` to an endpoint.
+    /// Send a packet received from `recv_addr` to an endpoint. If no
+    /// `downstream_socket` is provided, the packet is mirrored and any replies
+    /// are discarded.
     #[tracing::instrument(level="trace", skip_all, fields(source = %recv_addr, dest = %endpoint.address))]
     async fn session_send_packet(
         packet: &[u8],
         recv_addr: &EndpointAddress,
         endpoint: &Endpoint,
-        downstream_socket: &Arc<UdpSocket>,
+        downstream_socket: Option<&Arc<UdpSocket>>,
         config: &Arc<Config>,
         sessions: &SessionMap,
         asn_info: Option<crate::maxmind_db::IpNetEntry>,
@@ -206,13 +264,21 @@ impl DownstreamReceiveWorkerConfig {
         let send_future = match sessions.get(&session_key) {
             Some(entry) => entry.send(packet),
             None => {
-                let session = Session::new(
-                    config.clone(),
-                    session_key.source.clone(),
-                    downstream_socket.clone(),
-                    endpoint.clone(),
-                    asn_info,
-                )?;
+                let session = match downstream_socket {
+                    Some(downstream_socket) => Session::new(
+                        config.clone(),
+                        session_key.source.clone(),
+                        downstream_socket.clone(),
+                        endpoint.clone(),
+                        asn_info,
+                    )?,
+                    None => Session::new_mirror(
+                        config.clone(),
+                        session_key.source.clone(),
+                        endpoint.clone(),
+                        asn_info,
+                    )?,
+                };
 
                 let future = session.send(packet);
                 sessions.insert(session_key, session);
@@ -235,3 +301,32 @@ pub enum PipelineError {
     #[error("OS level error: {0}")]
     Io(#[from] std::io::Error),
 }
+
+impl PipelineError {
+    /// Whether the packet was intentionally dropped by a filter, rather than
+    /// due to an error.
+    pub fn is_drop(&self) -> bool {
+        matches!(self, Self::Filter(error) if error.is_drop())
+    }
+
+    /// The reason the packet was dropped, used as the `reason` label for
+    /// `packets_dropped_total`.
+    pub fn drop_reason(&self) -> &'static str {
+        match self {
+            Self::NoUpstreamEndpoints => "NoConfiguredEndpoints",
+            Self::Filter(error) => error
+                .drop_reason()
+                .map_or("FilterError", crate::filters::DropReason::label),
+            Self::Qcmp(_) => "Qcmp",
+            Self::Io(_) => "Io",
+        }
+    }
+
+    /// The name of the filter that stopped processing the packet, if any.
+    pub fn filter_name(&self) -> &str {
+        match self {
+            Self::Filter(error) => error.filter_name().unwrap_or_default(),
+            _ => "",
+        }
+    }
+}
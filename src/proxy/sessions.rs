@@ -27,7 +27,7 @@ use tokio::{
 
 use crate::{
     endpoint::{Endpoint, EndpointAddress},
-    filters::{Filter, WriteContext},
+    filters::{canary, Filter, WriteContext},
     maxmind_db::IpNetEntry,
     utils::Loggable,
 };
@@ -49,6 +49,9 @@ pub struct Session {
     shutdown_tx: watch::Sender<()>,
     /// The ASN information.
     asn_info: Option<IpNetEntry>,
+    /// Whether this session mirrors packets, in which case replies are
+    /// discarded instead of being sent downstream.
+    mirror: bool,
 }
 
 // A (source, destination) address pair that uniquely identifies a session.
@@ -82,6 +85,28 @@ impl Session {
         downstream_socket: Arc<UdpSocket>,
         dest: Endpoint,
         asn_info: Option<IpNetEntry>,
+    ) -> Result<Self, super::PipelineError> {
+        Self::with_downstream(config, source, Some(downstream_socket), dest, asn_info)
+    }
+
+    /// Creates a session that mirrors packets from `source` to `dest`, any
+    /// packets received from `dest` are discarded.
+    #[tracing::instrument(skip_all)]
+    pub fn new_mirror(
+        config: Arc<crate::Config>,
+        source: EndpointAddress,
+        dest: Endpoint,
+        asn_info: Option<IpNetEntry>,
+    ) -> Result<Self, super::PipelineError> {
+        Self::with_downstream(config, source, None, dest, asn_info)
+    }
+
+    fn with_downstream(
+        config: Arc<crate::Config>,
+        source: EndpointAddress,
+        downstream_socket: Option<Arc<UdpSocket>>,
+        dest: Endpoint,
+        asn_info: Option<IpNetEntry>,
     ) -> Result<Self, super::PipelineError> {
         let (shutdown_tx, shutdown_rx) = watch::channel::<()>(());
 
@@ -93,12 +118,15 @@ impl Session {
             created_at: Instant::now(),
             shutdown_tx,
             asn_info,
+            mirror: downstream_socket.is_none(),
         };
 
-        tracing::debug!(source = %s.source, dest = ?s.dest, "Session created");
+        tracing::debug!(source = %s.source, dest = ?s.dest, mirror = s.mirror, "Session created");
 
-        self::metrics::total_sessions().inc();
-        s.active_session_metric().inc();
+        if !s.mirror {
+            self::metrics::total_sessions().inc();
+            s.active_session_metric().inc();
+        }
         s.run(downstream_socket, shutdown_rx);
         Ok(s)
     }
@@ -126,7 +154,7 @@ impl Session {
 
     /// run starts processing receiving upstream udp packets
     /// and sending them back downstream
-    fn run(&self, downstream_socket: Arc<UdpSocket>, mut shutdown_rx: watch::Receiver<()>) {
+    fn run(&self, downstream_socket: Option<Arc<UdpSocket>>, mut shutdown_rx: watch::Receiver<()>) {
         let source = self.source.clone();
         let config = self.config.clone();
         let endpoint = self.dest.clone();
@@ -156,6 +184,11 @@ impl Session {
                                 tracing::error!(%error, %source, dest = ?endpoint, "Error receiving packet");
                             },
                             Ok((size, recv_addr)) => {
+                                let Some(downstream_socket) = &downstream_socket else {
+                                    tracing::trace!(%recv_addr, dest = ?endpoint, "discarding reply from mirror endpoint");
+                                    continue;
+                                };
+
                                 let received_at = chrono::Utc::now().timestamp_nanos();
                                 if let Some(last_received_at) = last_received_at {
                                     crate::metrics::packet_jitter(crate::metrics::WRITE, asn_info).set(received_at - last_received_at);
@@ -167,7 +200,7 @@ impl Session {
 
                                 let timer = crate::metrics::processing_time(crate::metrics::WRITE).start_timer();
                                 let result = Session::process_recv_packet(
-                                    &downstream_socket,
+                                    downstream_socket,
                                     ReceivedPacketContext {
                                         config: config.clone(),
                                         packet: &buf[..size],
@@ -178,13 +211,16 @@ impl Session {
                                 timer.stop_and_record();
                                 if let Err(error) = result {
                                     error.log();
-                                    let label = format!("proxy::Session::process_recv_packet: {error}");
                                     crate::metrics::packets_dropped_total(
                                         crate::metrics::WRITE,
-                                        &label,
+                                        error.drop_reason(),
+                                        error.filter_name(),
                                         asn_info
                                     ).inc();
-                                    crate::metrics::errors_total(crate::metrics::WRITE, &label, asn_info).inc();
+                                    if !error.is_drop() {
+                                        let label = format!("proxy::Session::process_recv_packet: {error}");
+                                        crate::metrics::errors_total(crate::metrics::WRITE, &label, asn_info).inc();
+                                    }
                                 }
                             }
                         };
@@ -224,7 +260,16 @@ impl Session {
             packet.to_vec(),
         );
 
-        config.filters.load().write(&mut context).await?;
+        let canary = config
+            .canary
+            .try_load()
+            .filter(|canary| canary.sample())
+            .map(|canary| (canary, context.clone()));
+        let result = config.filters.load().write(&mut context).await;
+        if let Some((canary, copy)) = canary {
+            canary.write(copy, canary::Decision::write(&result, &context));
+        }
+        result?;
 
         let addr = dest.to_socket_addr().await.map_err(Error::ToSocketAddr)?;
         let packet = context.contents.as_ref();
@@ -252,8 +297,10 @@ impl Session {
 
 impl Drop for Session {
     fn drop(&mut self) {
-        self.active_session_metric().dec();
-        metrics::duration_secs().observe(self.created_at.elapsed().as_secs() as f64);
+        if !self.mirror {
+            self.active_session_metric().dec();
+            metrics::duration_secs().observe(self.created_at.elapsed().as_secs() as f64);
+        }
 
         if let Err(error) = self.shutdown_tx.send(()) {
             tracing::warn!(%error, "Error sending session shutdown signal");
@@ -273,12 +320,43 @@ pub enum Error {
     Filter(#[from] crate::filters::FilterError),
 }
 
+impl Error {
+    /// Whether the packet was intentionally dropped by a filter, rather than
+    /// due to an error.
+    pub fn is_drop(&self) -> bool {
+        matches!(self, Self::Filter(error) if error.is_drop())
+    }
+
+    /// The reason the packet was dropped, used as the `reason` label for
+    /// `packets_dropped_total`.
+    pub fn drop_reason(&self) -> &'static str {
+        match self {
+            Self::ToSocketAddr(_) => "ToSocketAddr",
+            Self::SendTo(_) => "SendTo",
+            Self::Filter(error) => error
+                .drop_reason()
+                .map_or("FilterError", crate::filters::DropReason::label),
+        }
+    }
+
+    /// The name of the filter that stopped processing the packet, if any.
+    pub fn filter_name(&self) -> &str {
+        match self {
+            Self::Filter(error) => error.filter_name().unwrap_or_default(),
+            _ => "",
+        }
+    }
+}
+
 impl Loggable for Error {
     fn log(&self) {
         match self {
             Self::ToSocketAddr(error) | Self::SendTo(error) => {
                 tracing::error!(kind=%error.kind(), "{}", self)
             }
+            Self::Filter(error) if error.is_drop() => {
+                tracing::debug!("{}", self);
+            }
             Self::Filter(_) => {
                 tracing::error!("{}", self);
             }
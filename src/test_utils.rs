@@ -307,6 +307,7 @@ pub fn config_with_dummy_endpoint() -> Config {
         }]
         .into_iter()
         .collect(),
+        ..<_>::default()
     });
 
     config
@@ -201,6 +201,12 @@ where
         self.0.inner.contains_key(key)
     }
 
+    /// Returns an iterator over the entries of the map, without resetting
+    /// their TTL.
+    pub fn iter(&self) -> dashmap::iter::Iter<K, Value<V>> {
+        self.0.inner.iter()
+    }
+
     /// Inserts a key-value pair into the map.
     /// The value will be set to expire at the configured TTL after the time of insertion.
     /// If a previous value existed for this key, that value is returned.
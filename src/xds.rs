@@ -127,7 +127,10 @@ mod google {
 
 crate::include_proto!("quilkin.relay.v1alpha1");
 
+mod auth;
 pub(crate) mod client;
+pub mod clients;
+pub mod merge;
 mod metrics;
 mod resource;
 pub(crate) mod server;
@@ -136,7 +139,9 @@ pub(crate) use self::quilkin::relay::v1alpha1 as relay;
 use self::xds as envoy;
 
 pub use self::{
+    auth::{ClientAuth, ServerAuth},
     client::{AdsClient, Client},
+    clients::Clients,
     resource::{Resource, ResourceType},
     server::ControlPlane,
     service::discovery::v3::aggregated_discovery_service_client::AggregatedDiscoveryServiceClient,
@@ -184,11 +189,11 @@ mod tests {
         .unwrap();
 
         // Test that the client can handle the manager dropping out.
-        let handle = tokio::spawn(server::spawn(xds_port, xds_config.clone()));
+        let handle = tokio::spawn(server::spawn(xds_port, xds_config.clone(), <_>::default()));
 
         tokio::time::sleep(std::time::Duration::from_millis(50)).await;
         let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(());
-        tokio::spawn(server::spawn(xds_port, xds_config.clone()));
+        tokio::spawn(server::spawn(xds_port, xds_config.clone(), <_>::default()));
         let client_proxy = crate::cli::Proxy {
             port: client_addr.port(),
             management_server: vec![format!("http://0.0.0.0:{}", xds_port).parse().unwrap()],
@@ -201,7 +206,7 @@ mod tests {
         tokio::time::sleep(std::time::Duration::from_millis(50)).await;
         handle.abort();
         tokio::time::sleep(std::time::Duration::from_millis(50)).await;
-        tokio::spawn(server::spawn(xds_port, xds_config.clone()));
+        tokio::spawn(server::spawn(xds_port, xds_config.clone(), <_>::default()));
         tokio::time::sleep(std::time::Duration::from_millis(50)).await;
 
         const VERSION_KEY: &str = "quilkin.dev/load_balancer/version";
@@ -221,9 +226,10 @@ mod tests {
                 Match::as_filter_config(r#match::Config {
                     on_write: None,
                     on_read: Some(r#match::DirectionalConfig {
-                        metadata_key: VERSION_KEY.into(),
+                        metadata_key: Some(VERSION_KEY.into()),
                         branches: vec![r#match::Branch {
-                            value: 1.into(),
+                            value: Some(1u64.into()),
+                            condition: None,
                             filter: Capture::as_filter_config(capture::Config {
                                 metadata_key: TOKEN_KEY.into(),
                                 strategy: capture::Suffix {
@@ -232,7 +238,8 @@ mod tests {
                                 }
                                 .into(),
                             })
-                            .unwrap(),
+                            .unwrap()
+                            .into(),
                         }],
                         fallthrough: <_>::default(),
                     }),
@@ -241,13 +248,15 @@ mod tests {
                 Match::as_filter_config(r#match::Config {
                     on_write: None,
                     on_read: Some(r#match::DirectionalConfig {
-                        metadata_key: VERSION_KEY.into(),
+                        metadata_key: Some(VERSION_KEY.into()),
                         branches: vec![r#match::Branch {
-                            value: 1.into(),
+                            value: Some(1u64.into()),
+                            condition: None,
                             filter: TokenRouter::as_filter_config(token_router::Config {
                                 metadata_key: TOKEN_KEY.into(),
                             })
-                            .unwrap(),
+                            .unwrap()
+                            .into(),
                         }],
                         fallthrough: <_>::default(),
                     }),
@@ -295,10 +304,11 @@ mod tests {
         .map(Arc::new)
         .unwrap();
 
-        tokio::spawn(server::spawn(23456, config.clone()));
+        tokio::spawn(server::spawn(23456, config.clone(), <_>::default()));
         let client = Client::connect(
             "test-client".into(),
             vec!["http://127.0.0.1:23456".try_into().unwrap()],
+            <_>::default(),
         )
         .await
         .unwrap();
@@ -368,4 +378,275 @@ mod tests {
             assert_eq!(iter.next().unwrap(), filters[1].clone().into());
         }
     }
+
+    /// Waits for up to a second for `condition` to hold.
+    async fn eventually(condition: impl Fn() -> bool) -> bool {
+        for _ in 0..20 {
+            if condition() {
+                return true;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        condition()
+    }
+
+    #[tokio::test]
+    async fn delta_removes_clusters() {
+        let server_config = Arc::new(Config::default());
+        server_config.clusters.modify(|clusters| {
+            clusters.insert(crate::cluster::Cluster::new(
+                "a",
+                [crate::endpoint::LocalityEndpoints::from(Endpoint::new(
+                    (std::net::Ipv4Addr::LOCALHOST, 1).into(),
+                ))],
+            ));
+        });
+        let xds_port = crate::test_utils::available_addr().await.port();
+        tokio::spawn(server::spawn(
+            xds_port,
+            server_config.clone(),
+            <_>::default(),
+        ));
+
+        let config = Arc::new(Config::default());
+        let client = Client::connect(
+            "test-client".into(),
+            vec![format!("http://127.0.0.1:{xds_port}").try_into().unwrap()],
+            <_>::default(),
+        )
+        .await
+        .unwrap();
+        let mut stream = client.xds_client_stream(config.clone());
+        stream
+            .discovery_request(ResourceType::Endpoint, &[])
+            .await
+            .unwrap();
+
+        assert!(eventually(|| config.clusters.read().get("a").is_some()).await);
+
+        server_config.clusters.modify(|clusters| {
+            clusters.remove("a");
+        });
+
+        assert!(eventually(|| config.clusters.read().get("a").is_none()).await);
+    }
+
+    #[tokio::test]
+    async fn rejected_response_is_nacked() {
+        use futures::StreamExt;
+
+        let response = |version: &str, filter: &str| {
+            let listener = config::listener::v3::Listener {
+                filter_chains: vec![config::listener::v3::FilterChain {
+                    filters: vec![config::listener::v3::Filter {
+                        name: filter.into(),
+                        config_type: None,
+                    }],
+                    ..<_>::default()
+                }],
+                ..<_>::default()
+            };
+            service::discovery::v3::DiscoveryResponse {
+                version_info: version.into(),
+                resources: vec![ResourceType::Listener.encode_to_any(&listener).unwrap()],
+                type_url: ResourceType::Listener.type_url().into(),
+                nonce: format!("nonce-{version}"),
+                ..<_>::default()
+            }
+        };
+
+        let config = Arc::new(Config::default());
+        let mut requests = client::handle_discovery_responses(
+            "test-client".into(),
+            futures::stream::iter([Ok(response("1", Debug::NAME)), Ok(response("2", "unknown"))]),
+            {
+                let config = config.clone();
+                move |resources| config.apply_all(resources)
+            },
+        );
+
+        let ack = requests.next().await.unwrap().unwrap();
+        assert_eq!("1", ack.version_info);
+        assert!(ack.error_detail.is_none());
+
+        // The invalid filter chain is rejected, reporting the version that's
+        // still applied.
+        let nack = requests.next().await.unwrap().unwrap();
+        assert_eq!("1", nack.version_info);
+        assert_eq!("nonce-2", nack.response_nonce);
+        assert!(nack.error_detail.unwrap().message.contains("unknown"));
+        assert_eq!(1, config.filters.load().len());
+    }
+
+    #[tokio::test]
+    async fn merges_management_servers() {
+        let mut management_servers = Vec::new();
+        for port in [1, 2] {
+            let server_config = Arc::new(Config::default());
+            server_config.clusters.modify(|clusters| {
+                clusters.insert_default(vec![Endpoint::new(
+                    (std::net::Ipv4Addr::LOCALHOST, port).into(),
+                )]);
+            });
+            let xds_port = crate::test_utils::available_addr().await.port();
+            tokio::spawn(server::spawn(xds_port, server_config, <_>::default()));
+            management_servers.push(format!("http://127.0.0.1:{xds_port}").parse().unwrap());
+        }
+
+        let config = Arc::new(Config::default());
+        let proxy = crate::cli::Proxy {
+            port: crate::test_utils::available_addr().await.port(),
+            management_server: management_servers,
+            merge_management_servers: true,
+            ..<_>::default()
+        };
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(());
+        tokio::spawn({
+            let config = config.clone();
+            async move { proxy.run(config, shutdown_rx).await }
+        });
+
+        assert!(eventually(|| config.clusters.read().endpoints().count() == 2).await);
+    }
+
+    #[tokio::test]
+    async fn delta_falls_back_to_state_of_the_world() {
+        use service::discovery::v3::{
+            aggregated_discovery_service_server::{
+                AggregatedDiscoveryService, AggregatedDiscoveryServiceServer,
+            },
+            DeltaDiscoveryRequest, DiscoveryRequest,
+        };
+
+        /// A management server that only supports state of the world.
+        struct StateOfTheWorld(ControlPlane);
+
+        #[tonic::async_trait]
+        impl AggregatedDiscoveryService for StateOfTheWorld {
+            type StreamAggregatedResourcesStream =
+                <ControlPlane as AggregatedDiscoveryService>::StreamAggregatedResourcesStream;
+            type DeltaAggregatedResourcesStream =
+                <ControlPlane as AggregatedDiscoveryService>::DeltaAggregatedResourcesStream;
+
+            async fn stream_aggregated_resources(
+                &self,
+                request: tonic::Request<tonic::Streaming<DiscoveryRequest>>,
+            ) -> Result<tonic::Response<Self::StreamAggregatedResourcesStream>, tonic::Status>
+            {
+                AggregatedDiscoveryService::stream_aggregated_resources(&self.0, request).await
+            }
+
+            async fn delta_aggregated_resources(
+                &self,
+                _request: tonic::Request<tonic::Streaming<DeltaDiscoveryRequest>>,
+            ) -> Result<tonic::Response<Self::DeltaAggregatedResourcesStream>, tonic::Status>
+            {
+                Err(tonic::Status::unimplemented("delta xDS"))
+            }
+        }
+
+        let server_config = Arc::new(Config::default());
+        server_config.clusters.modify(|clusters| {
+            clusters.insert(crate::cluster::Cluster::new(
+                "a",
+                [crate::endpoint::LocalityEndpoints::from(Endpoint::new(
+                    (std::net::Ipv4Addr::LOCALHOST, 1).into(),
+                ))],
+            ));
+        });
+        let xds_port = crate::test_utils::available_addr().await.port();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(AggregatedDiscoveryServiceServer::new(StateOfTheWorld(
+                    ControlPlane::from_arc(server_config),
+                )))
+                .serve((std::net::Ipv4Addr::LOCALHOST, xds_port).into()),
+        );
+
+        let config = Arc::new(Config::default());
+        let client = Client::connect(
+            "test-client".into(),
+            vec![format!("http://127.0.0.1:{xds_port}").try_into().unwrap()],
+            <_>::default(),
+        )
+        .await
+        .unwrap();
+        let mut stream = client.xds_client_stream(config.clone());
+        stream
+            .discovery_request(ResourceType::Endpoint, &[])
+            .await
+            .unwrap();
+
+        assert!(eventually(|| config.clusters.read().get("a").is_some()).await);
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_and_token() {
+        let cert = |name: &str| {
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/certs")
+                .join(name)
+        };
+        let tokens = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(tokens.path(), "secret\n").unwrap();
+
+        let server_config = Arc::new(Config::default());
+        server_config.clusters.modify(|clusters| {
+            clusters.insert(crate::cluster::Cluster::new(
+                "a",
+                [crate::endpoint::LocalityEndpoints::from(Endpoint::new(
+                    (std::net::Ipv4Addr::LOCALHOST, 1).into(),
+                ))],
+            ));
+        });
+        let xds_port = crate::test_utils::available_addr().await.port();
+        tokio::spawn(server::spawn(
+            xds_port,
+            server_config,
+            ServerAuth {
+                tls_cert: Some(cert("server.pem")),
+                tls_key: Some(cert("server.key")),
+                tls_client_ca: Some(cert("ca.pem")),
+                tls_allowed_sans: vec!["client.quilkin.dev".into()],
+                auth_token_file: Some(tokens.path().into()),
+            },
+        ));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let subscribe = |token: &'static str| {
+            let token_file = tempfile::NamedTempFile::new().unwrap();
+            std::fs::write(token_file.path(), token).unwrap();
+            let auth = ClientAuth {
+                upstream_tls_ca: Some(cert("ca.pem")),
+                upstream_tls_cert: Some(cert("client.pem")),
+                upstream_tls_key: Some(cert("client.key")),
+                upstream_tls_domain: None,
+                upstream_auth_token_file: Some(token_file.path().into()),
+            };
+            async move {
+                let config = Arc::new(Config::default());
+                let client = Client::connect(
+                    "test-client".into(),
+                    vec![format!("https://localhost:{xds_port}").try_into().unwrap()],
+                    auth,
+                )
+                .await
+                .unwrap();
+                let mut stream = client.xds_client_stream(config.clone());
+                stream
+                    .discovery_request(ResourceType::Endpoint, &[])
+                    .await
+                    .unwrap();
+                (config, stream, token_file)
+            }
+        };
+
+        let (config, _stream, _token) = subscribe("secret").await;
+        assert!(eventually(|| config.clusters.read().get("a").is_some()).await);
+
+        let (config, _stream, _token) = subscribe("wrong").await;
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        assert!(config.clusters.read().get("a").is_none());
+    }
 }
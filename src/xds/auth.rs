@@ -0,0 +1,487 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! TLS and bearer token authentication for the xDS, mDS and relay gRPC
+//! services.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const AUTHORIZATION: &str = "authorization";
+const BEARER: &str = "Bearer ";
+
+/// Authentication required by Quilkin's gRPC servers.
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct ServerAuth {
+    /// A PEM encoded certificate chain to serve gRPC over TLS with.
+    #[clap(long, env = "QUILKIN_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// The PEM encoded private key for `--tls-cert`.
+    #[clap(long, env = "QUILKIN_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// PEM encoded CA certificates to verify client certificates with. When
+    /// set, clients must present a certificate signed by one of them.
+    #[clap(long, env = "QUILKIN_TLS_CLIENT_CA", requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+    /// The subject alternative names (DNS names or IP addresses) allowed in
+    /// client certificates. Any verified client is allowed when empty.
+    #[clap(
+        long = "tls-allowed-san",
+        env = "QUILKIN_TLS_ALLOWED_SANS",
+        value_delimiter = ',',
+        requires = "tls_client_ca"
+    )]
+    pub tls_allowed_sans: Vec<String>,
+    /// A file containing the bearer tokens accepted by the server, one per
+    /// line.
+    #[clap(long, env = "QUILKIN_AUTH_TOKEN_FILE")]
+    pub auth_token_file: Option<PathBuf>,
+}
+
+impl ServerAuth {
+    /// Returns an interceptor rejecting requests without an accepted bearer
+    /// token, if any are configured.
+    pub(crate) fn interceptor(&self) -> RequireToken {
+        RequireToken {
+            tokens: self.auth_token_file.clone().map(|path| {
+                Arc::new(Reloading::new(vec![path.clone()], move || {
+                    Ok(Arc::new(
+                        std::fs::read_to_string(&path)?
+                            .lines()
+                            .map(str::trim)
+                            .filter(|token| !token.is_empty())
+                            .map(String::from)
+                            .collect::<Vec<_>>(),
+                    ))
+                }))
+            }),
+        }
+    }
+
+    /// Serves `router` on `port`, over TLS if a certificate is configured.
+    pub(crate) async fn serve(
+        &self,
+        router: tonic::transport::server::Router,
+        port: u16,
+    ) -> crate::Result<()> {
+        let address = (std::net::Ipv6Addr::UNSPECIFIED, port).into();
+        let (Some(cert), Some(key)) = (self.tls_cert.clone(), self.tls_key.clone()) else {
+            return router.serve(address).await.map_err(From::from);
+        };
+
+        let client_ca = self.tls_client_ca.clone();
+        let mut paths = vec![cert.clone(), key.clone()];
+        paths.extend(client_ca.clone());
+        let acceptor = Reloading::new(paths, move || {
+            server_config(&cert, &key, client_ca.as_deref())
+                .map(|config| TlsAcceptor::from(Arc::new(config)))
+        });
+        // Load the configuration up front so that errors are reported on
+        // startup rather than on the first connection.
+        acceptor.get()?;
+
+        let listener = TcpListener::bind(address).await?;
+        router
+            .serve_with_incoming(incoming(
+                listener,
+                acceptor,
+                Arc::from(self.tls_allowed_sans.clone()),
+            ))
+            .await
+            .map_err(From::from)
+    }
+}
+
+/// Authentication used when connecting to a management or relay server.
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct ClientAuth {
+    /// PEM encoded CA certificates to verify `https` upstream servers with.
+    #[clap(long, env = "QUILKIN_UPSTREAM_TLS_CA")]
+    pub upstream_tls_ca: Option<PathBuf>,
+    /// A PEM encoded certificate chain to present to upstream servers.
+    #[clap(long, env = "QUILKIN_UPSTREAM_TLS_CERT", requires = "upstream_tls_key")]
+    pub upstream_tls_cert: Option<PathBuf>,
+    /// The PEM encoded private key for `--upstream-tls-cert`.
+    #[clap(long, env = "QUILKIN_UPSTREAM_TLS_KEY", requires = "upstream_tls_cert")]
+    pub upstream_tls_key: Option<PathBuf>,
+    /// The name to verify upstream server certificates against, instead of
+    /// the host in the server's URL.
+    #[clap(long, env = "QUILKIN_UPSTREAM_TLS_DOMAIN")]
+    pub upstream_tls_domain: Option<String>,
+    /// A file containing the bearer token to send to upstream servers.
+    #[clap(long, env = "QUILKIN_UPSTREAM_AUTH_TOKEN_FILE")]
+    pub upstream_auth_token_file: Option<PathBuf>,
+}
+
+impl ClientAuth {
+    /// Applies the TLS configuration to `endpoint`, reading the certificates
+    /// from disk so that each new connection uses the latest ones.
+    pub(crate) fn configure(
+        &self,
+        endpoint: tonic::transport::Endpoint,
+    ) -> crate::Result<tonic::transport::Endpoint> {
+        if self.upstream_tls_ca.is_none() && self.upstream_tls_cert.is_none() {
+            return Ok(endpoint);
+        }
+
+        let mut tls = tonic::transport::ClientTlsConfig::new();
+        if let Some(ca) = &self.upstream_tls_ca {
+            tls = tls.ca_certificate(tonic::transport::Certificate::from_pem(std::fs::read(ca)?));
+        }
+        if let (Some(cert), Some(key)) = (&self.upstream_tls_cert, &self.upstream_tls_key) {
+            tls = tls.identity(tonic::transport::Identity::from_pem(
+                std::fs::read(cert)?,
+                std::fs::read(key)?,
+            ));
+        }
+        if let Some(domain) = &self.upstream_tls_domain {
+            tls = tls.domain_name(domain);
+        }
+
+        Ok(endpoint.tls_config(tls)?)
+    }
+
+    /// Returns an interceptor adding the configured bearer token to requests.
+    pub(crate) fn interceptor(&self) -> AttachToken {
+        AttachToken {
+            token: self.upstream_auth_token_file.clone().map(|path| {
+                Arc::new(Reloading::new(vec![path.clone()], move || {
+                    let token = std::fs::read_to_string(&path)?;
+                    let token = token.lines().next().unwrap_or_default().trim();
+                    Ok(format!("{BEARER}{token}").parse()?)
+                }))
+            }),
+        }
+    }
+}
+
+/// Rejects requests that don't have one of the accepted bearer tokens.
+#[derive(Clone, Default)]
+pub struct RequireToken {
+    tokens: Option<Arc<Reloading<Arc<Vec<String>>>>>,
+}
+
+impl tonic::service::Interceptor for RequireToken {
+    fn call(&mut self, request: tonic::Request<()>) -> tonic::Result<tonic::Request<()>> {
+        let Some(tokens) = &self.tokens else {
+            return Ok(request);
+        };
+
+        let tokens = tokens.get().map_err(|error| {
+            tracing::error!(%error, "failed to read auth tokens");
+            tonic::Status::unavailable("failed to read auth tokens")
+        })?;
+
+        let token = request
+            .metadata()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER));
+
+        match token {
+            Some(token) if tokens.iter().any(|accepted| accepted == token) => Ok(request),
+            Some(_) => Err(tonic::Status::unauthenticated("invalid bearer token")),
+            None => Err(tonic::Status::unauthenticated("bearer token required")),
+        }
+    }
+}
+
+/// Adds the configured bearer token to requests.
+#[derive(Clone, Default)]
+pub struct AttachToken {
+    token: Option<Arc<Reloading<tonic::metadata::AsciiMetadataValue>>>,
+}
+
+impl tonic::service::Interceptor for AttachToken {
+    fn call(&mut self, mut request: tonic::Request<()>) -> tonic::Result<tonic::Request<()>> {
+        if let Some(token) = &self.token {
+            let token = token.get().map_err(|error| {
+                tracing::error!(%error, "failed to read auth token");
+                tonic::Status::unauthenticated("failed to read auth token")
+            })?;
+            request.metadata_mut().insert(AUTHORIZATION, token);
+        }
+
+        Ok(request)
+    }
+}
+
+/// A value loaded from files, which is loaded again whenever any of them
+/// are modified.
+struct Reloading<T> {
+    paths: Vec<PathBuf>,
+    load: Box<dyn Fn() -> crate::Result<T> + Send + Sync>,
+    cached: parking_lot::Mutex<Option<(Vec<Option<SystemTime>>, T)>>,
+}
+
+impl<T: Clone> Reloading<T> {
+    fn new(
+        paths: Vec<PathBuf>,
+        load: impl Fn() -> crate::Result<T> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            paths,
+            load: Box::new(load),
+            cached: <_>::default(),
+        }
+    }
+
+    /// Returns the current value, keeping the previous one if the files
+    /// have changed but can't be loaded, such as in the middle of a rotation.
+    fn get(&self) -> crate::Result<T> {
+        let modified: Vec<_> = self
+            .paths
+            .iter()
+            .map(|path| {
+                std::fs::metadata(path)
+                    .and_then(|meta| meta.modified())
+                    .ok()
+            })
+            .collect();
+
+        let mut cached = self.cached.lock();
+        match &*cached {
+            Some((loaded, value)) if *loaded == modified => return Ok(value.clone()),
+            _ => {}
+        }
+
+        match (self.load)() {
+            Ok(value) => {
+                tracing::info!(paths = ?self.paths, "loaded credentials");
+                *cached = Some((modified, value.clone()));
+                Ok(value)
+            }
+            Err(error) => match &*cached {
+                Some((_, value)) => {
+                    tracing::warn!(%error, paths = ?self.paths, "failed to reload credentials, keeping the previous ones");
+                    Ok(value.clone())
+                }
+                None => Err(error),
+            },
+        }
+    }
+}
+
+fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> crate::Result<rustls::ServerConfig> {
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in read_certificates(client_ca)? {
+                roots.add(&cert)?;
+            }
+            builder.with_client_cert_verifier(
+                rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed(),
+            )
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(read_certificates(cert)?, read_private_key(key)?)?;
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    Ok(config)
+}
+
+fn read_certificates(path: &Path) -> crate::Result<Vec<rustls::Certificate>> {
+    let file = std::fs::File::open(path)?;
+    let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(file))?;
+    if certs.is_empty() {
+        return Err(eyre::eyre!("no certificates found in `{}`", path.display()));
+    }
+
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+fn read_private_key(path: &Path) -> crate::Result<rustls::PrivateKey> {
+    let file = std::fs::File::open(path)?;
+    rustls_pemfile::read_all(&mut std::io::BufReader::new(file))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| eyre::eyre!("no private key found in `{}`", path.display()))
+}
+
+/// Checks that the client certificate has one of the `allowed` subject
+/// alternative names, if any are given.
+fn check_subject_names(
+    connection: &rustls::ServerConnection,
+    allowed: &[String],
+) -> crate::Result<()> {
+    if allowed.is_empty() {
+        return Ok(());
+    }
+
+    let cert = connection
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .ok_or_else(|| eyre::eyre!("no client certificate"))?;
+    let cert = webpki::EndEntityCert::try_from(&*cert.0)
+        .map_err(|error| eyre::eyre!("invalid client certificate: {error:?}"))?;
+
+    allowed
+        .iter()
+        .filter_map(|name| webpki::SubjectNameRef::try_from_ascii_str(name).ok())
+        .any(|name| cert.verify_is_valid_for_subject_name(name).is_ok())
+        .then_some(())
+        .ok_or_else(|| eyre::eyre!("client certificate doesn't have an allowed name"))
+}
+
+/// Accepts connections from `listener`, completing TLS handshakes
+/// concurrently so that a slow client doesn't hold up others.
+fn incoming(
+    listener: TcpListener,
+    acceptor: Reloading<TlsAcceptor>,
+    allowed_sans: Arc<[String]>,
+) -> tokio_stream::wrappers::ReceiverStream<std::io::Result<TlsStream<TcpStream>>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(32);
+
+    tokio::spawn(async move {
+        while !tx.is_closed() {
+            let (stream, peer) = match listener.accept().await {
+                Ok(value) => value,
+                Err(error) => {
+                    tracing::warn!(%error, "failed to accept connection");
+                    continue;
+                }
+            };
+
+            let acceptor = match acceptor.get() {
+                Ok(acceptor) => acceptor,
+                Err(error) => {
+                    tracing::error!(%error, "failed to load TLS configuration");
+                    continue;
+                }
+            };
+
+            let tx = tx.clone();
+            let allowed_sans = allowed_sans.clone();
+            tokio::spawn(async move {
+                let stream =
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(error)) => {
+                            tracing::warn!(%peer, %error, "TLS handshake failed");
+                            return;
+                        }
+                        Err(_) => {
+                            tracing::warn!(%peer, "TLS handshake timed out");
+                            return;
+                        }
+                    };
+
+                if let Err(error) = check_subject_names(stream.get_ref().1, &allowed_sans) {
+                    tracing::warn!(%peer, %error, "rejected client certificate");
+                    return;
+                }
+
+                let _ = tx.send(Ok(stream)).await;
+            });
+        }
+    });
+
+    tokio_stream::wrappers::ReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::service::Interceptor;
+
+    use super::*;
+
+    pub(crate) fn cert_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/certs")
+            .join(name)
+    }
+
+    #[test]
+    fn require_token() {
+        let tokens = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(tokens.path(), "abc\n\ndef\n").unwrap();
+        let mut interceptor = ServerAuth {
+            auth_token_file: Some(tokens.path().into()),
+            ..<_>::default()
+        }
+        .interceptor();
+
+        let request = |token: Option<&str>| {
+            let mut request = tonic::Request::new(());
+            if let Some(token) = token {
+                request
+                    .metadata_mut()
+                    .insert(AUTHORIZATION, token.parse().unwrap());
+            }
+            request
+        };
+
+        assert!(interceptor.call(request(Some("Bearer def"))).is_ok());
+        assert_eq!(
+            tonic::Code::Unauthenticated,
+            interceptor
+                .call(request(Some("Bearer xyz")))
+                .unwrap_err()
+                .code()
+        );
+        assert_eq!(
+            tonic::Code::Unauthenticated,
+            interceptor.call(request(None)).unwrap_err().code()
+        );
+
+        // Tokens are reloaded when the file changes.
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::write(tokens.path(), "xyz\n").unwrap();
+        assert!(interceptor.call(request(Some("Bearer xyz"))).is_ok());
+        assert!(interceptor.call(request(Some("Bearer abc"))).is_err());
+
+        // Requests pass through the token attached by the client.
+        let token = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(token.path(), "xyz\n").unwrap();
+        let attached = ClientAuth {
+            upstream_auth_token_file: Some(token.path().into()),
+            ..<_>::default()
+        }
+        .interceptor()
+        .call(request(None))
+        .unwrap();
+        assert!(interceptor.call(attached).is_ok());
+    }
+
+    #[test]
+    fn loads_server_config() {
+        assert!(server_config(
+            &cert_path("server.pem"),
+            &cert_path("server.key"),
+            Some(&cert_path("ca.pem")),
+        )
+        .is_ok());
+        assert!(server_config(&cert_path("ca.pem"), &cert_path("server.pem"), None).is_err());
+    }
+}
@@ -14,12 +14,19 @@
  * limitations under the License.
  */
 
-use std::{collections::HashSet, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
 use futures::StreamExt;
 use rand::Rng;
 use tokio::sync::{broadcast, Mutex};
-use tonic::transport::{channel::Channel as TonicChannel, Endpoint, Error as TonicError};
+use tonic::{
+    codegen::InterceptedService,
+    transport::{channel::Channel as TonicChannel, Endpoint, Error as TonicError},
+};
 use tracing::Instrument;
 use tryhard::{
     backoff_strategies::{BackoffStrategy, ExponentialBackoff},
@@ -33,16 +40,19 @@ use crate::{
         relay::aggregated_control_plane_discovery_service_client::AggregatedControlPlaneDiscoveryServiceClient,
         service::discovery::v3::{
             aggregated_discovery_service_client::AggregatedDiscoveryServiceClient,
-            DiscoveryRequest, DiscoveryResponse,
+            DeltaDiscoveryRequest, DeltaDiscoveryResponse, DiscoveryRequest, DiscoveryResponse,
         },
-        Resource, ResourceType,
+        ClientAuth, Resource, ResourceType,
     },
     Result,
 };
 
-type AdsGrpcClient = AggregatedDiscoveryServiceClient<TonicChannel>;
-type MdsGrpcClient = AggregatedControlPlaneDiscoveryServiceClient<TonicChannel>;
+type AuthChannel = InterceptedService<TonicChannel, super::auth::AttachToken>;
+type AdsGrpcClient = AggregatedDiscoveryServiceClient<AuthChannel>;
+type MdsGrpcClient = AggregatedControlPlaneDiscoveryServiceClient<AuthChannel>;
 type SubscribedResources = Arc<Mutex<HashSet<(ResourceType, Vec<String>)>>>;
+/// The versions of the resources received over delta xDS, by resource name.
+type ResourceVersions = Arc<parking_lot::Mutex<HashMap<ResourceType, HashMap<String, String>>>>;
 
 pub type AdsClient = Client<AdsGrpcClient>;
 pub type AdsStream = BidirectionalStream<AdsGrpcClient>;
@@ -54,8 +64,10 @@ pub trait ServiceClient: Clone + Sized + Send + 'static {
     type Request: Clone + Send + Sync + Sized + 'static + std::fmt::Debug;
     type Response: Clone + Send + Sync + Sized + 'static + std::fmt::Debug;
 
-    async fn connect(endpoint: tonic::transport::Endpoint)
-        -> Result<Self, tonic::transport::Error>;
+    async fn connect(
+        endpoint: tonic::transport::Endpoint,
+        auth: super::auth::AttachToken,
+    ) -> Result<Self, tonic::transport::Error>;
     async fn stream_requests<S: tonic::IntoStreamingRequest<Message = Self::Request> + Send>(
         &mut self,
         stream: S,
@@ -69,8 +81,12 @@ impl ServiceClient for AdsGrpcClient {
 
     async fn connect(
         endpoint: tonic::transport::Endpoint,
+        auth: super::auth::AttachToken,
     ) -> Result<Self, tonic::transport::Error> {
-        AdsGrpcClient::connect(endpoint).await
+        Ok(AggregatedDiscoveryServiceClient::with_interceptor(
+            endpoint.connect().await?,
+            auth,
+        ))
     }
 
     async fn stream_requests<S: tonic::IntoStreamingRequest<Message = Self::Request> + Send>(
@@ -88,8 +104,14 @@ impl ServiceClient for MdsGrpcClient {
 
     async fn connect(
         endpoint: tonic::transport::Endpoint,
+        auth: super::auth::AttachToken,
     ) -> Result<Self, tonic::transport::Error> {
-        MdsGrpcClient::connect(endpoint).await
+        Ok(
+            AggregatedControlPlaneDiscoveryServiceClient::with_interceptor(
+                endpoint.connect().await?,
+                auth,
+            ),
+        )
     }
 
     async fn stream_requests<S: tonic::IntoStreamingRequest<Message = Self::Request> + Send>(
@@ -105,21 +127,46 @@ impl ServiceClient for MdsGrpcClient {
 pub struct Client<C: ServiceClient> {
     client: C,
     identifier: Arc<str>,
+    locality: Option<crate::endpoint::Locality>,
+    remove_on_disconnect: bool,
     management_servers: Vec<Endpoint>,
+    auth: ClientAuth,
 }
 
 impl<C: ServiceClient> Client<C> {
     #[tracing::instrument(skip_all, level = "trace", fields(servers = ?management_servers))]
-    pub async fn connect(identifier: String, management_servers: Vec<Endpoint>) -> Result<Self> {
-        let client = Self::connect_with_backoff(&management_servers).await?;
+    pub async fn connect(
+        identifier: String,
+        management_servers: Vec<Endpoint>,
+        auth: ClientAuth,
+    ) -> Result<Self> {
+        let client = Self::connect_with_backoff(&management_servers, &auth).await?;
         Ok(Self {
             client,
             identifier: Arc::from(identifier),
+            locality: None,
+            remove_on_disconnect: false,
             management_servers,
+            auth,
         })
     }
 
-    async fn connect_with_backoff(management_servers: &[Endpoint]) -> Result<C> {
+    /// Sets the locality sent to the management server, so it can select
+    /// resources based on where the client is.
+    pub fn with_locality(mut self, locality: Option<crate::endpoint::Locality>) -> Self {
+        self.locality = locality;
+        self
+    }
+
+    /// Sets whether the resources received from the management server are
+    /// removed when the connection to it is lost, rather than kept until it
+    /// reconnects.
+    pub fn remove_on_disconnect(mut self, remove: bool) -> Self {
+        self.remove_on_disconnect = remove;
+        self
+    }
+
+    async fn connect_with_backoff(management_servers: &[Endpoint], auth: &ClientAuth) -> Result<C> {
         use crate::config::{
             BACKOFF_INITIAL_DELAY_MILLISECONDS, BACKOFF_MAX_DELAY_SECONDS,
             BACKOFF_MAX_JITTER_MILLISECONDS, CONNECTION_TIMEOUT,
@@ -157,6 +204,11 @@ impl<C: ServiceClient> Client<C> {
                     tracing::warn!(?error, "Unable to connect to the XDS server");
                     RetryPolicy::Delay(delay)
                 }
+                RpcSessionError::Tls(ref error) => {
+                    // The credentials may be mid-rotation, so try again later.
+                    tracing::warn!(%error, "Unable to load TLS credentials");
+                    RetryPolicy::Delay(delay)
+                }
                 RpcSessionError::Receive(ref status) => {
                     tracing::warn!(status = ?status, "Failed to receive response from XDS server");
                     RetryPolicy::Delay(delay)
@@ -189,7 +241,11 @@ impl<C: ServiceClient> Client<C> {
                             ));
                         }
 
-                        C::connect(endpoint)
+                        let endpoint = auth
+                            .configure(endpoint)
+                            .map_err(|error| RpcSessionError::Tls(error.to_string()))?;
+
+                        C::connect(endpoint, auth.interceptor())
                             .instrument(tracing::debug_span!(
                                 "AggregatedDiscoveryServiceClient::connect"
                             ))
@@ -224,7 +280,7 @@ impl AdsClient {
 
 /// An active xDS gRPC management stream.
 pub struct BidirectionalStream<C: ServiceClient> {
-    identifier: Arc<str>,
+    node: Node,
     requests: broadcast::Sender<C::Request>,
     handle_discovery_response: tokio::task::JoinHandle<Result<()>>,
     subscribed_resources: SubscribedResources,
@@ -235,19 +291,97 @@ impl AdsStream {
         Client {
             client,
             identifier,
+            locality,
+            remove_on_disconnect,
             management_servers,
+            auth,
         }: &AdsClient,
         config: Arc<Config>,
     ) -> Self {
         let mut client = client.clone();
         let identifier = identifier.clone();
+        let node = node(&identifier, locality.as_ref());
+        let remove_on_disconnect = *remove_on_disconnect;
         let management_servers = management_servers.clone();
+        let auth = auth.clone();
         Self::connect(
-            identifier.clone(),
+            node.clone(),
             move |(mut requests, mut rx), subscribed_resources| async move {
                 tracing::trace!("starting xDS client stream task");
+                let versions = ResourceVersions::default();
+                let received = config.clone();
+                // Delta xDS is used until the server tells us it's not
+                // supported, after which we fall back to state of the world.
+                let mut delta = true;
                 loop {
                     let config = config.clone();
+                    if delta {
+                        tracing::trace!("connecting to delta grpc stream");
+                        let (acks, ack_rx) = tokio::sync::mpsc::unbounded_channel();
+                        let subscriptions = tokio_stream::wrappers::BroadcastStream::from(rx)
+                            .filter_map(|result| futures::future::ready(result.ok()))
+                            .map({
+                                let versions = versions.clone();
+                                move |request| delta_subscription(request, &versions)
+                            });
+                        let result = client
+                            .delta_aggregated_resources(futures::stream::select(
+                                subscriptions,
+                                tokio_stream::wrappers::UnboundedReceiverStream::new(ack_rx),
+                            ))
+                            .in_current_span()
+                            .await
+                            .map(|streaming| streaming.into_inner());
+
+                        match result {
+                            Ok(stream) => {
+                                let mut stream = handle_delta_discovery_responses(
+                                    (&*identifier).into(),
+                                    stream,
+                                    versions.clone(),
+                                    {
+                                        let config = config.clone();
+                                        move |resources| config.apply_all(resources)
+                                    },
+                                    move |resource_type, name| config.remove(resource_type, name),
+                                );
+
+                                while let Some(result) = stream.next().await {
+                                    match result {
+                                        Ok(ack) => acks.send(ack)?,
+                                        Err(error) => {
+                                            tracing::warn!(%error, "delta xds stream error");
+                                            break;
+                                        }
+                                    }
+                                }
+
+                                tracing::info!("Lost connection to xDS, retrying");
+                                if remove_on_disconnect {
+                                    remove_resources(&received, &versions);
+                                }
+                                client =
+                                    AdsClient::connect_with_backoff(&management_servers, &auth)
+                                        .await?;
+                            }
+                            Err(status) if status.code() == tonic::Code::Unimplemented => {
+                                tracing::info!("management server doesn't support delta xDS, falling back to state of the world");
+                                delta = false;
+                            }
+                            Err(error) => {
+                                tracing::warn!(%error, "stream broken");
+                                client =
+                                    AdsClient::connect_with_backoff(&management_servers, &auth)
+                                        .await?;
+                            }
+                        }
+
+                        rx = requests.subscribe();
+                        Self::refresh_resources(&node, &subscribed_resources, &mut requests)
+                            .await?;
+                        continue;
+                    }
+
                     tracing::trace!("connecting to grpc stream");
                     let result = client
                         .stream_requests(
@@ -265,14 +399,11 @@ impl AdsStream {
                         Ok(stream) => stream,
                         Err(error) => {
                             tracing::warn!(%error, "stream broken");
-                            client = AdsClient::connect_with_backoff(&management_servers).await?;
+                            client =
+                                AdsClient::connect_with_backoff(&management_servers, &auth).await?;
                             rx = requests.subscribe();
-                            Self::refresh_resources(
-                                &identifier,
-                                &subscribed_resources,
-                                &mut requests,
-                            )
-                            .await?;
+                            Self::refresh_resources(&node, &subscribed_resources, &mut requests)
+                                .await?;
                             continue;
                         }
                     };
@@ -281,7 +412,7 @@ impl AdsStream {
                     let mut stream = handle_discovery_responses(
                         (&*identifier).into(),
                         stream,
-                        move |resource| config.apply(resource),
+                        move |resources| config.apply_all(resources),
                     );
 
                     loop {
@@ -303,10 +434,12 @@ impl AdsStream {
                     }
 
                     tracing::info!("Lost connection to xDS, retrying");
-                    client = AdsClient::connect_with_backoff(&management_servers).await?;
+                    if remove_on_disconnect {
+                        remove_resources(&received, &versions);
+                    }
+                    client = AdsClient::connect_with_backoff(&management_servers, &auth).await?;
                     rx = requests.subscribe();
-                    Self::refresh_resources(&identifier, &subscribed_resources, &mut requests)
-                        .await?;
+                    Self::refresh_resources(&node, &subscribed_resources, &mut requests).await?;
                 }
             },
         )
@@ -322,12 +455,7 @@ impl AdsStream {
             .lock()
             .await
             .insert((resource_type, names.to_vec()));
-        Self::discovery_request_without_cache(
-            &self.identifier,
-            &mut self.requests,
-            resource_type,
-            names,
-        )
+        Self::discovery_request_without_cache(&self.node, &mut self.requests, resource_type, names)
     }
 }
 
@@ -336,15 +464,19 @@ impl MdsStream {
         Client {
             client,
             identifier,
+            locality,
             management_servers,
+            auth,
+            ..
         }: &MdsClient,
         config: Arc<Config>,
     ) -> Self {
         let mut client = client.clone();
         let identifier = identifier.clone();
         let management_servers = management_servers.clone();
+        let auth = auth.clone();
         Self::connect(
-            identifier.clone(),
+            node(&identifier, locality.as_ref()),
             move |(requests, mut rx), _| async move {
                 tracing::trace!("starting relay client stream task");
                 loop {
@@ -376,7 +508,7 @@ impl MdsStream {
                     }
 
                     tracing::warn!("lost connection to relay server, retrying");
-                    client = MdsClient::connect_with_backoff(&management_servers)
+                    client = MdsClient::connect_with_backoff(&management_servers, &auth)
                         .await
                         .unwrap();
                     rx = requests.subscribe();
@@ -388,7 +520,7 @@ impl MdsStream {
 
 impl<C: ServiceClient> BidirectionalStream<C> {
     pub fn connect<F>(
-        identifier: Arc<str>,
+        node: Node,
         response_task: impl FnOnce(
             (
                 broadcast::Sender<C::Request>,
@@ -412,7 +544,7 @@ impl<C: ServiceClient> BidirectionalStream<C> {
         });
 
         Self {
-            identifier,
+            node,
             requests,
             handle_discovery_response,
             subscribed_resources,
@@ -424,29 +556,25 @@ impl<C: ServiceClient> BidirectionalStream<C> {
     }
 
     async fn refresh_resources(
-        identifier: &str,
+        node: &Node,
         subscribed_resources: &SubscribedResources,
         requests: &mut broadcast::Sender<DiscoveryRequest>,
     ) -> Result<()> {
         for (resource, names) in subscribed_resources.lock().await.iter() {
-            Self::discovery_request_without_cache(identifier, requests, *resource, names)?;
+            Self::discovery_request_without_cache(node, requests, *resource, names)?;
         }
 
         Ok(())
     }
 
     pub(crate) fn discovery_request_without_cache(
-        identifier: &str,
+        node: &Node,
         requests: &mut broadcast::Sender<DiscoveryRequest>,
         resource_type: ResourceType,
         names: &[String],
     ) -> Result<()> {
         let request = DiscoveryRequest {
-            node: Some(Node {
-                id: identifier.into(),
-                user_agent_name: "quilkin".into(),
-                ..Node::default()
-            }),
+            node: Some(node.clone()),
             resource_names: names.to_vec(),
             type_url: resource_type.type_url().into(),
             ..DiscoveryRequest::default()
@@ -457,6 +585,27 @@ impl<C: ServiceClient> BidirectionalStream<C> {
     }
 }
 
+/// Removes every resource received from a management server, along with the
+/// versions of them reported when reconnecting.
+fn remove_resources(config: &Config, versions: &ResourceVersions) {
+    config
+        .clusters
+        .modify(|clusters| clusters.replace(<_>::default()));
+    config.filters.remove();
+    versions.lock().clear();
+}
+
+/// The node sent in discovery requests, identifying the client to the
+/// management server.
+pub(crate) fn node(identifier: &str, locality: Option<&crate::endpoint::Locality>) -> Node {
+    Node {
+        id: identifier.into(),
+        user_agent_name: "quilkin".into(),
+        locality: locality.cloned().map(From::from),
+        ..Node::default()
+    }
+}
+
 impl<C: ServiceClient> Drop for BidirectionalStream<C> {
     fn drop(&mut self) {
         self.handle_discovery_response.abort();
@@ -473,15 +622,123 @@ enum RpcSessionError {
 
     #[error("Error occurred while receiving data. Status: {0}")]
     Receive(tonic::Status),
+
+    #[error("Failed to configure TLS.\n {0}")]
+    Tls(String),
+}
+
+/// Converts a state of the world subscription into its delta equivalent,
+/// including the versions of any resources we already have.
+fn delta_subscription(
+    request: DiscoveryRequest,
+    versions: &ResourceVersions,
+) -> DeltaDiscoveryRequest {
+    let initial_resource_versions = request
+        .type_url
+        .parse::<ResourceType>()
+        .ok()
+        .and_then(|resource_type| versions.lock().get(&resource_type).cloned())
+        .unwrap_or_default();
+
+    DeltaDiscoveryRequest {
+        node: request.node,
+        type_url: request.type_url,
+        resource_names_subscribe: request.resource_names,
+        initial_resource_versions,
+        ..<_>::default()
+    }
+}
+
+pub fn handle_delta_discovery_responses(
+    identifier: String,
+    stream: impl futures::Stream<Item = tonic::Result<DeltaDiscoveryResponse>> + 'static + Send,
+    versions: ResourceVersions,
+    on_new_resources: impl Fn(&[Resource]) -> crate::Result<()> + Send + Sync + 'static,
+    on_removed_resource: impl Fn(ResourceType, &str) -> crate::Result<()> + Send + Sync + 'static,
+) -> std::pin::Pin<Box<dyn futures::Stream<Item = Result<DeltaDiscoveryRequest>> + Send>> {
+    Box::pin(async_stream::try_stream! {
+        let _stream_metrics = super::metrics::StreamConnectionMetrics::new(identifier.clone());
+        tracing::info!("awaiting delta response");
+        for await response in stream
+        {
+            let response = match response {
+                Ok(response) => response,
+                Err(error) => {
+                    tracing::warn!(%error, "Error from xDS server");
+                    break;
+                }
+            };
+
+            let control_plane_identifier = response.control_plane.as_ref().map(|cp| cp.identifier.clone()).unwrap_or_default();
+
+            super::metrics::discovery_responses(&control_plane_identifier, &response.type_url).inc();
+            tracing::info!(
+                version = &*response.system_version_info,
+                r#type = &*response.type_url,
+                nonce = &*response.nonce,
+                changed = response.resources.len(),
+                removed = response.removed_resources.len(),
+                "received delta response"
+            );
+
+            let result = response.type_url.parse::<ResourceType>().map_err(From::from).and_then(|resource_type| {
+                // Every resource is decoded and applied together, so that a
+                // rejected response leaves the previous configuration in place.
+                let resources = response
+                    .resources
+                    .iter()
+                    .filter_map(|resource| resource.resource.clone())
+                    .map(Resource::try_from)
+                    .collect::<crate::Result<Vec<_>>>()?;
+                tracing::info!(resources = resources.len(), "applying resources");
+                (on_new_resources)(&resources)?;
+
+                let mut versions = versions.lock();
+                let versions = versions.entry(resource_type).or_default();
+                for resource in &response.resources {
+                    versions.insert(resource.name.clone(), resource.version.clone());
+                }
+
+                for name in &response.removed_resources {
+                    tracing::info!(name, "removing resource");
+                    (on_removed_resource)(resource_type, name)?;
+                    versions.remove(name);
+                }
+
+                crate::Result::<()>::Ok(())
+            });
+
+            let mut request = DeltaDiscoveryRequest {
+                type_url: response.type_url,
+                response_nonce: response.nonce,
+                ..<_>::default()
+            };
+            if let Err(error) = result {
+                super::metrics::nacks(&control_plane_identifier, &request.type_url).inc();
+                request.error_detail = Some(crate::xds::google::rpc::Status {
+                    code: 3,
+                    message: error.to_string(),
+                    ..<_>::default()
+                });
+            } else {
+                super::metrics::acks(&control_plane_identifier, &request.type_url).inc();
+            }
+
+            yield request;
+        }
+    })
 }
 
 pub fn handle_discovery_responses(
     identifier: String,
     stream: impl futures::Stream<Item = tonic::Result<DiscoveryResponse>> + 'static + Send,
-    on_new_resource: impl Fn(&Resource) -> crate::Result<()> + Send + Sync + 'static,
+    on_new_resources: impl Fn(&[Resource]) -> crate::Result<()> + Send + Sync + 'static,
 ) -> std::pin::Pin<Box<dyn futures::Stream<Item = Result<DiscoveryRequest>> + Send>> {
     Box::pin(async_stream::try_stream! {
         let _stream_metrics = super::metrics::StreamConnectionMetrics::new(identifier.clone());
+        // The last version accepted of each type, which is what a NACK
+        // reports back to the management server.
+        let mut versions = HashMap::<String, String>::new();
         tracing::info!("awaiting response");
         for await response in stream
         {
@@ -505,25 +762,40 @@ pub fn handle_discovery_responses(
 
             let result = response
                 .resources
-                .iter()
-                .cloned()
+                .into_iter()
                 .map(Resource::try_from)
-                .try_for_each(|resource| {
-                    let resource = resource?;
-                    tracing::info!("applying resource");
-                    (on_new_resource)(&resource)
+                .collect::<crate::Result<Vec<_>>>()
+                .and_then(|resources| {
+                    tracing::info!(resources = resources.len(), "applying resources");
+                    (on_new_resources)(&resources)?;
+                    Ok(resources)
                 });
 
-            let mut request = DiscoveryRequest::try_from(response)?;
-            if let Err(error) = result {
-                super::metrics::nacks(&control_plane_identifier, &request.type_url).inc();
-                request.error_detail = Some(crate::xds::google::rpc::Status {
-                    code: 3,
-                    message: error.to_string(),
-                    ..<_>::default()
-                });
-            } else {
-                super::metrics::acks(&control_plane_identifier, &request.type_url).inc();
+            let mut request = DiscoveryRequest {
+                type_url: response.type_url,
+                response_nonce: response.nonce,
+                ..<_>::default()
+            };
+            match result {
+                Ok(resources) => {
+                    super::metrics::acks(&control_plane_identifier, &request.type_url).inc();
+                    versions.insert(request.type_url.clone(), response.version_info.clone());
+                    request.version_info = response.version_info;
+                    request.resource_names = resources
+                        .iter()
+                        .map(|resource| resource.name().to_owned())
+                        .collect();
+                }
+                Err(error) => {
+                    super::metrics::nacks(&control_plane_identifier, &request.type_url).inc();
+                    tracing::warn!(%error, version = &*response.version_info, "rejecting response");
+                    request.version_info = versions.get(&request.type_url).cloned().unwrap_or_default();
+                    request.error_detail = Some(crate::xds::google::rpc::Status {
+                        code: 3,
+                        message: error.to_string(),
+                        ..<_>::default()
+                    });
+                }
             }
 
             yield request;
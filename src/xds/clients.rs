@@ -0,0 +1,358 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The clients connected to a management server, what they're subscribed to
+//! and whether they've accepted the resources they've been sent, as well as
+//! the agents connected to a relay, as listed by the admin API.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::{endpoint::Locality, xds::config::core::v3::Node};
+
+/// The number of responses of each type remembered per client, to match
+/// their ACKs and NACKs against.
+const SENT_CAPACITY: usize = 16;
+
+/// The clients connected to a management server.
+#[derive(Debug, Default)]
+pub struct Clients {
+    clients: Mutex<BTreeMap<String, ClientState>>,
+    agents: Mutex<BTreeMap<String, AgentState>>,
+}
+
+#[derive(Debug)]
+struct ClientState {
+    streams: usize,
+    locality: Option<Locality>,
+    connected_at: DateTime<Utc>,
+    resources: BTreeMap<String, ResourceState>,
+}
+
+#[derive(Debug)]
+struct AgentState {
+    streams: usize,
+    connected_at: DateTime<Utc>,
+}
+
+/// The subscription to a single resource type of a client, and the responses
+/// it has been sent.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ResourceState {
+    /// The subscribed resource names, empty if subscribed to every resource.
+    pub names: Vec<String>,
+    /// The last response sent to the client.
+    pub last_sent: Option<Response>,
+    /// The last response the client accepted.
+    pub last_acked: Option<Response>,
+    /// The last response the client rejected.
+    pub last_nack: Option<Nack>,
+    #[serde(skip)]
+    sent: VecDeque<Response>,
+}
+
+/// A discovery response sent to a client.
+#[derive(Clone, Debug, Serialize)]
+pub struct Response {
+    pub version: String,
+    pub nonce: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A response rejected by a client.
+#[derive(Clone, Debug, Serialize)]
+pub struct Nack {
+    /// The rejected version, if the response is still remembered.
+    pub version: Option<String>,
+    pub nonce: String,
+    /// Why the client rejected the response.
+    pub error: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A connected client, as listed by [`Clients::summary`].
+#[derive(Clone, Debug, Serialize)]
+pub struct ClientSummary {
+    pub id: String,
+    pub locality: Option<Locality>,
+    /// When the client's first current stream was opened.
+    pub connected_at: DateTime<Utc>,
+    /// How long the client has been connected, in seconds.
+    pub connected_seconds: i64,
+    /// The state of each subscribed resource type, by type URL.
+    pub resources: BTreeMap<String, ResourceState>,
+}
+
+/// An agent connected to a relay, as listed by [`Clients::agents`].
+#[derive(Clone, Debug, Serialize)]
+pub struct AgentSummary {
+    pub id: String,
+    /// When the agent's first current stream was opened.
+    pub connected_at: DateTime<Utc>,
+    /// How long the agent has been connected, in seconds.
+    pub connected_seconds: i64,
+}
+
+impl Clients {
+    /// Registers a stream from the client `node`, which is listed until every
+    /// stream from it has been dropped.
+    pub(crate) fn connect(self: &Arc<Self>, node: &Node) -> Connection {
+        let locality = node.locality.clone().map(Locality::from);
+        self.clients
+            .lock()
+            .entry(node.id.clone())
+            .or_insert_with(|| ClientState {
+                streams: 0,
+                locality: None,
+                connected_at: Utc::now(),
+                resources: BTreeMap::new(),
+            })
+            .connect(locality);
+
+        Connection {
+            clients: self.clone(),
+            id: node.id.clone(),
+            kind: Kind::Client,
+        }
+    }
+
+    /// Registers an mDS stream from the agent `id`, which is listed until
+    /// every stream from it has been dropped.
+    pub(crate) fn connect_agent(self: &Arc<Self>, id: &str) -> Connection {
+        self.agents
+            .lock()
+            .entry(id.into())
+            .or_insert_with(|| AgentState {
+                streams: 0,
+                connected_at: Utc::now(),
+            })
+            .streams += 1;
+
+        Connection {
+            clients: self.clone(),
+            id: id.into(),
+            kind: Kind::Agent,
+        }
+    }
+
+    /// Records the client `id` subscribing to `names` of `type_url`, or every
+    /// resource if `names` is empty.
+    pub(crate) fn subscribed(&self, id: &str, type_url: &str, names: Vec<String>) {
+        self.modify(id, type_url, |state| state.names = names);
+    }
+
+    /// Records a response sent to the client `id`.
+    pub(crate) fn sent(&self, id: &str, type_url: &str, version: &str, nonce: &str) {
+        let response = Response {
+            version: version.into(),
+            nonce: nonce.into(),
+            timestamp: Utc::now(),
+        };
+
+        self.modify(id, type_url, |state| {
+            if state.sent.len() == SENT_CAPACITY {
+                state.sent.pop_front();
+            }
+            state.sent.push_back(response.clone());
+            state.last_sent = Some(response);
+        });
+    }
+
+    /// Records the client `id` accepting the response with `nonce`.
+    pub(crate) fn acked(&self, id: &str, type_url: &str, nonce: &str) {
+        self.modify(id, type_url, |state| {
+            if let Some(response) = state.sent.iter().find(|sent| sent.nonce == nonce) {
+                state.last_acked = Some(response.clone());
+            }
+        });
+    }
+
+    /// Records the client `id` rejecting the response with `nonce`.
+    pub(crate) fn nacked(&self, id: &str, type_url: &str, nonce: &str, error: &str) {
+        self.modify(id, type_url, |state| {
+            state.last_nack = Some(Nack {
+                version: state
+                    .sent
+                    .iter()
+                    .find(|sent| sent.nonce == nonce)
+                    .map(|sent| sent.version.clone()),
+                nonce: nonce.into(),
+                error: error.into(),
+                timestamp: Utc::now(),
+            });
+        });
+    }
+
+    /// Lists the connected clients.
+    pub fn summary(&self) -> Vec<ClientSummary> {
+        let now = Utc::now();
+        self.clients
+            .lock()
+            .iter()
+            .map(|(id, state)| ClientSummary {
+                id: id.clone(),
+                locality: state.locality.clone(),
+                connected_at: state.connected_at,
+                connected_seconds: (now - state.connected_at).num_seconds(),
+                resources: state.resources.clone(),
+            })
+            .collect()
+    }
+
+    /// Lists the agents connected via mDS.
+    pub fn agents(&self) -> Vec<AgentSummary> {
+        let now = Utc::now();
+        self.agents
+            .lock()
+            .iter()
+            .map(|(id, state)| AgentSummary {
+                id: id.clone(),
+                connected_at: state.connected_at,
+                connected_seconds: (now - state.connected_at).num_seconds(),
+            })
+            .collect()
+    }
+
+    fn modify(&self, id: &str, type_url: &str, modify: impl FnOnce(&mut ResourceState)) {
+        if let Some(state) = self.clients.lock().get_mut(id) {
+            (modify)(state.resources.entry(type_url.into()).or_default());
+        }
+    }
+}
+
+impl ClientState {
+    fn connect(&mut self, locality: Option<Locality>) {
+        self.streams += 1;
+        if locality.is_some() {
+            self.locality = locality;
+        }
+    }
+}
+
+/// Connected clients aren't part of the configuration they're compared with.
+impl PartialEq for Clients {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+enum Kind {
+    Client,
+    Agent,
+}
+
+/// A stream from a client or agent, removing it from [`Clients`] when it was
+/// their last one.
+pub(crate) struct Connection {
+    clients: Arc<Clients>,
+    id: String,
+    kind: Kind,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        match self.kind {
+            Kind::Client => {
+                let mut clients = self.clients.clients.lock();
+                if let Some(state) = clients.get_mut(&self.id) {
+                    state.streams -= 1;
+                    if state.streams == 0 {
+                        clients.remove(&self.id);
+                    }
+                }
+            }
+            Kind::Agent => {
+                let mut agents = self.clients.agents.lock();
+                if let Some(state) = agents.get_mut(&self.id) {
+                    state.streams -= 1;
+                    if state.streams == 0 {
+                        agents.remove(&self.id);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPE_URL: &str = crate::xds::ResourceType::Cluster.type_url();
+
+    #[test]
+    fn acks_and_nacks() {
+        let clients = Arc::new(Clients::default());
+        let connection = clients.connect(&Node {
+            id: "proxy".into(),
+            ..<_>::default()
+        });
+
+        clients.sent("proxy", TYPE_URL, "1", "a");
+        clients.acked("proxy", TYPE_URL, "a");
+        clients.sent("proxy", TYPE_URL, "2", "b");
+        clients.nacked("proxy", TYPE_URL, "b", "invalid filter");
+
+        let summary = clients.summary();
+        assert_eq!(1, summary.len());
+        let state = &summary[0].resources[TYPE_URL];
+        assert_eq!("2", state.last_sent.as_ref().unwrap().version);
+        assert_eq!("1", state.last_acked.as_ref().unwrap().version);
+        let nack = state.last_nack.as_ref().unwrap();
+        assert_eq!(Some("2"), nack.version.as_deref());
+        assert_eq!("invalid filter", nack.error);
+
+        drop(connection);
+        assert!(clients.summary().is_empty());
+    }
+
+    #[test]
+    fn subscriptions_and_agents() {
+        let clients = Arc::new(Clients::default());
+        let node = Node {
+            id: "proxy".into(),
+            locality: Some(crate::xds::config::core::v3::Locality {
+                region: "us-east1".into(),
+                ..<_>::default()
+            }),
+            ..<_>::default()
+        };
+        let first = clients.connect(&node);
+        let second = clients.connect(&node);
+        let agent = clients.connect_agent("agent");
+
+        clients.subscribed("proxy", TYPE_URL, vec!["a".into()]);
+        let summary = clients.summary();
+        assert_eq!("us-east1", summary[0].locality.as_ref().unwrap().region);
+        assert_eq!(vec!["a"], summary[0].resources[TYPE_URL].names);
+        assert!(summary[0].resources[TYPE_URL].last_sent.is_none());
+        assert_eq!("agent", clients.agents()[0].id);
+
+        // The client is listed until its last stream closes.
+        drop(first);
+        assert_eq!(1, clients.summary().len());
+        drop(second);
+        assert!(clients.summary().is_empty());
+        drop(agent);
+        assert!(clients.agents().is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Merging the resources received from several management servers at once.
+//!
+//! Each management server's resources are applied to a separate [`Config`],
+//! which are merged into the proxy's configuration whenever any of them
+//! change. Clusters are merged by name, with the endpoints of every source
+//! combined, while the filter chain is taken from the source with the highest
+//! precedence that has sent one.
+
+use std::{pin::Pin, sync::Arc};
+
+use futures::{Stream, StreamExt};
+
+use crate::{
+    cluster::ClusterMap,
+    config::{history, Config},
+};
+
+/// The resources received from a single management server.
+#[derive(Clone, Debug)]
+pub struct Source {
+    /// The address of the management server.
+    pub management_server: String,
+    pub config: Arc<Config>,
+}
+
+impl Source {
+    pub fn new(management_server: impl Into<String>) -> Self {
+        let config = Config::default();
+        // The filter chain is only used once a listener has been received.
+        config.filters.remove();
+        Self {
+            management_server: management_server.into(),
+            config: Arc::new(config),
+        }
+    }
+}
+
+/// Merges `sources`, in decreasing order of precedence, into `config`
+/// whenever any of them change.
+pub fn spawn(
+    config: Arc<Config>,
+    sources: Vec<Source>,
+) -> tokio::task::JoinHandle<crate::Result<()>> {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut changes: Vec<Pin<Box<dyn Stream<Item = ()> + Send>>> = vec![Box::pin(
+        tokio_stream::wrappers::UnboundedReceiverStream::new(rx),
+    )];
+
+    for source in &sources {
+        source.config.filters.watch({
+            let tx = tx.clone();
+            move |_| {
+                let _ = tx.send(());
+            }
+        });
+        changes.push(Box::pin(
+            tokio_stream::wrappers::WatchStream::from_changes(source.config.clusters.watch())
+                .map(drop),
+        ));
+    }
+
+    let mut changes = futures::stream::select_all(changes);
+    tokio::spawn(async move {
+        while changes.next().await.is_some() {
+            merge(&config, &sources);
+        }
+
+        Ok(())
+    })
+}
+
+fn merge(config: &Config, sources: &[Source]) {
+    let clusters = ClusterMap::default();
+    for source in sources {
+        let source_clusters = source.config.clusters.read();
+        super::metrics::source_endpoints(&source.management_server)
+            .set(source_clusters.endpoints().count() as i64);
+
+        for cluster in source_clusters.iter() {
+            let new = clusters.get(&cluster.name).is_none();
+            let mut entry = clusters.default_entry(cluster.name.clone());
+            // A cluster's settings are taken from the first source with it.
+            if new {
+                entry.lb_policy = cluster.lb_policy;
+                entry.health_checks = cluster.health_checks.clone();
+                entry.connection_limits = cluster.connection_limits.clone();
+            }
+            for locality in cluster.localities.iter() {
+                entry.localities.insert(locality.clone());
+            }
+        }
+    }
+
+    tracing::debug!(
+        sources = sources.len(),
+        "merging management server resources"
+    );
+    config
+        .clusters
+        .modify(|current| current.replace(clusters.clone()));
+
+    let chain = sources.iter().find_map(|source| {
+        source
+            .config
+            .filters
+            .try_load()
+            .map(|chain| (&source.management_server, chain))
+    });
+    if let Some((management_server, chain)) = chain {
+        if *config.filters.load() != *chain {
+            tracing::info!(%management_server, "using filter chain from management server");
+            config.replace_filters(crate::filters::FilterChain::clone(&chain));
+        }
+    }
+
+    config.apply_metrics();
+    config.history.record(history::Source::Xds, config);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        cluster::Cluster,
+        endpoint::{Endpoint, LocalityEndpoints},
+        filters::{FilterChain, StaticFilter},
+    };
+
+    fn cluster(name: &str, port: u16) -> Cluster {
+        Cluster::new(
+            name,
+            [LocalityEndpoints::from(Endpoint::new(
+                (std::net::Ipv4Addr::LOCALHOST, port).into(),
+            ))],
+        )
+    }
+
+    fn chain(name: &str) -> FilterChain {
+        FilterChain::try_from(vec![crate::config::Filter {
+            name: name.into(),
+            label: None,
+            config: None,
+        }])
+        .unwrap()
+    }
+
+    #[test]
+    fn merges_sources() {
+        let config = Config::default();
+        let primary = Source::new("http://primary");
+        let secondary = Source::new("http://secondary");
+        let sources = [primary.clone(), secondary.clone()];
+
+        primary.config.clusters.modify(|clusters| {
+            clusters.insert(cluster("a", 1));
+        });
+        secondary.config.clusters.modify(|clusters| {
+            clusters.insert(cluster("a", 2));
+            clusters.insert(cluster("b", 3));
+        });
+        secondary
+            .config
+            .replace_filters(chain(crate::filters::Debug::NAME));
+        merge(&config, &sources);
+
+        let ports = |name: &str| {
+            let mut ports: Vec<_> = config
+                .clusters
+                .read()
+                .get(name)
+                .unwrap()
+                .endpoints()
+                .map(|endpoint| endpoint.address.port())
+                .collect();
+            ports.sort();
+            ports
+        };
+        assert_eq!(vec![1, 2], ports("a"));
+        assert_eq!(vec![3], ports("b"));
+        assert_eq!(chain(crate::filters::Debug::NAME), *config.filters.load());
+
+        // The primary's filter chain takes precedence once it has one.
+        primary
+            .config
+            .replace_filters(chain(crate::filters::Drop::NAME));
+        merge(&config, &sources);
+        assert_eq!(chain(crate::filters::Drop::NAME), *config.filters.load());
+
+        // A source going away only removes its own endpoints.
+        secondary
+            .config
+            .clusters
+            .modify(|clusters| clusters.replace(<_>::default()));
+        secondary.config.filters.remove();
+        merge(&config, &sources);
+        assert_eq!(vec![1], ports("a"));
+        assert!(config.clusters.read().get("b").is_none());
+        assert_eq!(chain(crate::filters::Drop::NAME), *config.filters.load());
+    }
+}
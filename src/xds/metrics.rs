@@ -20,6 +20,7 @@ use prometheus::{IntCounterVec, IntGaugeVec};
 pub(crate) const NODE_LABEL: &str = "node";
 pub(crate) const CONTROL_PLANE_LABEL: &str = "control_plane";
 pub(crate) const TYPE_LABEL: &str = "type";
+pub(crate) const MANAGEMENT_SERVER_LABEL: &str = "management_server";
 
 pub(crate) fn active_control_planes(control_plane: &str) -> prometheus::IntGauge {
     static ACTIVE_CONTROL_PLANES: Lazy<IntGaugeVec> = Lazy::new(|| {
@@ -101,6 +102,22 @@ pub(crate) fn nacks(control_plane: &str, type_url: &str) -> prometheus::IntCount
     NACKS.with_label_values(&[control_plane, type_url])
 }
 
+pub(crate) fn source_endpoints(management_server: &str) -> prometheus::IntGauge {
+    static SOURCE_ENDPOINTS: Lazy<IntGaugeVec> = Lazy::new(|| {
+        prometheus::register_int_gauge_vec_with_registry! {
+            prometheus::opts! {
+                "xds_source_endpoints",
+                "Number of endpoints received from each merged management server",
+            },
+            &[MANAGEMENT_SERVER_LABEL],
+            crate::metrics::registry(),
+        }
+        .unwrap()
+    });
+
+    SOURCE_ENDPOINTS.with_label_values(&[management_server])
+}
+
 pub struct StreamConnectionMetrics {
     control_plane: String,
 }
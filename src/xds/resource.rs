@@ -75,6 +75,34 @@ impl Resource {
     }
 }
 
+/// Returns an opaque version for an encoded resource, derived from its
+/// contents so that unchanged resources keep the same version.
+pub fn resource_version(resource: &prost_types::Any) -> String {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    resource.type_url.hash(&mut hasher);
+    resource.value.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Returns the version of a set of named resources, which only changes when
+/// a resource is added, removed, or its contents change.
+pub fn resources_version(resources: &[(String, prost_types::Any)]) -> String {
+    use std::hash::{Hash, Hasher};
+
+    let mut sorted: Vec<_> = resources.iter().collect();
+    sorted.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for (name, resource) in sorted {
+        name.hash(&mut hasher);
+        resource.type_url.hash(&mut hasher);
+        resource.value.hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
 impl TryFrom<prost_types::Any> for Resource {
     type Error = eyre::Error;
 
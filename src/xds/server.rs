@@ -14,27 +14,33 @@
  * limitations under the License.
  */
 
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use cached::Cached;
-use futures::{Stream, TryFutureExt, TryStreamExt};
+use futures::{Stream, TryStreamExt};
 use tokio_stream::StreamExt;
 use tracing_futures::Instrument;
 
 use crate::{
     config::Config,
     xds::{
+        config::core::v3::Node,
         metrics,
         relay::aggregated_control_plane_discovery_service_server::{
             AggregatedControlPlaneDiscoveryService, AggregatedControlPlaneDiscoveryServiceServer,
         },
+        resource::{resource_version, resources_version},
         service::discovery::v3::{
             aggregated_discovery_service_server::{
                 AggregatedDiscoveryService, AggregatedDiscoveryServiceServer,
             },
             DeltaDiscoveryRequest, DeltaDiscoveryResponse, DiscoveryRequest, DiscoveryResponse,
+            Resource as DeltaResource,
         },
-        ResourceType,
+        ResourceType, ServerAuth,
     },
 };
 
@@ -42,25 +48,29 @@ use crate::{
 pub fn spawn(
     port: u16,
     config: std::sync::Arc<crate::Config>,
+    auth: ServerAuth,
 ) -> impl std::future::Future<Output = crate::Result<()>> {
-    let server = AggregatedDiscoveryServiceServer::new(ControlPlane::from_arc(config));
+    let server = AggregatedDiscoveryServiceServer::with_interceptor(
+        ControlPlane::from_arc(config),
+        auth.interceptor(),
+    );
     let server = tonic::transport::Server::builder().add_service(server);
     tracing::info!("serving management server on port `{port}`");
-    server
-        .serve((std::net::Ipv6Addr::UNSPECIFIED, port).into())
-        .map_err(From::from)
+    async move { auth.serve(server, port).await }
 }
 
 pub(crate) fn control_plane_discovery_server(
     port: u16,
     config: Arc<Config>,
+    auth: ServerAuth,
 ) -> impl std::future::Future<Output = crate::Result<()>> {
-    let server = AggregatedControlPlaneDiscoveryServiceServer::new(ControlPlane::from_arc(config));
+    let server = AggregatedControlPlaneDiscoveryServiceServer::with_interceptor(
+        ControlPlane::from_arc(config),
+        auth.interceptor(),
+    );
     let server = tonic::transport::Server::builder().add_service(server);
     tracing::info!("serving relay server on port `{port}`");
-    server
-        .serve((std::net::Ipv6Addr::UNSPECIFIED, port).into())
-        .map_err(From::from)
+    async move { auth.serve(server, port).await }
 }
 
 #[derive(Clone)]
@@ -72,7 +82,8 @@ pub struct ControlPlane {
 struct Watchers {
     sender: tokio::sync::watch::Sender<()>,
     receiver: tokio::sync::watch::Receiver<()>,
-    version: std::sync::atomic::AtomicU64,
+    /// The content version of each resource as of the last update.
+    versions: parking_lot::Mutex<HashMap<String, String>>,
 }
 
 impl Default for Watchers {
@@ -81,8 +92,70 @@ impl Default for Watchers {
         Self {
             sender,
             receiver,
-            version: <_>::default(),
+            versions: <_>::default(),
+        }
+    }
+}
+
+/// The resources of a single type that a delta xDS client is subscribed to,
+/// and the versions of them it has been sent.
+#[derive(Debug, Default)]
+struct DeltaSubscription {
+    /// The subscribed resource names, `None` if subscribed to every resource.
+    names: Option<HashSet<String>>,
+    versions: HashMap<String, String>,
+}
+
+impl DeltaSubscription {
+    const WILDCARD: &'static str = "*";
+
+    fn new(request: &DeltaDiscoveryRequest) -> Self {
+        let mut this = Self {
+            names: Some(HashSet::new()),
+            versions: request.initial_resource_versions.clone(),
+        };
+
+        if request.resource_names_subscribe.is_empty() {
+            this.names = None;
+        }
+
+        this.update(request);
+        this
+    }
+
+    /// The subscribed resource names, empty if subscribed to every resource.
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.names.iter().flatten().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Applies the subscription changes in `request`, returning whether any
+    /// names were newly subscribed to.
+    fn update(&mut self, request: &DeltaDiscoveryRequest) -> bool {
+        let mut subscribed = false;
+        for name in &request.resource_names_subscribe {
+            if name == Self::WILDCARD {
+                subscribed |= self.names.take().is_some();
+            } else if let Some(names) = &mut self.names {
+                subscribed |= names.insert(name.clone());
+            }
+        }
+
+        for name in &request.resource_names_unsubscribe {
+            if name == Self::WILDCARD {
+                self.names.get_or_insert_with(HashSet::new);
+            } else if let Some(names) = &mut self.names {
+                names.remove(name);
+            }
+            self.versions.remove(name);
         }
+
+        if let Some(names) = &self.names {
+            self.versions.retain(|name, _| names.contains(name));
+        }
+
+        subscribed
     }
 }
 
@@ -98,10 +171,18 @@ impl ControlPlane {
             watchers: <_>::default(),
         };
 
+        for resource_type in [
+            ResourceType::Cluster,
+            ResourceType::Endpoint,
+            ResourceType::Listener,
+        ] {
+            this.update_versions(resource_type);
+        }
+
         tokio::spawn({
             let this = this.clone();
+            let mut watcher = this.config.clusters.watch();
             async move {
-                let mut watcher = this.config.clusters.watch();
                 loop {
                     tracing::debug!(?watcher, "waiting for changes");
                     if let Err(error) = watcher.changed().await {
@@ -120,41 +201,111 @@ impl ControlPlane {
             }
         });
 
+        // The node rules only change what each proxy is sent, so the
+        // resources' global versions can't tell whether a push is needed.
+        this.config.nodes.watch({
+            let this = this.clone();
+            move |_| {
+                for resource_type in [
+                    ResourceType::Cluster,
+                    ResourceType::Endpoint,
+                    ResourceType::Listener,
+                ] {
+                    this.update_versions(resource_type);
+                    this.notify(resource_type);
+                }
+            }
+        });
+
         this
     }
 
+    /// Records the current version of each resource of `resource_type`,
+    /// returning whether any of them have changed.
+    fn update_versions(&self, resource_type: ResourceType) -> bool {
+        let versions: HashMap<_, _> =
+            match self.config.discovery_resources(resource_type, None, &[]) {
+                Ok(resources) => resources
+                    .iter()
+                    .map(|(name, resource)| (name.clone(), resource_version(resource)))
+                    .collect(),
+                Err(error) => {
+                    tracing::warn!(%error, %resource_type, "encoding resources failed");
+                    return true;
+                }
+            };
+
+        let mut current = self.watchers[resource_type].versions.lock();
+        let changed = *current != versions;
+        *current = versions;
+        changed
+    }
+
+    /// Notifies streams of `resource_type` of an update, unless none of its
+    /// resources' contents have changed.
     fn push_update(&self, resource_type: ResourceType) {
+        if !self.update_versions(resource_type) {
+            tracing::debug!(%resource_type, "resources unchanged, skipping update");
+            return;
+        }
+
+        self.notify(resource_type);
+    }
+
+    /// Notifies streams of `resource_type` of an update.
+    fn notify(&self, resource_type: ResourceType) {
         let watchers = &self.watchers[resource_type];
-        watchers
-            .version
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         tracing::debug!(%resource_type, watchers=watchers.sender.receiver_count(), "pushing update");
         if let Err(error) = watchers.sender.send(()) {
             tracing::warn!(%error, "pushing update failed");
         }
     }
 
+    /// Records `response` being sent to the client `id`.
+    fn sent(&self, id: &str, response: &DiscoveryResponse) {
+        self.config.xds_clients.sent(
+            id,
+            &response.type_url,
+            &response.version_info,
+            &response.nonce,
+        );
+    }
+
+    /// Records the delta `response` being sent to `node`.
+    fn delta_sent(&self, node: &Node, response: &DeltaDiscoveryResponse) {
+        self.config.xds_clients.sent(
+            &node.id,
+            &response.type_url,
+            &response.system_version_info,
+            &response.nonce,
+        );
+    }
+
     pub(crate) fn discovery_response(
         &self,
-        id: &str,
+        node: &Node,
         resource_type: ResourceType,
         names: &[String],
     ) -> Result<DiscoveryResponse, tonic::Status> {
-        let mut response = self
+        tracing::trace!(id = %node.id, %resource_type, ?names, "creating discovery response");
+        let resources = self
             .config
-            .discovery_request(id, resource_type, names)
+            .discovery_resources(resource_type, Some(node), names)
             .map_err(|error| tonic::Status::internal(error.to_string()))?;
-        let watchers = &self.watchers[resource_type];
 
-        let nonce = uuid::Uuid::new_v4();
-        response.version_info = watchers
-            .version
-            .load(std::sync::atomic::Ordering::Relaxed)
-            .to_string();
-        response.control_plane = Some(crate::xds::config::core::v3::ControlPlane {
-            identifier: (*self.config.id.load()).clone(),
-        });
-        response.nonce = nonce.to_string();
+        let response = DiscoveryResponse {
+            version_info: resources_version(&resources),
+            resources: resources
+                .into_iter()
+                .map(|(_, resource)| resource)
+                .collect(),
+            type_url: resource_type.type_url().into(),
+            nonce: uuid::Uuid::new_v4().to_string(),
+            control_plane: Some(crate::xds::config::core::v3::ControlPlane {
+                identifier: (*self.config.id.load()).clone(),
+            }),
+            ..<_>::default()
+        };
 
         tracing::trace!(
             id = &*response.version_info,
@@ -166,6 +317,231 @@ impl ControlPlane {
         Ok(response)
     }
 
+    /// Returns the resources in `subscription` that have changed since they
+    /// were last sent, and the ones that have since been removed.
+    fn delta_discovery_response(
+        &self,
+        node: &Node,
+        resource_type: ResourceType,
+        subscription: &mut DeltaSubscription,
+    ) -> Result<DeltaDiscoveryResponse, tonic::Status> {
+        let names: Vec<_> = subscription.names.iter().flatten().cloned().collect();
+        let resources = match &subscription.names {
+            Some(names) if names.is_empty() => Vec::new(),
+            _ => self
+                .config
+                .discovery_resources(resource_type, Some(node), &names)
+                .map_err(|error| tonic::Status::internal(error.to_string()))?,
+        };
+        let system_version_info = resources_version(&resources);
+
+        let mut current = HashSet::new();
+        let mut changed = Vec::new();
+        for (name, resource) in resources {
+            let version = resource_version(&resource);
+            current.insert(name.clone());
+            if subscription.versions.get(&name) != Some(&version) {
+                subscription.versions.insert(name.clone(), version.clone());
+                changed.push(DeltaResource {
+                    name,
+                    version,
+                    resource: Some(resource),
+                    ..<_>::default()
+                });
+            }
+        }
+
+        let removed_resources: Vec<_> = subscription
+            .versions
+            .keys()
+            .filter(|name| !current.contains(*name))
+            .cloned()
+            .collect();
+        for name in &removed_resources {
+            subscription.versions.remove(name);
+        }
+
+        let response = DeltaDiscoveryResponse {
+            system_version_info,
+            resources: changed,
+            type_url: resource_type.type_url().into(),
+            removed_resources,
+            nonce: uuid::Uuid::new_v4().to_string(),
+            control_plane: Some(crate::xds::config::core::v3::ControlPlane {
+                identifier: (*self.config.id.load()).clone(),
+            }),
+            ..<_>::default()
+        };
+
+        tracing::trace!(
+            r#type = &*response.type_url,
+            nonce = &*response.nonce,
+            changed = response.resources.len(),
+            removed = response.removed_resources.len(),
+            "delta discovery response"
+        );
+
+        Ok(response)
+    }
+
+    /// Handles a single delta discovery request, returning a response if the
+    /// request subscribed to new resources.
+    fn delta_discovery_request(
+        &self,
+        node: &Node,
+        subscriptions: &mut HashMap<ResourceType, DeltaSubscription>,
+        request: &DeltaDiscoveryRequest,
+    ) -> Result<Option<DeltaDiscoveryResponse>, tonic::Status> {
+        let id = request
+            .node
+            .as_ref()
+            .map(|node| &*node.id)
+            .unwrap_or(&node.id);
+        let resource_type: ResourceType = request.type_url.parse()?;
+        metrics::discovery_requests(id, resource_type.type_url()).inc();
+
+        if !request.response_nonce.is_empty() {
+            if let Some(error) = &request.error_detail {
+                metrics::nacks(id, resource_type.type_url()).inc();
+                self.config.xds_clients.nacked(
+                    id,
+                    resource_type.type_url(),
+                    &request.response_nonce,
+                    &error.message,
+                );
+                tracing::error!(nonce = %request.response_nonce, ?error, "NACK");
+            } else {
+                metrics::acks(id, resource_type.type_url()).inc();
+                self.config.xds_clients.acked(
+                    id,
+                    resource_type.type_url(),
+                    &request.response_nonce,
+                );
+                tracing::trace!(nonce = %request.response_nonce, "ACK");
+            }
+        }
+
+        let (subscription, subscribed) = match subscriptions.entry(resource_type) {
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                tracing::trace!(%id, %resource_type, "initial delta request");
+                (entry.insert(DeltaSubscription::new(request)), true)
+            }
+            std::collections::hash_map::Entry::Occupied(entry) => {
+                let subscription = entry.into_mut();
+                let subscribed = subscription.update(request);
+                (subscription, subscribed)
+            }
+        };
+
+        self.config.xds_clients.subscribed(
+            &node.id,
+            resource_type.type_url(),
+            subscription.names(),
+        );
+        if !subscribed {
+            return Ok(None);
+        }
+
+        self.delta_discovery_response(node, resource_type, subscription)
+            .map(Some)
+    }
+
+    pub async fn delta_aggregated_resources<S>(
+        &self,
+        mut streaming: S,
+    ) -> Result<
+        impl Stream<Item = Result<DeltaDiscoveryResponse, tonic::Status>> + Send,
+        tonic::Status,
+    >
+    where
+        S: Stream<Item = Result<DeltaDiscoveryRequest, tonic::Status>>
+            + Send
+            + std::marker::Unpin
+            + 'static,
+    {
+        tracing::trace!("starting delta stream");
+        let message = streaming.next().await.ok_or_else(|| {
+            tracing::error!("No message found");
+            tonic::Status::invalid_argument("No message found")
+        })??;
+
+        let Some(node) = message.node.clone() else {
+            tracing::error!("Node identifier was not found");
+            return Err(tonic::Status::invalid_argument("Node identifier required"));
+        };
+
+        let this = Self::clone(self);
+        let mut subscriptions = HashMap::new();
+        let mut changes = tokio_stream::StreamMap::new();
+        for resource_type in [
+            ResourceType::Cluster,
+            ResourceType::Endpoint,
+            ResourceType::Listener,
+        ] {
+            changes.insert(
+                resource_type,
+                tokio_stream::wrappers::WatchStream::from_changes(
+                    self.watchers[resource_type].receiver.clone(),
+                ),
+            );
+        }
+
+        let connection = this.config.xds_clients.connect(&node);
+        let response = this.delta_discovery_request(&node, &mut subscriptions, &message)?;
+        let span = tracing::info_span!("delta_xds_stream", %node.id);
+
+        Ok(Box::pin(async_stream::try_stream! {
+            let _connection = connection;
+            if let Some(response) = response {
+                this.delta_sent(&node, &response);
+                yield response;
+            }
+
+            loop {
+                tokio::select! {
+                    Some((resource_type, ())) = changes.next() => {
+                        let Some(subscription) = subscriptions.get_mut(&resource_type) else {
+                            continue;
+                        };
+
+                        match this.delta_discovery_response(&node, resource_type, subscription) {
+                            Ok(response) if response.resources.is_empty() && response.removed_resources.is_empty() => {}
+                            Ok(response) => {
+                                tracing::trace!("sending new delta discovery response");
+                                this.delta_sent(&node, &response);
+                                yield response;
+                            }
+                            Err(error) => tracing::error!(%error, "failed to create delta discovery response"),
+                        }
+                    }
+                    new_message = streaming.next() => {
+                        let new_message = match new_message.transpose() {
+                            Ok(Some(value)) => value,
+                            Ok(None) => break,
+                            Err(error) => {
+                                tracing::error!(%error, "error receiving message");
+                                continue;
+                            }
+                        };
+
+                        match this.delta_discovery_request(&node, &mut subscriptions, &new_message) {
+                            Ok(Some(response)) => {
+                                this.delta_sent(&node, &response);
+                                yield response;
+                            }
+                            Ok(None) => {}
+                            Err(error) => {
+                                tracing::error!(%error, url=%new_message.type_url, "invalid delta request");
+                            }
+                        }
+                    }
+                }
+            }
+
+            tracing::info!("terminating delta stream");
+        }.instrument(span)))
+    }
+
     pub async fn stream_aggregated_resources<S>(
         &self,
         mut streaming: S,
@@ -196,20 +572,37 @@ impl ControlPlane {
 
         tracing::trace!(id = %node.id, %resource_type, "initial request");
         metrics::discovery_requests(&id, resource_type.type_url()).inc();
-        let response = this.discovery_response(&id, resource_type, &message.resource_names)?;
+        let connection = this.config.xds_clients.connect(&node);
+        this.config.xds_clients.subscribed(
+            &id,
+            resource_type.type_url(),
+            message.resource_names.clone(),
+        );
+        let response = this.discovery_response(&node, resource_type, &message.resource_names)?;
         pending_acks.cache_set(response.nonce.clone(), ());
+        let mut version = response.version_info.clone();
+        let span = tracing::info_span!("xds_stream", %node.id, %resource_type);
 
         Ok(Box::pin(async_stream::try_stream! {
+            let _connection = connection;
+            this.sent(&id, &response);
             yield response;
 
             loop {
                 tokio::select! {
                     _ = rx.changed() => {
-                        tracing::trace!("sending new discovery response");
-                        yield this.discovery_response(&id, resource_type, &message.resource_names).map(|response| {
-                            pending_acks.cache_set(response.nonce.clone(), ());
-                            response
-                        })?;
+                        match this.discovery_response(&node, resource_type, &message.resource_names) {
+                            // The update didn't change any of the subscribed resources.
+                            Ok(response) if response.version_info == version => {}
+                            Ok(response) => {
+                                tracing::trace!("sending new discovery response");
+                                version = response.version_info.clone();
+                                pending_acks.cache_set(response.nonce.clone(), ());
+                                this.sent(&id, &response);
+                                yield response;
+                            }
+                            Err(error) => tracing::error!(%error, "failed to create discovery response"),
+                        }
                     }
                     new_message = streaming.next() => {
                         let new_message = match new_message.transpose() {
@@ -235,10 +628,15 @@ impl ControlPlane {
 
                         if let Some(error) = &new_message.error_detail {
                             metrics::nacks(id, resource_type.type_url()).inc();
+                            this.config.xds_clients.nacked(id, resource_type.type_url(), &new_message.response_nonce, &error.message);
                             tracing::error!(nonce = %new_message.response_nonce, ?error, "NACK");
-                            // Currently just resend previous discovery response.
+                            // The client keeps its previous configuration, resending the
+                            // rejected one would only be rejected again.
+                            continue
                         } else if uuid::Uuid::parse_str(&new_message.response_nonce).is_ok() {
                             if pending_acks.cache_get(&new_message.response_nonce).is_some() {
+                                metrics::acks(id, resource_type.type_url()).inc();
+                                this.config.xds_clients.acked(id, resource_type.type_url(), &new_message.response_nonce);
                                 tracing::info!(nonce = %new_message.response_nonce, "ACK");
                                 continue
                             } else {
@@ -247,16 +645,18 @@ impl ControlPlane {
                             }
                         }
 
-                        yield this.discovery_response(id, resource_type, &message.resource_names).map(|response| {
+                        let response = this.discovery_response(&node, resource_type, &message.resource_names).map(|response| {
                             pending_acks.cache_set(response.nonce.clone(), ());
                             response
                         }).unwrap();
+                        this.sent(id, &response);
+                        yield response;
                     }
                 }
             }
 
             tracing::info!("terminating stream");
-        }.instrument(tracing::info_span!("xds_stream", %node.id, %resource_type))))
+        }.instrument(span)))
     }
 }
 
@@ -265,7 +665,7 @@ impl AggregatedDiscoveryService for ControlPlane {
     type StreamAggregatedResourcesStream =
         std::pin::Pin<Box<dyn Stream<Item = Result<DiscoveryResponse, tonic::Status>> + Send>>;
     type DeltaAggregatedResourcesStream =
-        tokio_stream::wrappers::ReceiverStream<Result<DeltaDiscoveryResponse, tonic::Status>>;
+        std::pin::Pin<Box<dyn Stream<Item = Result<DeltaDiscoveryResponse, tonic::Status>> + Send>>;
 
     #[tracing::instrument(skip_all)]
     async fn stream_aggregated_resources(
@@ -279,13 +679,16 @@ impl AggregatedDiscoveryService for ControlPlane {
         )))
     }
 
+    #[tracing::instrument(skip_all)]
     async fn delta_aggregated_resources(
         &self,
-        _request: tonic::Request<tonic::Streaming<DeltaDiscoveryRequest>>,
+        request: tonic::Request<tonic::Streaming<DeltaDiscoveryRequest>>,
     ) -> Result<tonic::Response<Self::DeltaAggregatedResourcesStream>, tonic::Status> {
-        Err(tonic::Status::unimplemented(
-            "Quilkin doesn't currently support Delta xDS",
-        ))
+        Ok(tonic::Response::new(Box::pin(
+            self.delta_aggregated_resources(request.into_inner())
+                .in_current_span()
+                .await?,
+        )))
     }
 }
 
@@ -314,13 +717,15 @@ impl AggregatedControlPlaneDiscoveryService for ControlPlane {
         };
 
         tracing::info!(%identifier, "new control plane discovery stream");
+        let connection = self.config.xds_clients.connect_agent(&identifier);
         let config = self.config.clone();
+        let node = super::client::node(&identifier, None);
         let stream = super::client::AdsStream::connect(
-            Arc::from(&*identifier),
+            node.clone(),
             move |(mut requests, _rx), _subscribed_resources| async move {
                 tracing::info!(%identifier, "sending initial discovery request");
                 crate::xds::client::MdsStream::discovery_request_without_cache(
-                    &identifier,
+                    &node,
                     &mut requests,
                     crate::xds::ResourceType::Cluster,
                     &[],
@@ -330,7 +735,7 @@ impl AggregatedControlPlaneDiscoveryService for ControlPlane {
                 let mut response_handler = super::client::handle_discovery_responses(
                     identifier.clone(),
                     responses,
-                    move |resource| config.apply(resource),
+                    move |resources| config.apply_all(resources),
                 );
 
                 loop {
@@ -343,6 +748,7 @@ impl AggregatedControlPlaneDiscoveryService for ControlPlane {
         );
 
         Ok(tonic::Response::new(Box::pin(async_stream::stream! {
+            let _connection = connection;
             for await request in tokio_stream::wrappers::BroadcastStream::new(stream.requests().subscribe())
                 .map_err(|error| tonic::Status::internal(error.to_string()))
             {
@@ -478,4 +884,297 @@ mod tests {
             .unwrap()
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn delta_response() {
+        const RESOURCE: ResourceType = ResourceType::Endpoint;
+
+        let endpoint =
+            |port| crate::endpoint::Endpoint::new((std::net::Ipv4Addr::LOCALHOST, port).into());
+        let config = Arc::new(Config::default());
+        config.clusters.modify(|clusters| {
+            clusters.insert(crate::cluster::Cluster::new(
+                "a",
+                [crate::endpoint::LocalityEndpoints::from(endpoint(1))],
+            ));
+        });
+        let client = ControlPlane::from_arc(config.clone());
+        let (tx, rx) = tokio::sync::mpsc::channel(256);
+
+        let request = DeltaDiscoveryRequest {
+            node: Some(Node {
+                id: "quilkin".into(),
+                ..Node::default()
+            }),
+            type_url: RESOURCE.type_url().into(),
+            ..<_>::default()
+        };
+
+        tx.send(Ok(request.clone())).await.unwrap();
+        let mut stream = timeout(
+            TIMEOUT_DURATION,
+            client.delta_aggregated_resources(tokio_stream::wrappers::ReceiverStream::new(rx)),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        let message = timeout(TIMEOUT_DURATION, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, message.resources.len());
+        assert_eq!("a", message.resources[0].name);
+        let version_a = message.resources[0].version.clone();
+
+        // Only the added cluster is sent.
+        config.clusters.modify(|clusters| {
+            clusters.insert(crate::cluster::Cluster::new(
+                "b",
+                [crate::endpoint::LocalityEndpoints::from(endpoint(2))],
+            ));
+        });
+        let message = timeout(TIMEOUT_DURATION, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, message.resources.len());
+        assert_eq!("b", message.resources[0].name);
+        assert!(message.removed_resources.is_empty());
+
+        config.clusters.modify(|clusters| {
+            clusters.remove("b");
+        });
+        let message = timeout(TIMEOUT_DURATION, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(message.resources.is_empty());
+        assert_eq!(vec!["b".to_owned()], message.removed_resources);
+
+        // A reconnecting client that already has `a` isn't sent it again,
+        // and is told about clusters that no longer exist.
+        let (tx, rx) = tokio::sync::mpsc::channel(256);
+        tx.send(Ok(DeltaDiscoveryRequest {
+            initial_resource_versions: [
+                ("a".to_owned(), version_a),
+                ("c".to_owned(), "0".to_owned()),
+            ]
+            .into(),
+            ..request
+        }))
+        .await
+        .unwrap();
+        let mut stream = client
+            .delta_aggregated_resources(tokio_stream::wrappers::ReceiverStream::new(rx))
+            .await
+            .unwrap();
+        let message = timeout(TIMEOUT_DURATION, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(message.resources.is_empty());
+        assert_eq!(vec!["c".to_owned()], message.removed_resources);
+    }
+
+    #[tokio::test]
+    async fn named_subscription() {
+        const RESOURCE: ResourceType = ResourceType::Endpoint;
+
+        let endpoint = |port| {
+            crate::endpoint::LocalityEndpoints::from(crate::endpoint::Endpoint::new(
+                (std::net::Ipv4Addr::LOCALHOST, port).into(),
+            ))
+        };
+        let config = Arc::new(Config::default());
+        config.clusters.modify(|clusters| {
+            clusters.insert(crate::cluster::Cluster::new("a", [endpoint(1)]));
+            clusters.insert(crate::cluster::Cluster::new("b", [endpoint(2)]));
+        });
+        let client = ControlPlane::from_arc(config.clone());
+        let (tx, rx) = tokio::sync::mpsc::channel(256);
+
+        tx.send(Ok(DiscoveryRequest {
+            node: Some(Node {
+                id: "quilkin".into(),
+                ..Node::default()
+            }),
+            resource_names: vec!["a".into()],
+            type_url: RESOURCE.type_url().into(),
+            ..DiscoveryRequest::default()
+        }))
+        .await
+        .unwrap();
+        let mut stream = client
+            .stream_aggregated_resources(tokio_stream::wrappers::ReceiverStream::new(rx))
+            .await
+            .unwrap();
+
+        let message = timeout(TIMEOUT_DURATION, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, message.resources.len());
+        let version = message.version_info.clone();
+
+        // Changes to clusters that aren't subscribed to aren't pushed.
+        config.clusters.modify(|clusters| {
+            clusters.insert(crate::cluster::Cluster::new("b", [endpoint(3)]));
+        });
+        assert!(
+            timeout(std::time::Duration::from_millis(100), stream.next())
+                .await
+                .is_err()
+        );
+
+        config.clusters.modify(|clusters| {
+            clusters.insert(crate::cluster::Cluster::new("a", [endpoint(4)]));
+        });
+        let message = timeout(TIMEOUT_DURATION, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, message.resources.len());
+        assert_ne!(version, message.version_info);
+    }
+
+    #[tokio::test]
+    async fn node_rules() {
+        use crate::{
+            config::{EndpointScope, NodeRule},
+            endpoint::{Endpoint, Locality, LocalityEndpoints},
+            xds::config::endpoint::v3::ClusterLoadAssignment,
+        };
+        use prost::Message;
+
+        const RESOURCE: ResourceType = ResourceType::Endpoint;
+
+        let endpoints = |region: &str, port| {
+            LocalityEndpoints::from((
+                Endpoint::new((std::net::Ipv4Addr::LOCALHOST, port).into()),
+                Locality::region(region),
+            ))
+        };
+        let config = Arc::new(Config::default());
+        config.clusters.modify(|clusters| {
+            clusters.insert(crate::cluster::Cluster::new(
+                "default",
+                [endpoints("eu", 1), endpoints("us", 2)],
+            ));
+        });
+        config.nodes.store(Arc::new(vec![NodeRule {
+            node: <_>::default(),
+            filters: None,
+            endpoints: EndpointScope::Region,
+        }]));
+        let client = ControlPlane::from_arc(config.clone());
+        let (tx, rx) = tokio::sync::mpsc::channel(256);
+
+        tx.send(Ok(DiscoveryRequest {
+            node: Some(crate::xds::client::node(
+                "quilkin",
+                Some(&Locality::region("eu")),
+            )),
+            type_url: RESOURCE.type_url().into(),
+            ..DiscoveryRequest::default()
+        }))
+        .await
+        .unwrap();
+        let mut stream = client
+            .stream_aggregated_resources(tokio_stream::wrappers::ReceiverStream::new(rx))
+            .await
+            .unwrap();
+
+        let count_endpoints = |message: DiscoveryResponse| {
+            let assignment = ClusterLoadAssignment::decode(&*message.resources[0].value).unwrap();
+            assignment
+                .endpoints
+                .iter()
+                .map(|locality| locality.lb_endpoints.len())
+                .sum::<usize>()
+        };
+
+        let message = timeout(TIMEOUT_DURATION, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, count_endpoints(message));
+
+        // Changing the rules pushes the resources selected by the new ones.
+        config.nodes.store(Arc::new(Vec::new()));
+        let message = timeout(TIMEOUT_DURATION, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(2, count_endpoints(message));
+    }
+
+    #[tokio::test]
+    async fn records_acks_and_nacks() {
+        const RESOURCE: ResourceType = ResourceType::Listener;
+
+        let config = Arc::new(Config::default());
+        let client = ControlPlane::from_arc(config.clone());
+        let (tx, rx) = tokio::sync::mpsc::channel(256);
+        let mut request = DiscoveryRequest {
+            node: Some(Node {
+                id: "quilkin".into(),
+                ..Node::default()
+            }),
+            type_url: RESOURCE.type_url().into(),
+            ..DiscoveryRequest::default()
+        };
+
+        tx.send(Ok(request.clone())).await.unwrap();
+        let mut stream = timeout(
+            TIMEOUT_DURATION,
+            client.stream_aggregated_resources(Box::pin(
+                tokio_stream::wrappers::ReceiverStream::new(rx),
+            )),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let response = timeout(TIMEOUT_DURATION, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        request.response_nonce = response.nonce.clone();
+        request.error_detail = Some(crate::xds::google::rpc::Status {
+            code: 3,
+            message: "invalid filter".into(),
+            ..<_>::default()
+        });
+        tx.send(Ok(request)).await.unwrap();
+
+        // The rejected response isn't resent.
+        assert!(
+            timeout(std::time::Duration::from_millis(500), stream.next())
+                .await
+                .is_err()
+        );
+
+        let summary = config.xds_clients.summary();
+        assert_eq!(1, summary.len());
+        assert_eq!("quilkin", summary[0].id);
+        let state = &summary[0].resources[RESOURCE.type_url()];
+        assert_eq!(response.nonce, state.last_sent.as_ref().unwrap().nonce);
+        assert!(state.last_acked.is_none());
+        let nack = state.last_nack.as_ref().unwrap();
+        assert_eq!(Some(&*response.version_info), nack.version.as_deref());
+        assert_eq!("invalid filter", nack.error);
+
+        drop(stream);
+        assert!(config.xds_clients.summary().is_empty());
+    }
 }
@@ -0,0 +1,82 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use tokio::time::{timeout, Duration};
+
+use quilkin::{
+    endpoint::Endpoint,
+    test_utils::{available_addr, TestHelper},
+};
+
+#[tokio::test]
+async fn canary_does_not_affect_traffic() {
+    let mut t = TestHelper::default();
+    let yaml = "
+version: v1alpha1
+canary:
+  filters:
+    - name: quilkin.filters.drop.v1alpha1.Drop
+";
+    let echo = t.run_echo_server().await;
+    let metrics_port = available_addr().await.port();
+
+    let local_addr = available_addr().await;
+    let server_proxy = quilkin::cli::Proxy {
+        port: local_addr.port(),
+        ..<_>::default()
+    };
+    let server_config = std::sync::Arc::new(quilkin::Config::from_reader(yaml.as_bytes()).unwrap());
+    server_config
+        .clusters
+        .modify(|clusters| clusters.insert_default(vec![Endpoint::new(echo.clone())]));
+    t.run_server(
+        server_config,
+        server_proxy,
+        Some(Some((std::net::Ipv6Addr::UNSPECIFIED, metrics_port).into())),
+    );
+
+    let (mut recv_chan, socket) = t.open_socket_and_recv_multiple_packets().await;
+    socket.send_to(b"hello", &local_addr).await.unwrap();
+    let value = timeout(Duration::from_secs(5), recv_chan.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!("hello", value);
+
+    // The canary chain is evaluated in the background.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let response = hyper::Client::new()
+        .get(
+            format!("http://localhost:{metrics_port}/metrics")
+                .parse()
+                .unwrap(),
+        )
+        .await
+        .map(|resp| resp.into_body())
+        .map(hyper::body::to_bytes)
+        .unwrap()
+        .await
+        .unwrap();
+    let response = String::from_utf8(response.to_vec()).unwrap();
+
+    for event in ["read", "write"] {
+        let regex = regex::Regex::new(&format!(
+            r#"quilkin_filter_canary_differences_total\{{difference="decision",event="{event}"\}} 1"#
+        ))
+        .unwrap();
+        assert!(regex.is_match(&response), "{response}");
+    }
+}
@@ -0,0 +1,92 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::Duration;
+
+use tokio::{sync::mpsc, time::timeout};
+
+use quilkin::{
+    cluster::Cluster,
+    config::Filter,
+    endpoint::Endpoint,
+    filters::{Mirror, StaticFilter},
+    test_utils::{available_addr, TestHelper},
+};
+
+#[tokio::test]
+async fn mirror_filter() {
+    let mut t = TestHelper::default();
+
+    let echo = t.run_echo_server().await;
+    let (mirror_tx, mut mirror_rx) = mpsc::unbounded_channel();
+    let mirror = t
+        .run_echo_server_with_tap(move |_, packet, _| {
+            let _ = mirror_tx.send(packet.to_vec());
+        })
+        .await;
+
+    let yaml = "
+cluster: mirror
+";
+
+    let server_addr = available_addr().await;
+    let server_proxy = quilkin::cli::Proxy {
+        port: server_addr.port(),
+        ..<_>::default()
+    };
+    let server_config = std::sync::Arc::new(quilkin::Config::default());
+    server_config.clusters.modify(|clusters| {
+        clusters.insert_default(vec![Endpoint::new(echo.clone())]);
+        clusters.insert(Cluster::new(
+            "mirror",
+            vec![vec![Endpoint::new(mirror.clone())].into()],
+        ));
+    });
+    server_config.filters.store(
+        quilkin::filters::FilterChain::try_from(vec![Filter {
+            name: Mirror::factory().name().into(),
+            label: None,
+            config: serde_yaml::from_str(yaml).unwrap(),
+        }])
+        .map(std::sync::Arc::new)
+        .unwrap(),
+    );
+    t.run_server(server_config, server_proxy, None);
+
+    let msg = "hello";
+    let (mut rx, socket) = t.open_socket_and_recv_multiple_packets().await;
+    socket.send_to(msg.as_bytes(), &server_addr).await.unwrap();
+
+    assert_eq!(
+        msg,
+        timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    );
+    assert_eq!(
+        msg.as_bytes(),
+        timeout(Duration::from_secs(5), mirror_rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    );
+
+    // The reply from the mirror endpoint should be discarded, and the mirror
+    // endpoint should only receive the copy of the packet.
+    assert!(timeout(Duration::from_secs(1), rx.recv()).await.is_err());
+    assert!(mirror_rx.try_recv().is_err());
+}
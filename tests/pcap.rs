@@ -0,0 +1,106 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::Duration;
+
+use tokio::time::timeout;
+
+use quilkin::{
+    config::Filter,
+    endpoint::Endpoint,
+    filters::{pcap, Pcap, StaticFilter},
+    test_utils::{available_addr, TestHelper},
+};
+
+#[tokio::test]
+async fn pcap_filter() {
+    let mut t = TestHelper::default();
+    let directory = tempfile::tempdir().unwrap();
+
+    let yaml = format!("directory: {}", directory.path().display());
+    let echo = t.run_echo_server().await;
+
+    let server_addr = available_addr().await;
+    let server_proxy = quilkin::cli::Proxy {
+        port: server_addr.port(),
+        ..<_>::default()
+    };
+    let server_config = std::sync::Arc::new(quilkin::Config::default());
+    server_config
+        .clusters
+        .modify(|clusters| clusters.insert_default(vec![Endpoint::new(echo.clone())]));
+    server_config.filters.store(
+        quilkin::filters::FilterChain::try_from(vec![Filter {
+            name: Pcap::factory().name().into(),
+            label: None,
+            config: serde_yaml::from_str(&yaml).unwrap(),
+        }])
+        .map(std::sync::Arc::new)
+        .unwrap(),
+    );
+    t.run_server(server_config, server_proxy, None);
+
+    let (mut rx, socket) = t.open_socket_and_recv_multiple_packets().await;
+
+    // Nothing is captured until capture has been armed.
+    socket.send_to(b"before", &server_addr).await.unwrap();
+    assert_eq!(
+        "before",
+        timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    );
+    assert_eq!(0, directory.path().read_dir().unwrap().count());
+
+    pcap::arm(None, Some(2)).unwrap();
+    socket.send_to(b"hello", &server_addr).await.unwrap();
+    assert_eq!(
+        "hello",
+        timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    );
+    assert!(!pcap::status().armed);
+
+    // The packets are written to disk in the background, so poll the capture
+    // file until both the request and its reply have been written.
+    let count = |contents: &[u8], needle: &[u8]| {
+        contents
+            .windows(needle.len())
+            .filter(|window| *window == needle)
+            .count()
+    };
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    let contents = loop {
+        let contents = directory
+            .path()
+            .read_dir()
+            .unwrap()
+            .next()
+            .map(|file| std::fs::read(file.unwrap().path()).unwrap())
+            .unwrap_or_default();
+
+        if count(&contents, b"hello") >= 2 || tokio::time::Instant::now() >= deadline {
+            break contents;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+
+    assert_eq!(2, count(&contents, b"hello"));
+    assert_eq!(0, count(&contents, b"before"));
+}
@@ -0,0 +1,86 @@
+/*
+ * Copyright 2023 Google LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::time::Duration;
+
+use tokio::time::timeout;
+
+use quilkin::{
+    config::Filter,
+    endpoint::Endpoint,
+    filters::{StaticFilter, Wasm},
+    test_utils::{available_addr, TestHelper},
+};
+
+/// Drops packets starting with `x`, and appends `!` to replies.
+const MODULE: &str = r#"
+(module
+  (import "quilkin" "drop" (func $drop))
+  (import "quilkin" "set_contents" (func $set_contents (param i32 i32)))
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32)
+    i32.const 0)
+  (func (export "read") (param $ptr i32) (param $len i32)
+    (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 120))
+      (then (call $drop))))
+  (func (export "write") (param $ptr i32) (param $len i32)
+    (i32.store8 (i32.add (local.get $ptr) (local.get $len)) (i32.const 33))
+    (call $set_contents (local.get $ptr) (i32.add (local.get $len) (i32.const 1)))))
+"#;
+
+#[tokio::test]
+async fn wasm_filter() {
+    let mut t = TestHelper::default();
+    let echo = t.run_echo_server().await;
+
+    let module = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(module.path(), wat::parse_str(MODULE).unwrap()).unwrap();
+    let yaml = format!("path: {}", module.path().display());
+
+    let server_addr = available_addr().await;
+    let server_proxy = quilkin::cli::Proxy {
+        port: server_addr.port(),
+        ..<_>::default()
+    };
+    let server_config = std::sync::Arc::new(quilkin::Config::default());
+    server_config
+        .clusters
+        .modify(|clusters| clusters.insert_default(vec![Endpoint::new(echo.clone())]));
+    server_config.filters.store(
+        quilkin::filters::FilterChain::try_from(vec![Filter {
+            name: Wasm::factory().name().into(),
+            label: None,
+            config: serde_yaml::from_str(&yaml).unwrap(),
+        }])
+        .map(std::sync::Arc::new)
+        .unwrap(),
+    );
+    t.run_server(server_config, server_proxy, None);
+
+    let (mut rx, socket) = t.open_socket_and_recv_multiple_packets().await;
+
+    socket.send_to(b"xyz", &server_addr).await.unwrap();
+    socket.send_to(b"hello", &server_addr).await.unwrap();
+
+    assert_eq!(
+        "hello!",
+        timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    );
+    assert!(timeout(Duration::from_secs(1), rx.recv()).await.is_err());
+}